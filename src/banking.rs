@@ -0,0 +1,164 @@
+use core::fmt;
+
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+pub mod atari;
+pub mod c64;
+pub mod nes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The image size doesn't fit the banking scheme.
+    ImageSize { name: &'static str, size: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ImageSize { name, size } => write!(f, "{} image of {} bytes has an unsupported size", name, size),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Memory split into equally sized banks, with a number of windows that
+/// each show one bank. Offsets address the windows laid out back to back,
+/// so window `n` starts at offset `n * bank_size`.
+#[derive(Debug, Clone)]
+pub struct Banks {
+    data: Vec<u8>,
+    bank_size: usize,
+    windows: Vec<usize>,
+    writable: bool,
+}
+
+impl Banks {
+    /// Banks over `data`, whose length must be a nonzero multiple of
+    /// `bank_size`. All windows start out showing bank 0.
+    pub fn new(data: Vec<u8>, bank_size: usize, windows: usize) -> Self {
+        assert!(!data.is_empty() && data.len().is_multiple_of(bank_size), "data must be whole banks");
+        Banks { data, bank_size, windows: vec![0; windows], writable: false }
+    }
+
+    /// Zeroed, writable banks.
+    pub fn ram(size: usize, bank_size: usize, windows: usize) -> Self {
+        Banks { writable: true, ..Self::new(vec![0; size], bank_size, windows) }
+    }
+
+    pub fn bank_size(&self) -> usize {
+        self.bank_size
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.data.len() / self.bank_size
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Shows `bank` in `window`. Like hardware with unconnected high bank
+    /// bits, out of range banks wrap around.
+    pub fn select(&mut self, window: usize, bank: usize) {
+        self.windows[window] = bank % self.bank_count();
+    }
+
+    pub fn selected(&self, window: usize) -> usize {
+        self.windows[window]
+    }
+
+    fn index(&self, offset: usize) -> usize {
+        let (window, offset) = (offset / self.bank_size, offset % self.bank_size);
+        self.windows[window] * self.bank_size + offset
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[self.index(offset)]
+    }
+
+    /// Writes through a window; ignored unless the banks are RAM.
+    pub fn write(&mut self, offset: usize, value: u8) {
+        if self.writable {
+            let index = self.index(offset);
+            self.data[index] = value;
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+// The selected banks, and the contents if they are RAM.
+impl SaveState for Banks {
+    fn save(&self, out: &mut StateWriter) {
+        out.u32(self.windows.len() as u32);
+        for &bank in &self.windows {
+            out.u32(bank as u32);
+        }
+        if self.writable {
+            out.bytes(&self.data);
+        }
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("banks", version, 1)?;
+        if input.u32()? as usize != self.windows.len() {
+            return Err(savestate::Error::Invalid("wrong number of bank windows"));
+        }
+        for window in 0..self.windows.len() {
+            let bank = input.u32()? as usize;
+            if bank >= self.bank_count() {
+                return Err(savestate::Error::Invalid("bank out of range"));
+            }
+            self.windows[window] = bank;
+        }
+        if self.writable {
+            input.bytes_into(&mut self.data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        let data: Vec<u8> = (0..8).collect();
+        let mut banks = Banks::new(data, 2, 2);
+        assert_eq!(banks.bank_count(), 4);
+        banks.select(0, 3);
+        banks.select(1, 5);
+        assert_eq!((banks.read(0), banks.read(1), banks.read(2), banks.read(3)), (6, 7, 2, 3));
+        banks.write(0, 0xFF);
+        assert_eq!(banks.read(0), 6);
+
+        let mut ram = Banks::ram(4, 2, 1);
+        ram.select(0, 1);
+        ram.write(1, 9);
+        assert_eq!(ram.data(), &[0, 0, 0, 9]);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut ram = Banks::ram(8, 2, 2);
+        ram.select(0, 3);
+        ram.select(1, 1);
+        ram.write(0, 5);
+        let state = savestate::save("banks", &ram);
+
+        let mut restored = Banks::ram(8, 2, 2);
+        savestate::load("banks", &mut restored, &state).unwrap();
+        assert_eq!((restored.selected(0), restored.selected(1)), (3, 1));
+        assert_eq!(restored.data(), ram.data());
+
+        let mut smaller = Banks::ram(4, 2, 2);
+        assert_eq!(
+            savestate::load("banks", &mut smaller, &state),
+            Err(savestate::Error::Invalid("bank out of range"))
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Atari 2600 cartridges. A cartridge is a `Device` to be mapped over the
+//! 4K cartridge space at $1000-$1FFF (and its mirrors) with a mask of $0FFF.
+
+use super::{Banks, Error};
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+/// How the banks of a cartridge are selected. Accessing (reading or
+/// writing) one of a run of hotspot addresses at the top of the cartridge
+/// space selects the corresponding 4K bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// 8K in two banks, hotspots $1FF8-$1FF9.
+    F8,
+    /// 16K in four banks, hotspots $1FF6-$1FF9.
+    F6,
+    /// 32K in eight banks, hotspots $1FF4-$1FFB.
+    F4,
+}
+
+impl Scheme {
+    fn banks(self) -> usize {
+        match self {
+            Scheme::F8 => 2,
+            Scheme::F6 => 4,
+            Scheme::F4 => 8,
+        }
+    }
+
+    fn first_hotspot(self) -> u16 {
+        match self {
+            Scheme::F8 => 0x0FF8,
+            Scheme::F6 => 0x0FF6,
+            Scheme::F4 => 0x0FF4,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cartridge {
+    scheme: Scheme,
+    rom: Banks,
+}
+
+impl Cartridge {
+    /// Starts in the last bank, which holds the reset vector on most
+    /// cartridges.
+    pub fn new(scheme: Scheme, rom: Vec<u8>) -> Result<Self, Error> {
+        if rom.len() != scheme.banks() * 0x1000 {
+            return Err(Error::ImageSize { name: "cartridge", size: rom.len() });
+        }
+        let mut rom = Banks::new(rom, 0x1000, 1);
+        rom.select(0, scheme.banks() - 1);
+        Ok(Cartridge { scheme, rom })
+    }
+
+    pub fn bank(&self) -> usize {
+        self.rom.selected(0)
+    }
+
+    fn access(&mut self, offset: u16) {
+        let bank = (offset & 0x0FFF).wrapping_sub(self.scheme.first_hotspot()) as usize;
+        if bank < self.scheme.banks() {
+            self.rom.select(0, bank);
+        }
+    }
+}
+
+impl Device for Cartridge {
+    fn peek(&self, offset: u16) -> u8 {
+        self.rom.read(offset as usize & 0x0FFF)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        self.access(offset);
+        self.peek(offset)
+    }
+
+    fn write(&mut self, offset: u16, _: u8) {
+        self.access(offset);
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Cartridge {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("rom", &self.rom);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("cartridge", version, 1)?;
+        input.section("rom", &mut self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(banks: usize) -> Vec<u8> {
+        (0..banks * 0x1000).map(|i| (i / 0x1000) as u8).collect()
+    }
+
+    #[test]
+    fn test_f8() {
+        let mut cart = Cartridge::new(Scheme::F8, image(2)).unwrap();
+        assert_eq!(cart.peek(0x0000), 1);
+        cart.read(0x0FF8);
+        assert_eq!(cart.peek(0x0000), 0);
+        cart.write(0x0FF9, 0);
+        assert_eq!(cart.peek(0x0000), 1);
+        // Peeking and other addresses leave the bank alone.
+        cart.peek(0x0FF8);
+        cart.read(0x0FF7);
+        cart.read(0x0FFA);
+        assert_eq!(cart.bank(), 1);
+        assert!(Cartridge::new(Scheme::F8, image(4)).is_err());
+    }
+
+    #[test]
+    fn test_f6() {
+        let mut cart = Cartridge::new(Scheme::F6, image(4)).unwrap();
+        assert_eq!(cart.bank(), 3);
+        cart.read(0x0FF7);
+        assert_eq!(cart.read(0x0123), 1);
+        cart.read(0x0FF5);
+        assert_eq!(cart.bank(), 1);
+    }
+
+    #[test]
+    fn test_f4() {
+        let mut cart = Cartridge::new(Scheme::F4, image(8)).unwrap();
+        for bank in 0..8 {
+            cart.read(0x0FF4 + bank);
+            assert_eq!(cart.peek(0x0000), bank as u8);
+        }
+        cart.read(0x0FFC);
+        assert_eq!(cart.bank(), 7);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut cart = Cartridge::new(Scheme::F6, image(4)).unwrap();
+        cart.read(0x0FF7);
+        let state = savestate::save("cartridge", &cart);
+        let mut restored = Cartridge::new(Scheme::F6, image(4)).unwrap();
+        savestate::load("cartridge", &mut restored, &state).unwrap();
+        assert_eq!(restored.bank(), 1);
+    }
+}
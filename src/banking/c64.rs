@@ -0,0 +1,237 @@
+//! C64 cartridges, seen from the expansion port.
+
+use super::{Banks, Error};
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+/// A cartridge on the C64 expansion port. ROML is at $8000-$9FFF and ROMH
+/// at $A000-$BFFF, or $E000-$FFFF in Ultimax mode; I/O 1 and I/O 2 are the
+/// pages at $DE00 and $DF00. Which of them the CPU sees is decided by the
+/// C64's PLA from the GAME and EXROM lines.
+pub trait ExpansionPort {
+    fn roml(&self, offset: u16) -> u8;
+    fn romh(&self, offset: u16) -> u8;
+
+    /// `None` when the cartridge doesn't drive the data bus.
+    fn io1_read(&mut self, _offset: u8) -> Option<u8> {
+        None
+    }
+
+    fn io1_write(&mut self, _offset: u8, _value: u8) {}
+
+    fn io2_read(&mut self, _offset: u8) -> Option<u8> {
+        None
+    }
+
+    fn io2_write(&mut self, _offset: u8, _value: u8) {}
+
+    /// Whether /GAME is asserted (pulled low).
+    fn game(&self) -> bool;
+
+    /// Whether /EXROM is asserted (pulled low).
+    fn exrom(&self) -> bool;
+}
+
+/// Ocean cartridges: up to 64 8K banks selected by writing to $DE00. The
+/// selected bank shows at both ROML and ROMH; 512K images run in 8K mode,
+/// everything else in 16K mode.
+#[derive(Debug, Clone)]
+pub struct Ocean {
+    rom: Banks,
+}
+
+impl Ocean {
+    pub fn new(rom: Vec<u8>) -> Result<Self, Error> {
+        if rom.is_empty() || rom.len() > 0x80000 || !rom.len().is_multiple_of(0x2000) {
+            return Err(Error::ImageSize { name: "Ocean cartridge", size: rom.len() });
+        }
+        Ok(Ocean { rom: Banks::new(rom, 0x2000, 1) })
+    }
+
+    pub fn bank(&self) -> usize {
+        self.rom.selected(0)
+    }
+}
+
+impl ExpansionPort for Ocean {
+    fn roml(&self, offset: u16) -> u8 {
+        self.rom.read(offset as usize & 0x1FFF)
+    }
+
+    fn romh(&self, offset: u16) -> u8 {
+        self.roml(offset)
+    }
+
+    fn io1_write(&mut self, _: u8, value: u8) {
+        self.rom.select(0, (value & 0x3F) as usize);
+    }
+
+    fn game(&self) -> bool {
+        self.rom.data().len() != 0x80000
+    }
+
+    fn exrom(&self) -> bool {
+        true
+    }
+}
+
+/// EasyFlash: up to 64 banks of 8K ROML plus 8K ROMH, 256 bytes of RAM at
+/// $DF00, a bank register at $DE00 and a control register at $DE02. The
+/// image holds each bank's ROML followed by its ROMH. Flash programming
+/// is not emulated.
+#[derive(Debug, Clone)]
+pub struct EasyFlash {
+    rom: Banks,
+    ram: [u8; 256],
+    control: u8,
+    boot: bool,
+}
+
+impl EasyFlash {
+    /// `boot` is the position of the boot jumper: when set, /GAME is held
+    /// low until the control register takes over, so the cartridge starts
+    /// in Ultimax mode.
+    pub fn new(rom: Vec<u8>, boot: bool) -> Result<Self, Error> {
+        if rom.is_empty() || rom.len() > 0x100000 || !rom.len().is_multiple_of(0x4000) {
+            return Err(Error::ImageSize { name: "EasyFlash", size: rom.len() });
+        }
+        Ok(EasyFlash { rom: Banks::new(rom, 0x4000, 1), ram: [0; 256], control: 0, boot })
+    }
+
+    pub fn bank(&self) -> usize {
+        self.rom.selected(0)
+    }
+
+    /// The state of the LED.
+    pub fn led(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+}
+
+impl ExpansionPort for EasyFlash {
+    fn roml(&self, offset: u16) -> u8 {
+        self.rom.read(offset as usize & 0x1FFF)
+    }
+
+    fn romh(&self, offset: u16) -> u8 {
+        self.rom.read(0x2000 | offset as usize & 0x1FFF)
+    }
+
+    fn io1_write(&mut self, offset: u8, value: u8) {
+        match offset & 2 {
+            0 => self.rom.select(0, (value & 0x3F) as usize),
+            _ => self.control = value & 0x87,
+        }
+    }
+
+    fn io2_read(&mut self, offset: u8) -> Option<u8> {
+        Some(self.ram[offset as usize])
+    }
+
+    fn io2_write(&mut self, offset: u8, value: u8) {
+        self.ram[offset as usize] = value;
+    }
+
+    fn game(&self) -> bool {
+        match self.control & 4 {
+            0 => self.boot,
+            _ => self.control & 1 != 0,
+        }
+    }
+
+    fn exrom(&self) -> bool {
+        self.control & 2 != 0
+    }
+}
+
+impl SaveState for Ocean {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("rom", &self.rom);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("ocean", version, 1)?;
+        input.section("rom", &mut self.rom)
+    }
+}
+
+impl SaveState for EasyFlash {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("rom", &self.rom);
+        out.bytes(&self.ram);
+        out.u8(self.control);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("easyflash", version, 1)?;
+        input.section("rom", &mut self.rom)?;
+        input.bytes_into(&mut self.ram)?;
+        self.control = input.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(chips: usize) -> Vec<u8> {
+        (0..chips * 0x2000).map(|i| (i / 0x2000) as u8).collect()
+    }
+
+    #[test]
+    fn test_ocean() {
+        let mut cart = Ocean::new(image(16)).unwrap();
+        assert!(cart.game() && cart.exrom());
+        cart.io1_write(0, 0x45);
+        assert_eq!(cart.bank(), 5);
+        assert_eq!((cart.roml(0x0000), cart.romh(0x1FFF)), (5, 5));
+        assert_eq!(cart.io1_read(0), None);
+
+        let cart = Ocean::new(image(64)).unwrap();
+        assert!(!cart.game() && cart.exrom());
+        assert!(Ocean::new(image(65)).is_err());
+    }
+
+    #[test]
+    fn test_easyflash() {
+        let mut cart = EasyFlash::new(image(128), true).unwrap();
+        // Boots in Ultimax mode from the jumper.
+        assert!(cart.game() && !cart.exrom());
+
+        cart.io1_write(0x00, 3);
+        assert_eq!((cart.roml(0x0000), cart.romh(0x0000)), (6, 7));
+
+        // M=1, X=1, G=1: 16K mode. Then M=1, X=1, G=0: 8K mode.
+        cart.io1_write(0x02, 0x87);
+        assert!(cart.game() && cart.exrom() && cart.led());
+        cart.io1_write(0x02, 0x06);
+        assert!(!cart.game() && cart.exrom() && !cart.led());
+        // M=0, X=0: cartridge off, unless the jumper holds /GAME low.
+        cart.io1_write(0x02, 0x00);
+        assert!(cart.game() && !cart.exrom());
+        assert!(!EasyFlash::new(image(2), false).unwrap().game());
+
+        cart.io2_write(0x80, 0x42);
+        assert_eq!(cart.io2_read(0x80), Some(0x42));
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut cart = Ocean::new(image(16)).unwrap();
+        cart.io1_write(0, 9);
+        let state = savestate::save("cartridge", &cart);
+        let mut restored = Ocean::new(image(16)).unwrap();
+        savestate::load("cartridge", &mut restored, &state).unwrap();
+        assert_eq!(restored.bank(), 9);
+
+        let mut cart = EasyFlash::new(image(128), true).unwrap();
+        cart.io1_write(0x00, 3);
+        cart.io1_write(0x02, 0x87);
+        cart.io2_write(0x10, 0x42);
+        let state = savestate::save("cartridge", &cart);
+        let mut restored = EasyFlash::new(image(128), true).unwrap();
+        savestate::load("cartridge", &mut restored, &state).unwrap();
+        assert_eq!((restored.bank(), restored.led()), (3, true));
+        assert_eq!(restored.io2_read(0x10), Some(0x42));
+    }
+}
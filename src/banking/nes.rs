@@ -0,0 +1,743 @@
+//! NES cartridge boards. The CPU side of a board is a `Device` to be
+//! mapped at `CPU_RANGE`, covering 8K of PRG RAM at $6000 and PRG ROM at
+//! $8000. The PPU side covers the pattern tables at $0000-$1FFF.
+
+use core::ops::RangeInclusive;
+
+use super::{Banks, Error};
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+pub const CPU_RANGE: RangeInclusive<u16> = 0x6000..=0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleLow,
+    SingleHigh,
+}
+
+pub trait Cartridge: Device {
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, value: u8);
+    fn mirroring(&self) -> Mirroring;
+}
+
+// What every board has: PRG RAM, PRG ROM and CHR ROM or RAM.
+#[derive(Debug, Clone)]
+struct Board {
+    ram: Vec<u8>,
+    prg: Banks,
+    chr: Banks,
+    mirroring: Mirroring,
+}
+
+impl Board {
+    // An empty CHR image means the board has 8K of CHR RAM instead.
+    fn new(
+        prg: Vec<u8>,
+        prg_bank: usize,
+        prg_windows: usize,
+        chr: Vec<u8>,
+        chr_bank: usize,
+        mirroring: Mirroring,
+    ) -> Result<Self, Error> {
+        if prg.is_empty() || !prg.len().is_multiple_of(prg_bank) {
+            return Err(Error::ImageSize { name: "PRG ROM", size: prg.len() });
+        }
+        if !chr.len().is_multiple_of(chr_bank) {
+            return Err(Error::ImageSize { name: "CHR ROM", size: chr.len() });
+        }
+        let chr_windows = 0x2000 / chr_bank;
+        let chr = match chr.is_empty() {
+            true => Banks::ram(0x2000, chr_bank, chr_windows),
+            false => Banks::new(chr, chr_bank, chr_windows),
+        };
+        let mut board = Board { ram: vec![0; 0x2000], prg: Banks::new(prg, prg_bank, prg_windows), chr, mirroring };
+        for window in 0..prg_windows {
+            board.prg.select(window, window);
+        }
+        for window in 0..chr_windows {
+            board.chr.select(window, window);
+        }
+        Ok(board)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        match offset {
+            0x0000..=0x1FFF => self.ram[offset as usize],
+            _ => self.prg.read(offset as usize - 0x2000),
+        }
+    }
+
+    fn last_prg(&self) -> usize {
+        self.prg.bank_count() - 1
+    }
+}
+
+impl SaveState for Board {
+    fn save(&self, out: &mut StateWriter) {
+        out.bytes(&self.ram);
+        out.section("prg", &self.prg);
+        out.section("chr", &self.chr);
+        out.u8(self.mirroring as u8);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("board", version, 1)?;
+        input.bytes_into(&mut self.ram)?;
+        input.section("prg", &mut self.prg)?;
+        input.section("chr", &mut self.chr)?;
+        self.mirroring = match input.u8()? {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::SingleLow,
+            3 => Mirroring::SingleHigh,
+            _ => return Err(savestate::Error::Invalid("invalid mirroring")),
+        };
+        Ok(())
+    }
+}
+
+macro_rules! cartridge {
+    ($name:ident) => {
+        impl Cartridge for $name {
+            fn ppu_read(&mut self, addr: u16) -> u8 {
+                self.board.chr.read(addr as usize & 0x1FFF)
+            }
+
+            fn ppu_write(&mut self, addr: u16, value: u8) {
+                self.board.chr.write(addr as usize & 0x1FFF, value)
+            }
+
+            fn mirroring(&self) -> Mirroring {
+                self.board.mirroring
+            }
+        }
+    };
+}
+
+/// Mapper 0: 16K or 32K of PRG ROM and 8K of CHR, no banking.
+#[derive(Debug, Clone)]
+pub struct Nrom {
+    board: Board,
+}
+
+impl Nrom {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Result<Self, Error> {
+        if !matches!(prg.len(), 0x4000 | 0x8000) {
+            return Err(Error::ImageSize { name: "PRG ROM", size: prg.len() });
+        }
+        if !matches!(chr.len(), 0 | 0x2000) {
+            return Err(Error::ImageSize { name: "CHR ROM", size: chr.len() });
+        }
+        Ok(Nrom { board: Board::new(prg, 0x4000, 2, chr, 0x2000, mirroring)? })
+    }
+}
+
+impl Device for Nrom {
+    fn peek(&self, offset: u16) -> u8 {
+        self.board.peek(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        if offset < 0x2000 {
+            self.board.ram[offset as usize] = value;
+        }
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+cartridge!(Nrom);
+
+impl SaveState for Nrom {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("board", &self.board);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("nrom", version, 1)?;
+        input.section("board", &mut self.board)
+    }
+}
+
+/// Mapper 2: a switchable 16K PRG bank at $8000 with the last bank fixed at
+/// $C000. The ROM drives the data bus during register writes, so the bank
+/// number is ANDed with the byte at the written address.
+#[derive(Debug, Clone)]
+pub struct Uxrom {
+    board: Board,
+}
+
+impl Uxrom {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Result<Self, Error> {
+        let mut board = Board::new(prg, 0x4000, 2, chr, 0x2000, mirroring)?;
+        board.prg.select(0, 0);
+        board.prg.select(1, board.last_prg());
+        Ok(Uxrom { board })
+    }
+}
+
+impl Device for Uxrom {
+    fn peek(&self, offset: u16) -> u8 {
+        self.board.peek(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset {
+            0x0000..=0x1FFF => self.board.ram[offset as usize] = value,
+            _ => {
+                let bank = value & self.board.peek(offset);
+                self.board.prg.select(0, bank as usize);
+            }
+        }
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+cartridge!(Uxrom);
+
+impl SaveState for Uxrom {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("board", &self.board);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("uxrom", version, 1)?;
+        input.section("board", &mut self.board)
+    }
+}
+
+/// Mapper 3: fixed PRG ROM like NROM and a switchable 8K CHR bank, with bus
+/// conflicts like UxROM.
+#[derive(Debug, Clone)]
+pub struct Cnrom {
+    board: Board,
+}
+
+impl Cnrom {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Result<Self, Error> {
+        if !matches!(prg.len(), 0x4000 | 0x8000) {
+            return Err(Error::ImageSize { name: "PRG ROM", size: prg.len() });
+        }
+        Ok(Cnrom { board: Board::new(prg, 0x4000, 2, chr, 0x2000, mirroring)? })
+    }
+}
+
+impl Device for Cnrom {
+    fn peek(&self, offset: u16) -> u8 {
+        self.board.peek(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset {
+            0x0000..=0x1FFF => self.board.ram[offset as usize] = value,
+            _ => {
+                let bank = value & self.board.peek(offset);
+                self.board.chr.select(0, bank as usize);
+            }
+        }
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+cartridge!(Cnrom);
+
+impl SaveState for Cnrom {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("board", &self.board);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("cnrom", version, 1)?;
+        input.section("board", &mut self.board)
+    }
+}
+
+/// Mapper 1: registers loaded one bit at a time through a serial port.
+/// Supports 16K and 32K PRG banking and 4K and 8K CHR banking.
+#[derive(Debug, Clone)]
+pub struct Mmc1 {
+    board: Board,
+    shift: u8,
+    count: u8,
+    control: u8,
+    chr0: u8,
+    chr1: u8,
+    prg: u8,
+}
+
+impl Mmc1 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>) -> Result<Self, Error> {
+        let board = Board::new(prg, 0x4000, 2, chr, 0x1000, Mirroring::SingleLow)?;
+        let mut mmc1 = Mmc1 { board, shift: 0, count: 0, control: 0x0C, chr0: 0, chr1: 0, prg: 0 };
+        mmc1.update();
+        Ok(mmc1)
+    }
+
+    fn update(&mut self) {
+        let (prg, chr) = (&mut self.board.prg, &mut self.board.chr);
+        let bank = (self.prg & 0x0F) as usize;
+        let last = prg.bank_count() - 1;
+        let (low, high) = match (self.control >> 2) & 3 {
+            0 | 1 => (bank & !1, bank | 1),
+            2 => (0, bank),
+            _ => (bank, last),
+        };
+        prg.select(0, low);
+        prg.select(1, high);
+
+        let (chr0, chr1) = (self.chr0 as usize, self.chr1 as usize);
+        let (low, high) = match self.control & 0x10 {
+            0 => (chr0 & !1, chr0 | 1),
+            _ => (chr0, chr1),
+        };
+        chr.select(0, low);
+        chr.select(1, high);
+
+        self.board.mirroring = match self.control & 3 {
+            0 => Mirroring::SingleLow,
+            1 => Mirroring::SingleHigh,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        };
+    }
+}
+
+impl Device for Mmc1 {
+    fn peek(&self, offset: u16) -> u8 {
+        self.board.peek(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        if offset < 0x2000 {
+            self.board.ram[offset as usize] = value;
+            return;
+        }
+        if value & 0x80 != 0 {
+            (self.shift, self.count) = (0, 0);
+            self.control |= 0x0C;
+        } else {
+            self.shift |= (value & 1) << self.count;
+            self.count += 1;
+            if self.count < 5 {
+                return;
+            }
+            let register = match (offset + 0x6000) >> 13 & 3 {
+                0 => &mut self.control,
+                1 => &mut self.chr0,
+                2 => &mut self.chr1,
+                _ => &mut self.prg,
+            };
+            *register = self.shift;
+            (self.shift, self.count) = (0, 0);
+        }
+        self.update();
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+cartridge!(Mmc1);
+
+impl SaveState for Mmc1 {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("board", &self.board);
+        for value in [self.shift, self.count, self.control, self.chr0, self.chr1, self.prg] {
+            out.u8(value);
+        }
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("mmc1", version, 1)?;
+        input.section("board", &mut self.board)?;
+        for value in [&mut self.shift, &mut self.count, &mut self.control, &mut self.chr0, &mut self.chr1, &mut self.prg] {
+            *value = input.u8()?;
+        }
+        if self.count >= 5 {
+            return Err(savestate::Error::Invalid("invalid MMC1 shift count"));
+        }
+        Ok(())
+    }
+}
+
+/// Mapper 4: 8K PRG and 1K/2K CHR banking with a scanline counter that
+/// raises an IRQ. The PPU should call `clock_scanline` once per rendered
+/// line, where the real chip sees PPU A12 rise.
+#[derive(Debug, Clone)]
+pub struct Mmc3 {
+    board: Board,
+    bank_select: u8,
+    registers: [u8; 8],
+    ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+}
+
+impl Mmc3 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>) -> Result<Self, Error> {
+        let board = Board::new(prg, 0x2000, 4, chr, 0x0400, Mirroring::Vertical)?;
+        if board.prg.bank_count() < 2 {
+            return Err(Error::ImageSize { name: "PRG ROM", size: board.prg.data().len() });
+        }
+        let mut mmc3 = Mmc3 {
+            board,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+        };
+        mmc3.update();
+        Ok(mmc3)
+    }
+
+    fn update(&mut self) {
+        let r = self.registers.map(|r| r as usize);
+        let last = self.board.last_prg();
+        let prg = match self.bank_select & 0x40 {
+            0 => [r[6], r[7], last - 1, last],
+            _ => [last - 1, r[7], r[6], last],
+        };
+        for (window, bank) in prg.into_iter().enumerate() {
+            self.board.prg.select(window, bank);
+        }
+
+        let chr = [r[0] & !1, r[0] | 1, r[1] & !1, r[1] | 1, r[2], r[3], r[4], r[5]];
+        let invert = if self.bank_select & 0x80 != 0 { 4 } else { 0 };
+        for window in 0..8 {
+            self.board.chr.select(window, chr[(window + invert) % 8]);
+        }
+    }
+
+    pub fn clock_scanline(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq = true;
+        }
+    }
+}
+
+impl Device for Mmc3 {
+    fn peek(&self, offset: u16) -> u8 {
+        match offset {
+            // With the PRG RAM disabled nothing drives the bus, which
+            // still holds the high byte of the address from the operand
+            // fetch. `read` defaults to this, so the debugger sees the
+            // same value as the CPU.
+            0x0000..=0x1FFF if self.ram_protect & 0x80 == 0 => ((offset + 0x6000) >> 8) as u8,
+            _ => self.board.peek(offset),
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        if offset < 0x2000 {
+            if self.ram_protect & 0xC0 == 0x80 {
+                self.board.ram[offset as usize] = value;
+            }
+            return;
+        }
+        match (offset + 0x6000) & 0xE001 {
+            0x8000 => self.bank_select = value,
+            0x8001 => self.registers[(self.bank_select & 7) as usize] = value,
+            0xA000 => {
+                self.board.mirroring = match value & 1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                }
+            }
+            0xA001 => self.ram_protect = value,
+            0xC000 => self.irq_latch = value,
+            0xC001 => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000 => {
+                self.irq_enabled = false;
+                self.irq = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+        self.update();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+cartridge!(Mmc3);
+
+impl SaveState for Mmc3 {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("board", &self.board);
+        out.u8(self.bank_select);
+        out.bytes(&self.registers);
+        out.u8(self.ram_protect);
+        out.u8(self.irq_latch);
+        out.u8(self.irq_counter);
+        out.bool(self.irq_reload);
+        out.bool(self.irq_enabled);
+        out.bool(self.irq);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("mmc3", version, 1)?;
+        input.section("board", &mut self.board)?;
+        self.bank_select = input.u8()?;
+        input.bytes_into(&mut self.registers)?;
+        self.ram_protect = input.u8()?;
+        self.irq_latch = input.u8()?;
+        self.irq_counter = input.u8()?;
+        self.irq_reload = input.bool()?;
+        self.irq_enabled = input.bool()?;
+        self.irq = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each byte holds its bank number.
+    fn image(banks: usize, bank_size: usize) -> Vec<u8> {
+        (0..banks * bank_size).map(|i| (i / bank_size) as u8).collect()
+    }
+
+    fn read(cart: &impl Device, addr: u16) -> u8 {
+        cart.peek(addr - CPU_RANGE.start())
+    }
+
+    fn write(cart: &mut impl Device, addr: u16, value: u8) {
+        cart.write(addr - CPU_RANGE.start(), value)
+    }
+
+    #[test]
+    fn test_nrom() {
+        let mut cart = Nrom::new(image(1, 0x4000), Vec::new(), Mirroring::Vertical).unwrap();
+        write(&mut cart, 0x6000, 0x42);
+        assert_eq!(read(&cart, 0x6000), 0x42);
+        assert_eq!((read(&cart, 0x8000), read(&cart, 0xFFFF)), (0, 0));
+
+        // No CHR ROM, so CHR RAM.
+        cart.ppu_write(0x1234, 7);
+        assert_eq!(cart.ppu_read(0x1234), 7);
+
+        let cart = Nrom::new(image(2, 0x4000), image(1, 0x2000), Mirroring::Horizontal).unwrap();
+        assert_eq!((read(&cart, 0x8000), read(&cart, 0xC000)), (0, 1));
+        assert_eq!(cart.mirroring(), Mirroring::Horizontal);
+        assert!(Nrom::new(image(3, 0x4000), Vec::new(), Mirroring::Vertical).is_err());
+    }
+
+    #[test]
+    fn test_uxrom() {
+        let mut prg = image(8, 0x4000);
+        prg[0x1C000] = 0xFF;
+        prg[0x1C001] = 0x03;
+        let mut cart = Uxrom::new(prg, Vec::new(), Mirroring::Vertical).unwrap();
+        assert_eq!((read(&cart, 0x8000), read(&cart, 0xC002)), (0, 7));
+        write(&mut cart, 0xC000, 5);
+        assert_eq!((read(&cart, 0x8000), read(&cart, 0xC002)), (5, 7));
+        // Bus conflict: $C001 holds $03.
+        write(&mut cart, 0xC001, 6);
+        assert_eq!(read(&cart, 0x8000), 2);
+    }
+
+    #[test]
+    fn test_cnrom() {
+        let mut prg = image(2, 0x4000);
+        prg[0x7FF0] = 0xFF;
+        let mut cart = Cnrom::new(prg, image(4, 0x2000), Mirroring::Vertical).unwrap();
+        assert_eq!(cart.ppu_read(0x0000), 0);
+        write(&mut cart, 0xFFF0, 3);
+        assert_eq!(cart.ppu_read(0x1FFF), 3);
+        cart.ppu_write(0x0000, 9);
+        assert_eq!(cart.ppu_read(0x0000), 3);
+    }
+
+    fn mmc1_write(cart: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            write(cart, addr, value >> bit & 1);
+        }
+    }
+
+    #[test]
+    fn test_mmc1() {
+        let mut cart = Mmc1::new(image(8, 0x4000), image(8, 0x1000)).unwrap();
+        // Powers up with the last bank fixed at $C000.
+        assert_eq!(read(&cart, 0xC000), 7);
+        mmc1_write(&mut cart, 0xE000, 3);
+        assert_eq!((read(&cart, 0x8000), read(&cart, 0xC000)), (3, 7));
+
+        // Control: 32K PRG mode, 4K CHR mode, horizontal mirroring.
+        mmc1_write(&mut cart, 0x8000, 0x13);
+        assert_eq!((read(&cart, 0x8000), read(&cart, 0xC000)), (2, 3));
+        assert_eq!(cart.mirroring(), Mirroring::Horizontal);
+        mmc1_write(&mut cart, 0xA000, 5);
+        mmc1_write(&mut cart, 0xC000, 2);
+        assert_eq!((cart.ppu_read(0x0000), cart.ppu_read(0x1000)), (5, 2));
+
+        // A write with bit 7 set resets the shift register and PRG mode.
+        write(&mut cart, 0x8000, 1);
+        write(&mut cart, 0x8000, 0x80);
+        assert_eq!((read(&cart, 0x8000), read(&cart, 0xC000)), (3, 7));
+        mmc1_write(&mut cart, 0xE000, 4);
+        assert_eq!(read(&cart, 0x8000), 4);
+    }
+
+    #[test]
+    fn test_mmc3_banking() {
+        let mut cart = Mmc3::new(image(16, 0x2000), image(32, 0x0400)).unwrap();
+        write(&mut cart, 0x8000, 6);
+        write(&mut cart, 0x8001, 3);
+        write(&mut cart, 0x8000, 7);
+        write(&mut cart, 0x8001, 4);
+        let prg = |cart: &Mmc3| [0x8000, 0xA000, 0xC000, 0xE000].map(|a| read(cart, a));
+        assert_eq!(prg(&cart), [3, 4, 14, 15]);
+        write(&mut cart, 0x8000, 0x40);
+        assert_eq!(prg(&cart), [14, 4, 3, 15]);
+
+        write(&mut cart, 0x8001, 9);
+        write(&mut cart, 0x8000, 2);
+        write(&mut cart, 0x8001, 20);
+        let chr = |cart: &mut Mmc3| [0x0000, 0x0400, 0x1000].map(|a| cart.ppu_read(a));
+        assert_eq!(chr(&mut cart), [8, 9, 20]);
+        write(&mut cart, 0x8000, 0x80);
+        assert_eq!(chr(&mut cart), [20, 5, 8]);
+
+        write(&mut cart, 0xA000, 1);
+        assert_eq!(cart.mirroring(), Mirroring::Horizontal);
+        write(&mut cart, 0xA001, 0xC0);
+        write(&mut cart, 0x6000, 1);
+        assert_eq!(read(&cart, 0x6000), 0);
+        // Disabled PRG RAM reads as open bus.
+        write(&mut cart, 0xA001, 0x00);
+        assert_eq!((read(&cart, 0x6000), read(&cart, 0x7FFF)), (0x60, 0x7F));
+        assert_eq!(cart.read(0x7FFF - CPU_RANGE.start()), 0x7F);
+    }
+
+    #[test]
+    fn test_mmc3_irq() {
+        let mut cart = Mmc3::new(image(4, 0x2000), Vec::new()).unwrap();
+        write(&mut cart, 0xC000, 2);
+        write(&mut cart, 0xC001, 0);
+        write(&mut cart, 0xE001, 0);
+        cart.clock_scanline();
+        cart.clock_scanline();
+        assert!(!cart.irq());
+        cart.clock_scanline();
+        assert!(cart.irq());
+        write(&mut cart, 0xE000, 0);
+        assert!(!cart.irq());
+        // Reloads and counts down again.
+        for _ in 0..3 {
+            cart.clock_scanline();
+        }
+        assert!(!cart.irq());
+    }
+
+    // Saves `cart` and loads the state into `into`.
+    fn round_trip<T: SaveState>(cart: &T, into: &mut T) {
+        let state = savestate::save("cartridge", cart);
+        savestate::load("cartridge", into, &state).unwrap();
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut cart = Nrom::new(image(1, 0x4000), Vec::new(), Mirroring::Vertical).unwrap();
+        write(&mut cart, 0x6000, 0x42);
+        cart.ppu_write(0x0123, 7);
+        let mut restored = Nrom::new(image(1, 0x4000), Vec::new(), Mirroring::Vertical).unwrap();
+        round_trip(&cart, &mut restored);
+        assert_eq!((read(&restored, 0x6000), restored.ppu_read(0x0123)), (0x42, 7));
+
+        let mut cart = Uxrom::new(image(8, 0x4000), Vec::new(), Mirroring::Vertical).unwrap();
+        write(&mut cart, 0xC000, 5);
+        let mut restored = Uxrom::new(image(8, 0x4000), Vec::new(), Mirroring::Vertical).unwrap();
+        round_trip(&cart, &mut restored);
+        assert_eq!(read(&restored, 0x8000), 5);
+
+        let mut prg = image(2, 0x4000);
+        prg[0x7FF0] = 0xFF;
+        let mut cart = Cnrom::new(prg.clone(), image(4, 0x2000), Mirroring::Horizontal).unwrap();
+        write(&mut cart, 0xFFF0, 3);
+        let mut restored = Cnrom::new(prg, image(4, 0x2000), Mirroring::Horizontal).unwrap();
+        round_trip(&cart, &mut restored);
+        assert_eq!(restored.ppu_read(0x0000), 3);
+
+        // Halfway through loading a register.
+        let mut cart = Mmc1::new(image(8, 0x4000), image(8, 0x1000)).unwrap();
+        mmc1_write(&mut cart, 0x8000, 0x13);
+        write(&mut cart, 0xE000, 1);
+        write(&mut cart, 0xE000, 0);
+        let mut restored = Mmc1::new(image(8, 0x4000), image(8, 0x1000)).unwrap();
+        round_trip(&cart, &mut restored);
+        assert_eq!(restored.mirroring(), Mirroring::Horizontal);
+        for _ in 0..3 {
+            write(&mut restored, 0xE000, 0);
+        }
+        assert_eq!((read(&restored, 0x8000), read(&restored, 0xC000)), (0, 1));
+
+        let mut cart = Mmc3::new(image(16, 0x2000), image(32, 0x0400)).unwrap();
+        write(&mut cart, 0x8000, 6);
+        write(&mut cart, 0x8001, 3);
+        write(&mut cart, 0xC000, 1);
+        write(&mut cart, 0xC001, 0);
+        write(&mut cart, 0xE001, 0);
+        let mut restored = Mmc3::new(image(16, 0x2000), image(32, 0x0400)).unwrap();
+        round_trip(&cart, &mut restored);
+        assert_eq!(read(&restored, 0x8000), 3);
+        restored.clock_scanline();
+        assert!(!restored.irq());
+        restored.clock_scanline();
+        assert!(restored.irq());
+    }
+}
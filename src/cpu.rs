@@ -16,35 +16,72 @@ pub struct CpuWithBus<'c, B, V> {
 
 impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     pub fn reset(&mut self) {
+        self.dummy_read(self.cpu.pc);
+        self.dummy_read(self.cpu.pc);
+        for _ in 0..3 {
+            self.dummy_read(u16::from_le_bytes([self.cpu.sp, STACK_BASE]));
+            self.cpu.sp = self.cpu.sp.wrapping_sub(1);
+        }
         self.cpu.reg.i = true;
-        self.cpu.sp = self.cpu.sp.wrapping_sub(3);
+        if V::CMOS {
+            self.cpu.reg.d = false;
+        }
+        self.cpu.waiting = false;
+        self.cpu.stopped = false;
         self.cpu.pc = self.read_u16(VECTOR_BASE, RESET_VECTOR);
     }
     
     pub fn irq(&mut self) {
+        // WAI resumes on IRQ even while interrupts are disabled.
+        self.cpu.waiting = false;
         if !self.cpu.reg.i {
+            self.dummy_read(self.cpu.pc);
+            self.dummy_read(self.cpu.pc);
             self.interrupt(IRQ_BRK_VECTOR, false);
         }
     }
 
     pub fn nmi(&mut self) {
+        self.cpu.waiting = false;
+        self.dummy_read(self.cpu.pc);
+        self.dummy_read(self.cpu.pc);
         self.interrupt(NMI_VECTOR, false);
     }
 
     pub fn step(&mut self) {
+        if self.cpu.waiting || self.cpu.stopped {
+            self.cpu.cycles += 1;
+            return;
+        }
         let (instr_code, addr_mode) = V::decode(self.take_u8_at_pc()).unwrap();
-        let op_input = self.execute_addressing(addr_mode);
+        let op_input = self.execute_addressing(instr_code, addr_mode);
         self.execute_operation((instr_code, op_input));
     }
 
+    fn read(&mut self, addr: u16) -> u8 {
+        self.cpu.cycles += 1;
+        self.bus.read(addr)
+    }
+
+    // A cycle whose data the CPU ignores.
+    fn dummy_read(&mut self, addr: u16) {
+        self.cpu.cycles += 1;
+        self.bus.dummy_read(addr);
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.cpu.cycles += 1;
+        self.bus.write(addr, value);
+    }
+
     fn read_u16(&mut self, high: u8, low: u8) -> u16 {
         let u16_low = u16::from_le_bytes([low, high]);
         let u16_high = u16::from_le_bytes([low.wrapping_add(1), high]);
-        u16::from_le_bytes([self.bus.read(u16_low), self.bus.read(u16_high)])
+        u16::from_le_bytes([self.read(u16_low), self.read(u16_high)])
     }
 
     fn take_u8_at_pc(&mut self) -> u8 {
-        let byte = self.bus.read(self.cpu.pc);
+        let byte = self.read(self.cpu.pc);
         self.cpu.pc = self.cpu.pc.wrapping_add(1);
         byte
     }
@@ -55,14 +92,31 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
 
     fn stack_push(&mut self, value: u8) {
         let addr = u16::from_le_bytes([self.cpu.sp, STACK_BASE]);
-        self.bus.write(addr, value);
+        self.write(addr, value);
         self.cpu.sp = self.cpu.sp.wrapping_sub(1);
     }
 
     fn stack_pop(&mut self) -> u8 {
         self.cpu.sp = self.cpu.sp.wrapping_add(1);
         let addr = u16::from_le_bytes([self.cpu.sp, STACK_BASE]);
-        self.bus.read(addr)
+        self.read(addr)
+    }
+
+    // The internal cycle before a pull reads the current top of stack.
+    fn stack_peek(&mut self) {
+        self.dummy_read(u16::from_le_bytes([self.cpu.sp, STACK_BASE]));
+    }
+
+    // Read-modify-write instructions write the unmodified value back before
+    // the result; the 65C02 reads it a second time instead.
+    fn read_for_modify(&mut self, addr: u16) -> u8 {
+        let value = self.read(addr);
+        if V::CMOS {
+            self.dummy_read(addr);
+        } else {
+            self.write(addr, value);
+        }
+        value
     }
 
     fn interrupt(&mut self, vector: u8, brk: bool) {
@@ -71,12 +125,51 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
         self.stack_push(pc_low);
         self.stack_push(self.cpu.reg.get_status(brk));
         self.cpu.reg.i = true;
+        if V::CMOS {
+            self.cpu.reg.d = false;
+        }
         self.cpu.pc = self.read_u16(VECTOR_BASE, vector);
     }
 
-    fn execute_addressing(&mut self, am: AddressingMode) -> OperationInput {
+    // Indexing that carries into the high byte costs a cycle reading from
+    // the address before the carry; the 65C02 reads `cmos_addr` instead.
+    // Stores and read-modify-write instructions always take the cycle.
+    fn index(&mut self, code: InstructionCode, base: u16, index: u8, cmos_addr: u16) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        let crossed = (base ^ addr) & 0xFF00 != 0;
+        let always = match code {
+            InstructionCode::STA | InstructionCode::STZ => true,
+            InstructionCode::INC | InstructionCode::DEC => true,
+            InstructionCode::ASL | InstructionCode::LSR |
+            InstructionCode::ROL | InstructionCode::ROR => !V::CMOS,
+            _ => false,
+        };
+        match (V::CMOS, crossed) {
+            (true, true) => self.dummy_read(cmos_addr),
+            (false, true) => self.dummy_read((base & 0xFF00) | (addr & 0x00FF)),
+            (_, false) if always => self.dummy_read(addr),
+            _ => {}
+        }
+        addr
+    }
+
+    // The cycle spent adding an index to a zero page address.
+    fn dummy_read_zp_index(&mut self, base: u8) {
+        match V::CMOS {
+            true => self.dummy_read(self.cpu.pc.wrapping_sub(1)),
+            false => self.dummy_read(base as u16),
+        }
+    }
+
+    fn execute_addressing(&mut self, code: InstructionCode, am: AddressingMode) -> OperationInput {
         match am {
+            // JSR pushes the return address before fetching the high byte
+            // of the target, so it reads its own operand.
+            AddressingMode::ABS if matches!(code, InstructionCode::JSR) => {
+                OperationInput::IMP
+            }
             AddressingMode::ACC | AddressingMode::IMP => {
+                self.dummy_read(self.cpu.pc);
                 OperationInput::IMP
             }
             AddressingMode::IMM => {
@@ -88,11 +181,15 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
                 OperationInput::ADR(addr)
             }
             AddressingMode::ZPX => {
-                let addr = self.take_u8_at_pc().wrapping_add(self.cpu.reg.get_x()) as u16;
+                let base = self.take_u8_at_pc();
+                self.dummy_read_zp_index(base);
+                let addr = base.wrapping_add(self.cpu.reg.get_x()) as u16;
                 OperationInput::ADR(addr)
             }
             AddressingMode::ZPY => {
-                let addr = self.take_u8_at_pc().wrapping_add(self.cpu.reg.get_y()) as u16;
+                let base = self.take_u8_at_pc();
+                self.dummy_read_zp_index(base);
+                let addr = base.wrapping_add(self.cpu.reg.get_y()) as u16;
                 OperationInput::ADR(addr)
             }
             AddressingMode::REL => {
@@ -104,29 +201,59 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
                 OperationInput::ADR(addr)
             }
             AddressingMode::ABX => {
-                let addr = self.take_u16_at_pc().wrapping_add(self.cpu.reg.get_x() as u16);
+                let base = self.take_u16_at_pc();
+                let addr = self.index(code, base, self.cpu.reg.get_x(), self.cpu.pc.wrapping_sub(1));
                 OperationInput::ADR(addr)
             }
             AddressingMode::ABY => {
-                let addr = self.take_u16_at_pc().wrapping_add(self.cpu.reg.get_y() as u16);
+                let base = self.take_u16_at_pc();
+                let addr = self.index(code, base, self.cpu.reg.get_y(), self.cpu.pc.wrapping_sub(1));
                 OperationInput::ADR(addr)
             }
             AddressingMode::IND => {
                 let low = self.take_u8_at_pc();
                 let high = self.take_u8_at_pc();
-                let addr = self.read_u16(high, low);
+                let addr = match V::CMOS {
+                    true => {
+                        self.dummy_read(self.cpu.pc.wrapping_sub(1));
+                        let ptr = u16::from_le_bytes([low, high]);
+                        u16::from_le_bytes([self.read(ptr), self.read(ptr.wrapping_add(1))])
+                    }
+                    // The NMOS part doesn't carry into the high byte of the pointer.
+                    false => self.read_u16(high, low),
+                };
                 OperationInput::ADR(addr)
             }
             AddressingMode::INX => {
-                let low = self.take_u8_at_pc().wrapping_add(self.cpu.reg.get_x());
+                let base = self.take_u8_at_pc();
+                self.dummy_read_zp_index(base);
+                let low = base.wrapping_add(self.cpu.reg.get_x());
                 let addr = self.read_u16(0, low);
                 OperationInput::ADR(addr)
             }
             AddressingMode::INY => {
-                let low = self.take_u8_at_pc().wrapping_add(self.cpu.reg.get_y());
+                let low = self.take_u8_at_pc();
+                let base = self.read_u16(0, low);
+                let addr = self.index(code, base, self.cpu.reg.get_y(), low.wrapping_add(1) as u16);
+                OperationInput::ADR(addr)
+            }
+            AddressingMode::ZPI => {
+                let low = self.take_u8_at_pc();
                 let addr = self.read_u16(0, low);
                 OperationInput::ADR(addr)
             }
+            AddressingMode::IAX => {
+                let base = self.take_u16_at_pc();
+                self.dummy_read(self.cpu.pc.wrapping_sub(1));
+                let ptr = base.wrapping_add(self.cpu.reg.get_x() as u16);
+                let addr = u16::from_le_bytes([self.read(ptr), self.read(ptr.wrapping_add(1))]);
+                OperationInput::ADR(addr)
+            }
+            // BBR/BBS read the tested byte before fetching the offset.
+            AddressingMode::ZPR => {
+                let addr = self.take_u8_at_pc() as u16;
+                OperationInput::ADR(addr)
+            }
         }
     }
 
@@ -147,6 +274,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
 
             (InstructionCode::BEQ, OperationInput::REL(offset)) => self.beq(offset),
 
+            (InstructionCode::BIT, OperationInput::IMM(val)) => self.bit_imm(val),
             (InstructionCode::BIT, OperationInput::ADR(addr)) => self.bit(addr),
 
             (InstructionCode::BMI, OperationInput::REL(offset)) => self.bmi(offset),
@@ -178,6 +306,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
             (InstructionCode::CPY, OperationInput::IMM(val)) => self.cpy_imm(val),
             (InstructionCode::CPY, OperationInput::ADR(addr)) => self.cpy_adr(addr),
 
+            (InstructionCode::DEC, OperationInput::IMP) => self.dec_imp(),
             (InstructionCode::DEC, OperationInput::ADR(addr)) => self.dec(addr),
 
             (InstructionCode::DEX, OperationInput::IMP) => self.dex(),
@@ -187,6 +316,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
             (InstructionCode::EOR, OperationInput::IMM(val)) => self.eor_imm(val),
             (InstructionCode::EOR, OperationInput::ADR(addr)) => self.eor_adr(addr),
 
+            (InstructionCode::INC, OperationInput::IMP) => self.inc_imp(),
             (InstructionCode::INC, OperationInput::ADR(addr)) => self.inc(addr),
 
             (InstructionCode::INX, OperationInput::IMP) => self.inx(),
@@ -195,7 +325,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
 
             (InstructionCode::JMP, OperationInput::ADR(addr)) => self.jmp(addr),
 
-            (InstructionCode::JSR, OperationInput::ADR(addr)) => self.jsr(addr),
+            (InstructionCode::JSR, OperationInput::IMP) => self.jsr(),
 
             (InstructionCode::LDA, OperationInput::IMM(val)) => self.lda_imm(val),
             (InstructionCode::LDA, OperationInput::ADR(addr)) => self.lda_adr(addr),
@@ -210,6 +340,8 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
             (InstructionCode::LSR, OperationInput::ADR(addr)) => self.lsr_adr(addr),
 
             (InstructionCode::NOP, OperationInput::IMP) => self.nop(),
+            (InstructionCode::NOP, OperationInput::IMM(_)) => self.nop(),
+            (InstructionCode::NOP, OperationInput::ADR(addr)) => self.dummy_read(addr),
 
             (InstructionCode::ORA, OperationInput::IMM(val)) => self.ora_imm(val),
             (InstructionCode::ORA, OperationInput::ADR(addr)) => self.ora_adr(addr),
@@ -259,11 +391,42 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
 
             (InstructionCode::TYA, OperationInput::IMP) => self.tya(),
 
+            (InstructionCode::BBR(bit), OperationInput::ADR(addr)) => self.bbr(bit, addr),
+
+            (InstructionCode::BBS(bit), OperationInput::ADR(addr)) => self.bbs(bit, addr),
+
+            (InstructionCode::BRA, OperationInput::REL(offset)) => self.bra(offset),
+
+            (InstructionCode::PHX, OperationInput::IMP) => self.phx(),
+
+            (InstructionCode::PHY, OperationInput::IMP) => self.phy(),
+
+            (InstructionCode::PLX, OperationInput::IMP) => self.plx(),
+
+            (InstructionCode::PLY, OperationInput::IMP) => self.ply(),
+
+            (InstructionCode::RMB(bit), OperationInput::ADR(addr)) => self.rmb(bit, addr),
+
+            (InstructionCode::SMB(bit), OperationInput::ADR(addr)) => self.smb(bit, addr),
+
+            (InstructionCode::STP, OperationInput::IMP) => self.stp(),
+
+            (InstructionCode::STZ, OperationInput::ADR(addr)) => self.stz(addr),
+
+            (InstructionCode::TRB, OperationInput::ADR(addr)) => self.trb(addr),
+
+            (InstructionCode::TSB, OperationInput::ADR(addr)) => self.tsb(addr),
+
+            (InstructionCode::WAI, OperationInput::IMP) => self.wai(),
+
             _illegal => panic!(),
         }
     }
 
     fn adc_imm(&mut self, value: u8) {
+        if self.cpu.reg.d {
+            return self.adc_decimal(value);
+        }
         let value = value as u16;
         let carry = self.cpu.reg.c as u16;
         let result = self.cpu.reg.get_a() as u16 + value + carry;
@@ -275,8 +438,42 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
         self.cpu.reg.update_a(result as u8);
     }
 
+    // NMOS decimal mode: Z comes from the binary sum, N and V from the sum
+    // before the high nibble is adjusted. The 65C02 takes a cycle longer and
+    // sets N and Z from the result.
+    fn adc_decimal(&mut self, value: u8) {
+        let a = self.cpu.reg.get_a();
+        let carry = self.cpu.reg.c as u8;
+        let binary = a.wrapping_add(value).wrapping_add(carry);
+        let mut low = (a & 0x0F) + (value & 0x0F) + carry;
+        if low > 0x09 {
+            low += 0x06;
+        }
+        let mut high = (a >> 4) + (value >> 4) + (low > 0x0F) as u8;
+        let unadjusted = high << 4 | (low & 0x0F);
+        self.cpu.reg.update_nz_flags(unadjusted);
+        self.cpu.reg.z = binary == 0;
+        self.cpu.reg.v = !(a ^ value) & (a ^ unadjusted) & 0x80 != 0;
+        if high > 0x09 {
+            high += 0x06;
+        }
+        self.cpu.reg.c = high > 0x0F;
+        self.set_decimal_result(high << 4 | (low & 0x0F));
+    }
+
+    fn set_decimal_result(&mut self, result: u8) {
+        let (n, z) = (self.cpu.reg.n, self.cpu.reg.z);
+        self.cpu.reg.update_a(result);
+        if V::CMOS {
+            self.dummy_read(self.cpu.pc.wrapping_sub(1));
+        } else {
+            self.cpu.reg.n = n;
+            self.cpu.reg.z = z;
+        }
+    }
+
     fn adc_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.adc_imm(value);
     }
 
@@ -285,7 +482,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn and_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.and_imm(value);
     }
     
@@ -295,59 +492,71 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn asl_adr(&mut self, addr: u16) {
-        let n = self.bus.read(addr);
+        let n = self.read_for_modify(addr);
         self.cpu.reg.c = n & 0b1000_0000 != 0;
         let result = n << 1;
-        self.bus.write(addr, result);
+        self.write(addr, result);
         self.cpu.reg.update_nz_flags(result);
     }
     
     fn bcc(&mut self, offset: u16) {
         if !self.cpu.reg.c {
-            let addr = self.cpu.pc.wrapping_add(offset);
-            self.cpu.pc = addr;
+            self.branch(offset);
         }
     }
 
     fn bcs(&mut self, offset: u16) {
         if self.cpu.reg.c {
-            let addr = self.cpu.pc.wrapping_add(offset);
-            self.cpu.pc = addr;
+            self.branch(offset);
         }
     }
 
     fn beq(&mut self, offset: u16) {
         if self.cpu.reg.z {
-            let addr = self.cpu.pc.wrapping_add(offset);
-            self.cpu.pc = addr;
+            self.branch(offset);
+        }
+    }
+
+    // A taken branch spends a cycle reading the next opcode and another when
+    // the target is on a different page.
+    fn branch(&mut self, offset: u16) {
+        self.dummy_read(self.cpu.pc);
+        let addr = self.cpu.pc.wrapping_add(offset);
+        if (self.cpu.pc ^ addr) & 0xFF00 != 0 {
+            match V::CMOS {
+                true => self.dummy_read(self.cpu.pc),
+                false => self.dummy_read((self.cpu.pc & 0xFF00) | (addr & 0x00FF)),
+            }
         }
+        self.cpu.pc = addr;
     }
 
     fn bit(&mut self, addr: u16) {
-        let n = self.bus.read(addr);
+        let n = self.read(addr);
         self.cpu.reg.z = self.cpu.reg.get_a() & n == 0;
         self.cpu.reg.v = n & 0b0100_0000 != 0;
         self.cpu.reg.n = n & 0b1000_0000 != 0;
     }
 
+    fn bit_imm(&mut self, value: u8) {
+        self.cpu.reg.z = self.cpu.reg.get_a() & value == 0;
+    }
+
     fn bmi(&mut self, offset: u16) {
         if self.cpu.reg.n {
-            let addr = self.cpu.pc.wrapping_add(offset);
-            self.cpu.pc = addr;
+            self.branch(offset);
         }
     }
 
     fn bne(&mut self, offset: u16) {
         if !self.cpu.reg.z {
-            let addr = self.cpu.pc.wrapping_add(offset);
-            self.cpu.pc = addr;
+            self.branch(offset);
         }
     }
 
     fn bpl(&mut self, offset: u16) {
         if !self.cpu.reg.n {
-            let addr = self.cpu.pc.wrapping_add(offset);
-            self.cpu.pc = addr;
+            self.branch(offset);
         }
     }
 
@@ -358,15 +567,13 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
 
     fn bvc(&mut self, offset: u16) {
         if !self.cpu.reg.v {
-            let addr = self.cpu.pc.wrapping_add(offset);
-            self.cpu.pc = addr;
+            self.branch(offset);
         }
     }
 
     fn bvs(&mut self, offset: u16) {
         if self.cpu.reg.v {
-            let addr = self.cpu.pc.wrapping_add(offset);
-            self.cpu.pc = addr;
+            self.branch(offset);
         }
     }
 
@@ -392,7 +599,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn cmp_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.cmp_imm(value);
     }
 
@@ -402,7 +609,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn cpx_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.cpx_imm(value);
     }
 
@@ -412,14 +619,18 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn cpy_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.cpy_imm(value);
     }
     
+    fn dec_imp(&mut self) {
+        self.cpu.reg.update_a(self.cpu.reg.get_a().wrapping_sub(1));
+    }
+
     fn dec(&mut self, addr: u16) {
-        let n = self.bus.read(addr);
+        let n = self.read_for_modify(addr);
         let result = n.wrapping_sub(1);
-        self.bus.write(addr, result);
+        self.write(addr, result);
         self.cpu.reg.update_nz_flags(result);
     }
 
@@ -436,14 +647,18 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn eor_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.eor_imm(value);
     }
 
+    fn inc_imp(&mut self) {
+        self.cpu.reg.update_a(self.cpu.reg.get_a().wrapping_add(1));
+    }
+
     fn inc(&mut self, addr: u16) {
-        let n = self.bus.read(addr);
+        let n = self.read_for_modify(addr);
         let result = n.wrapping_add(1);
-        self.bus.write(addr, result);
+        self.write(addr, result);
         self.cpu.reg.update_nz_flags(result);
     }
 
@@ -459,12 +674,14 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
         self.cpu.pc = addr;
     }
 
-    fn jsr(&mut self, addr: u16) {
-        let return_addr = self.cpu.pc.wrapping_sub(1);
-        let [ret_low, ret_high] = return_addr.to_le_bytes();
+    fn jsr(&mut self) {
+        let low = self.take_u8_at_pc();
+        self.stack_peek();
+        let [ret_low, ret_high] = self.cpu.pc.to_le_bytes();
         self.stack_push(ret_high);
         self.stack_push(ret_low);
-        self.cpu.pc = addr;
+        let high = self.read(self.cpu.pc);
+        self.cpu.pc = u16::from_le_bytes([low, high]);
     }
 
     fn lda_imm(&mut self, value: u8) {
@@ -472,7 +689,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn lda_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.lda_imm(value);
     }
 
@@ -481,7 +698,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn ldx_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.ldx_imm(value);
     }
 
@@ -490,7 +707,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn ldy_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.ldy_imm(value);
     }
 
@@ -500,10 +717,10 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn lsr_adr(&mut self, addr: u16) {
-        let n = self.bus.read(addr);
+        let n = self.read_for_modify(addr);
         self.cpu.reg.c = n & 0b0000_0001 != 0;
         let result = n >> 1;
-        self.bus.write(addr, result);
+        self.write(addr, result);
         self.cpu.reg.update_nz_flags(result);
     }
 
@@ -514,7 +731,7 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn ora_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.ora_imm(value);
     }
 
@@ -523,15 +740,17 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn php(&mut self) {
-        self.stack_push(self.cpu.reg.get_status(false));
+        self.stack_push(self.cpu.reg.get_status(true));
     }
 
     fn pla(&mut self) {
+        self.stack_peek();
         let value = self.stack_pop();
         self.cpu.reg.update_a(value);
     }
 
     fn plp(&mut self) {
+        self.stack_peek();
         let status = self.stack_pop();
         self.cpu.reg.set_status(status);
     }
@@ -543,11 +762,11 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn rol_adr(&mut self, addr: u16) {
-        let n = self.bus.read(addr);
+        let n = self.read_for_modify(addr);
         let carry = self.cpu.reg.c;
         self.cpu.reg.c = n & 0b1000_0000 != 0;
         let result = (n << 1) | carry as u8;
-        self.bus.write(addr, result);
+        self.write(addr, result);
         self.cpu.reg.update_nz_flags(result);
     }
 
@@ -558,15 +777,16 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn ror_adr(&mut self, addr: u16) {
-        let n = self.bus.read(addr);
+        let n = self.read_for_modify(addr);
         let carry = self.cpu.reg.c;
         self.cpu.reg.c = n & 0b0000_0001 != 0;
         let result = (n >> 1) | ((carry as u8) << 7);
-        self.bus.write(addr, result);
+        self.write(addr, result);
         self.cpu.reg.update_nz_flags(result);
     }
 
     fn rti(&mut self) {
+        self.stack_peek();
         let status = self.stack_pop();
         self.cpu.reg.set_status(status);
         let pc_low = self.stack_pop();
@@ -575,17 +795,52 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn rts(&mut self) {
+        self.stack_peek();
         let pc_low = self.stack_pop();
         let pc_high = self.stack_pop();
-        self.cpu.pc = u16::from_le_bytes([pc_low, pc_high]).wrapping_add(1);
+        self.cpu.pc = u16::from_le_bytes([pc_low, pc_high]);
+        self.dummy_read(self.cpu.pc);
+        self.cpu.pc = self.cpu.pc.wrapping_add(1);
     }
 
     fn sbc_imm(&mut self, value: u8) {
+        if self.cpu.reg.d {
+            return self.sbc_decimal(value);
+        }
         self.adc_imm(!value); // 2s complement
     }
 
+    // Decimal mode: C and V are those of the binary subtraction, as are N
+    // and Z on the NMOS part. The 65C02 adjusts the binary difference.
+    fn sbc_decimal(&mut self, value: u8) {
+        let a = self.cpu.reg.get_a() as i16;
+        let borrow = !self.cpu.reg.c as i16;
+        let low = (a & 0x0F) - (value as i16 & 0x0F) - borrow;
+        let result = if V::CMOS {
+            let mut result = a - value as i16 - borrow;
+            if result < 0 {
+                result -= 0x60;
+            }
+            if low < 0 {
+                result -= 0x06;
+            }
+            result
+        } else {
+            let low = if low < 0 { ((low - 0x06) & 0x0F) - 0x10 } else { low };
+            let mut result = (a & 0xF0) - (value as i16 & 0xF0) + low;
+            if result < 0 {
+                result -= 0x60;
+            }
+            result
+        };
+        self.cpu.reg.d = false;
+        self.adc_imm(!value);
+        self.cpu.reg.d = true;
+        self.set_decimal_result(result as u8);
+    }
+
     fn sbc_adr(&mut self, addr: u16) {
-        let value = self.bus.read(addr);
+        let value = self.read(addr);
         self.sbc_imm(value);
     }
 
@@ -602,15 +857,15 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
     }
 
     fn sta(&mut self, addr: u16)  {
-        self.bus.write(addr, self.cpu.reg.get_a());
+        self.write(addr, self.cpu.reg.get_a());
     }
 
     fn stx(&mut self, addr: u16) {
-        self.bus.write(addr, self.cpu.reg.get_x());
+        self.write(addr, self.cpu.reg.get_x());
     }
 
     fn sty(&mut self, addr: u16) {
-        self.bus.write(addr, self.cpu.reg.get_y());
+        self.write(addr, self.cpu.reg.get_y());
     }
 
     fn tax(&mut self) {
@@ -637,9 +892,88 @@ impl<B: Bus, V: Variant> CpuWithBus<'_, B, V> {
         self.cpu.reg.update_a(self.cpu.reg.get_y());
     }
 
+    fn bbr(&mut self, bit: u8, addr: u16) {
+        let n = self.read(addr);
+        self.dummy_read(addr);
+        let offset = self.take_u8_at_pc() as i8 as u16;
+        if n & (1 << bit) == 0 {
+            self.branch(offset);
+        }
+    }
+
+    fn bbs(&mut self, bit: u8, addr: u16) {
+        let n = self.read(addr);
+        self.dummy_read(addr);
+        let offset = self.take_u8_at_pc() as i8 as u16;
+        if n & (1 << bit) != 0 {
+            self.branch(offset);
+        }
+    }
+
+    fn bra(&mut self, offset: u16) {
+        self.branch(offset);
+    }
+
+    fn phx(&mut self) {
+        self.stack_push(self.cpu.reg.get_x());
+    }
+
+    fn phy(&mut self) {
+        self.stack_push(self.cpu.reg.get_y());
+    }
+
+    fn plx(&mut self) {
+        self.stack_peek();
+        let value = self.stack_pop();
+        self.cpu.reg.update_x(value);
+    }
+
+    fn ply(&mut self) {
+        self.stack_peek();
+        let value = self.stack_pop();
+        self.cpu.reg.update_y(value);
+    }
+
+    fn rmb(&mut self, bit: u8, addr: u16) {
+        let n = self.read_for_modify(addr);
+        self.write(addr, n & !(1 << bit));
+    }
+
+    fn smb(&mut self, bit: u8, addr: u16) {
+        let n = self.read_for_modify(addr);
+        self.write(addr, n | (1 << bit));
+    }
+
+    fn stp(&mut self) {
+        self.dummy_read(self.cpu.pc);
+        self.cpu.stopped = true;
+    }
+
+    fn stz(&mut self, addr: u16) {
+        self.write(addr, 0);
+    }
+
+    fn trb(&mut self, addr: u16) {
+        let n = self.read_for_modify(addr);
+        self.cpu.reg.z = self.cpu.reg.get_a() & n == 0;
+        self.write(addr, n & !self.cpu.reg.get_a());
+    }
+
+    fn tsb(&mut self, addr: u16) {
+        let n = self.read_for_modify(addr);
+        self.cpu.reg.z = self.cpu.reg.get_a() & n == 0;
+        self.write(addr, n | self.cpu.reg.get_a());
+    }
+
+    fn wai(&mut self) {
+        self.dummy_read(self.cpu.pc);
+        self.cpu.waiting = true;
+    }
+
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_field_names, clippy::unnecessary_cast)]
 mod tests {
     use super::*;
 
@@ -665,6 +999,36 @@ mod tests {
         CpuWithBus {cpu: cpu, bus: bus}
     }
 
+    #[test]
+    fn test_adc_decimal() {
+        let mut cwb = get_cpu();
+        cwb.cpu.reg.d = true;
+        cwb.cpu.reg.update_a(0x58);
+        cwb.adc_imm(0x46);
+        assert_eq!(cwb.cpu.reg.get_a(), 0x04);
+        assert!(cwb.cpu.reg.c);
+
+        cwb.adc_imm(0x05);
+        assert_eq!(cwb.cpu.reg.get_a(), 0x10);
+        assert!(!cwb.cpu.reg.c);
+    }
+
+    #[test]
+    fn test_sbc_decimal() {
+        let mut cwb = get_cpu();
+        cwb.cpu.reg.d = true;
+        cwb.cpu.reg.c = true;
+        cwb.cpu.reg.update_a(0x40);
+        cwb.sbc_imm(0x13);
+        assert_eq!(cwb.cpu.reg.get_a(), 0x27);
+        assert!(cwb.cpu.reg.c);
+
+        cwb.sbc_imm(0x28);
+        assert_eq!(cwb.cpu.reg.get_a(), 0x99);
+        assert!(!cwb.cpu.reg.c);
+        assert!(cwb.cpu.reg.d);
+    }
+
     #[test]
     fn test_and_imm() {
         let mut cwb = get_cpu();
@@ -1171,8 +1535,10 @@ mod tests {
     #[test]
     fn test_jsr() {
         let mut cwb = get_cpu();
-        cwb.cpu.pc = 0xABCD;
-        cwb.jsr(0x1234);
+        cwb.cpu.pc = 0xABCB;
+        cwb.bus.write(0xABCB, 0x34);
+        cwb.bus.write(0xABCC, 0x12);
+        cwb.jsr();
         assert_eq!(cwb.stack_pop(), 0xCC);
         assert_eq!(cwb.stack_pop(), 0xAB);
         assert_eq!(cwb.cpu.pc, 0x1234);
@@ -1407,7 +1773,7 @@ mod tests {
         let mut cwb = get_cpu();
         cwb.cpu.reg.c = true;
         cwb.php();
-        assert_eq!(cwb.stack_pop(), cwb.cpu.reg.get_status(false));
+        assert_eq!(cwb.stack_pop(), cwb.cpu.reg.get_status(true));
     }
 
     #[test]
@@ -0,0 +1,769 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::dbginfo::DebugInfo;
+use crate::debugger::{Debugger, Expr, StopReason};
+use crate::json::Value;
+use crate::{Bus, Cpu, Variant};
+
+// Instructions executed between checks for new requests while running.
+const RUN_CHUNK: u64 = 10_000;
+// Upper bound on instructions executed by a single step request.
+const STEP_LIMIT: u64 = 1_000_000;
+// Instructions kept for stepping back.
+const JOURNAL_LIMIT: usize = 100_000;
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REF: i64 = 1;
+const LABELS_REF: i64 = 2;
+
+/// Reads one `Content-Length` framed message. Returns `None` at end of input.
+pub(crate) fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Value::parse(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(crate) fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Reads messages on a separate thread so requests such as `pause` can be
+/// handled while the program runs.
+pub fn spawn_reader(mut reader: impl BufRead + Send + 'static) -> Receiver<Value> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            if tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepKind {
+    Over,
+    In,
+    Out,
+}
+
+/// A Debug Adapter Protocol server for programs built with cc65/ca65 and
+/// linked with an ld65 debug info file.
+pub struct DapServer<V, B, W> {
+    pub cpu: Cpu<V>,
+    pub bus: B,
+    pub debugger: Debugger,
+    pub info: Option<DebugInfo>,
+    source_root: PathBuf,
+    out: W,
+    seq: i64,
+    running: bool,
+    stop_on_entry: bool,
+    pending: Vec<Value>,
+    // Source path -> debugger breakpoint ids
+    breakpoints: HashMap<String, Vec<usize>>,
+}
+
+// Negative values are shown in two's complement, widened from `width`
+// digits as far as they need, so -1 is $FF rather than sixteen Fs.
+fn hex_value(value: i64, width: usize) -> String {
+    let mut digits = width;
+    while digits < 16 && value < -(1 << (digits * 4 - 1)) {
+        digits *= 2;
+    }
+    let unsigned = match value < 0 && digits < 16 {
+        true => value as u64 & ((1 << (digits * 4)) - 1),
+        false => value as u64,
+    };
+    format!("${:0width$X} ({})", unsigned, value, width = width)
+}
+
+fn parse_address(s: &str) -> Option<u16> {
+    let s = s.trim();
+    match s.strip_prefix('$').or_else(|| s.strip_prefix("0x")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn event(event: &str, body: Value) -> Value {
+    Value::object([("type", "event".into()), ("event", event.into()), ("body", body)])
+}
+
+fn stopped_event(reason: &str, text: Option<String>) -> Value {
+    let mut body = Value::object([
+        ("reason", reason.into()),
+        ("threadId", THREAD_ID.into()),
+        ("allThreadsStopped", true.into()),
+    ]);
+    if let Some(text) = text {
+        body.insert("text", text.into());
+    }
+    event("stopped", body)
+}
+
+impl<V: Variant, B: Bus, W: Write> DapServer<V, B, W> {
+    pub fn new(cpu: Cpu<V>, bus: B, out: W) -> Self {
+        let mut debugger = Debugger::new();
+        debugger.set_journal_limit(JOURNAL_LIMIT);
+        DapServer {
+            cpu,
+            bus,
+            debugger,
+            info: None,
+            source_root: PathBuf::new(),
+            out,
+            seq: 0,
+            running: false,
+            stop_on_entry: false,
+            pending: Vec::new(),
+            breakpoints: HashMap::new(),
+        }
+    }
+
+    /// Writes `program` into the bus. Segments in the debug info that record
+    /// an output offset are placed at their run addresses; otherwise the
+    /// whole image goes to `load_address`.
+    pub fn load(
+        &mut self,
+        program: &[u8],
+        info: Option<DebugInfo>,
+        load_address: Option<u16>,
+        source_root: &Path,
+    ) -> Result<(), String> {
+        let segments: Vec<_> = info.iter()
+            .flat_map(|info| info.segments.values())
+            .filter_map(|seg| Some((seg.start, seg.output_offset? as usize, seg.size as usize)))
+            .collect();
+        if let Some(base) = load_address {
+            for (i, byte) in program.iter().enumerate() {
+                self.bus.write(base.wrapping_add(i as u16), *byte);
+            }
+        } else if !segments.is_empty() {
+            for (start, offset, size) in segments {
+                let bytes = program.get(offset..offset + size).ok_or("program is shorter than its segments")?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    self.bus.write((start as u16).wrapping_add(i as u16), *byte);
+                }
+            }
+        } else {
+            return Err("no load address given and no segment offsets in debug info".to_string());
+        }
+        self.info = info;
+        self.source_root = source_root.to_path_buf();
+        self.debugger.clear_call_stack();
+        self.debugger.clear_journal();
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message.insert("seq", self.seq.into());
+        write_message(&mut self.out, &message)
+    }
+
+    fn stop(&mut self, reason: StopReason) -> Value {
+        self.running = false;
+        match reason {
+            StopReason::Breakpoint { .. } => stopped_event("breakpoint", None),
+            StopReason::Watchpoint { .. } => stopped_event("data breakpoint", None),
+            StopReason::IllegalOpcode { pc, opcode } => {
+                let text = format!("illegal opcode ${:02X} at ${:04X}", opcode, pc);
+                stopped_event("exception", Some(text))
+            }
+            StopReason::Step | StopReason::Limit => stopped_event("step", None),
+            StopReason::HistoryStart => {
+                stopped_event("step", Some("reached the start of the recorded history".to_string()))
+            }
+        }
+    }
+
+    fn current_line(&self) -> Option<(u32, u32)> {
+        let line = self.info.as_ref()?.line_for_addr(self.cpu.pc)?;
+        Some((line.file, line.line))
+    }
+
+    fn step(&mut self, kind: StepKind) -> StopReason {
+        let start = self.current_line();
+        let depth = self.debugger.call_stack().len();
+        for _ in 0..STEP_LIMIT {
+            let reason = match kind {
+                StepKind::Over => self.debugger.step_over(&mut self.cpu, &mut self.bus, STEP_LIMIT),
+                StepKind::In => self.debugger.run_until(&mut self.cpu, &mut self.bus, 1, |_| true),
+                StepKind::Out => self.debugger.step_out(&mut self.cpu, &mut self.bus, STEP_LIMIT),
+            };
+            if reason != StopReason::Step {
+                return reason;
+            }
+            let depth_now = self.debugger.call_stack().len();
+            let line = self.current_line();
+            let done = match kind {
+                _ if start.is_none() => true,
+                StepKind::Over => depth_now < depth || (line.is_some() && line != start && depth_now <= depth),
+                StepKind::In => line.is_some() && line != start,
+                StepKind::Out => line.is_some() || depth_now == 0,
+            };
+            if done {
+                return reason;
+            }
+        }
+        StopReason::Limit
+    }
+
+    // Steps back to the previous source line, skipping over subroutine
+    // calls, or one instruction without debug info.
+    fn step_back(&mut self) -> StopReason {
+        let start = self.current_line();
+        let depth = self.debugger.call_stack().len();
+        loop {
+            if let Some(reason) = self.debugger.reverse_step(&mut self.cpu, &mut self.bus) {
+                return reason;
+            }
+            let line = self.current_line();
+            let outside = self.debugger.call_stack().len() <= depth;
+            if start.is_none() || (outside && line.is_some() && line != start) {
+                return StopReason::Step;
+            }
+        }
+    }
+
+    fn source(&self, file: u32) -> Value {
+        let name = &self.info.as_ref().unwrap().files[&file].name;
+        let path = self.source_root.join(name);
+        let short = Path::new(name).file_name().map_or(name.clone(), |n| n.to_string_lossy().into_owned());
+        Value::object([("name", short.into()), ("path", path.to_string_lossy().into_owned().into())])
+    }
+
+    fn frame(&self, id: usize, pc: u16, entry: u16) -> Value {
+        let info = self.info.as_ref();
+        let name = info.and_then(|i| i.label_for_addr(entry))
+            .map_or_else(|| format!("${:04X}", entry), |s| s.name.clone());
+        let mut frame = Value::object([
+            ("id", id.into()),
+            ("name", name.into()),
+            ("line", 0.into()),
+            ("column", 1.into()),
+            ("instructionPointerReference", format!("0x{:04X}", pc).into()),
+        ]);
+        if let Some(line) = info.and_then(|i| i.line_for_addr(pc)) {
+            frame.insert("line", line.line.into());
+            frame.insert("source", self.source(line.file));
+        }
+        frame
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"].as_str().ok_or("missing source path")?.to_string();
+        for id in self.breakpoints.remove(&path).unwrap_or_default() {
+            self.debugger.remove(id);
+        }
+        let file = self.info.as_ref().and_then(|i| i.find_file(&path));
+        let mut ids = Vec::new();
+        let mut result = Vec::new();
+        for bp in args["breakpoints"].as_array().unwrap_or_default() {
+            let line = bp["line"].as_i64().ok_or("missing breakpoint line")? as u32;
+            let found = file.and_then(|f| self.info.as_ref().unwrap().addrs_for_line(f, line));
+            let Some((actual, addrs)) = found else {
+                result.push(Value::object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "no code at this line".into()),
+                ]));
+                continue;
+            };
+            // "hitCondition" is the hit count at which to start stopping.
+            let ignore = bp["hitCondition"].as_str()
+                .and_then(|h| h.trim().trim_start_matches(">=").trim().parse::<u64>().ok())
+                .map_or(0, |n| n.saturating_sub(1));
+            let mut message = None;
+            for addr in addrs {
+                let id = match bp["condition"].as_str() {
+                    Some(cond) => match self.debugger.add_conditional_breakpoint(addr, cond) {
+                        Ok(id) => id,
+                        Err(e) => {
+                            message = Some(e.to_string());
+                            continue;
+                        }
+                    },
+                    None => self.debugger.add_breakpoint(addr),
+                };
+                self.debugger.breakpoint_mut(id).unwrap().ignore_count = ignore;
+                ids.push(id);
+            }
+            let mut entry = Value::object([("verified", message.is_none().into()), ("line", actual.into())]);
+            if let Some(message) = message {
+                entry.insert("message", message.into());
+            }
+            result.push(entry);
+        }
+        self.breakpoints.insert(path, ids);
+        Ok(Value::object([("breakpoints", result.into())]))
+    }
+
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        let var = |name: &str, value: String| {
+            Value::object([("name", name.into()), ("value", value.into()), ("variablesReference", 0.into())])
+        };
+        match reference {
+            REGISTERS_REF => {
+                let reg = &self.cpu.reg;
+                let flags: String = [(reg.n, 'N'), (reg.v, 'V'), (false, '-'), (false, 'B'),
+                                     (reg.d, 'D'), (reg.i, 'I'), (reg.z, 'Z'), (reg.c, 'C')]
+                    .iter()
+                    .map(|(set, c)| if *set { *c } else { '.' })
+                    .collect();
+                vec![
+                    var("A", hex_value(reg.get_a() as i64, 2)),
+                    var("X", hex_value(reg.get_x() as i64, 2)),
+                    var("Y", hex_value(reg.get_y() as i64, 2)),
+                    var("SP", hex_value(self.cpu.sp as i64, 2)),
+                    var("PC", hex_value(self.cpu.pc as i64, 4)),
+                    var("P", format!("${:02X} {}", reg.get_status(false), flags)),
+                ]
+            }
+            LABELS_REF => {
+                let Some(info) = &self.info else { return Vec::new() };
+                let mut labels: Vec<_> = info.labels().collect();
+                labels.sort_by_key(|s| (s.value, s.name.clone()));
+                labels.iter()
+                    .map(|s| {
+                        let addr = s.value as u16;
+                        let value = match s.size {
+                            Some(2) => {
+                                let word = u16::from_le_bytes([self.bus.peek(addr), self.bus.peek(addr.wrapping_add(1))]);
+                                hex_value(word as i64, 4)
+                            }
+                            _ => hex_value(self.bus.peek(addr) as i64, 2),
+                        };
+                        var(&s.name, value)
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn evaluate(&self, expression: &str) -> Result<String, String> {
+        let label = self.info.as_ref().and_then(|i| i.labels().find(|s| s.name == expression));
+        if let Some(label) = label {
+            return Ok(hex_value(self.bus.peek(label.value as u16) as i64, 2));
+        }
+        let expr = Expr::parse(expression).map_err(|e| e.to_string())?;
+        Ok(hex_value(expr.eval(&self.cpu, &self.bus), 2))
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program_path = args["program"].as_str().ok_or("missing `program`")?;
+        let program = std::fs::read(program_path).map_err(|e| format!("{}: {}", program_path, e))?;
+        let dbg_path = match args["debugInfo"].as_str() {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(program_path).with_extension("dbg"),
+        };
+        let info = match std::fs::read_to_string(&dbg_path) {
+            Ok(src) => Some(DebugInfo::parse(&src).map_err(|e| format!("{}: {}", dbg_path.display(), e))?),
+            Err(_) if args["debugInfo"].is_null() => None,
+            Err(e) => return Err(format!("{}: {}", dbg_path.display(), e)),
+        };
+        let load_address = match args["loadAddress"].as_str() {
+            Some(s) => Some(parse_address(s).ok_or("invalid `loadAddress`")?),
+            None => args["loadAddress"].as_i64().map(|a| a as u16),
+        };
+        let root = dbg_path.parent().unwrap_or(Path::new("")).to_path_buf();
+        self.load(&program, info, load_address, &root)?;
+
+        self.cpu.reset(&mut self.bus);
+        match &args["entry"] {
+            Value::Number(n) => self.cpu.pc = *n as u16,
+            Value::String(s) => {
+                let label = self.info.as_ref().and_then(|i| i.labels().find(|l| l.name == *s));
+                self.cpu.pc = match label {
+                    Some(label) => label.value as u16,
+                    None => parse_address(s).ok_or_else(|| format!("unknown entry `{}`", s))?,
+                };
+            }
+            _ => {}
+        }
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    fn handle(&mut self, command: &str, args: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => {
+                let initialized = event("initialized", Value::Null);
+                self.pending.push(initialized);
+                Ok(Value::object([
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsConditionalBreakpoints", true.into()),
+                    ("supportsHitConditionalBreakpoints", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                    ("supportsStepBack", true.into()),
+                ]))
+            }
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(Value::Null),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    let stopped = stopped_event("entry", None);
+                    self.pending.push(stopped);
+                } else {
+                    self.running = true;
+                }
+                Ok(Value::Null)
+            }
+            "threads" => {
+                let thread = Value::object([("id", THREAD_ID.into()), ("name", "6502".into())]);
+                Ok(Value::object([("threads", vec![thread].into())]))
+            }
+            "stackTrace" => {
+                let stack = self.debugger.call_stack();
+                let mut frames = Vec::new();
+                let mut pc = self.cpu.pc;
+                for (i, f) in stack.iter().rev().enumerate() {
+                    frames.push(self.frame(i, pc, f.entry));
+                    pc = f.call_site;
+                }
+                frames.push(self.frame(stack.len(), pc, pc));
+                let total = frames.len();
+                Ok(Value::object([("stackFrames", frames.into()), ("totalFrames", total.into())]))
+            }
+            "scopes" => {
+                let scope = |name: &str, reference: i64| Value::object([
+                    ("name", name.into()),
+                    ("variablesReference", reference.into()),
+                    ("expensive", false.into()),
+                ]);
+                let mut scopes = vec![scope("Registers", REGISTERS_REF)];
+                if self.info.is_some() {
+                    scopes.push(scope("Labels", LABELS_REF));
+                }
+                Ok(Value::object([("scopes", scopes.into())]))
+            }
+            "variables" => {
+                let reference = args["variablesReference"].as_i64().ok_or("missing variablesReference")?;
+                Ok(Value::object([("variables", self.variables(reference).into())]))
+            }
+            "evaluate" => {
+                let expression = args["expression"].as_str().ok_or("missing expression")?;
+                let result = self.evaluate(expression)?;
+                Ok(Value::object([("result", result.into()), ("variablesReference", 0.into())]))
+            }
+            "continue" => {
+                self.running = true;
+                Ok(Value::object([("allThreadsContinued", true.into())]))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let kind = match command {
+                    "next" => StepKind::Over,
+                    "stepIn" => StepKind::In,
+                    _ => StepKind::Out,
+                };
+                let reason = self.step(kind);
+                let stopped = self.stop(reason);
+                self.pending.push(stopped);
+                Ok(Value::Null)
+            }
+            "stepBack" | "reverseContinue" => {
+                let reason = match command {
+                    "stepBack" => self.step_back(),
+                    _ => self.debugger.reverse_continue(&mut self.cpu, &mut self.bus, STEP_LIMIT),
+                };
+                let stopped = self.stop(reason);
+                self.pending.push(stopped);
+                Ok(Value::Null)
+            }
+            "pause" => {
+                self.running = false;
+                self.pending.push(stopped_event("pause", None));
+                Ok(Value::Null)
+            }
+            "disconnect" => Ok(Value::Null),
+            _ => Err(format!("unsupported request `{}`", command)),
+        }
+    }
+
+    /// Handles one incoming message. Returns false once the client has
+    /// disconnected.
+    pub(crate) fn dispatch(&mut self, message: &Value) -> io::Result<bool> {
+        if message["type"].as_str() != Some("request") {
+            return Ok(true);
+        }
+        let command = message["command"].as_str().unwrap_or_default().to_string();
+        let mut response = Value::object([
+            ("type", "response".into()),
+            ("request_seq", message["seq"].clone()),
+            ("command", command.as_str().into()),
+        ]);
+        match self.handle(&command, &message["arguments"]) {
+            Ok(body) => {
+                response.insert("success", true.into());
+                if !body.is_null() {
+                    response.insert("body", body);
+                }
+            }
+            Err(e) => {
+                response.insert("success", false.into());
+                response.insert("message", e.into());
+            }
+        }
+        self.send(response)?;
+        for event in core::mem::take(&mut self.pending) {
+            self.send(event)?;
+        }
+        Ok(command != "disconnect")
+    }
+
+    /// Runs a slice of the program if it is running, reporting a stop.
+    pub fn poll(&mut self) -> io::Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        let reason = self.debugger.run_until_stop(&mut self.cpu, &mut self.bus, RUN_CHUNK);
+        if reason != StopReason::Limit {
+            let stopped = self.stop(reason);
+            self.send(stopped)?;
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self, input: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let message = match self.running {
+                true => match input.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) => {
+                        self.poll()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                },
+                false => match input.recv() {
+                    Ok(message) => message,
+                    Err(_) => return Ok(()),
+                },
+            };
+            if !self.dispatch(&message)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmos6502::Nmos6502;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    // main:  LDX #$00; loop: JSR sub; INX; JMP loop
+    // sub:   INC counter; RTS
+    const PROGRAM: [u8; 0x14] = [
+        0xA2, 0x00, 0x20, 0x10, 0x02, 0xE8, 0x4C, 0x02, 0x02, 0, 0, 0, 0, 0, 0, 0,
+        0xEE, 0x00, 0x03, 0x60,
+    ];
+
+    const DBG: &str = "\
+file\tid=0,name=\"main.s\",size=100,mtime=0,mod=0
+line\tid=0,file=0,line=3,span=0
+line\tid=1,file=0,line=4,span=1
+line\tid=2,file=0,line=5,span=2
+line\tid=3,file=0,line=6,span=3
+line\tid=4,file=0,line=9,span=4
+line\tid=5,file=0,line=10,span=5
+seg\tid=0,name=\"CODE\",start=0x0200,size=0x14,addrsize=absolute,type=ro,oname=\"main.bin\",ooffs=0
+seg\tid=1,name=\"BSS\",start=0x0300,size=0x01,addrsize=absolute,type=rw
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=3
+span\tid=2,seg=0,start=5,size=1
+span\tid=3,seg=0,start=6,size=3
+span\tid=4,seg=0,start=16,size=3
+span\tid=5,seg=0,start=19,size=1
+sym\tid=0,name=\"main\",addrsize=absolute,scope=0,def=0,val=0x200,seg=0,type=lab
+sym\tid=1,name=\"sub\",addrsize=absolute,scope=0,def=4,val=0x210,seg=0,type=lab
+sym\tid=2,name=\"counter\",addrsize=absolute,scope=0,def=6,val=0x300,seg=1,type=lab
+";
+
+    fn get_server() -> DapServer<Nmos6502, MockBus, Vec<u8>> {
+        let mut cpu = Cpu::<Nmos6502>::new();
+        cpu.pc = 0x0200;
+        cpu.sp = 0xFF;
+        let mut server = DapServer::new(cpu, MockBus([0; 65536]), Vec::new());
+        let info = DebugInfo::parse(DBG).unwrap();
+        server.load(&PROGRAM, Some(info), None, Path::new("/src")).unwrap();
+        server
+    }
+
+    fn output(server: &mut DapServer<Nmos6502, MockBus, Vec<u8>>) -> Vec<Value> {
+        let bytes: Vec<u8> = server.out.drain(..).collect();
+        let mut reader = io::Cursor::new(bytes);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn request(server: &mut DapServer<Nmos6502, MockBus, Vec<u8>>, command: &str, args: Value) -> Vec<Value> {
+        let message = Value::object([
+            ("seq", 1.into()),
+            ("type", "request".into()),
+            ("command", command.into()),
+            ("arguments", args),
+        ]);
+        server.dispatch(&message).unwrap();
+        let messages = output(server);
+        assert_eq!(messages[0]["success"].as_bool(), Some(true), "{}", messages[0]);
+        messages
+    }
+
+    fn set_breakpoints(server: &mut DapServer<Nmos6502, MockBus, Vec<u8>>, lines: &[u32]) -> Value {
+        let bps: Vec<Value> = lines.iter().map(|l| Value::object([("line", (*l).into())])).collect();
+        let args = Value::object([
+            ("source", Value::object([("path", "/src/main.s".into())])),
+            ("breakpoints", bps.into()),
+        ]);
+        request(server, "setBreakpoints", args).remove(0)
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut out = Vec::new();
+        write_message(&mut out, &Value::object([("a", 1.into())])).unwrap();
+        assert_eq!(out, b"Content-Length: 7\r\n\r\n{\"a\":1}");
+        let message = read_message(&mut io::Cursor::new(out)).unwrap().unwrap();
+        assert_eq!(message["a"].as_i64(), Some(1));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut server = get_server();
+        let messages = request(&mut server, "initialize", Value::Null);
+        assert_eq!(messages[1]["event"].as_str(), Some("initialized"));
+
+        let response = set_breakpoints(&mut server, &[9, 7, 11]);
+        let bps = &response["body"]["breakpoints"];
+        assert_eq!(bps[0]["line"].as_i64(), Some(9));
+        assert_eq!(bps[1]["line"].as_i64(), Some(9), "moved to the next line with code");
+        assert_eq!(bps[2]["verified"].as_bool(), Some(false));
+
+        request(&mut server, "configurationDone", Value::Null);
+        server.poll().unwrap();
+        let stopped = output(&mut server).remove(0);
+        assert_eq!(stopped["body"]["reason"].as_str(), Some("breakpoint"));
+        assert_eq!(server.cpu.pc, 0x0210);
+
+        let trace = request(&mut server, "stackTrace", Value::Null).remove(0);
+        let frames = &trace["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"].as_str(), Some("sub"));
+        assert_eq!(frames[0]["line"].as_i64(), Some(9));
+        assert_eq!(frames[0]["source"]["path"].as_str(), Some("/src/main.s"));
+        assert_eq!(frames[1]["name"].as_str(), Some("main"));
+        assert_eq!(frames[1]["line"].as_i64(), Some(4));
+
+        set_breakpoints(&mut server, &[]);
+        request(&mut server, "continue", Value::Null);
+        server.poll().unwrap();
+        assert!(output(&mut server).is_empty());
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut server = get_server();
+        let line = |server: &DapServer<_, _, _>| server.current_line().unwrap().1;
+
+        let messages = request(&mut server, "next", Value::Null);
+        assert_eq!(messages[1]["body"]["reason"].as_str(), Some("step"));
+        assert_eq!(line(&server), 4);
+
+        request(&mut server, "next", Value::Null);
+        assert_eq!((line(&server), server.bus.read(0x0300)), (5, 1));
+
+        request(&mut server, "next", Value::Null);
+        request(&mut server, "next", Value::Null);
+        assert_eq!(line(&server), 4);
+        request(&mut server, "stepIn", Value::Null);
+        assert_eq!(line(&server), 9);
+        request(&mut server, "stepOut", Value::Null);
+        assert_eq!((line(&server), server.bus.read(0x0300)), (5, 2));
+
+        request(&mut server, "next", Value::Null);
+        request(&mut server, "next", Value::Null);
+        set_breakpoints(&mut server, &[10]);
+        let messages = request(&mut server, "next", Value::Null);
+        assert_eq!(messages[1]["body"]["reason"].as_str(), Some("breakpoint"));
+        assert_eq!(line(&server), 10);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut server = get_server();
+        let line = |server: &DapServer<_, _, _>| server.current_line().unwrap().1;
+        request(&mut server, "next", Value::Null);
+        request(&mut server, "next", Value::Null);
+        assert_eq!((line(&server), server.bus.read(0x0300)), (5, 1));
+
+        request(&mut server, "stepBack", Value::Null);
+        assert_eq!((line(&server), server.bus.read(0x0300)), (4, 0));
+
+        let messages = request(&mut server, "reverseContinue", Value::Null);
+        assert!(messages[1]["body"]["text"].as_str().unwrap().contains("start"));
+        assert_eq!(server.cpu.pc, 0x0200);
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut server = get_server();
+        server.bus.write(0x0300, 0x2A);
+        server.cpu.reg.update_a(0x80);
+
+        let vars = request(&mut server, "variables", Value::object([("variablesReference", REGISTERS_REF.into())]));
+        let vars = &vars[0]["body"]["variables"];
+        assert_eq!(vars[0]["value"].as_str(), Some("$80 (128)"));
+        assert_eq!(vars[5]["value"].as_str(), Some("$A0 N......."));
+
+        let vars = request(&mut server, "variables", Value::object([("variablesReference", LABELS_REF.into())]));
+        let vars = &vars[0]["body"]["variables"];
+        assert_eq!(vars[2]["name"].as_str(), Some("counter"));
+        assert_eq!(vars[2]["value"].as_str(), Some("$2A (42)"));
+
+        let result = request(&mut server, "evaluate", Value::object([("expression", "counter".into())]));
+        assert_eq!(result[0]["body"]["result"].as_str(), Some("$2A (42)"));
+        let result = request(&mut server, "evaluate", Value::object([("expression", "A == $80".into())]));
+        assert_eq!(result[0]["body"]["result"].as_str(), Some("$01 (1)"));
+        let result = request(&mut server, "evaluate", Value::object([("expression", "-1".into())]));
+        assert_eq!(result[0]["body"]["result"].as_str(), Some("$FF (-1)"));
+        assert_eq!(hex_value(-129, 2), "$FF7F (-129)");
+        assert_eq!(hex_value(0x1234, 2), "$1234 (4660)");
+        assert_eq!(hex_value(i64::MIN, 2), "$8000000000000000 (-9223372036854775808)");
+    }
+}
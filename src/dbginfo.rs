@@ -0,0 +1,378 @@
+use core::fmt;
+use std::collections::{BTreeSet, HashMap};
+
+/// Line type for assembler source in ld65 debug info; `1` marks C source.
+pub const LINE_ASM: u32 = 0;
+pub const LINE_C: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub file: u32,
+    pub line: u32,
+    pub kind: u32,
+    pub spans: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    pub output_name: Option<String>,
+    pub output_offset: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub seg: u32,
+    pub start: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: i64,
+    pub kind: String,
+    pub size: Option<u32>,
+}
+
+/// Debug information written by `ld65 --dbgfile`.
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    pub files: HashMap<u32, SourceFile>,
+    pub lines: HashMap<u32, Line>,
+    pub segments: HashMap<u32, Segment>,
+    pub spans: HashMap<u32, Span>,
+    pub symbols: Vec<Symbol>,
+    // Start addresses, in order, of the ranges over which the line found
+    // for an address stays the same, with that line's id. Built by `parse`.
+    line_index: Vec<(u32, Option<u32>)>,
+}
+
+fn parse_number(value: &str) -> Option<i64> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// Splits `key=value,key="quoted, value"` into pairs.
+fn parse_fields(s: &str) -> Option<HashMap<&str, &str>> {
+    let mut fields = HashMap::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], quoted[end + 1..].strip_prefix(',').unwrap_or(&quoted[end + 1..]))
+            }
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        fields.insert(key.trim(), value);
+        rest = after.trim_start();
+    }
+    Some(fields)
+}
+
+struct Record<'a> {
+    line: usize,
+    fields: HashMap<&'a str, &'a str>,
+}
+
+impl Record<'_> {
+    fn error(&self, message: String) -> Error {
+        Error { line: self.line, message }
+    }
+
+    fn str(&self, key: &str) -> Result<&str, Error> {
+        self.fields.get(key).copied().ok_or_else(|| self.error(format!("missing `{}`", key)))
+    }
+
+    fn num(&self, key: &str) -> Result<i64, Error> {
+        let value = self.str(key)?;
+        parse_number(value).ok_or_else(|| self.error(format!("invalid number `{}`", value)))
+    }
+
+    fn opt_num(&self, key: &str) -> Result<Option<i64>, Error> {
+        match self.fields.contains_key(key) {
+            true => self.num(key).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn id(&self) -> Result<u32, Error> {
+        self.num("id").map(|id| id as u32)
+    }
+}
+
+impl DebugInfo {
+    pub fn parse(src: &str) -> Result<DebugInfo, Error> {
+        let mut info = DebugInfo::default();
+        let mut line_files = Vec::new();
+        for (n, text) in src.lines().enumerate() {
+            let text = text.trim_end();
+            if text.is_empty() {
+                continue;
+            }
+            let (kind, rest) = text.split_once(['\t', ' ']).unwrap_or((text, ""));
+            let fields = parse_fields(rest).ok_or_else(|| Error { line: n + 1, message: "malformed record".to_string() })?;
+            let rec = Record { line: n + 1, fields };
+            match kind {
+                "file" => {
+                    info.files.insert(rec.id()?, SourceFile { name: rec.str("name")?.to_string() });
+                }
+                "line" => {
+                    let spans = match rec.fields.get("span") {
+                        Some(list) => list.split('+')
+                            .map(|s| parse_number(s).map(|s| s as u32))
+                            .collect::<Option<_>>()
+                            .ok_or_else(|| rec.error("invalid span list".to_string()))?,
+                        None => Vec::new(),
+                    };
+                    let line = Line {
+                        file: rec.num("file")? as u32,
+                        line: rec.num("line")? as u32,
+                        kind: rec.opt_num("type")?.unwrap_or(0) as u32,
+                        spans,
+                    };
+                    line_files.push((rec.line, line.file));
+                    info.lines.insert(rec.id()?, line);
+                }
+                "seg" => {
+                    let seg = Segment {
+                        name: rec.str("name")?.to_string(),
+                        start: rec.num("start")? as u32,
+                        size: rec.num("size")? as u32,
+                        output_name: rec.fields.get("oname").map(|s| s.to_string()),
+                        output_offset: rec.opt_num("ooffs")?.map(|o| o as u32),
+                    };
+                    info.segments.insert(rec.id()?, seg);
+                }
+                "span" => {
+                    let span = Span {
+                        seg: rec.num("seg")? as u32,
+                        start: rec.num("start")? as u32,
+                        size: rec.num("size")? as u32,
+                    };
+                    info.spans.insert(rec.id()?, span);
+                }
+                "sym" => {
+                    // Imports carry no value of their own.
+                    if let Some(value) = rec.opt_num("val")? {
+                        info.symbols.push(Symbol {
+                            name: rec.str("name")?.to_string(),
+                            value,
+                            kind: rec.fields.get("type").unwrap_or(&"lab").to_string(),
+                            size: rec.opt_num("size")?.map(|s| s as u32),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Lines may come before their files, so they're checked at the end.
+        if let Some((line, file)) = line_files.into_iter().find(|(_, f)| !info.files.contains_key(f)) {
+            return Err(Error { line, message: format!("unknown file {}", file) });
+        }
+        info.index_lines();
+        Ok(info)
+    }
+
+    // Sweeps the span boundaries in address order, keeping the lines that
+    // cover each range ordered by preference.
+    fn index_lines(&mut self) {
+        let mut starts = Vec::new();
+        let mut ends = Vec::new();
+        for (id, line) in &self.lines {
+            for (start, end) in line.spans.iter().filter_map(|s| self.span_range(*s)) {
+                if start < end {
+                    let key = (line.kind != LINE_C, end - start, line.line, *id);
+                    starts.push((start, key));
+                    ends.push((end, key));
+                }
+            }
+        }
+        starts.sort_unstable();
+        ends.sort_unstable();
+
+        let mut active = BTreeSet::new();
+        let (mut starts, mut ends) = (starts.into_iter().peekable(), ends.into_iter().peekable());
+        self.line_index.clear();
+        loop {
+            let addr = match (starts.peek(), ends.peek()) {
+                (Some(&(start, _)), Some(&(end, _))) => start.min(end),
+                (None, Some(&(end, _))) => end,
+                _ => break,
+            };
+            while let Some((_, key)) = ends.next_if(|(end, _)| *end == addr) {
+                active.remove(&key);
+            }
+            while let Some((_, key)) = starts.next_if(|(start, _)| *start == addr) {
+                active.insert(key);
+            }
+            let best = active.first().map(|key| key.3);
+            if self.line_index.last().map(|(_, id)| *id) != Some(best) {
+                self.line_index.push((addr, best));
+            }
+        }
+    }
+
+    /// Absolute address range `start..end` covered by a span.
+    pub fn span_range(&self, span: u32) -> Option<(u32, u32)> {
+        let span = self.spans.get(&span)?;
+        let start = self.segments.get(&span.seg)?.start.checked_add(span.start)?;
+        Some((start, start.checked_add(span.size)?))
+    }
+
+    /// Finds the source line for `addr`, preferring C lines over assembler
+    /// lines and narrower spans over wider ones.
+    pub fn line_for_addr(&self, addr: u16) -> Option<&Line> {
+        let i = self.line_index.partition_point(|(start, _)| *start <= addr as u32);
+        let (_, id) = self.line_index.get(i.checked_sub(1)?)?;
+        self.lines.get(&(*id)?)
+    }
+
+    /// Finds the file whose name matches the tail of `path`.
+    pub fn find_file(&self, path: &str) -> Option<u32> {
+        let path = path.replace('\\', "/");
+        self.files.iter()
+            .filter(|(_, f)| {
+                let name = f.name.replace('\\', "/");
+                path == name || path.ends_with(&format!("/{}", name.trim_start_matches("./")))
+            })
+            .max_by_key(|(_, f)| f.name.len())
+            .map(|(id, _)| *id)
+    }
+
+    /// Start addresses of the code for `line` in `file`. When the line has
+    /// no code the next line that does is used; its number is returned.
+    pub fn addrs_for_line(&self, file: u32, line: u32) -> Option<(u32, Vec<u16>)> {
+        let candidates = self.lines.values().filter(|l| l.file == file && l.line >= line && !l.spans.is_empty());
+        let best = candidates.clone().map(|l| l.line).min()?;
+        let mut addrs: Vec<u16> = candidates
+            .filter(|l| l.line == best)
+            .flat_map(|l| l.spans.iter().filter_map(|s| self.span_range(*s)))
+            .map(|(start, _)| start as u16)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        Some((best, addrs))
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|s| s.kind == "lab")
+    }
+
+    /// The closest label at or below `addr`.
+    pub fn label_for_addr(&self, addr: u16) -> Option<&Symbol> {
+        self.labels()
+            .filter(|s| (0..=addr as i64).contains(&s.value))
+            .max_by_key(|s| (s.value, !s.name.starts_with('@')))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBG: &str = "\
+version\tmajor=2,minor=0
+info\tcsym=0,file=2,lib=0,line=4,mod=1,scope=1,seg=2,span=4,sym=3,type=1
+file\tid=0,name=\"main.s\",size=120,mtime=0x5F000000,mod=0
+file\tid=1,name=\"inc/util.s\",size=40,mtime=0x5F000000,mod=0
+line\tid=0,file=0,line=3,span=0
+line\tid=1,file=0,line=4,span=1
+line\tid=2,file=0,line=9,span=2+3
+line\tid=3,file=1,line=1,type=2,span=3
+mod\tid=0,name=\"main.o\",file=0
+seg\tid=0,name=\"CODE\",start=0x000200,size=0x0010,addrsize=absolute,type=ro,oname=\"main.bin\",ooffs=0
+seg\tid=1,name=\"BSS\",start=0x000300,size=0x0002,addrsize=absolute,type=rw
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=3
+span\tid=2,seg=0,start=5,size=6
+span\tid=3,seg=0,start=8,size=1
+sym\tid=0,name=\"main\",addrsize=absolute,scope=0,def=0,val=0x200,seg=0,type=lab
+sym\tid=1,name=\"counter\",addrsize=absolute,size=2,scope=0,def=1,val=0x300,seg=1,type=lab
+sym\tid=2,name=\"WIDTH\",addrsize=zeropage,scope=0,def=2,val=0x28,type=equ
+sym\tid=3,name=\"print\",addrsize=absolute,scope=0,ref=1,type=imp
+";
+
+    #[test]
+    fn test_parse() {
+        let info = DebugInfo::parse(DBG).unwrap();
+        assert_eq!(info.files[&1].name, "inc/util.s");
+        assert_eq!(info.lines[&2].spans, vec![2, 3]);
+        assert_eq!(info.segments[&0].output_offset, Some(0));
+        assert_eq!(info.segments[&1].output_name, None);
+        assert_eq!(info.symbols.len(), 3);
+        assert_eq!(info.labels().count(), 2);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let info = DebugInfo::parse(DBG).unwrap();
+        assert_eq!(info.line_for_addr(0x0203).unwrap().line, 4);
+        assert_eq!(info.line_for_addr(0x0208).unwrap().file, 1);
+        // The enclosing span takes over again after a nested one.
+        assert_eq!(info.line_for_addr(0x0209).unwrap().line, 9);
+        assert_eq!(info.line_for_addr(0x020A).unwrap().line, 9);
+        assert!(info.line_for_addr(0x01FF).is_none());
+        assert!(info.line_for_addr(0x020B).is_none());
+        assert!(info.line_for_addr(0x0210).is_none());
+
+        assert_eq!(info.find_file("/home/me/project/main.s"), Some(0));
+        assert_eq!(info.find_file("/home/me/project/inc/util.s"), Some(1));
+        assert_eq!(info.find_file("/home/me/project/other.s"), None);
+
+        assert_eq!(info.addrs_for_line(0, 4), Some((4, vec![0x0202])));
+        assert_eq!(info.addrs_for_line(0, 5), Some((9, vec![0x0205, 0x0208])));
+        assert_eq!(info.addrs_for_line(0, 10), None);
+
+        assert_eq!(info.label_for_addr(0x0205).unwrap().name, "main");
+        assert_eq!(info.label_for_addr(0x0301).unwrap().name, "counter");
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = DebugInfo::parse("file\tid=0,name=\"a.s\"\nline\tid=0,file=0,line=x").unwrap_err();
+        assert_eq!(err, Error { line: 2, message: "invalid number `x`".to_string() });
+        assert_eq!(DebugInfo::parse("seg\tid=0,name=\"CODE").unwrap_err().message, "malformed record");
+        let err = DebugInfo::parse("line\tid=0,file=3,line=1\nfile\tid=0,name=\"a.s\"").unwrap_err();
+        assert_eq!(err, Error { line: 1, message: "unknown file 3".to_string() });
+    }
+
+    #[test]
+    fn test_span_overflow() {
+        let info = DebugInfo::parse("\
+file\tid=0,name=\"a.s\"
+line\tid=0,file=0,line=1,span=0+1
+seg\tid=0,name=\"CODE\",start=0xFFFFFFFF,size=1
+span\tid=0,seg=0,start=1,size=1
+span\tid=1,seg=0,start=0,size=1
+").unwrap();
+        assert_eq!(info.span_range(0), None);
+        assert_eq!(info.span_range(1), None);
+        assert!(info.line_for_addr(0xFFFF).is_none());
+    }
+}
@@ -0,0 +1,723 @@
+use core::cell::RefCell;
+use core::ops::RangeInclusive;
+
+use crate::instruction::InstructionCode;
+use crate::snapshot::CpuSnapshot;
+use crate::{Bus, Cpu, Variant};
+
+pub mod expr;
+pub mod journal;
+
+pub use expr::{Expr, ParseError};
+pub use journal::Journal;
+
+use journal::{Entry, JournalBus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+    Execute,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::Read | WatchKind::ReadWrite, Access::Read) |
+            (WatchKind::Write | WatchKind::ReadWrite, Access::Write) |
+            (WatchKind::Execute, Access::Execute)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint { id: usize, pc: u16 },
+    Watchpoint { id: usize, addr: u16, access: Access, kind: WatchKind },
+    IllegalOpcode { pc: u16, opcode: u8 },
+    Step,
+    Limit,
+    /// Running backwards reached the oldest journaled state.
+    HistoryStart,
+}
+
+/// A PC breakpoint. Without an address the condition is checked before
+/// every instruction.
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub addr: Option<u16>,
+    pub condition: Option<Expr>,
+    pub enabled: bool,
+    pub ignore_count: u64,
+    pub hits: u64,
+}
+
+/// A subroutine or interrupt entered through JSR or BRK. It is popped once
+/// the stack pointer climbs back to where it was before the call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub call_site: u16,
+    pub entry: u16,
+    pub sp: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    pub condition: Option<Expr>,
+    pub enabled: bool,
+    pub ignore_count: u64,
+    pub hits: u64,
+}
+
+// Counts a hit if the condition holds and reports whether to stop.
+fn hit<V>(
+    condition: &Option<Expr>,
+    ignore_count: u64,
+    hits: &mut u64,
+    cpu: &Cpu<V>,
+    bus: &impl Bus,
+) -> bool {
+    if condition.as_ref().is_some_and(|c| !c.holds(cpu, bus)) {
+        return false;
+    }
+    *hits += 1;
+    *hits > ignore_count
+}
+
+/// Wraps a bus and records every access made through it.
+pub struct WatchBus<'b, B> {
+    pub bus: &'b mut B,
+    pub accesses: RefCell<Vec<(u16, Access)>>,
+}
+
+impl<'b, B: Bus> WatchBus<'b, B> {
+    pub fn new(bus: &'b mut B) -> Self {
+        WatchBus { bus, accesses: RefCell::new(Vec::new()) }
+    }
+}
+
+impl<B: Bus> Bus for WatchBus<'_, B> {
+    fn read(&self, addr: u16) -> u8 {
+        self.accesses.borrow_mut().push((addr, Access::Read));
+        self.bus.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.accesses.get_mut().push((addr, Access::Write));
+        self.bus.write(addr, value)
+    }
+
+    // Dummy cycles don't trigger read watchpoints.
+    fn dummy_read(&self, addr: u16) {
+        self.bus.dummy_read(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: Vec<(usize, Breakpoint)>,
+    watchpoints: Vec<(usize, Watchpoint)>,
+    frames: Vec<Frame>,
+    journal: Journal,
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn take_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) -> usize {
+        self.insert_breakpoint(Some(addr), None)
+    }
+
+    pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: &str) -> Result<usize, ParseError> {
+        let condition = Expr::parse(condition)?;
+        Ok(self.insert_breakpoint(Some(addr), Some(condition)))
+    }
+
+    /// Stops before any instruction at which `condition` holds.
+    pub fn add_stop_condition(&mut self, condition: &str) -> Result<usize, ParseError> {
+        let condition = Expr::parse(condition)?;
+        Ok(self.insert_breakpoint(None, Some(condition)))
+    }
+
+    fn insert_breakpoint(&mut self, addr: Option<u16>, condition: Option<Expr>) -> usize {
+        let id = self.take_id();
+        let bp = Breakpoint { addr, condition, enabled: true, ignore_count: 0, hits: 0 };
+        self.breakpoints.push((id, bp));
+        id
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> usize {
+        let id = self.take_id();
+        let wp = Watchpoint { range, kind, condition: None, enabled: true, ignore_count: 0, hits: 0 };
+        self.watchpoints.push((id, wp));
+        id
+    }
+
+    pub fn add_conditional_watchpoint(
+        &mut self,
+        range: RangeInclusive<u16>,
+        kind: WatchKind,
+        condition: &str,
+    ) -> Result<usize, ParseError> {
+        let condition = Expr::parse(condition)?;
+        let id = self.add_watchpoint(range, kind);
+        self.watchpoint_mut(id).unwrap().condition = Some(condition);
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let (bps, wps) = (self.breakpoints.len(), self.watchpoints.len());
+        self.breakpoints.retain(|(i, _)| *i != id);
+        self.watchpoints.retain(|(i, _)| *i != id);
+        bps + wps != self.breakpoints.len() + self.watchpoints.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    pub fn breakpoint(&self, id: usize) -> Option<&Breakpoint> {
+        self.breakpoints.iter().find(|(i, _)| *i == id).map(|(_, bp)| bp)
+    }
+
+    pub fn breakpoint_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.breakpoints.iter_mut().find(|(i, _)| *i == id).map(|(_, bp)| bp)
+    }
+
+    pub fn watchpoint(&self, id: usize) -> Option<&Watchpoint> {
+        self.watchpoints.iter().find(|(i, _)| *i == id).map(|(_, wp)| wp)
+    }
+
+    pub fn watchpoint_mut(&mut self, id: usize) -> Option<&mut Watchpoint> {
+        self.watchpoints.iter_mut().find(|(i, _)| *i == id).map(|(_, wp)| wp)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints.iter().map(|(id, bp)| (*id, bp))
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints.iter().map(|(id, wp)| (*id, wp))
+    }
+
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Forgets tracked calls, e.g. after the PC or SP was changed by hand.
+    pub fn clear_call_stack(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Keeps an undo journal of the last `limit` instructions so execution
+    /// can be reversed. Zero (the default) turns it off.
+    pub fn set_journal_limit(&mut self, limit: usize) {
+        self.journal.set_limit(limit);
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Forgets the undo history, e.g. after memory was changed by hand.
+    pub fn clear_journal(&mut self) {
+        self.journal.clear();
+    }
+
+    // Checks breakpoints and execute watchpoints for the instruction at PC.
+    fn check_pc<V>(&mut self, cpu: &Cpu<V>, bus: &impl Bus) -> Option<StopReason> {
+        let pc = cpu.pc;
+        for (id, bp) in self.breakpoints.iter_mut() {
+            if !bp.enabled || bp.addr.is_some_and(|addr| addr != pc) {
+                continue;
+            }
+            if hit(&bp.condition, bp.ignore_count, &mut bp.hits, cpu, bus) {
+                return Some(StopReason::Breakpoint { id: *id, pc });
+            }
+        }
+        self.check_watchpoints(&[(pc, Access::Execute)], cpu, bus)
+    }
+
+    fn check_watchpoints<V>(
+        &mut self,
+        accesses: &[(u16, Access)],
+        cpu: &Cpu<V>,
+        bus: &impl Bus,
+    ) -> Option<StopReason> {
+        for &(addr, access) in accesses {
+            for (id, wp) in self.watchpoints.iter_mut() {
+                if !wp.enabled || !wp.kind.matches(access) || !wp.range.contains(&addr) {
+                    continue;
+                }
+                if hit(&wp.condition, wp.ignore_count, &mut wp.hits, cpu, bus) {
+                    return Some(StopReason::Watchpoint { id: *id, addr, access, kind: wp.kind });
+                }
+            }
+        }
+        None
+    }
+
+    /// Executes one instruction, reporting any read or write watchpoint it
+    /// triggered. Breakpoints at the current PC are not checked.
+    pub fn step<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B) -> Option<StopReason> {
+        let (pc, sp) = (cpu.pc, cpu.sp);
+        let opcode = bus.peek(pc);
+        let Some((instr_code, addr_mode)) = V::decode(opcode) else {
+            return Some(StopReason::IllegalOpcode { pc, opcode });
+        };
+
+        let before = cpu.snapshot();
+        let frames = self.journal.is_enabled().then(|| self.frames.clone());
+        let mut journal = JournalBus::new(bus);
+        let mut watch = WatchBus::new(&mut journal);
+        cpu.step(&mut watch);
+        let mut accesses = watch.accesses.into_inner();
+        let writes = journal.writes;
+
+        if matches!(instr_code, InstructionCode::JSR | InstructionCode::BRK) {
+            self.frames.push(Frame { call_site: pc, entry: cpu.pc, sp });
+        }
+        self.pop_frames(cpu);
+        self.record(before, writes, frames);
+
+        // Opcode and operand fetches are not data reads.
+        let len = addr_mode.operand_len();
+        let mut fetches = len + 1;
+        accesses.retain(|(addr, access)| {
+            let is_fetch = fetches > 0 && *access == Access::Read && addr.wrapping_sub(pc) <= len;
+            fetches -= is_fetch as u16;
+            !is_fetch
+        });
+        self.check_watchpoints(&accesses, cpu, bus)
+    }
+
+    // A frame is done once SP is back at or above where it was before the
+    // call. SP is compared relative to the frame's, as a signed depth, so
+    // that a stack wrapping past $00 doesn't end frames early.
+    fn pop_frames<V>(&mut self, cpu: &Cpu<V>) {
+        while self.frames.last().is_some_and(|f| f.sp.wrapping_sub(cpu.sp) as i8 <= 0) {
+            self.frames.pop();
+        }
+    }
+
+    fn record(&mut self, cpu: CpuSnapshot, writes: Vec<(u16, u8)>, frames: Option<Vec<Frame>>) {
+        if let Some(frames) = frames {
+            let frames = (frames != self.frames).then_some(frames);
+            self.journal.push(Entry { cpu, writes, frames });
+        }
+    }
+
+    // Runs an interrupt sequence, tracking it as a call and journaling it.
+    fn interrupt<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B, f: impl FnOnce(&mut Cpu<V>, &mut JournalBus<B>)) {
+        let (pc, sp) = (cpu.pc, cpu.sp);
+        let before = cpu.snapshot();
+        let frames = self.journal.is_enabled().then(|| self.frames.clone());
+        let mut journal = JournalBus::new(bus);
+        f(cpu, &mut journal);
+        let writes = journal.writes;
+        if cpu.sp != sp {
+            self.frames.push(Frame { call_site: pc, entry: cpu.pc, sp });
+        }
+        self.record(before, writes, frames);
+    }
+
+    /// Signals an IRQ through the debugger so it can be stepped back over.
+    pub fn irq<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B) {
+        self.interrupt(cpu, bus, |cpu, bus| cpu.irq(bus));
+    }
+
+    /// Signals an NMI through the debugger so it can be stepped back over.
+    pub fn nmi<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B) {
+        self.interrupt(cpu, bus, |cpu, bus| cpu.nmi(bus));
+    }
+
+    /// Undoes the last journaled instruction or interrupt. Reports a write
+    /// watchpoint on a byte it restored, or `HistoryStart` if there was
+    /// nothing left to undo.
+    pub fn reverse_step<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B) -> Option<StopReason> {
+        let Some(entry) = self.journal.pop() else {
+            return Some(StopReason::HistoryStart);
+        };
+        entry.undo(cpu, bus);
+        if let Some(frames) = entry.frames {
+            self.frames = frames;
+        }
+        self.check_reverse_watchpoints(&entry.writes, cpu, bus)
+    }
+
+    /// Runs backwards until a breakpoint at PC or a write watchpoint
+    /// triggers, the journal runs out or `max_instructions` were undone.
+    /// Read watchpoints are not checked, and hit counts are left alone.
+    pub fn reverse_continue<V: Variant, B: Bus>(
+        &mut self,
+        cpu: &mut Cpu<V>,
+        bus: &mut B,
+        max_instructions: u64,
+    ) -> StopReason {
+        for _ in 0..max_instructions {
+            if let Some(reason) = self.reverse_step(cpu, bus) {
+                return reason;
+            }
+            let pc = cpu.pc;
+            let bp = self.breakpoints.iter().find(|(_, bp)| {
+                bp.enabled
+                    && bp.addr.is_none_or(|addr| addr == pc)
+                    && bp.condition.as_ref().is_none_or(|c| c.holds(cpu, bus))
+            });
+            if let Some(&(id, _)) = bp {
+                return StopReason::Breakpoint { id, pc };
+            }
+        }
+        StopReason::Limit
+    }
+
+    fn check_reverse_watchpoints<V>(&self, writes: &[(u16, u8)], cpu: &Cpu<V>, bus: &impl Bus) -> Option<StopReason> {
+        for &(addr, _) in writes {
+            for (id, wp) in &self.watchpoints {
+                if wp.enabled
+                    && wp.kind.matches(Access::Write)
+                    && wp.range.contains(&addr)
+                    && wp.condition.as_ref().is_none_or(|c| c.holds(cpu, bus))
+                {
+                    return Some(StopReason::Watchpoint { id: *id, addr, access: Access::Write, kind: wp.kind });
+                }
+            }
+        }
+        None
+    }
+
+    /// Runs until a breakpoint or watchpoint triggers, an illegal opcode is
+    /// reached or `max_instructions` have executed. The instruction at the
+    /// current PC always executes, so a stopped program can be resumed.
+    pub fn run_until_stop<V: Variant, B: Bus>(
+        &mut self,
+        cpu: &mut Cpu<V>,
+        bus: &mut B,
+        max_instructions: u64,
+    ) -> StopReason {
+        self.run_until(cpu, bus, max_instructions, |_| false)
+    }
+
+    /// Like `run_until_stop`, but also stops with `StopReason::Step` as soon
+    /// as `done` returns true after an instruction.
+    pub fn run_until<V: Variant, B: Bus>(
+        &mut self,
+        cpu: &mut Cpu<V>,
+        bus: &mut B,
+        max_instructions: u64,
+        mut done: impl FnMut(&Self) -> bool,
+    ) -> StopReason {
+        for _ in 0..max_instructions {
+            if let Some(reason) = self.step(cpu, bus) {
+                return reason;
+            }
+            if done(self) {
+                return StopReason::Step;
+            }
+            if let Some(reason) = self.check_pc(cpu, bus) {
+                return reason;
+            }
+        }
+        StopReason::Limit
+    }
+
+    /// Executes one instruction, running called subroutines to completion.
+    pub fn step_over<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B, max_instructions: u64) -> StopReason {
+        let depth = self.frames.len();
+        self.run_until(cpu, bus, max_instructions, |dbg| dbg.frames.len() <= depth)
+    }
+
+    /// Runs until the current subroutine returns.
+    pub fn step_out<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B, max_instructions: u64) -> StopReason {
+        let depth = self.frames.len();
+        self.run_until(cpu, bus, max_instructions, |dbg| dbg.frames.len() < depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmos6502::Nmos6502;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    // $0200: LDX #$00; loop: INX; STX $10; LDA $20; JMP loop
+    fn get_machine() -> (Cpu<Nmos6502>, MockBus) {
+        let mut bus = MockBus([0; 65536]);
+        let program = [0xA2, 0x00, 0xE8, 0x86, 0x10, 0xA5, 0x20, 0x4C, 0x02, 0x02];
+        bus.0[0x0200..0x0200 + program.len()].copy_from_slice(&program);
+        let mut cpu = Cpu::<Nmos6502>::new();
+        cpu.pc = 0x0200;
+        cpu.sp = 0xFF;
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut dbg = Debugger::new();
+        let id = dbg.add_breakpoint(0x0205);
+
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), StopReason::Breakpoint { id, pc: 0x0205 });
+        assert_eq!(cpu.reg.get_x(), 1);
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), StopReason::Breakpoint { id, pc: 0x0205 });
+        assert_eq!(cpu.reg.get_x(), 2);
+        assert_eq!(dbg.breakpoint(id).unwrap().hits, 2);
+
+        dbg.breakpoint_mut(id).unwrap().ignore_count = 4;
+        dbg.run_until_stop(&mut cpu, &mut bus, 100);
+        assert_eq!(cpu.reg.get_x(), 5);
+
+        assert!(dbg.remove(id));
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), StopReason::Limit);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut dbg = Debugger::new();
+        let id = dbg.add_conditional_breakpoint(0x0205, "X == 3 && [$10] == 3").unwrap();
+
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), StopReason::Breakpoint { id, pc: 0x0205 });
+        assert_eq!(cpu.reg.get_x(), 3);
+        assert_eq!(dbg.breakpoint(id).unwrap().hits, 1);
+
+        let id = dbg.add_stop_condition("x == $10").unwrap();
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), StopReason::Breakpoint { id, pc: 0x0203 });
+        assert!(dbg.add_stop_condition("x ==").is_err());
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut dbg = Debugger::new();
+        let write = dbg.add_watchpoint(0x10..=0x10, WatchKind::Write);
+        let read = dbg.add_watchpoint(0x20..=0x2F, WatchKind::Read);
+
+        let stop = StopReason::Watchpoint { id: write, addr: 0x10, access: Access::Write, kind: WatchKind::Write };
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), stop);
+        assert_eq!(cpu.pc, 0x0205);
+
+        let stop = StopReason::Watchpoint { id: read, addr: 0x20, access: Access::Read, kind: WatchKind::Read };
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), stop);
+        assert_eq!(cpu.pc, 0x0207);
+
+        dbg.clear();
+        let exec = dbg.add_watchpoint(0x0202..=0x0203, WatchKind::Execute);
+        let stop = StopReason::Watchpoint { id: exec, addr: 0x0202, access: Access::Execute, kind: WatchKind::Execute };
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), stop);
+    }
+
+    #[test]
+    fn test_fetches_are_not_data_reads() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut dbg = Debugger::new();
+        dbg.add_watchpoint(0x0200..=0x02FF, WatchKind::ReadWrite);
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 50), StopReason::Limit);
+    }
+
+    #[test]
+    fn test_conditional_watchpoint() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut dbg = Debugger::new();
+        let id = dbg.add_conditional_watchpoint(0x10..=0x10, WatchKind::Write, "[$10] >= 4").unwrap();
+        dbg.run_until_stop(&mut cpu, &mut bus, 100);
+        assert_eq!(bus.read(0x10), 4);
+        assert_eq!(dbg.watchpoint(id).unwrap().hits, 1);
+    }
+
+    // $0300: JSR $0310; NOP; BRK  $0310: JSR $0320; RTS  $0320: INX; RTS
+    fn get_call_machine() -> (Cpu<Nmos6502>, MockBus) {
+        let (mut cpu, mut bus) = get_machine();
+        bus.0[0x0300..0x0305].copy_from_slice(&[0x20, 0x10, 0x03, 0xEA, 0x00]);
+        bus.0[0x0310..0x0314].copy_from_slice(&[0x20, 0x20, 0x03, 0x60]);
+        bus.0[0x0320..0x0322].copy_from_slice(&[0xE8, 0x60]);
+        cpu.pc = 0x0300;
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let (mut cpu, mut bus) = get_call_machine();
+        let mut dbg = Debugger::new();
+        dbg.step(&mut cpu, &mut bus);
+        dbg.step(&mut cpu, &mut bus);
+        let frames = [
+            Frame { call_site: 0x0300, entry: 0x0310, sp: 0xFF },
+            Frame { call_site: 0x0310, entry: 0x0320, sp: 0xFD },
+        ];
+        assert_eq!(dbg.call_stack(), &frames);
+        dbg.step(&mut cpu, &mut bus);
+        dbg.step(&mut cpu, &mut bus);
+        assert_eq!(dbg.call_stack(), &frames[..1]);
+        dbg.step(&mut cpu, &mut bus);
+        assert!(dbg.call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_call_stack_wraps() {
+        let (mut cpu, mut bus) = get_call_machine();
+        cpu.sp = 0x01;
+        let mut dbg = Debugger::new();
+        dbg.step(&mut cpu, &mut bus);
+        dbg.step(&mut cpu, &mut bus);
+        assert_eq!(cpu.sp, 0xFD);
+        assert_eq!(dbg.call_stack().len(), 2);
+        assert_eq!(dbg.step_out(&mut cpu, &mut bus, 100), StopReason::Step);
+        assert_eq!((cpu.pc, dbg.call_stack().len()), (0x0313, 1));
+        assert_eq!(dbg.step_out(&mut cpu, &mut bus, 100), StopReason::Step);
+        assert_eq!((cpu.pc, cpu.sp), (0x0303, 0x01));
+    }
+
+    #[test]
+    fn test_single_opcode_fetch() {
+        struct CountingBus(MockBus, core::cell::Cell<usize>);
+        impl Bus for CountingBus {
+            fn read(&self, addr: u16) -> u8 {
+                self.1.set(self.1.get() + (addr == 0x0200) as usize);
+                self.0.read(addr)
+            }
+            fn write(&mut self, addr: u16, value: u8) {
+                self.0.write(addr, value)
+            }
+            fn peek(&self, addr: u16) -> u8 {
+                self.0.read(addr)
+            }
+        }
+        let (mut cpu, bus) = get_machine();
+        let mut bus = CountingBus(bus, Default::default());
+        Debugger::new().step(&mut cpu, &mut bus);
+        assert_eq!(bus.1.get(), 1);
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let (mut cpu, mut bus) = get_call_machine();
+        let mut dbg = Debugger::new();
+        assert_eq!(dbg.step_over(&mut cpu, &mut bus, 100), StopReason::Step);
+        assert_eq!((cpu.pc, cpu.reg.get_x()), (0x0303, 1));
+
+        let (mut cpu, mut bus) = get_call_machine();
+        dbg.step(&mut cpu, &mut bus);
+        dbg.step(&mut cpu, &mut bus);
+        assert_eq!(dbg.step_out(&mut cpu, &mut bus, 100), StopReason::Step);
+        assert_eq!(cpu.pc, 0x0313);
+        assert_eq!(dbg.step_out(&mut cpu, &mut bus, 100), StopReason::Step);
+        assert_eq!(cpu.pc, 0x0303);
+
+        let (mut cpu, mut bus) = get_call_machine();
+        let id = dbg.add_breakpoint(0x0320);
+        assert_eq!(dbg.step_over(&mut cpu, &mut bus, 100), StopReason::Breakpoint { id, pc: 0x0320 });
+    }
+
+    fn journaled(limit: usize) -> (Cpu<Nmos6502>, MockBus, Debugger) {
+        let (cpu, bus) = get_machine();
+        let mut dbg = Debugger::new();
+        dbg.set_journal_limit(limit);
+        (cpu, bus, dbg)
+    }
+
+    #[test]
+    fn test_reverse_step() {
+        let (mut cpu, mut bus, mut dbg) = journaled(100);
+        let mut states = Vec::new();
+        for _ in 0..20 {
+            states.push((cpu.snapshot(), bus.0));
+            dbg.step(&mut cpu, &mut bus);
+        }
+        while let Some((snapshot, mem)) = states.pop() {
+            assert_eq!(dbg.reverse_step(&mut cpu, &mut bus), None);
+            assert_eq!(cpu.snapshot(), snapshot);
+            assert!(bus.0 == mem);
+        }
+        assert_eq!(dbg.reverse_step(&mut cpu, &mut bus), Some(StopReason::HistoryStart));
+
+        // Only the last `limit` instructions can be undone.
+        let (mut cpu, mut bus, mut dbg) = journaled(3);
+        dbg.run_until_stop(&mut cpu, &mut bus, 10);
+        assert_eq!(dbg.journal().len(), 3);
+        assert_eq!(dbg.reverse_continue(&mut cpu, &mut bus, 100), StopReason::HistoryStart);
+        assert_eq!(cpu.pc, 0x0205);
+    }
+
+    #[test]
+    fn test_reverse_continue() {
+        let (mut cpu, mut bus, mut dbg) = journaled(100);
+        dbg.run_until_stop(&mut cpu, &mut bus, 20);
+        let id = dbg.add_conditional_breakpoint(0x0203, "X == 3").unwrap();
+        assert_eq!(dbg.reverse_continue(&mut cpu, &mut bus, 100), StopReason::Breakpoint { id, pc: 0x0203 });
+        assert_eq!((cpu.reg.get_x(), bus.read(0x10)), (3, 2));
+        assert_eq!(dbg.breakpoint(id).unwrap().hits, 0);
+
+        // Stops having undone the write, before the store.
+        dbg.clear();
+        let id = dbg.add_watchpoint(0x10..=0x10, WatchKind::Write);
+        let stop = StopReason::Watchpoint { id, addr: 0x10, access: Access::Write, kind: WatchKind::Write };
+        assert_eq!(dbg.reverse_continue(&mut cpu, &mut bus, 100), stop);
+        assert_eq!((cpu.pc, bus.read(0x10)), (0x0203, 1));
+    }
+
+    #[test]
+    fn test_reverse_across_interrupt() {
+        let (mut cpu, mut bus) = get_call_machine();
+        let mut dbg = Debugger::new();
+        dbg.set_journal_limit(100);
+        // IRQ handler at $0400: INX; RTI
+        bus.0[0x0400..0x0402].copy_from_slice(&[0xE8, 0x40]);
+        bus.0[0xFFFE..].copy_from_slice(&[0x00, 0x04]);
+
+        dbg.step(&mut cpu, &mut bus);
+        let (before, mem) = (cpu.snapshot(), bus.0);
+        dbg.irq(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x0400);
+        assert_eq!(dbg.call_stack().len(), 2);
+        dbg.step(&mut cpu, &mut bus);
+        dbg.step(&mut cpu, &mut bus);
+        assert_eq!((cpu.pc, dbg.call_stack().len()), (0x0310, 1));
+
+        for _ in 0..3 {
+            assert_eq!(dbg.reverse_step(&mut cpu, &mut bus), None);
+        }
+        assert_eq!(cpu.snapshot(), before);
+        assert!(bus.0 == mem);
+        assert_eq!(dbg.call_stack(), &[Frame { call_site: 0x0300, entry: 0x0310, sp: 0xFF }]);
+    }
+
+    #[test]
+    fn test_illegal_opcode() {
+        let (mut cpu, mut bus) = get_machine();
+        bus.write(0x0205, 0x02);
+        let mut dbg = Debugger::new();
+        let stop = StopReason::IllegalOpcode { pc: 0x0205, opcode: 0x02 };
+        assert_eq!(dbg.run_until_stop(&mut cpu, &mut bus, 100), stop);
+        assert_eq!(cpu.pc, 0x0205);
+    }
+}
@@ -0,0 +1,343 @@
+use core::fmt;
+
+use crate::{Bus, Cpu};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A, X, Y, SP, PC, P,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    C, Z, I, D, V, N,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not, Neg, Complement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or, And,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    BitOr, BitXor, BitAnd,
+    Add, Sub,
+}
+
+/// A parsed stop condition such as `A == $FF && [$D012] > 100`.
+///
+/// Values are evaluated as `i64`; a condition holds when it evaluates to a
+/// non-zero value. `[addr]` reads a byte from the bus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    Flag(Flag),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub pos: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.pos)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Expr, ParseError> {
+        let mut parser = Parser { src: src.as_bytes(), pos: 0 };
+        let expr = parser.parse_binary(0)?;
+        parser.skip_whitespace();
+        if parser.pos != parser.src.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval<V>(&self, cpu: &Cpu<V>, bus: &impl Bus) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Register(r) => match r {
+                Register::A => cpu.reg.get_a() as i64,
+                Register::X => cpu.reg.get_x() as i64,
+                Register::Y => cpu.reg.get_y() as i64,
+                Register::SP => cpu.sp as i64,
+                Register::PC => cpu.pc as i64,
+                Register::P => cpu.reg.get_status(false) as i64,
+            },
+            Expr::Flag(f) => match f {
+                Flag::C => cpu.reg.c as i64,
+                Flag::Z => cpu.reg.z as i64,
+                Flag::I => cpu.reg.i as i64,
+                Flag::D => cpu.reg.d as i64,
+                Flag::V => cpu.reg.v as i64,
+                Flag::N => cpu.reg.n as i64,
+            },
+            Expr::Memory(addr) => bus.peek(addr.eval(cpu, bus) as u16) as i64,
+            Expr::Unary(op, e) => {
+                let v = e.eval(cpu, bus);
+                match op {
+                    UnaryOp::Not => (v == 0) as i64,
+                    UnaryOp::Neg => v.wrapping_neg(),
+                    UnaryOp::Complement => !v,
+                }
+            }
+            Expr::Binary(BinaryOp::Or, l, r) => {
+                (l.eval(cpu, bus) != 0 || r.eval(cpu, bus) != 0) as i64
+            }
+            Expr::Binary(BinaryOp::And, l, r) => {
+                (l.eval(cpu, bus) != 0 && r.eval(cpu, bus) != 0) as i64
+            }
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.eval(cpu, bus), r.eval(cpu, bus));
+                match op {
+                    BinaryOp::Eq => (l == r) as i64,
+                    BinaryOp::Ne => (l != r) as i64,
+                    BinaryOp::Lt => (l < r) as i64,
+                    BinaryOp::Le => (l <= r) as i64,
+                    BinaryOp::Gt => (l > r) as i64,
+                    BinaryOp::Ge => (l >= r) as i64,
+                    BinaryOp::BitOr => l | r,
+                    BinaryOp::BitXor => l ^ r,
+                    BinaryOp::BitAnd => l & r,
+                    BinaryOp::Add => l.wrapping_add(r),
+                    BinaryOp::Sub => l.wrapping_sub(r),
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn holds<V>(&self, cpu: &Cpu<V>, bus: &impl Bus) -> bool {
+        self.eval(cpu, bus) != 0
+    }
+}
+
+// Binary operators by precedence level, loosest first.
+const LEVELS: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne), ("<=", BinaryOp::Le),
+      (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+struct Parser<'s> {
+    src: &'s [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError { pos: self.pos, message: message.to_string() }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.src.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.src.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let rest = &self.src[self.pos..];
+        if !rest.starts_with(token.as_bytes()) {
+            return false;
+        }
+        // Don't split `&&` into `&` `&`, or `||` into `|` `|`.
+        if token.len() == 1 && rest.get(1) == Some(&token.as_bytes()[0]) && b"&|".contains(&rest[0]) {
+            return false;
+        }
+        self.pos += token.len();
+        true
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, ParseError> {
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+        let mut lhs = self.parse_binary(level + 1)?;
+        'outer: loop {
+            for (token, op) in LEVELS[level] {
+                if self.eat(token) {
+                    let rhs = self.parse_binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let op = if self.peek() == Some(b'!') && self.src.get(self.pos + 1) != Some(&b'=') {
+            Some(UnaryOp::Not)
+        } else if self.peek() == Some(b'-') {
+            Some(UnaryOp::Neg)
+        } else if self.peek() == Some(b'~') {
+            Some(UnaryOp::Complement)
+        } else {
+            None
+        };
+        match op {
+            Some(op) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            None => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let e = self.parse_binary(0)?;
+                if !self.eat(")") {
+                    return Err(self.error("expected `)`"));
+                }
+                Ok(e)
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let e = self.parse_binary(0)?;
+                if !self.eat("]") {
+                    return Err(self.error("expected `]`"));
+                }
+                Ok(Expr::Memory(Box::new(e)))
+            }
+            Some(b'$') => {
+                self.pos += 1;
+                self.parse_number(16)
+            }
+            Some(b'%') => {
+                self.pos += 1;
+                self.parse_number(2)
+            }
+            Some(b'0') if matches!(self.src.get(self.pos + 1), Some(b'x' | b'X')) => {
+                self.pos += 2;
+                self.parse_number(16)
+            }
+            Some(c) if c.is_ascii_digit() => self.parse_number(10),
+            Some(c) if c.is_ascii_alphabetic() => self.parse_name(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+
+    fn parse_number(&mut self, radix: u32) -> Result<Expr, ParseError> {
+        let start = self.pos;
+        while self.src.get(self.pos).is_some_and(|c| (*c as char).is_digit(radix)) {
+            self.pos += 1;
+        }
+        let digits = core::str::from_utf8(&self.src[start..self.pos]).unwrap();
+        i64::from_str_radix(digits, radix)
+            .map(Expr::Number)
+            .map_err(|_| ParseError { pos: start, message: "invalid number".to_string() })
+    }
+
+    fn parse_name(&mut self) -> Result<Expr, ParseError> {
+        let start = self.pos;
+        while self.src.get(self.pos).is_some_and(|c| c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        let name = core::str::from_utf8(&self.src[start..self.pos]).unwrap().to_ascii_uppercase();
+        let expr = match name.as_str() {
+            "A" => Expr::Register(Register::A),
+            "X" => Expr::Register(Register::X),
+            "Y" => Expr::Register(Register::Y),
+            "S" | "SP" => Expr::Register(Register::SP),
+            "PC" => Expr::Register(Register::PC),
+            "P" => Expr::Register(Register::P),
+            "C" => Expr::Flag(Flag::C),
+            "Z" => Expr::Flag(Flag::Z),
+            "I" => Expr::Flag(Flag::I),
+            "D" => Expr::Flag(Flag::D),
+            "V" => Expr::Flag(Flag::V),
+            "N" => Expr::Flag(Flag::N),
+            _ => return Err(ParseError { pos: start, message: format!("unknown name `{}`", name) }),
+        };
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmos6502::Nmos6502;
+
+    // Reads have side effects on real hardware, so expressions only peek.
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { panic!("read ${:04X}", addr) }
+        fn peek(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    fn eval(src: &str, cpu: &Cpu<Nmos6502>, bus: &MockBus) -> i64 {
+        Expr::parse(src).unwrap().eval(cpu, bus)
+    }
+
+    #[test]
+    fn test_numbers() {
+        let cpu = Cpu::<Nmos6502>::new();
+        let bus = MockBus([0; 65536]);
+        assert_eq!(eval("$FF", &cpu, &bus), 0xFF);
+        assert_eq!(eval("0x10", &cpu, &bus), 0x10);
+        assert_eq!(eval("%101", &cpu, &bus), 5);
+        assert_eq!(eval("100", &cpu, &bus), 100);
+        assert_eq!(eval("-1", &cpu, &bus), -1);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let cpu = Cpu::<Nmos6502>::new();
+        let bus = MockBus([0; 65536]);
+        assert_eq!(eval("1 + 2 == 3", &cpu, &bus), 1);
+        assert_eq!(eval("1 | 2 & 0", &cpu, &bus), 1);
+        assert_eq!(eval("(1 | 2) & 2", &cpu, &bus), 2);
+        assert_eq!(eval("0 || 1 && 0", &cpu, &bus), 0);
+        assert_eq!(eval("!0 && 2 != 3", &cpu, &bus), 1);
+    }
+
+    #[test]
+    fn test_registers_flags_memory() {
+        let mut cpu = Cpu::<Nmos6502>::new();
+        let mut bus = MockBus([0; 65536]);
+        cpu.reg.update_a(0xFF);
+        cpu.reg.c = true;
+        cpu.pc = 0x1234;
+        bus.write(0xD012, 101);
+        assert!(Expr::parse("A == $FF && [$D012] > 100").unwrap().holds(&cpu, &bus));
+        assert!(Expr::parse("a == $ff && c && pc == $1234").unwrap().holds(&cpu, &bus));
+        assert!(!Expr::parse("[$D000 + $12] <= 100").unwrap().holds(&cpu, &bus));
+        assert_eq!(eval("N", &cpu, &bus), 1);
+        assert_eq!(eval("P & $80", &cpu, &bus), 0x80);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Expr::parse("A ==").unwrap_err().pos, 4);
+        assert_eq!(Expr::parse("Q").unwrap_err().pos, 0);
+        assert_eq!(Expr::parse("[1").unwrap_err().message, "expected `]`");
+        assert_eq!(Expr::parse("1 2").unwrap_err().pos, 2);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+
+use super::Frame;
+use crate::snapshot::CpuSnapshot;
+use crate::{Bus, Cpu, Variant};
+
+/// Wraps a bus and remembers the previous value of every byte written
+/// through it, in write order.
+pub struct JournalBus<'b, B> {
+    pub bus: &'b mut B,
+    pub writes: Vec<(u16, u8)>,
+}
+
+impl<'b, B: Bus> JournalBus<'b, B> {
+    pub fn new(bus: &'b mut B) -> Self {
+        JournalBus { bus, writes: Vec::new() }
+    }
+}
+
+impl<B: Bus> Bus for JournalBus<'_, B> {
+    fn read(&self, addr: u16) -> u8 {
+        self.bus.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.writes.push((addr, self.bus.peek(addr)));
+        self.bus.write(addr, value)
+    }
+
+    fn dummy_read(&self, addr: u16) {
+        self.bus.dummy_read(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+}
+
+/// The state needed to undo one instruction or interrupt.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub cpu: CpuSnapshot,
+    /// Overwritten bytes as (address, old value), in write order.
+    pub writes: Vec<(u16, u8)>,
+    /// The call stack from before, if it changed.
+    pub frames: Option<Vec<Frame>>,
+}
+
+impl Entry {
+    /// Puts the CPU and the written bytes back as they were. Bytes are
+    /// restored with `Bus::write`, so I/O registers see the writes again.
+    pub fn undo<V: Variant>(&self, cpu: &mut Cpu<V>, bus: &mut impl Bus) {
+        for &(addr, old) in self.writes.iter().rev() {
+            bus.write(addr, old);
+        }
+        cpu.restore(&self.cpu).expect("journal snapshots use the current version");
+    }
+}
+
+/// An undo log of the most recent `limit` instructions. A limit of zero
+/// disables it.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    limit: usize,
+}
+
+impl Journal {
+    pub fn new(limit: usize) -> Self {
+        Journal { entries: VecDeque::new(), limit }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        if self.is_enabled() {
+            self.entries.push_back(entry);
+            self.trim();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+
+    fn trim(&mut self) {
+        while self.entries.len() > self.limit {
+            self.entries.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmos6502::Nmos6502;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut bus = MockBus([0; 65536]);
+        // INC $10; INC $10
+        bus.0[0x0200..0x0204].copy_from_slice(&[0xE6, 0x10, 0xE6, 0x10]);
+        bus.0[0x10] = 0x41;
+        let mut cpu = Cpu::<Nmos6502>::new();
+        cpu.pc = 0x0200;
+        let before = cpu.snapshot();
+
+        let mut journal = Journal::new(1);
+        for _ in 0..2 {
+            let cpu_before = cpu.snapshot();
+            let mut journal_bus = JournalBus::new(&mut bus);
+            cpu.step(&mut journal_bus);
+            // The NMOS read-modify-write cycle writes twice.
+            assert_eq!(journal_bus.writes.len(), 2);
+            let writes = journal_bus.writes;
+            journal.push(Entry { cpu: cpu_before, writes, frames: None });
+        }
+        assert_eq!(bus.0[0x10], 0x43);
+        assert_eq!(journal.len(), 1);
+
+        journal.pop().unwrap().undo(&mut cpu, &mut bus);
+        assert_eq!(bus.0[0x10], 0x42);
+        assert_eq!(cpu.pc, 0x0202);
+        assert!(journal.pop().is_none());
+        assert_ne!(cpu.snapshot(), before);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut journal = Journal::default();
+        let cpu = Cpu::<Nmos6502>::new();
+        journal.push(Entry { cpu: cpu.snapshot(), writes: Vec::new(), frames: None });
+        assert!(journal.is_empty());
+    }
+}
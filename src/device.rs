@@ -0,0 +1,167 @@
+use core::cell::RefCell;
+use std::rc::Rc;
+
+use crate::machine::Machine;
+use crate::savestate::SaveState;
+use crate::{Bus, Variant};
+
+pub mod acia;
+pub mod cia;
+pub mod hd44780;
+pub mod pia;
+pub mod riot;
+pub mod rriot;
+pub mod serial;
+pub mod sid;
+pub mod via;
+
+/// A memory mapped peripheral. Addresses are offsets into the region the
+/// device is mapped at.
+pub trait Device {
+    /// Reads a register without side effects, for debuggers.
+    fn peek(&self, offset: u16) -> u8;
+
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8);
+
+    /// Advances the device by `cycles` CPU clock cycles.
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Whether the device is pulling the IRQ line low.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Whether the device is pulling the NMI line low.
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// The device's state, which `MemoryMap` saves with the memory it maps.
+    /// Devices that have one return `Some(self)`.
+    fn state(&self) -> Option<&dyn SaveState> {
+        None
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        None
+    }
+}
+
+/// Clocks a set of devices along with a machine and wires their interrupt
+/// outputs to its IRQ and NMI lines, which it then owns.
+///
+/// Devices are ticked after each instruction by the cycles it took, so
+/// they run up to one instruction behind the CPU.
+#[derive(Default)]
+pub struct Scheduler {
+    devices: Vec<Rc<RefCell<dyn Device>>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, device: Rc<RefCell<dyn Device>>) {
+        self.devices.push(device);
+    }
+
+    /// Advances every device and updates the interrupt lines.
+    pub fn tick<V: Variant, B: Bus>(&mut self, machine: &mut Machine<V, B>, cycles: u64) {
+        let (mut irq, mut nmi) = (false, false);
+        for device in &self.devices {
+            let mut device = device.borrow_mut();
+            device.tick(cycles);
+            irq |= device.irq();
+            nmi |= device.nmi();
+        }
+        machine.set_irq_line(irq);
+        machine.set_nmi_line(nmi);
+    }
+
+    /// Executes one instruction (taking a pending interrupt first) and
+    /// clocks the devices for the cycles it took.
+    pub fn step<V: Variant, B: Bus>(&mut self, machine: &mut Machine<V, B>) {
+        let start = machine.cpu.cycles;
+        machine.step();
+        self.tick(machine, machine.cpu.cycles - start);
+    }
+
+    /// Steps until at least `cycles` have elapsed, returning the number
+    /// that did.
+    pub fn run<V: Variant, B: Bus>(&mut self, machine: &mut Machine<V, B>, cycles: u64) -> u64 {
+        let start = machine.cpu.cycles;
+        while machine.cpu.cycles - start < cycles {
+            self.step(machine);
+        }
+        machine.cpu.cycles - start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_map::MemoryMap;
+    use crate::nmos6502::Nmos6502;
+
+    // Raises IRQ every `period` cycles until any register is read.
+    struct Timer {
+        period: u64,
+        count: u64,
+        irq: bool,
+    }
+
+    impl Device for Timer {
+        fn peek(&self, _: u16) -> u8 {
+            self.irq as u8
+        }
+
+        fn read(&mut self, _: u16) -> u8 {
+            let value = self.peek(0);
+            self.irq = false;
+            value
+        }
+
+        fn write(&mut self, _: u16, _: u8) {}
+
+        fn tick(&mut self, cycles: u64) {
+            self.count += cycles;
+            if self.count >= self.period {
+                self.count -= self.period;
+                self.irq = true;
+            }
+        }
+
+        fn irq(&self) -> bool {
+            self.irq
+        }
+    }
+
+    #[test]
+    fn test_timer_interrupts() {
+        let mut map = MemoryMap::new();
+        let ram = map.add_ram(0x0000..=0xFFFF, 0xFFFF);
+        let timer = Rc::new(RefCell::new(Timer { period: 1000, count: 0, irq: false }));
+        map.add_device(0xD000..=0xD000, 0, timer.clone());
+        let memory = map.memory_mut(ram).unwrap();
+        // $0200: CLI; loop: JMP loop  IRQ: LDA $D000; INC $10; RTI
+        memory[0x0200..0x0204].copy_from_slice(&[0x58, 0x4C, 0x01, 0x02]);
+        memory[0x0300..0x0306].copy_from_slice(&[0xAD, 0x00, 0xD0, 0xE6, 0x10, 0x40]);
+        memory[0xFFFE..].copy_from_slice(&[0x00, 0x03]);
+
+        let mut machine = Machine::<Nmos6502, _>::new(map);
+        machine.cpu.pc = 0x0200;
+        machine.cpu.sp = 0xFF;
+        let mut scheduler = Scheduler::new();
+        scheduler.add(timer.clone());
+
+        let elapsed = scheduler.run(&mut machine, 10_500);
+        assert!((10_500..10_510).contains(&elapsed));
+        assert_eq!(machine.bus.peek(0x10), 10);
+        assert!(!machine.irq_line());
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::VecDeque;
+use std::io;
+
+use super::serial::Port;
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+const PARITY: u8 = 0x01;
+const FRAMING: u8 = 0x02;
+const OVERRUN: u8 = 0x04;
+const RDRF: u8 = 0x08;
+const TDRE: u8 = 0x10;
+const IRQ: u8 = 0x80;
+
+// Baud rate divisors of the 1.8432 MHz crystal's 115200 Hz 16x clock, by
+// the low nibble of the control register. 0 selects the external receiver
+// clock, which is taken to be the crystal too.
+const DIVISORS: [u64; 16] = [1, 2304, 1536, 1048, 856, 768, 384, 192, 96, 64, 48, 32, 24, 16, 12, 6];
+const BASE_RATE: u64 = 115_200;
+
+#[derive(Debug, Clone, Default)]
+struct Shifter {
+    data: u8,
+    // Cycles until the last stop bit, or 0 when idle.
+    remaining: u64,
+}
+
+/// A MOS 6551 Asynchronous Communications Interface Adapter with a
+/// 1.8432 MHz crystal. It occupies 4 registers: data, status (writing it
+/// is a programmed reset), command and control.
+///
+/// Characters take as long as the baud rate and frame format say. The host
+/// end of the line queues bytes with `receive` and collects transmitted
+/// ones with `take_transmitted`, or connects a `Port` with `pump`. DCD and
+/// DSR are always asserted and CTS is ignored. Save states hold the chip
+/// but not those host-side queues.
+#[derive(Debug, Clone)]
+pub struct Acia {
+    clock: u64,
+    wdc_bug: bool,
+    status: u8,
+    command: u8,
+    control: u8,
+    tdr: u8,
+    rdr: u8,
+    tx: Shifter,
+    rx: Shifter,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Acia {
+    /// An ACIA in its power-on state, for a CPU running at `clock` Hz.
+    pub fn new(clock: u64) -> Self {
+        Acia {
+            clock,
+            wdc_bug: false,
+            status: TDRE,
+            command: 0x02,
+            control: 0,
+            tdr: 0,
+            rdr: 0,
+            tx: Shifter::default(),
+            rx: Shifter::default(),
+            input: VecDeque::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Emulates the W65C51N, whose TDRE status bit is stuck at 1: a byte
+    /// written to the data register goes straight to the transmitter,
+    /// replacing any character still being sent, and no transmit
+    /// interrupts are raised. Software has to wait out a character time
+    /// between writes.
+    pub fn set_wdc_bug(&mut self, enabled: bool) {
+        self.wdc_bug = enabled;
+    }
+
+    /// Queues a byte arriving on the receive line.
+    pub fn receive(&mut self, byte: u8) {
+        self.input.push_back(byte);
+    }
+
+    /// Bytes received but not yet assembled by the receiver.
+    pub fn pending(&self) -> usize {
+        self.input.len()
+    }
+
+    /// Takes the bytes that have finished transmitting.
+    pub fn take_transmitted(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Passes transmitted bytes to `port` and, once the receiver has
+    /// caught up, feeds it the next byte from the host.
+    pub fn pump(&mut self, port: &mut dyn Port) -> io::Result<()> {
+        if !self.output.is_empty() {
+            port.send(&self.take_transmitted())?;
+        }
+        if self.input.is_empty() {
+            if let Some(byte) = port.poll()? {
+                self.receive(byte);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether /DTR is asserted, which enables the receiver and interrupts.
+    pub fn dtr(&self) -> bool {
+        self.command & 0x01 != 0
+    }
+
+    /// Whether /RTS is asserted.
+    pub fn rts(&self) -> bool {
+        self.command & 0x0C != 0
+    }
+
+    fn tx_interrupts(&self) -> bool {
+        self.command & 0x0C == 0x04 && !self.wdc_bug
+    }
+
+    fn rx_interrupts(&self) -> bool {
+        self.command & 0x02 == 0
+    }
+
+    fn echo(&self) -> bool {
+        self.command & 0x1C == 0x10
+    }
+
+    fn data_bits(&self) -> u64 {
+        8 - (self.control as u64 >> 5 & 3)
+    }
+
+    fn parity(&self) -> bool {
+        self.command & 0x20 != 0
+    }
+
+    fn mask(&self) -> u8 {
+        (0xFF >> (8 - self.data_bits())) as u8
+    }
+
+    // The length of a character in CPU cycles: a start bit, the data bits,
+    // the parity bit and 1, 1.5 or 2 stop bits, counted in half bits.
+    fn frame_cycles(&self) -> u64 {
+        let stop = match (self.control & 0x80 != 0, self.data_bits(), self.parity()) {
+            (false, _, _) => 2,
+            (true, 5, false) => 3,
+            (true, 8, true) => 2,
+            (true, _, _) => 4,
+        };
+        let half_bits = 2 + 2 * self.data_bits() + 2 * self.parity() as u64 + stop;
+        let divisor = DIVISORS[self.control as usize & 0x0F];
+        (self.clock * divisor * half_bits).div_ceil(2 * BASE_RATE).max(1)
+    }
+
+    fn interrupt(&mut self) {
+        self.status |= IRQ;
+    }
+
+    fn start_transmit(&mut self, data: u8) {
+        self.tx = Shifter { data, remaining: self.frame_cycles() };
+        self.status |= TDRE;
+        if self.tx_interrupts() {
+            self.interrupt();
+        }
+    }
+
+    // Shifts out characters for `cycles`, loading the next one from the
+    // data register whenever the shifter goes idle.
+    fn tick_tx(&mut self, mut cycles: u64) {
+        loop {
+            if self.tx.remaining == 0 {
+                if self.status & TDRE != 0 {
+                    return;
+                }
+                self.start_transmit(self.tdr);
+            }
+            let elapsed = cycles.min(self.tx.remaining);
+            self.tx.remaining -= elapsed;
+            cycles -= elapsed;
+            if self.tx.remaining > 0 {
+                return;
+            }
+            self.output.push(self.tx.data & self.mask());
+        }
+    }
+
+    fn tick_rx(&mut self, mut cycles: u64) {
+        loop {
+            if self.rx.remaining == 0 {
+                if !self.dtr() {
+                    return;
+                }
+                let Some(data) = self.input.pop_front() else { return };
+                self.rx = Shifter { data, remaining: self.frame_cycles() };
+            }
+            let elapsed = cycles.min(self.rx.remaining);
+            self.rx.remaining -= elapsed;
+            cycles -= elapsed;
+            if self.rx.remaining > 0 {
+                return;
+            }
+            let data = self.rx.data & self.mask();
+            if self.status & RDRF != 0 {
+                self.status |= OVERRUN;
+            } else {
+                self.rdr = data;
+                self.status |= RDRF;
+            }
+            if self.echo() {
+                self.output.push(data);
+            }
+            if self.rx_interrupts() {
+                self.interrupt();
+            }
+        }
+    }
+}
+
+impl Device for Acia {
+    fn peek(&self, offset: u16) -> u8 {
+        match offset & 3 {
+            0 => self.rdr,
+            1 if self.wdc_bug => self.status | TDRE,
+            1 => self.status,
+            2 => self.command,
+            _ => self.control,
+        }
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        let value = self.peek(offset);
+        match offset & 3 {
+            0 => self.status &= !(RDRF | OVERRUN | FRAMING | PARITY),
+            1 => self.status &= !IRQ,
+            _ => {}
+        }
+        value
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset & 3 {
+            0 if self.wdc_bug => self.start_transmit(value),
+            0 => {
+                self.tdr = value;
+                self.status &= !TDRE;
+            }
+            1 => {
+                self.command &= 0xE0;
+                self.status &= !OVERRUN;
+            }
+            2 => {
+                self.command = value;
+                if self.tx_interrupts() && self.status & TDRE != 0 {
+                    self.interrupt();
+                }
+            }
+            _ => self.control = value,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.tick_tx(cycles);
+        self.tick_rx(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.status & IRQ != 0 && self.dtr()
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Acia {
+    fn save(&self, out: &mut StateWriter) {
+        for value in [self.status, self.command, self.control, self.tdr, self.rdr, self.tx.data, self.rx.data] {
+            out.u8(value);
+        }
+        out.u64(self.tx.remaining);
+        out.u64(self.rx.remaining);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("acia", version, 1)?;
+        for value in [
+            &mut self.status, &mut self.command, &mut self.control, &mut self.tdr, &mut self.rdr,
+            &mut self.tx.data, &mut self.rx.data,
+        ] {
+            *value = input.u8()?;
+        }
+        self.tx.remaining = input.u64()?;
+        self.rx.remaining = input.u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 MHz, 9600 baud, 8N1: 10 bits of 12 * 1_000_000 / 115_200 cycles.
+    const FRAME: u64 = 1042;
+
+    fn acia() -> Acia {
+        let mut acia = Acia::new(1_000_000);
+        acia.write(3, 0x1E);
+        acia.write(2, 0x0B);
+        acia
+    }
+
+    #[test]
+    fn test_transmit() {
+        let mut acia = acia();
+        assert_eq!(acia.frame_cycles(), FRAME);
+        acia.write(0, b'A');
+        assert_eq!(acia.read(1) & TDRE, 0);
+        // The first byte moves to the shifter, freeing the data register.
+        acia.tick(1);
+        assert_ne!(acia.read(1) & TDRE, 0);
+        acia.write(0, b'B');
+        acia.tick(FRAME - 2);
+        assert!(acia.take_transmitted().is_empty());
+        acia.tick(1);
+        assert_eq!(acia.take_transmitted(), b"A");
+        assert_ne!(acia.read(1) & TDRE, 0);
+        acia.tick(FRAME);
+        assert_eq!(acia.take_transmitted(), b"B");
+        assert!(!acia.irq());
+
+        // 7 data bits, even parity, 2 stop bits.
+        acia.write(3, 0xBE);
+        acia.write(2, 0x6B);
+        assert_eq!(acia.frame_cycles(), FRAME + 104);
+        acia.write(0, 0xC1);
+        acia.tick(FRAME + 104);
+        assert_eq!(acia.take_transmitted(), [0x41]);
+    }
+
+    #[test]
+    fn test_receive() {
+        let mut acia = acia();
+        acia.receive(b'x');
+        acia.receive(b'y');
+        acia.receive(b'z');
+        acia.tick(FRAME - 1);
+        assert_eq!(acia.read(1) & RDRF, 0);
+        acia.tick(1);
+        assert_ne!(acia.read(1) & RDRF, 0);
+        assert_eq!(acia.read(0), b'x');
+        assert_eq!(acia.read(1) & RDRF, 0);
+
+        // The third byte arrives before the second is read and is lost.
+        acia.tick(2 * FRAME);
+        assert_eq!(acia.read(1) & (RDRF | OVERRUN), RDRF | OVERRUN);
+        assert_eq!(acia.read(0), b'y');
+        assert_eq!(acia.read(1), TDRE);
+        assert_eq!(acia.pending(), 0);
+
+        // Nothing is received while /DTR is high.
+        acia.write(2, 0x0A);
+        acia.receive(b'w');
+        acia.tick(2 * FRAME);
+        assert_eq!(acia.pending(), 1);
+    }
+
+    #[test]
+    fn test_interrupts() {
+        let mut acia = acia();
+        // Receive interrupts enabled.
+        acia.write(2, 0x09);
+        acia.receive(0x55);
+        acia.tick(FRAME);
+        assert!(acia.irq());
+        assert_eq!(acia.read(1), IRQ | TDRE | RDRF);
+        assert!(!acia.irq());
+        assert_eq!(acia.read(0), 0x55);
+
+        // Transmit interrupts fire when the data register empties.
+        acia.write(2, 0x07);
+        assert!(acia.irq());
+        acia.read(1);
+        acia.write(0, 0x00);
+        assert!(!acia.irq());
+        acia.tick(1);
+        assert!(acia.irq());
+
+        // A programmed reset disables them.
+        acia.read(1);
+        acia.write(1, 0);
+        assert_eq!(acia.peek(2), 0x00);
+        acia.write(0, 0x00);
+        acia.tick(1);
+        assert!(!acia.irq());
+    }
+
+    #[test]
+    fn test_wdc_bug() {
+        let mut acia = acia();
+        acia.set_wdc_bug(true);
+        acia.write(2, 0x07);
+        acia.write(0, b'A');
+        assert_ne!(acia.read(1) & TDRE, 0);
+        acia.tick(FRAME / 2);
+        // Writing again cuts the first character off.
+        acia.write(0, b'B');
+        acia.tick(FRAME);
+        assert_eq!(acia.take_transmitted(), b"B");
+        assert!(!acia.irq());
+    }
+
+    #[test]
+    fn test_echo() {
+        let mut acia = acia();
+        acia.write(2, 0x13);
+        acia.receive(b'e');
+        acia.tick(FRAME);
+        assert_eq!(acia.take_transmitted(), b"e");
+        assert_eq!(acia.read(0), b'e');
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut acia = acia();
+        acia.write(0, b'A');
+        acia.tick(FRAME / 2);
+        acia.receive(b'x');
+        acia.tick(1);
+        let state = savestate::save("acia", &acia);
+
+        // Part way through sending and receiving a character.
+        let mut restored = Acia::new(1_000_000);
+        savestate::load("acia", &mut restored, &state).unwrap();
+        restored.tick(FRAME);
+        assert_eq!(restored.take_transmitted(), b"A");
+        assert_eq!(restored.read(0), b'x');
+    }
+}
@@ -0,0 +1,821 @@
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+const TA: u8 = 0x01;
+const TB: u8 = 0x02;
+const ALARM: u8 = 0x04;
+const SP: u8 = 0x08;
+const FLAG: u8 = 0x10;
+
+/// Which CIA is emulated. They differ in when a timer interrupt reaches
+/// the IRQ pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    /// The original 6526, which asserts IRQ a cycle after the interrupt
+    /// flag is set.
+    #[default]
+    Mos6526,
+    /// The 6526A (and 8521), which asserts IRQ in the same cycle.
+    Mos6526A,
+}
+
+#[derive(Debug, Clone)]
+struct Timer {
+    counter: u16,
+    latch: u16,
+    cr: u8,
+    // The toggle output, and whether the pulse output is high this cycle.
+    toggle: bool,
+    pulse: bool,
+}
+
+impl Timer {
+    fn new() -> Self {
+        Timer { counter: 0xFFFF, latch: 0xFFFF, cr: 0, toggle: false, pulse: false }
+    }
+
+    fn started(&self) -> bool {
+        self.cr & 0x01 != 0
+    }
+
+    fn write_cr(&mut self, value: u8) {
+        if value & 0x01 != 0 && !self.started() {
+            self.toggle = true;
+        }
+        if value & 0x10 != 0 {
+            self.counter = self.latch;
+        }
+        self.cr = value & !0x10;
+    }
+
+    fn write_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x00FF) | (value as u16) << 8;
+        if !self.started() {
+            self.counter = self.latch;
+        }
+    }
+
+    // Counts once, returning whether the timer underflowed.
+    fn count(&mut self) -> bool {
+        if self.counter > 0 {
+            self.counter -= 1;
+            return false;
+        }
+        self.counter = self.latch;
+        if self.cr & 0x08 != 0 {
+            self.cr &= !0x01;
+        }
+        self.toggle = !self.toggle;
+        self.pulse = true;
+        true
+    }
+
+    // The PB6/PB7 output, if enabled.
+    fn output(&self) -> Option<bool> {
+        match self.cr & 0x06 {
+            0x02 => Some(self.pulse),
+            0x06 => Some(self.toggle),
+            _ => None,
+        }
+    }
+}
+
+impl SaveState for Timer {
+    fn save(&self, out: &mut StateWriter) {
+        out.u16(self.counter);
+        out.u16(self.latch);
+        out.u8(self.cr);
+        out.bool(self.toggle);
+        out.bool(self.pulse);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("timer", version, 1)?;
+        self.counter = input.u16()?;
+        self.latch = input.u16()?;
+        self.cr = input.u8()?;
+        self.toggle = input.bool()?;
+        self.pulse = input.bool()?;
+        Ok(())
+    }
+}
+
+fn bcd_increment(value: u8) -> u8 {
+    match value & 0x0F {
+        9 => (value & 0xF0) + 0x10,
+        _ => value + 1,
+    }
+}
+
+/// A MOS 6526 Complex Interface Adapter, clocked at the CPU rate. It
+/// occupies 16 registers.
+///
+/// The port lines are pulled up and wired-AND: a line reads low when the
+/// CIA drives it low, the host pulls it low with `set_port_a` or
+/// `set_port_b`, or a key pressed with `set_key` connects it to a low line
+/// of the other port. That's how the C64 scans its keyboard, driving
+/// columns on port A and reading rows on port B.
+///
+/// The time-of-day clock counts pulses on its TOD pin, which the host
+/// either supplies with `tod_pulse` or has generated every so many cycles
+/// with `set_tod_period`.
+#[derive(Debug, Clone)]
+pub struct Cia {
+    model: Model,
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    pa_in: u8,
+    pb_in: u8,
+    // Bit b of keys[a] is set when PAa and PBb are connected.
+    keys: [u8; 8],
+
+    ta: Timer,
+    tb: Timer,
+
+    tod: [u8; 4],
+    alarm: [u8; 4],
+    tod_latch: Option<[u8; 4]>,
+    tod_stopped: bool,
+    tod_prescaler: u8,
+    tod_period: u64,
+    tod_count: u64,
+
+    sdr: u8,
+    sr: u8,
+    sr_bits: u8,
+    sr_loaded: bool,
+    cnt_in: bool,
+    cnt_out: bool,
+    sp_in: bool,
+    sp_out: bool,
+    flag_pin: bool,
+
+    icr: u8,
+    mask: u8,
+    ir: bool,
+    ir_delay: bool,
+}
+
+impl Default for Cia {
+    fn default() -> Self {
+        Self::new(Model::default())
+    }
+}
+
+impl Cia {
+    pub fn new(model: Model) -> Self {
+        Cia {
+            model,
+            ora: 0,
+            orb: 0,
+            ddra: 0,
+            ddrb: 0,
+            pa_in: 0xFF,
+            pb_in: 0xFF,
+            keys: [0; 8],
+            ta: Timer::new(),
+            tb: Timer::new(),
+            tod: [0, 0, 0, 0x01],
+            alarm: [0; 4],
+            tod_latch: None,
+            tod_stopped: false,
+            tod_prescaler: 0,
+            tod_period: 0,
+            tod_count: 0,
+            sdr: 0,
+            sr: 0,
+            sr_bits: 0,
+            sr_loaded: false,
+            cnt_in: true,
+            cnt_out: true,
+            sp_in: true,
+            sp_out: true,
+            flag_pin: true,
+            icr: 0,
+            mask: 0,
+            ir: false,
+            ir_delay: false,
+        }
+    }
+
+    // The port levels before the keyboard matrix.
+    fn drive_a(&self) -> u8 {
+        (self.ora | !self.ddra) & self.pa_in
+    }
+
+    fn drive_b(&self) -> u8 {
+        let mut value = (self.orb | !self.ddrb) & self.pb_in;
+        for (bit, timer) in [(0x40, &self.ta), (0x80, &self.tb)] {
+            match timer.output() {
+                Some(true) => value |= bit,
+                Some(false) => value &= !bit,
+                None => {}
+            }
+        }
+        value
+    }
+
+    /// The levels of the PA lines.
+    pub fn port_a(&self) -> u8 {
+        let b = self.drive_b();
+        (0..8).fold(self.drive_a(), |value, a| match self.keys[a] & !b {
+            0 => value,
+            _ => value & !(1 << a),
+        })
+    }
+
+    /// The levels of the PB lines, with PB6 and PB7 driven by the timers
+    /// if enabled.
+    pub fn port_b(&self) -> u8 {
+        let a = self.drive_a();
+        (0..8).filter(|&i| a & 1 << i == 0).fold(self.drive_b(), |value, i| value & !self.keys[i])
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.pa_in = value;
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.pb_in = value;
+    }
+
+    /// Connects or disconnects PA line `a` and PB line `b`. Both are taken
+    /// modulo 8.
+    pub fn set_key(&mut self, a: u8, b: u8, pressed: bool) {
+        let (a, b) = ((a & 7) as usize, b & 7);
+        match pressed {
+            true => self.keys[a] |= 1 << b,
+            false => self.keys[a] &= !(1 << b),
+        }
+    }
+
+    /// Drives the CNT pin. Rising edges clock the timers in CNT mode and
+    /// shift in a bit when the serial port is an input.
+    pub fn set_cnt(&mut self, level: bool) {
+        let rising = level && !self.cnt_in;
+        self.cnt_in = level;
+        if !rising {
+            return;
+        }
+        if self.ta.started() && self.ta.cr & 0x20 != 0 && self.ta.count() {
+            self.timer_a_underflow();
+        }
+        if self.tb.started() && self.tb.cr & 0x60 == 0x20 && self.tb.count() {
+            self.raise(TB);
+        }
+        if self.ta.cr & 0x40 == 0 {
+            self.sr = self.sr << 1 | self.sp_in as u8;
+            self.sr_bits += 1;
+            if self.sr_bits == 8 {
+                self.sr_bits = 0;
+                self.sdr = self.sr;
+                self.raise(SP);
+            }
+        }
+    }
+
+    /// Drives the SP pin, sampled when the serial port is an input.
+    pub fn set_sp(&mut self, level: bool) {
+        self.sp_in = level;
+    }
+
+    /// Drives the /FLAG pin. A falling edge sets the FLAG interrupt.
+    pub fn set_flag(&mut self, level: bool) {
+        if !level && self.flag_pin {
+            self.raise(FLAG);
+        }
+        self.flag_pin = level;
+    }
+
+    /// The CNT pin, which clocks the data out when the serial port is an
+    /// output.
+    pub fn cnt(&self) -> bool {
+        match self.ta.cr & 0x40 {
+            0 => self.cnt_in,
+            _ => self.cnt_out,
+        }
+    }
+
+    /// The SP pin.
+    pub fn sp(&self) -> bool {
+        match self.ta.cr & 0x40 {
+            0 => self.sp_in,
+            _ => self.sp_out,
+        }
+    }
+
+    /// Generates a TOD pulse every `cycles` cycles, or none when 0. On a
+    /// PAL C64 that's 19705 for 50 Hz.
+    pub fn set_tod_period(&mut self, cycles: u64) {
+        self.tod_period = cycles;
+        self.tod_count = 0;
+    }
+
+    /// One pulse on the TOD pin. The clock advances a tenth of a second
+    /// every 5 or 6 pulses, depending on whether CRA selects 50 or 60 Hz.
+    pub fn tod_pulse(&mut self) {
+        self.tod_prescaler += 1;
+        if self.tod_prescaler < if self.ta.cr & 0x80 != 0 { 5 } else { 6 } {
+            return;
+        }
+        self.tod_prescaler = 0;
+        if self.tod_stopped {
+            return;
+        }
+        self.tod[0] = (self.tod[0] + 1) % 10;
+        if self.tod[0] == 0 {
+            self.tod[1] = bcd_increment(self.tod[1]);
+            if self.tod[1] == 0x60 {
+                self.tod[1] = 0;
+                self.tod[2] = bcd_increment(self.tod[2]);
+                if self.tod[2] == 0x60 {
+                    self.tod[2] = 0;
+                    self.tod[3] = match self.tod[3] & 0x1F {
+                        0x11 => (self.tod[3] ^ 0x80) & 0x80 | 0x12,
+                        0x12 => self.tod[3] & 0x80 | 0x01,
+                        _ => bcd_increment(self.tod[3]),
+                    };
+                }
+            }
+        }
+        self.check_alarm();
+    }
+
+    fn check_alarm(&mut self) {
+        if self.tod == self.alarm {
+            self.raise(ALARM);
+        }
+    }
+
+    fn raise(&mut self, flag: u8) {
+        self.icr |= flag;
+        if self.mask & flag != 0 && !self.ir {
+            // The 6526 is a cycle late with timer interrupts.
+            match (self.model, flag & (TA | TB) != 0) {
+                (Model::Mos6526, true) => self.ir_delay = true,
+                _ => self.ir = true,
+            }
+        }
+    }
+
+    fn timer_a_underflow(&mut self) {
+        self.raise(TA);
+        if self.ta.cr & 0x40 != 0 {
+            self.shift_out();
+        }
+        if self.tb.started() {
+            let counts = match self.tb.cr & 0x60 {
+                0x40 => true,
+                0x60 => self.cnt_in,
+                _ => false,
+            };
+            if counts && self.tb.count() {
+                self.raise(TB);
+            }
+        }
+    }
+
+    // Each timer A underflow toggles CNT while there's data to send. Bits
+    // go out on SP as CNT falls and are done as it rises.
+    fn shift_out(&mut self) {
+        if self.sr_bits == 0 {
+            if !self.sr_loaded {
+                return;
+            }
+            self.sr = self.sdr;
+            self.sr_bits = 8;
+            self.sr_loaded = false;
+        }
+        self.cnt_out = !self.cnt_out;
+        if !self.cnt_out {
+            self.sp_out = self.sr & 0x80 != 0;
+            self.sr <<= 1;
+            return;
+        }
+        self.sr_bits -= 1;
+        if self.sr_bits == 0 {
+            self.raise(SP);
+        }
+    }
+
+    fn clock(&mut self) {
+        self.ta.pulse = false;
+        self.tb.pulse = false;
+        if self.ir_delay {
+            self.ir_delay = false;
+            self.ir = true;
+        }
+        if self.ta.started() && self.ta.cr & 0x20 == 0 && self.ta.count() {
+            self.timer_a_underflow();
+        }
+        if self.tb.started() && self.tb.cr & 0x60 == 0 && self.tb.count() {
+            self.raise(TB);
+        }
+        if self.tod_period > 0 {
+            self.tod_count += 1;
+            if self.tod_count == self.tod_period {
+                self.tod_count = 0;
+                self.tod_pulse();
+            }
+        }
+    }
+
+    fn read_register(&self, reg: u16) -> u8 {
+        let tod = self.tod_latch.unwrap_or(self.tod);
+        match reg {
+            0x0 => self.port_a(),
+            0x1 => self.port_b(),
+            0x2 => self.ddra,
+            0x3 => self.ddrb,
+            0x4 => self.ta.counter as u8,
+            0x5 => (self.ta.counter >> 8) as u8,
+            0x6 => self.tb.counter as u8,
+            0x7 => (self.tb.counter >> 8) as u8,
+            0x8..=0xB => tod[reg as usize - 8],
+            0xC => self.sdr,
+            0xD => self.icr | (self.ir as u8) << 7,
+            0xE => self.ta.cr,
+            _ => self.tb.cr,
+        }
+    }
+}
+
+impl Device for Cia {
+    fn peek(&self, offset: u16) -> u8 {
+        self.read_register(offset & 0xF)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        let reg = offset & 0xF;
+        let value = self.read_register(reg);
+        match reg {
+            // Reading the hours freezes the registers until the tenths
+            // are read, so the time can be read consistently.
+            0x8 => self.tod_latch = None,
+            0xB => self.tod_latch = Some(self.tod),
+            0xD => {
+                self.icr = 0;
+                self.ir = false;
+                self.ir_delay = false;
+            }
+            _ => {}
+        }
+        value
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset & 0xF {
+            0x0 => self.ora = value,
+            0x1 => self.orb = value,
+            0x2 => self.ddra = value,
+            0x3 => self.ddrb = value,
+            0x4 => self.ta.latch = (self.ta.latch & 0xFF00) | value as u16,
+            0x5 => self.ta.write_high(value),
+            0x6 => self.tb.latch = (self.tb.latch & 0xFF00) | value as u16,
+            0x7 => self.tb.write_high(value),
+            reg @ 0x8..=0xB => {
+                let i = reg as usize - 8;
+                let value = value & [0x0F, 0x7F, 0x7F, 0x9F][i];
+                if self.tb.cr & 0x80 != 0 {
+                    self.alarm[i] = value;
+                } else {
+                    // Writing the hours stops the clock until the tenths
+                    // are written.
+                    self.tod[i] = value;
+                    match i {
+                        3 => self.tod_stopped = true,
+                        0 => self.tod_stopped = false,
+                        _ => {}
+                    }
+                }
+                self.check_alarm();
+            }
+            0xC => {
+                self.sdr = value;
+                self.sr_loaded = self.ta.cr & 0x40 != 0;
+            }
+            0xD => {
+                match value & 0x80 {
+                    0 => self.mask &= !value,
+                    _ => self.mask |= value & 0x1F,
+                }
+                if self.icr & self.mask != 0 {
+                    self.ir = true;
+                }
+            }
+            0xE => {
+                if (value ^ self.ta.cr) & 0x40 != 0 {
+                    self.sr_bits = 0;
+                    self.cnt_out = true;
+                }
+                self.ta.write_cr(value);
+            }
+            _ => self.tb.write_cr(value),
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.ir
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Cia {
+    fn save(&self, out: &mut StateWriter) {
+        for value in [self.ora, self.orb, self.ddra, self.ddrb, self.pa_in, self.pb_in] {
+            out.u8(value);
+        }
+        out.bytes(&self.keys);
+        out.section("ta", &self.ta);
+        out.section("tb", &self.tb);
+        out.bytes(&self.tod);
+        out.bytes(&self.alarm);
+        out.bool(self.tod_latch.is_some());
+        out.bytes(&self.tod_latch.unwrap_or_default());
+        out.bool(self.tod_stopped);
+        out.u8(self.tod_prescaler);
+        out.u64(self.tod_period);
+        out.u64(self.tod_count);
+        for value in [self.sdr, self.sr, self.sr_bits] {
+            out.u8(value);
+        }
+        for value in [self.sr_loaded, self.cnt_in, self.cnt_out, self.sp_in, self.sp_out, self.flag_pin] {
+            out.bool(value);
+        }
+        out.u8(self.icr);
+        out.u8(self.mask);
+        out.bool(self.ir);
+        out.bool(self.ir_delay);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("cia", version, 1)?;
+        for value in [&mut self.ora, &mut self.orb, &mut self.ddra, &mut self.ddrb, &mut self.pa_in, &mut self.pb_in] {
+            *value = input.u8()?;
+        }
+        input.bytes_into(&mut self.keys)?;
+        input.section("ta", &mut self.ta)?;
+        input.section("tb", &mut self.tb)?;
+        input.bytes_into(&mut self.tod)?;
+        input.bytes_into(&mut self.alarm)?;
+        let latched = input.bool()?;
+        let mut latch = [0; 4];
+        input.bytes_into(&mut latch)?;
+        self.tod_latch = latched.then_some(latch);
+        self.tod_stopped = input.bool()?;
+        self.tod_prescaler = input.u8()?;
+        self.tod_period = input.u64()?;
+        self.tod_count = input.u64()?;
+        if self.tod_period > 0 && self.tod_count >= self.tod_period {
+            return Err(savestate::Error::Invalid("invalid TOD count"));
+        }
+        for value in [&mut self.sdr, &mut self.sr, &mut self.sr_bits] {
+            *value = input.u8()?;
+        }
+        for value in [&mut self.sr_loaded, &mut self.cnt_in, &mut self.cnt_out, &mut self.sp_in, &mut self.sp_out, &mut self.flag_pin] {
+            *value = input.bool()?;
+        }
+        self.icr = input.u8()?;
+        self.mask = input.u8()?;
+        self.ir = input.bool()?;
+        self.ir_delay = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRA: u16 = 0x0;
+    const PRB: u16 = 0x1;
+    const DDRA: u16 = 0x2;
+    const TALO: u16 = 0x4;
+    const TAHI: u16 = 0x5;
+    const TBLO: u16 = 0x6;
+    const TBHI: u16 = 0x7;
+    const TOD10: u16 = 0x8;
+    const TODSEC: u16 = 0x9;
+    const TODMIN: u16 = 0xA;
+    const TODHR: u16 = 0xB;
+    const SDR: u16 = 0xC;
+    const ICR: u16 = 0xD;
+    const CRA: u16 = 0xE;
+    const CRB: u16 = 0xF;
+
+    fn cycles_until_irq(cia: &mut Cia, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            cia.tick(1);
+            cia.irq()
+        })
+    }
+
+    #[test]
+    fn test_timer_a() {
+        let mut cia = Cia::new(Model::Mos6526A);
+        cia.write(TALO, 9);
+        cia.write(TAHI, 0);
+        cia.write(ICR, 0x81);
+        // Continuous, PB6 toggling.
+        cia.write(CRA, 0x07);
+        assert_eq!(cycles_until_irq(&mut cia, 100), Some(10));
+        assert_eq!(cia.peek(TALO), 9);
+        assert_eq!(cia.port_b() & 0x40, 0);
+        // Reading the ICR acknowledges the interrupt.
+        assert_eq!(cia.read(ICR), 0x81);
+        assert_eq!(cia.read(ICR), 0x00);
+        assert!(!cia.irq());
+        assert_eq!(cycles_until_irq(&mut cia, 100), Some(10));
+        assert_ne!(cia.port_b() & 0x40, 0);
+
+        // One-shot with a pulse on PB6.
+        cia.read(ICR);
+        cia.write(CRA, 0x1B);
+        assert_eq!(cycles_until_irq(&mut cia, 100), Some(10));
+        assert_ne!(cia.port_b() & 0x40, 0);
+        cia.tick(1);
+        assert_eq!(cia.port_b() & 0x40, 0);
+        assert_eq!(cia.peek(CRA) & 0x01, 0);
+    }
+
+    #[test]
+    fn test_cascade() {
+        let mut cia = Cia::default();
+        cia.write(TALO, 99);
+        cia.write(TAHI, 0);
+        cia.write(TBLO, 2);
+        cia.write(TBHI, 0);
+        cia.write(ICR, 0x82);
+        // Timer B counts timer A underflows.
+        cia.write(CRB, 0x41);
+        cia.write(CRA, 0x01);
+        cia.tick(299);
+        assert_eq!(cia.peek(ICR) & TB, 0);
+        cia.tick(1);
+        assert_eq!(cia.peek(ICR), TA | TB);
+        // The 6526 asserts IRQ a cycle later.
+        assert!(!cia.irq());
+        cia.tick(1);
+        assert_eq!(cia.read(ICR), 0x80 | TA | TB);
+
+        let mut new = Cia::new(Model::Mos6526A);
+        let mut old = Cia::new(Model::Mos6526);
+        for cia in [&mut new, &mut old] {
+            cia.write(TALO, 5);
+            cia.write(TAHI, 0);
+            cia.write(ICR, 0x81);
+            cia.write(CRA, 0x01);
+        }
+        assert_eq!(cycles_until_irq(&mut new, 20), Some(6));
+        assert_eq!(cycles_until_irq(&mut old, 20), Some(7));
+    }
+
+    #[test]
+    fn test_tod() {
+        let mut cia = Cia::default();
+        // 50 Hz.
+        cia.write(CRA, 0x80);
+        cia.write(TODHR, 0x91);
+        cia.write(TODMIN, 0x59);
+        cia.write(TODSEC, 0x59);
+        cia.write(TOD10, 0x08);
+        // Alarm at 12:00:00.0 AM.
+        cia.write(CRB, 0x80);
+        cia.write(TODHR, 0x12);
+        cia.write(TODMIN, 0x00);
+        cia.write(TODSEC, 0x00);
+        cia.write(TOD10, 0x00);
+        cia.write(CRB, 0x00);
+        cia.write(ICR, 0x84);
+
+        cia.set_tod_period(100);
+        cia.tick(500);
+        assert_eq!(cia.peek(TOD10), 9);
+        // The hours latch the time until the tenths are read.
+        assert_eq!(cia.read(TODHR), 0x91);
+        cia.tick(500);
+        assert_eq!((cia.read(TODMIN), cia.read(TODSEC), cia.read(TOD10)), (0x59, 0x59, 0x09));
+        assert_eq!(cia.read(TODHR), 0x12);
+        assert_eq!(cia.read(TOD10), 0);
+        assert!(cia.irq());
+        assert_eq!(cia.read(ICR), 0x80 | ALARM);
+
+        // Writing the hours stops the clock until the tenths are written.
+        cia.write(TODHR, 0x01);
+        cia.tick(1000);
+        assert_eq!(cia.peek(TOD10), 0);
+        cia.write(TOD10, 0);
+        cia.tick(1000);
+        assert_eq!(cia.peek(TOD10), 2);
+
+        // Writing the seconds or minutes leaves it running.
+        cia.write(TODSEC, 0x30);
+        cia.write(TODMIN, 0x15);
+        cia.tick(1000);
+        assert_eq!((cia.peek(TODMIN), cia.peek(TODSEC), cia.peek(TOD10)), (0x15, 0x30, 4));
+    }
+
+    #[test]
+    fn test_serial_port() {
+        let mut cia = Cia::default();
+        cia.write(TALO, 3);
+        cia.write(TAHI, 0);
+        // Output, timer A continuous.
+        cia.write(CRA, 0x41);
+        cia.write(SDR, 0xA5);
+        let mut bits = 0u8;
+        let mut cnt = cia.cnt();
+        for _ in 0..16 * 4 {
+            cia.tick(1);
+            if cia.cnt() && !cnt {
+                bits = bits << 1 | cia.sp() as u8;
+            }
+            cnt = cia.cnt();
+        }
+        assert_eq!(bits, 0xA5);
+        assert_eq!(cia.read(ICR), SP | TA);
+
+        // Input, clocked by CNT.
+        cia.write(CRA, 0x00);
+        for bit in (0..8).rev() {
+            cia.set_sp(0x3C >> bit & 1 != 0);
+            cia.set_cnt(false);
+            cia.set_cnt(true);
+        }
+        assert_eq!(cia.read(SDR), 0x3C);
+        assert_eq!(cia.read(ICR), SP);
+    }
+
+    #[test]
+    fn test_keyboard_matrix() {
+        let mut cia = Cia::default();
+        cia.write(DDRA, 0xFF);
+        cia.set_key(1, 4, true);
+        cia.set_key(6, 7, true);
+        cia.write(PRA, 0x00);
+        assert_eq!(cia.read(PRB), 0x6F);
+        cia.write(PRA, !0x02);
+        assert_eq!(cia.read(PRB), 0xEF);
+        cia.write(PRA, !0x40);
+        assert_eq!(cia.read(PRB), 0x7F);
+        cia.write(PRA, 0xFF);
+        assert_eq!(cia.read(PRB), 0xFF);
+        // A joystick on port A pulls lines low too.
+        cia.write(DDRA, 0x00);
+        cia.set_port_a(0xFE);
+        assert_eq!(cia.read(PRA), 0xFE);
+        assert_eq!(cia.read(PRB), 0xFF);
+
+        // Out-of-range lines wrap rather than panic.
+        cia.set_port_a(0xFF);
+        cia.write(DDRA, 0xFF);
+        cia.write(PRA, !0x01);
+        cia.set_key(8, 9, true);
+        assert_eq!(cia.read(PRB), 0xFD);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut cia = Cia::default();
+        cia.write(DDRA, 0xFF);
+        cia.write(PRA, !0x02);
+        cia.set_key(1, 4, true);
+        cia.write(TALO, 0x10);
+        cia.write(TAHI, 0);
+        cia.write(CRA, 0x01);
+        cia.write(ICR, 0x81);
+        cia.set_tod_period(100);
+        cia.tick(5);
+        assert_eq!(cia.read(TODHR), 0x01);
+        let state = savestate::save("cia", &cia);
+
+        let mut restored = Cia::default();
+        savestate::load("cia", &mut restored, &state).unwrap();
+        assert_eq!(restored.read(PRB), 0xEF);
+        assert_eq!(restored.peek(TALO), cia.peek(TALO));
+        for _ in 0..20 {
+            cia.tick(1);
+            restored.tick(1);
+            assert_eq!((restored.peek(TALO), restored.irq()), (cia.peek(TALO), cia.irq()));
+        }
+        assert!(restored.irq());
+        cia.tick(600);
+        restored.tick(600);
+        assert_eq!(restored.read(TOD10), cia.read(TOD10));
+    }
+}
@@ -0,0 +1,450 @@
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+/// A Hitachi HD44780 character LCD controller and its display, driven at
+/// the pin level with `set_pins` by whatever it is wired to.
+///
+/// It latches a byte (or, over a 4-bit interface, a nibble on D4-D7) on
+/// the falling edge of E, and drives the data pins while E and R/W are
+/// high. Instructions complete at once, so the busy flag always reads 0.
+/// Custom CGRAM characters are stored but shown by `text` as blanks.
+#[derive(Debug, Clone)]
+pub struct Hd44780 {
+    columns: usize,
+    rows: usize,
+    ddram: [u8; 0x80],
+    cgram: [u8; 0x40],
+    ac: u8,
+    cgram_selected: bool,
+    increment: bool,
+    shift_on_write: bool,
+    display_on: bool,
+    cursor: bool,
+    blink: bool,
+    shift: u8,
+    eight_bit: bool,
+    two_lines: bool,
+    // The high nibble of a 4-bit transfer, once it has been latched.
+    high_nibble: Option<u8>,
+    // A byte being read out over the 4-bit interface.
+    reading: Option<u8>,
+    e: bool,
+    rs: bool,
+    rw: bool,
+}
+
+impl Hd44780 {
+    /// A display of `columns` by `rows` characters (1, 2 or 4 rows) in its
+    /// power-on state: 8-bit interface, one line and the display off.
+    pub fn new(columns: usize, rows: usize) -> Self {
+        assert!(matches!(rows, 1 | 2 | 4), "an HD44780 drives 1, 2 or 4 rows");
+        Hd44780 {
+            columns,
+            rows,
+            ddram: [b' '; 0x80],
+            cgram: [0; 0x40],
+            ac: 0,
+            cgram_selected: false,
+            increment: true,
+            shift_on_write: false,
+            display_on: false,
+            cursor: false,
+            blink: false,
+            shift: 0,
+            eight_bit: true,
+            two_lines: false,
+            high_nibble: None,
+            reading: None,
+            e: false,
+            rs: false,
+            rw: false,
+        }
+    }
+
+    /// Sets the levels of RS, R/W, E and D0-D7.
+    pub fn set_pins(&mut self, rs: bool, rw: bool, e: bool, data: u8) {
+        let falling = self.e && !e;
+        self.e = e;
+        self.rs = rs;
+        self.rw = rw;
+        if !falling {
+            return;
+        }
+        if rw {
+            self.end_read();
+        } else if self.eight_bit {
+            self.write(rs, data);
+        } else if let Some(high) = self.high_nibble.take() {
+            self.write(rs, high | data >> 4);
+        } else {
+            self.high_nibble = Some(data & 0xF0);
+        }
+    }
+
+    /// What the controller drives on D0-D7, or None while they are inputs.
+    pub fn data_out(&self) -> Option<u8> {
+        if !(self.e && self.rw) {
+            return None;
+        }
+        let value = self.reading.unwrap_or_else(|| self.peek_read(self.rs));
+        match (self.eight_bit, self.reading.is_some()) {
+            (true, _) | (false, false) => Some(value),
+            (false, true) => Some(value << 4),
+        }
+    }
+
+    /// The visible characters, a line per row.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for row in 0..self.rows {
+            if row > 0 {
+                text.push('\n');
+            }
+            for column in 0..self.columns {
+                let c = match self.position(row, column) {
+                    Some(addr) if self.display_on => self.ddram[addr as usize],
+                    _ => b' ',
+                };
+                text.push(if (0x20..0x7F).contains(&c) { c as char } else { ' ' });
+            }
+        }
+        text
+    }
+
+    /// The cursor's row and column, if it is shown and on screen.
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        if !self.display_on || !(self.cursor || self.blink) || self.cgram_selected {
+            return None;
+        }
+        (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |column| (row, column)))
+            .find(|&(row, column)| self.position(row, column) == Some(self.ac))
+    }
+
+    pub fn cgram(&self) -> &[u8; 0x40] {
+        &self.cgram
+    }
+
+    // The DDRAM address shown at a position. Rows 3 and 4 of a 4-line
+    // display continue rows 1 and 2. In one-line mode only the first line
+    // is driven, so the rows showing the second are blank.
+    fn position(&self, row: usize, column: usize) -> Option<u8> {
+        let (line, offset) = match row {
+            0 | 1 => (row, 0),
+            _ => (row - 2, self.columns),
+        };
+        if line == 1 && !self.two_lines {
+            return None;
+        }
+        let line_len = if self.two_lines { 40 } else { 80 };
+        let index = (offset + column + self.shift as usize) % line_len;
+        Some((line * 0x40 + index) as u8)
+    }
+
+    fn peek_read(&self, rs: bool) -> u8 {
+        match (rs, self.cgram_selected) {
+            (false, _) => self.ac & 0x7F,
+            (true, false) => self.ddram[self.ac as usize & 0x7F],
+            (true, true) => self.cgram[self.ac as usize & 0x3F],
+        }
+    }
+
+    // Ends an E pulse with R/W high. A data read moves the address
+    // counter; over 4 bits that happens after the second nibble.
+    fn end_read(&mut self) {
+        let value = self.peek_read(self.rs);
+        if !self.eight_bit && self.reading.is_none() {
+            self.reading = Some(value);
+            return;
+        }
+        self.reading = None;
+        if self.rs {
+            self.step_address();
+        }
+    }
+
+    fn write(&mut self, rs: bool, value: u8) {
+        if rs {
+            match self.cgram_selected {
+                false => self.ddram[self.ac as usize & 0x7F] = value,
+                true => self.cgram[self.ac as usize & 0x3F] = value,
+            }
+            self.step_address();
+            if self.shift_on_write && !self.cgram_selected {
+                self.shift_display(self.increment);
+            }
+            return;
+        }
+        match value.leading_zeros() {
+            7 => {
+                self.ddram = [b' '; 0x80];
+                self.ac = 0;
+                self.cgram_selected = false;
+                self.increment = true;
+                self.shift = 0;
+            }
+            6 => {
+                self.ac = 0;
+                self.cgram_selected = false;
+                self.shift = 0;
+            }
+            5 => {
+                self.increment = value & 0x02 != 0;
+                self.shift_on_write = value & 0x01 != 0;
+            }
+            4 => {
+                self.display_on = value & 0x04 != 0;
+                self.cursor = value & 0x02 != 0;
+                self.blink = value & 0x01 != 0;
+            }
+            3 => match value & 0x08 {
+                0 => {
+                    self.cgram_selected = false;
+                    self.move_address(value & 0x04 != 0);
+                }
+                _ => self.shift_display(value & 0x04 == 0),
+            },
+            2 => {
+                self.eight_bit = value & 0x10 != 0;
+                self.two_lines = value & 0x08 != 0;
+                self.high_nibble = None;
+            }
+            1 => {
+                self.ac = value & 0x3F;
+                self.cgram_selected = true;
+            }
+            0 => {
+                self.ac = value & 0x7F;
+                self.cgram_selected = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn step_address(&mut self) {
+        match self.cgram_selected {
+            false => self.move_address(self.increment),
+            true => {
+                let step = if self.increment { 1 } else { 0x3F };
+                self.ac = (self.ac + step) & 0x3F;
+            }
+        }
+    }
+
+    // Moves through DDRAM, from the end of one line to the start of the
+    // next in 2-line mode. From an address past the end of the last line,
+    // which can be set but isn't shown, it wraps to the start of the first.
+    fn move_address(&mut self, right: bool) {
+        self.ac = match (self.two_lines, right, self.ac) {
+            (false, true, ac) if ac >= 0x4F => 0x00,
+            (false, false, 0x00) => 0x4F,
+            (true, true, 0x27) => 0x40,
+            (true, true, ac) if ac >= 0x67 => 0x00,
+            (true, false, 0x40) => 0x27,
+            (true, false, 0x00) => 0x67,
+            (_, true, ac) => ac + 1,
+            (_, false, ac) => ac - 1,
+        };
+    }
+
+    fn shift_display(&mut self, left: bool) {
+        let line_len = if self.two_lines { 40 } else { 80 };
+        self.shift = match left {
+            true => (self.shift + 1) % line_len,
+            false => (self.shift + line_len - 1) % line_len,
+        };
+    }
+}
+
+// The size of the glass is configuration and is not saved.
+impl SaveState for Hd44780 {
+    fn save(&self, out: &mut StateWriter) {
+        out.bytes(&self.ddram);
+        out.bytes(&self.cgram);
+        out.u8(self.ac);
+        out.u8(self.shift);
+        for value in [
+            self.cgram_selected, self.increment, self.shift_on_write, self.display_on, self.cursor,
+            self.blink, self.eight_bit, self.two_lines, self.e, self.rs, self.rw,
+        ] {
+            out.bool(value);
+        }
+        for value in [self.high_nibble, self.reading] {
+            out.bool(value.is_some());
+            out.u8(value.unwrap_or(0));
+        }
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("hd44780", version, 1)?;
+        input.bytes_into(&mut self.ddram)?;
+        input.bytes_into(&mut self.cgram)?;
+        self.ac = input.u8()?;
+        self.shift = input.u8()?;
+        for value in [
+            &mut self.cgram_selected, &mut self.increment, &mut self.shift_on_write, &mut self.display_on,
+            &mut self.cursor, &mut self.blink, &mut self.eight_bit, &mut self.two_lines, &mut self.e,
+            &mut self.rs, &mut self.rw,
+        ] {
+            *value = input.bool()?;
+        }
+        for value in [&mut self.high_nibble, &mut self.reading] {
+            let some = input.bool()?;
+            let byte = input.u8()?;
+            *value = some.then_some(byte);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(lcd: &mut Hd44780, rs: bool, value: u8) {
+        lcd.set_pins(rs, false, true, value);
+        lcd.set_pins(rs, false, false, value);
+    }
+
+    fn write_4bit(lcd: &mut Hd44780, rs: bool, value: u8) {
+        write(lcd, rs, value & 0xF0);
+        write(lcd, rs, value << 4);
+    }
+
+    #[test]
+    fn test_8bit() {
+        let mut lcd = Hd44780::new(16, 2);
+        write(&mut lcd, false, 0x38); // 8-bit, 2 lines
+        write(&mut lcd, false, 0x0E); // display and cursor on
+        write(&mut lcd, false, 0x06); // increment
+        write(&mut lcd, false, 0x01); // clear
+        for c in b"Hello" {
+            write(&mut lcd, true, *c);
+        }
+        write(&mut lcd, false, 0xC0 | 0x03); // line 2, column 3
+        write(&mut lcd, true, b'!');
+        assert_eq!(lcd.text(), "Hello           \n   !            ");
+        assert_eq!(lcd.cursor(), Some((1, 4)));
+
+        // Busy flag and address counter.
+        lcd.set_pins(false, true, true, 0xFF);
+        assert_eq!(lcd.data_out(), Some(0x44));
+        lcd.set_pins(false, true, false, 0xFF);
+        assert_eq!(lcd.data_out(), None);
+
+        // Shifting the display left.
+        write(&mut lcd, false, 0x18);
+        assert_eq!(lcd.text(), "ello            \n  !             ");
+        write(&mut lcd, false, 0x08);
+        assert_eq!(lcd.text(), format!("{:16}\n{:16}", "", ""));
+    }
+
+    #[test]
+    fn test_4bit() {
+        let mut lcd = Hd44780::new(16, 2);
+        // Function set to 4 bits as a single 8-bit transfer, then again
+        // over 4 bits for 2 lines.
+        write(&mut lcd, false, 0x20);
+        write_4bit(&mut lcd, false, 0x28);
+        write_4bit(&mut lcd, false, 0x0C);
+        write_4bit(&mut lcd, true, b'O');
+        write_4bit(&mut lcd, true, b'K');
+        assert!(lcd.text().starts_with("OK  "));
+
+        // Reading back the first character, a nibble at a time.
+        write_4bit(&mut lcd, false, 0x80);
+        let mut value = 0;
+        for shift in [4, 0] {
+            lcd.set_pins(true, true, true, 0xFF);
+            value |= (lcd.data_out().unwrap() >> 4) << shift;
+            lcd.set_pins(true, true, false, 0xFF);
+        }
+        assert_eq!(value, b'O');
+        lcd.set_pins(false, true, true, 0xFF);
+        assert_eq!(lcd.data_out(), Some(0x01));
+    }
+
+    #[test]
+    fn test_line_wrap() {
+        let mut lcd = Hd44780::new(20, 4);
+        write(&mut lcd, false, 0x38);
+        write(&mut lcd, false, 0x0C);
+        write(&mut lcd, false, 0x80 | 0x27);
+        write(&mut lcd, true, b'a');
+        write(&mut lcd, true, b'b');
+        write(&mut lcd, false, 0x80 | 0x14);
+        write(&mut lcd, true, b'c');
+        let text = lcd.text();
+        let rows: Vec<&str> = text.lines().collect();
+        assert!(rows[1].starts_with('b'));
+        assert!(rows[2].starts_with('c'));
+        assert_eq!(rows.len(), 4);
+    }
+
+    #[test]
+    fn test_address_wrap() {
+        let mut lcd = Hd44780::new(16, 1);
+        write(&mut lcd, false, 0x0C);
+        // Past the end of the line in 1-line mode.
+        write(&mut lcd, false, 0xFF);
+        for _ in 0..129 {
+            write(&mut lcd, true, b'x');
+        }
+        // One to wrap to $00, then 128 round the 80 addresses of the line.
+        lcd.set_pins(false, true, true, 0xFF);
+        assert_eq!(lcd.data_out(), Some(0x30));
+        lcd.set_pins(false, true, false, 0xFF);
+
+        let mut lcd = Hd44780::new(16, 2);
+        write(&mut lcd, false, 0x38);
+        write(&mut lcd, false, 0x80 | 0x68);
+        for _ in 0..0x29 {
+            write(&mut lcd, true, b'y');
+        }
+        lcd.set_pins(false, true, true, 0xFF);
+        assert_eq!(lcd.data_out(), Some(0x40));
+    }
+
+    #[test]
+    fn test_one_line_mode() {
+        let mut lcd = Hd44780::new(16, 2);
+        write(&mut lcd, false, 0x0C); // display on, still one line
+        for c in b"One line" {
+            write(&mut lcd, true, *c);
+        }
+        // Stays on the single 80 character line, past where line 2 starts.
+        write(&mut lcd, false, 0x80 | 0x4E);
+        write(&mut lcd, true, b'>');
+        assert_eq!(lcd.text(), format!("{:16}\n{:16}", "One line", ""));
+        for _ in 0..70 {
+            write(&mut lcd, false, 0x18);
+        }
+        assert_eq!(lcd.text(), format!("{:16}\n{:16}", "        > One li", ""));
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut lcd = Hd44780::new(16, 2);
+        write(&mut lcd, false, 0x20);
+        write_4bit(&mut lcd, false, 0x28);
+        write_4bit(&mut lcd, false, 0x0F);
+        for c in b"Saved" {
+            write_4bit(&mut lcd, true, *c);
+        }
+        write_4bit(&mut lcd, false, 0x18);
+        // Halfway through a 4-bit transfer.
+        write(&mut lcd, true, b'!' & 0xF0);
+        let state = savestate::save("lcd", &lcd);
+
+        let mut restored = Hd44780::new(16, 2);
+        savestate::load("lcd", &mut restored, &state).unwrap();
+        write(&mut restored, true, b'!' << 4);
+        assert_eq!(restored.text(), "aved!           \n                ");
+        assert_eq!(restored.cursor(), Some((0, 5)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_rows() {
+        Hd44780::new(20, 3);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+const IRQ1: u8 = 0x80;
+const IRQ2: u8 = 0x40;
+
+/// One side of a PIA: a port, its control register and the C1/C2 lines.
+#[derive(Debug, Clone)]
+struct Side {
+    or: u8,
+    ddr: u8,
+    input: u8,
+    cr: u8,
+    c1: bool,
+    c2_in: bool,
+    c2_out: bool,
+    c2_pulse: bool,
+}
+
+impl Side {
+    fn new() -> Self {
+        Side { or: 0, ddr: 0, input: 0xFF, cr: 0, c1: true, c2_in: true, c2_out: true, c2_pulse: false }
+    }
+
+    fn pins(&self) -> u8 {
+        (self.or & self.ddr) | (self.input & !self.ddr)
+    }
+
+    fn c2_output(&self) -> bool {
+        self.cr & 0x20 != 0
+    }
+
+    fn c2(&self) -> bool {
+        match self.cr & 0x38 {
+            0x00..=0x18 => self.c2_in,
+            0x20 | 0x28 => self.c2_out,
+            0x30 => false,
+            _ => true,
+        }
+    }
+
+    fn set_c1(&mut self, level: bool) {
+        if level != self.c1 && level == (self.cr & 0x02 != 0) {
+            self.cr |= IRQ1;
+            // Read and write strobes end on the active C1 edge.
+            if self.cr & 0x38 == 0x20 {
+                self.c2_out = true;
+            }
+        }
+        self.c1 = level;
+    }
+
+    fn set_c2(&mut self, level: bool) {
+        if !self.c2_output() && level != self.c2_in && level == (self.cr & 0x10 != 0) {
+            self.cr |= IRQ2;
+        }
+        self.c2_in = level;
+    }
+
+    // Strobes C2 low in the handshake and pulse output modes.
+    fn strobe(&mut self) {
+        if matches!(self.cr & 0x38, 0x20 | 0x28) {
+            self.c2_out = false;
+            self.c2_pulse = self.cr & 0x08 != 0;
+        }
+    }
+
+    fn write_cr(&mut self, value: u8) {
+        self.cr = (self.cr & 0xC0) | (value & 0x3F);
+        if self.c2_output() {
+            self.cr &= !IRQ2;
+        }
+        match self.cr & 0x38 {
+            0x30 => self.c2_out = false,
+            0x38 => self.c2_out = true,
+            _ => {}
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.cr & IRQ1 != 0 && self.cr & 0x01 != 0)
+            || (self.cr & IRQ2 != 0 && self.cr & 0x08 != 0 && !self.c2_output())
+    }
+
+    fn tick(&mut self) {
+        if self.c2_pulse {
+            self.c2_pulse = false;
+            self.c2_out = true;
+        }
+    }
+}
+
+impl SaveState for Side {
+    fn save(&self, out: &mut StateWriter) {
+        for value in [self.or, self.ddr, self.input, self.cr] {
+            out.u8(value);
+        }
+        for value in [self.c1, self.c2_in, self.c2_out, self.c2_pulse] {
+            out.bool(value);
+        }
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("side", version, 1)?;
+        for value in [&mut self.or, &mut self.ddr, &mut self.input, &mut self.cr] {
+            *value = input.u8()?;
+        }
+        for value in [&mut self.c1, &mut self.c2_in, &mut self.c2_out, &mut self.c2_pulse] {
+            *value = input.bool()?;
+        }
+        Ok(())
+    }
+}
+
+/// A Motorola 6821 (or MOS 6520) Peripheral Interface Adapter. It occupies
+/// 4 registers: port A or its data direction register (picked by bit 2 of
+/// control register A), control register A, then the same for port B.
+///
+/// The host side drives the inputs with `set_port_a`, `set_ca1` etc. and
+/// reads the outputs with `port_a`, `ca2` etc. Undriven inputs read as
+/// high, as if pulled up. The IRQA and IRQB outputs are wired together.
+#[derive(Debug, Clone)]
+pub struct Pia {
+    a: Side,
+    b: Side,
+}
+
+impl Default for Pia {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pia {
+    pub fn new() -> Self {
+        Pia { a: Side::new(), b: Side::new() }
+    }
+
+    /// The levels of the PA pins.
+    pub fn port_a(&self) -> u8 {
+        self.a.pins()
+    }
+
+    /// The levels of the PB pins.
+    pub fn port_b(&self) -> u8 {
+        self.b.pins()
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.a.input = value;
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.b.input = value;
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        self.a.set_c1(level);
+    }
+
+    pub fn set_ca2(&mut self, level: bool) {
+        self.a.set_c2(level);
+    }
+
+    pub fn set_cb1(&mut self, level: bool) {
+        self.b.set_c1(level);
+    }
+
+    pub fn set_cb2(&mut self, level: bool) {
+        self.b.set_c2(level);
+    }
+
+    /// CA2, which goes low after port A is read in the handshake modes.
+    pub fn ca2(&self) -> bool {
+        self.a.c2()
+    }
+
+    /// CB2, which goes low after port B is written in the handshake modes.
+    pub fn cb2(&self) -> bool {
+        self.b.c2()
+    }
+
+    pub fn irq_a(&self) -> bool {
+        self.a.irq()
+    }
+
+    pub fn irq_b(&self) -> bool {
+        self.b.irq()
+    }
+
+    fn read_register(&self, offset: u16) -> u8 {
+        let side = if offset & 2 == 0 { &self.a } else { &self.b };
+        match (offset & 1, side.cr & 0x04 != 0) {
+            (0, false) => side.ddr,
+            (0, true) => side.pins(),
+            _ => side.cr,
+        }
+    }
+}
+
+impl Device for Pia {
+    fn peek(&self, offset: u16) -> u8 {
+        self.read_register(offset & 3)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        let value = self.read_register(offset & 3);
+        match offset & 3 {
+            0 if self.a.cr & 0x04 != 0 => {
+                self.a.cr &= !(IRQ1 | IRQ2);
+                self.a.strobe();
+            }
+            2 if self.b.cr & 0x04 != 0 => self.b.cr &= !(IRQ1 | IRQ2),
+            _ => {}
+        }
+        value
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset & 3 {
+            0 if self.a.cr & 0x04 != 0 => self.a.or = value,
+            0 => self.a.ddr = value,
+            1 => self.a.write_cr(value),
+            2 if self.b.cr & 0x04 != 0 => {
+                self.b.or = value;
+                self.b.strobe();
+            }
+            2 => self.b.ddr = value,
+            _ => self.b.write_cr(value),
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if cycles > 0 {
+            self.a.tick();
+            self.b.tick();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_a() || self.irq_b()
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Pia {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("a", &self.a);
+        out.section("b", &self.b);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("pia", version, 1)?;
+        input.section("a", &mut self.a)?;
+        input.section("b", &mut self.b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRA: u16 = 0;
+    const CRA: u16 = 1;
+    const PRB: u16 = 2;
+    const CRB: u16 = 3;
+
+    #[test]
+    fn test_ports() {
+        let mut pia = Pia::new();
+        // Data direction registers until CR bit 2 is set.
+        pia.write(PRA, 0xF0);
+        pia.write(CRA, 0x04);
+        pia.write(PRA, 0xAA);
+        pia.set_port_a(0x05);
+        assert_eq!(pia.read(PRA), 0xA5);
+        pia.write(CRA, 0x00);
+        assert_eq!(pia.read(PRA), 0xF0);
+
+        pia.write(PRB, 0xFF);
+        pia.write(CRB, 0x04);
+        pia.write(PRB, 0x5A);
+        pia.set_port_b(0x00);
+        assert_eq!((pia.read(PRB), pia.port_b()), (0x5A, 0x5A));
+    }
+
+    #[test]
+    fn test_c1_interrupts() {
+        let mut pia = Pia::new();
+        // CA1 rising edges, interrupt enabled.
+        pia.write(CRA, 0x07);
+        pia.set_ca1(false);
+        assert_eq!(pia.peek(CRA) & IRQ1, 0);
+        pia.set_ca1(true);
+        assert_eq!(pia.peek(CRA), IRQ1 | 0x07);
+        assert!(pia.irq_a() && pia.irq());
+        // Writing the control register leaves the flags alone; reading
+        // the port clears them.
+        pia.write(CRA, 0x06);
+        assert!(!pia.irq());
+        assert_ne!(pia.peek(CRA) & IRQ1, 0);
+        pia.read(PRA);
+        assert_eq!(pia.peek(CRA) & IRQ1, 0);
+
+        // CB2 falling edges, interrupt enabled.
+        pia.write(CRB, 0x0C);
+        pia.set_cb2(false);
+        assert!(pia.irq_b());
+        pia.read(PRB);
+        assert!(!pia.irq_b());
+    }
+
+    #[test]
+    fn test_c2_outputs() {
+        let mut pia = Pia::new();
+        // CA2 handshake: low after a port A read until the active CA1 edge.
+        pia.write(CRA, 0x24);
+        assert!(pia.ca2());
+        pia.read(PRA);
+        assert!(!pia.ca2());
+        pia.tick(10);
+        assert!(!pia.ca2());
+        pia.set_ca1(false);
+        assert!(pia.ca2());
+
+        // CB2 pulse: low for a cycle after a port B write.
+        pia.write(CRB, 0x2C);
+        pia.write(PRB, 0x00);
+        assert!(!pia.cb2());
+        pia.tick(1);
+        assert!(pia.cb2());
+
+        // Manual output.
+        pia.write(CRB, 0x34);
+        assert!(!pia.cb2());
+        pia.write(CRB, 0x3C);
+        assert!(pia.cb2());
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut pia = Pia::new();
+        pia.write(CRA, 0x07);
+        pia.write(PRB, 0xFF);
+        pia.write(CRB, 0x04);
+        pia.write(PRB, 0x5A);
+        pia.set_ca1(false);
+        pia.set_ca1(true);
+        let state = savestate::save("pia", &pia);
+
+        let mut restored = Pia::new();
+        savestate::load("pia", &mut restored, &state).unwrap();
+        assert_eq!(restored.port_b(), 0x5A);
+        assert!(restored.irq_a());
+        assert_eq!(restored.peek(CRA), pia.peek(CRA));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+const TIMER: u8 = 0x80;
+const PA7: u8 = 0x40;
+
+/// The interval timer of the 6530 and 6532: an 8-bit counter decremented
+/// every 1, 8, 64 or 1024 cycles. It starts counting the cycle after it is
+/// written. Once it underflows it sets its flag and counts down every
+/// cycle until the flag is cleared by reading or writing it.
+#[derive(Debug, Clone)]
+pub(crate) struct IntervalTimer {
+    counter: u8,
+    shift: u8,
+    prescaler: u16,
+    pub flag: bool,
+}
+
+impl IntervalTimer {
+    pub fn new() -> Self {
+        IntervalTimer { counter: 0xFF, shift: 10, prescaler: 0, flag: false }
+    }
+
+    pub fn value(&self) -> u8 {
+        self.counter
+    }
+
+    /// Loads the counter, dividing the clock by 1, 8, 64 or 1024 for
+    /// `divider` 0 to 3.
+    pub fn write(&mut self, value: u8, divider: u8) {
+        self.counter = value;
+        self.shift = [0, 3, 6, 10][divider as usize & 3];
+        self.prescaler = 0;
+        self.flag = false;
+    }
+
+    pub fn clock(&mut self) {
+        if self.prescaler > 0 {
+            self.prescaler -= 1;
+            return;
+        }
+        self.counter = self.counter.wrapping_sub(1);
+        if self.counter == 0xFF {
+            self.flag = true;
+        }
+        if !self.flag {
+            self.prescaler = (1 << self.shift) - 1;
+        }
+    }
+}
+
+impl SaveState for IntervalTimer {
+    fn save(&self, out: &mut StateWriter) {
+        out.u8(self.counter);
+        out.u8(self.shift);
+        out.u16(self.prescaler);
+        out.bool(self.flag);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("timer", version, 1)?;
+        self.counter = input.u8()?;
+        self.shift = input.u8()?;
+        if !matches!(self.shift, 0 | 3 | 6 | 10) {
+            return Err(savestate::Error::Invalid("invalid timer divider"));
+        }
+        self.prescaler = input.u16()?;
+        self.flag = input.bool()?;
+        Ok(())
+    }
+}
+
+/// A MOS 6532 RAM-I/O-Timer, clocked at the CPU rate. Offset bit 7 is the
+/// RS pin: offsets below $80 address the 128 bytes of RAM and those from
+/// $80 up the I/O registers, so on the Atari 2600 it is mapped at $0200
+/// with a mask of $FF (under the TIA at $0200-$027F).
+///
+/// The host side drives the port inputs with `set_port_a` and `set_port_b`
+/// and reads the pins with `port_a` and `port_b`. Undriven inputs read as
+/// high, as if pulled up.
+#[derive(Debug, Clone)]
+pub struct Riot {
+    ram: [u8; 128],
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    pa_in: u8,
+    pb_in: u8,
+    timer: IntervalTimer,
+    timer_irq: bool,
+    pa7_flag: bool,
+    pa7_irq: bool,
+    pa7_positive: bool,
+}
+
+impl Default for Riot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Riot {
+    pub fn new() -> Self {
+        Riot {
+            ram: [0; 128],
+            ora: 0,
+            orb: 0,
+            ddra: 0,
+            ddrb: 0,
+            pa_in: 0xFF,
+            pb_in: 0xFF,
+            timer: IntervalTimer::new(),
+            timer_irq: false,
+            pa7_flag: false,
+            pa7_irq: false,
+            pa7_positive: false,
+        }
+    }
+
+    pub fn ram(&self) -> &[u8; 128] {
+        &self.ram
+    }
+
+    /// The levels of the PA pins.
+    pub fn port_a(&self) -> u8 {
+        (self.ora & self.ddra) | (self.pa_in & !self.ddra)
+    }
+
+    /// The levels of the PB pins.
+    pub fn port_b(&self) -> u8 {
+        (self.orb & self.ddrb) | (self.pb_in & !self.ddrb)
+    }
+
+    /// Drives the PA inputs. An edge on PA7 sets the PA7 flag.
+    pub fn set_port_a(&mut self, value: u8) {
+        self.update_port_a(|riot| riot.pa_in = value);
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.pb_in = value;
+    }
+
+    fn update_port_a(&mut self, f: impl FnOnce(&mut Self)) {
+        let old = self.port_a() & 0x80 != 0;
+        f(self);
+        let new = self.port_a() & 0x80 != 0;
+        if old != new && new == self.pa7_positive {
+            self.pa7_flag = true;
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        let timer = if self.timer.flag { TIMER } else { 0 };
+        timer | if self.pa7_flag { PA7 } else { 0 }
+    }
+
+    fn read_register(&self, offset: u16) -> u8 {
+        if offset & 0x80 == 0 {
+            return self.ram[offset as usize & 0x7F];
+        }
+        match (offset & 0x04 != 0, offset & 0x03) {
+            (false, 0) => self.port_a(),
+            (false, 1) => self.ddra,
+            (false, 2) => self.port_b(),
+            (false, _) => self.ddrb,
+            (true, 0 | 2) => self.timer.value(),
+            (true, _) => self.flags(),
+        }
+    }
+}
+
+impl Device for Riot {
+    fn peek(&self, offset: u16) -> u8 {
+        self.read_register(offset)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        let value = self.read_register(offset);
+        if offset & 0x84 == 0x84 {
+            match offset & 1 {
+                0 => {
+                    self.timer.flag = false;
+                    self.timer_irq = offset & 0x08 != 0;
+                }
+                _ => self.pa7_flag = false,
+            }
+        }
+        value
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        if offset & 0x80 == 0 {
+            self.ram[offset as usize & 0x7F] = value;
+            return;
+        }
+        match (offset & 0x04 != 0, offset & 0x10 != 0) {
+            (false, _) => match offset & 0x03 {
+                0 => self.update_port_a(|riot| riot.ora = value),
+                1 => self.update_port_a(|riot| riot.ddra = value),
+                2 => self.orb = value,
+                _ => self.ddrb = value,
+            },
+            (true, true) => {
+                self.timer.write(value, offset as u8 & 3);
+                self.timer_irq = offset & 0x08 != 0;
+            }
+            (true, false) => {
+                self.pa7_positive = offset & 0x01 != 0;
+                self.pa7_irq = offset & 0x02 != 0;
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.timer.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.timer.flag && self.timer_irq) || (self.pa7_flag && self.pa7_irq)
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Riot {
+    fn save(&self, out: &mut StateWriter) {
+        out.bytes(&self.ram);
+        for value in [self.ora, self.orb, self.ddra, self.ddrb, self.pa_in, self.pb_in] {
+            out.u8(value);
+        }
+        out.section("timer", &self.timer);
+        for value in [self.timer_irq, self.pa7_flag, self.pa7_irq, self.pa7_positive] {
+            out.bool(value);
+        }
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("riot", version, 1)?;
+        input.bytes_into(&mut self.ram)?;
+        for value in [&mut self.ora, &mut self.orb, &mut self.ddra, &mut self.ddrb, &mut self.pa_in, &mut self.pb_in] {
+            *value = input.u8()?;
+        }
+        input.section("timer", &mut self.timer)?;
+        for value in [&mut self.timer_irq, &mut self.pa7_flag, &mut self.pa7_irq, &mut self.pa7_positive] {
+            *value = input.bool()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRA: u16 = 0x80;
+    const DDRA: u16 = 0x81;
+    const DRB: u16 = 0x82;
+    const DDRB: u16 = 0x83;
+    const INTIM: u16 = 0x84;
+    const INSTAT: u16 = 0x85;
+    const TIM1T: u16 = 0x94;
+    const TIM64T: u16 = 0x96;
+    const EDGE: u16 = 0x84;
+
+    #[test]
+    fn test_ram_and_ports() {
+        let mut riot = Riot::new();
+        riot.write(0x00, 0x12);
+        riot.write(0x7F, 0x34);
+        assert_eq!((riot.read(0x00), riot.read(0x7F)), (0x12, 0x34));
+
+        riot.write(DDRA, 0x0F);
+        riot.write(DRA, 0xA5);
+        riot.set_port_a(0x30);
+        assert_eq!(riot.read(DRA), 0x35);
+        riot.write(DDRB, 0xFF);
+        riot.write(DRB, 0x5A);
+        assert_eq!(riot.port_b(), 0x5A);
+        assert_eq!(riot.read(DDRB), 0xFF);
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut riot = Riot::new();
+        riot.write(TIM64T, 2);
+        assert_eq!(riot.read(INTIM), 2);
+        riot.tick(1);
+        assert_eq!(riot.read(INTIM), 1);
+        riot.tick(63);
+        assert_eq!(riot.read(INTIM), 1);
+        riot.tick(1);
+        assert_eq!(riot.read(INTIM), 0);
+        riot.tick(63);
+        assert_eq!(riot.peek(INSTAT) & TIMER, 0);
+        riot.tick(1);
+        assert_eq!(riot.peek(INTIM), 0xFF);
+        assert_ne!(riot.peek(INSTAT) & TIMER, 0);
+        // After underflowing it counts every cycle.
+        riot.tick(3);
+        assert_eq!(riot.peek(INTIM), 0xFC);
+        // Reading the timer clears the flag.
+        riot.read(INTIM);
+        assert_eq!(riot.peek(INSTAT) & TIMER, 0);
+        assert!(!riot.irq());
+
+        // With A3 set the timer interrupts.
+        riot.write(TIM1T | 0x08, 4);
+        riot.tick(4);
+        assert!(!riot.irq());
+        riot.tick(1);
+        assert!(riot.irq());
+        riot.write(TIM1T, 4);
+        assert!(!riot.irq());
+    }
+
+    #[test]
+    fn test_pa7_edge() {
+        let mut riot = Riot::new();
+        // Negative edges, interrupt enabled.
+        riot.write(EDGE | 0x02, 0);
+        riot.set_port_a(0x7F);
+        assert!(riot.irq());
+        assert_eq!(riot.read(INSTAT), PA7);
+        assert!(!riot.irq());
+        riot.set_port_a(0xFF);
+        assert!(!riot.irq());
+
+        // Positive edges, also from the output register.
+        riot.write(EDGE | 0x03, 0);
+        riot.write(DDRA, 0x80);
+        riot.write(DRA, 0x00);
+        assert!(!riot.irq());
+        riot.write(DRA, 0x80);
+        assert!(riot.irq());
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut riot = Riot::new();
+        riot.write(0x12, 0x34);
+        riot.write(DDRA, 0xF0);
+        riot.write(DRA, 0xA0);
+        riot.write(TIM64T, 2);
+        riot.tick(100);
+        let state = savestate::save("riot", &riot);
+
+        let mut restored = Riot::new();
+        savestate::load("riot", &mut restored, &state).unwrap();
+        assert_eq!((restored.peek(0x12), restored.port_a()), (0x34, 0xAF));
+        assert_eq!(restored.peek(INTIM), riot.peek(INTIM));
+        riot.tick(100);
+        restored.tick(100);
+        assert_eq!((restored.peek(INTIM), restored.peek(INSTAT)), (riot.peek(INTIM), riot.peek(INSTAT)));
+        assert_eq!(restored.peek(INSTAT) & TIMER, TIMER);
+    }
+}
@@ -0,0 +1,212 @@
+use super::riot::IntervalTimer;
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+const TIMER: u8 = 0x80;
+
+/// The I/O and timer section of a MOS 6530 ROM-RAM-I/O-Timer, clocked at
+/// the CPU rate. Its 1K of mask ROM and 64 bytes of RAM answer at their
+/// own addresses and are mapped separately. The registers repeat every 16
+/// bytes: port A, its data direction register, the same for port B, then
+/// the timer from offset 4. Writing the timer at offsets 4-7 divides the
+/// clock by 1, 8, 64 or 1024; reading it at 6 (or 4), or the flags at 7,
+/// works as on the 6532. Offset bit 3 enables the timer interrupt.
+///
+/// The host side drives the port inputs with `set_port_a` and `set_port_b`
+/// and reads the pins with `port_a` and `port_b`. Undriven inputs read as
+/// high, as if pulled up.
+#[derive(Debug, Clone)]
+pub struct Rriot {
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    pa_in: u8,
+    pb_in: u8,
+    timer: IntervalTimer,
+    timer_irq: bool,
+}
+
+impl Default for Rriot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rriot {
+    pub fn new() -> Self {
+        Rriot {
+            ora: 0,
+            orb: 0,
+            ddra: 0,
+            ddrb: 0,
+            pa_in: 0xFF,
+            pb_in: 0xFF,
+            timer: IntervalTimer::new(),
+            timer_irq: false,
+        }
+    }
+
+    /// The levels of the PA pins.
+    pub fn port_a(&self) -> u8 {
+        (self.ora & self.ddra) | (self.pa_in & !self.ddra)
+    }
+
+    /// The levels of the PB pins.
+    pub fn port_b(&self) -> u8 {
+        (self.orb & self.ddrb) | (self.pb_in & !self.ddrb)
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.pa_in = value;
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.pb_in = value;
+    }
+
+    fn read_register(&self, offset: u16) -> u8 {
+        match (offset & 0x04 != 0, offset & 0x03) {
+            (false, 0) => self.port_a(),
+            (false, 1) => self.ddra,
+            (false, 2) => self.port_b(),
+            (false, _) => self.ddrb,
+            (true, 0 | 2) => self.timer.value(),
+            (true, _) => if self.timer.flag { TIMER } else { 0 },
+        }
+    }
+}
+
+impl Device for Rriot {
+    fn peek(&self, offset: u16) -> u8 {
+        self.read_register(offset)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        let value = self.read_register(offset);
+        if offset & 0x05 == 0x04 {
+            self.timer.flag = false;
+            self.timer_irq = offset & 0x08 != 0;
+        }
+        value
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match (offset & 0x04 != 0, offset & 0x03) {
+            (false, 0) => self.ora = value,
+            (false, 1) => self.ddra = value,
+            (false, 2) => self.orb = value,
+            (false, _) => self.ddrb = value,
+            (true, divider) => {
+                self.timer.write(value, divider as u8);
+                self.timer_irq = offset & 0x08 != 0;
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.timer.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.timer.flag && self.timer_irq
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Rriot {
+    fn save(&self, out: &mut StateWriter) {
+        for value in [self.ora, self.orb, self.ddra, self.ddrb, self.pa_in, self.pb_in] {
+            out.u8(value);
+        }
+        out.section("timer", &self.timer);
+        out.bool(self.timer_irq);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("rriot", version, 1)?;
+        for value in [&mut self.ora, &mut self.orb, &mut self.ddra, &mut self.ddrb, &mut self.pa_in, &mut self.pb_in] {
+            *value = input.u8()?;
+        }
+        input.section("timer", &mut self.timer)?;
+        self.timer_irq = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAD: u16 = 0x00;
+    const PADD: u16 = 0x01;
+    const PBD: u16 = 0x02;
+    const PBDD: u16 = 0x03;
+    const TIMER_1: u16 = 0x04;
+    const TIMER_8: u16 = 0x05;
+    const READ_TIMER: u16 = 0x06;
+    const READ_FLAGS: u16 = 0x07;
+    const IRQ_ENABLE: u16 = 0x08;
+
+    #[test]
+    fn test_ports() {
+        let mut rriot = Rriot::new();
+        rriot.write(PADD, 0x0F);
+        rriot.write(PAD, 0xA5);
+        rriot.set_port_a(0x30);
+        assert_eq!(rriot.read(PAD), 0x35);
+        // Registers repeat every 16 bytes.
+        rriot.write(PBDD | 0x30, 0xFF);
+        rriot.write(PBD, 0x5A);
+        assert_eq!((rriot.port_b(), rriot.read(PBD | 0x10)), (0x5A, 0x5A));
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut rriot = Rriot::new();
+        rriot.write(TIMER_8, 1);
+        rriot.tick(8);
+        assert_eq!(rriot.read(READ_TIMER), 0);
+        assert_eq!(rriot.peek(READ_FLAGS), 0);
+        rriot.tick(1);
+        assert_eq!(rriot.peek(READ_FLAGS), TIMER);
+        assert!(!rriot.irq());
+        // Reading the flags leaves them; reading the timer clears them.
+        rriot.read(READ_FLAGS);
+        assert_eq!(rriot.read(READ_TIMER), 0xFF);
+        assert_eq!(rriot.peek(READ_FLAGS), 0);
+
+        rriot.write(TIMER_1 | IRQ_ENABLE, 2);
+        rriot.tick(3);
+        assert!(rriot.irq());
+        rriot.read(READ_TIMER | IRQ_ENABLE);
+        assert!(!rriot.irq());
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut rriot = Rriot::new();
+        rriot.write(PADD, 0xF0);
+        rriot.write(PAD, 0xA0);
+        rriot.write(TIMER_8 | IRQ_ENABLE, 3);
+        rriot.tick(10);
+        let state = savestate::save("rriot", &rriot);
+
+        let mut restored = Rriot::new();
+        savestate::load("rriot", &mut restored, &state).unwrap();
+        assert_eq!((restored.port_a(), restored.peek(READ_TIMER)), (0xAF, rriot.peek(READ_TIMER)));
+        rriot.tick(30);
+        restored.tick(30);
+        assert_eq!(restored.peek(READ_TIMER), rriot.peek(READ_TIMER));
+        assert!(restored.irq());
+    }
+}
@@ -0,0 +1,143 @@
+//! The host end of emulated serial lines.
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+
+/// A byte stream on the host that a serial device talks to.
+pub trait Port {
+    /// The next byte from the host, if one has arrived. Never blocks.
+    fn poll(&mut self) -> io::Result<Option<u8>>;
+
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()>;
+}
+
+// Reads `reader` on a thread of its own so that polling never blocks.
+fn spawn_reader(mut reader: impl Read + Send + 'static) -> Receiver<u8> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 256];
+        while let Ok(n @ 1..) = reader.read(&mut buf) {
+            if buf[..n].iter().any(|&byte| tx.send(byte).is_err()) {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// The process's standard input and output. The terminal stays in line
+/// mode, so input arrives a line at a time.
+pub struct Stdio {
+    input: Receiver<u8>,
+    cr: bool,
+}
+
+impl Stdio {
+    /// With `cr` set, line feeds from the host are sent as carriage
+    /// returns, which is what most monitors expect from the Return key.
+    pub fn new(cr: bool) -> Self {
+        Stdio { input: spawn_reader(io::stdin()), cr }
+    }
+}
+
+impl Port for Stdio {
+    fn poll(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.input.try_recv().ok().map(|byte| match byte {
+            b'\n' if self.cr => b'\r',
+            _ => byte,
+        }))
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use pty::Pty;
+
+#[cfg(target_os = "linux")]
+mod pty {
+    use std::ffi::CStr;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::fd::AsRawFd;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    use super::{spawn_reader, Port};
+
+    fn check(result: libc::c_int) -> io::Result<()> {
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// A Linux pseudo-terminal. Terminal programs open the device at
+    /// `path`, which is in raw mode, and talk to the emulated machine.
+    pub struct Pty {
+        path: PathBuf,
+        input: Receiver<u8>,
+        output: Sender<Vec<u8>>,
+        // Held open so that reading the master doesn't fail while no
+        // program has the terminal open.
+        _slave: File,
+    }
+
+    impl Pty {
+        pub fn open() -> io::Result<Self> {
+            let master = OpenOptions::new().read(true).write(true).open("/dev/ptmx")?;
+            let fd = master.as_raw_fd();
+            let mut name = [0; 64];
+            // SAFETY: `fd` is an open pty master and `name` is writable for
+            // its whole length.
+            unsafe {
+                check(libc::grantpt(fd))?;
+                check(libc::unlockpt(fd))?;
+                check(libc::ptsname_r(fd, name.as_mut_ptr(), name.len()))?;
+            }
+            // SAFETY: ptsname_r succeeded, so `name` holds a C string.
+            let path = PathBuf::from(unsafe { CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned());
+
+            let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+            // SAFETY: `termios` is plain data filled in by tcgetattr, and
+            // the slave is an open terminal.
+            unsafe {
+                let mut termios = std::mem::zeroed();
+                check(libc::tcgetattr(slave.as_raw_fd(), &mut termios))?;
+                libc::cfmakeraw(&mut termios);
+                check(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios))?;
+            }
+
+            // Writes block once the terminal's buffer fills up with nobody
+            // reading, so they're done on a thread too.
+            let mut writer = master.try_clone()?;
+            let (output, queue) = mpsc::channel::<Vec<u8>>();
+            std::thread::spawn(move || {
+                while let Ok(bytes) = queue.recv() {
+                    if writer.write_all(&bytes).is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(Pty { path, input: spawn_reader(master), output, _slave: slave })
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    impl Port for Pty {
+        fn poll(&mut self) -> io::Result<Option<u8>> {
+            Ok(self.input.try_recv().ok())
+        }
+
+        fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.output.send(bytes.to_vec()).map_err(|_| io::ErrorKind::BrokenPipe.into())
+        }
+    }
+}
@@ -0,0 +1,667 @@
+use std::f64::consts::PI;
+use std::io::{self, Write};
+
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+const GATE: u8 = 0x01;
+const SYNC: u8 = 0x02;
+const RING: u8 = 0x04;
+const TEST: u8 = 0x08;
+const TRIANGLE: u8 = 0x10;
+const SAWTOOTH: u8 = 0x20;
+const PULSE: u8 = 0x40;
+const NOISE: u8 = 0x80;
+
+const NOISE_SEED: u32 = 0x7FFFF8;
+
+// Cycles per envelope step for each attack, decay and release rate.
+const RATE_PERIODS: [u16; 16] = [9, 32, 63, 95, 149, 220, 267, 313, 392, 977, 1954, 3126, 3907, 11720, 19532, 31251];
+
+/// Which SID is emulated. They differ in waveform DC offsets and in the
+/// filter's cutoff curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    #[default]
+    Mos6581,
+    Mos8580,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeState {
+    Attack,
+    DecaySustain,
+    Release,
+}
+
+#[derive(Debug, Clone)]
+struct Voice {
+    freq: u16,
+    pw: u16,
+    control: u8,
+    ad: u8,
+    sr: u8,
+
+    accumulator: u32,
+    // Whether the accumulator's MSB rose this cycle, for hard sync.
+    msb_rising: bool,
+    noise: u32,
+
+    state: EnvelopeState,
+    level: u8,
+    rate_counter: u16,
+    exponential_counter: u8,
+    hold_zero: bool,
+}
+
+impl Voice {
+    fn new() -> Self {
+        Voice {
+            freq: 0,
+            pw: 0,
+            control: 0,
+            ad: 0,
+            sr: 0,
+            accumulator: 0,
+            msb_rising: false,
+            noise: NOISE_SEED,
+            state: EnvelopeState::Release,
+            level: 0,
+            rate_counter: 0,
+            exponential_counter: 0,
+            hold_zero: true,
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        let gate = value & GATE != 0;
+        if gate && self.control & GATE == 0 {
+            self.state = EnvelopeState::Attack;
+            self.hold_zero = false;
+        } else if !gate && self.control & GATE != 0 {
+            self.state = EnvelopeState::Release;
+        }
+        if value & TEST != 0 {
+            self.accumulator = 0;
+            self.noise = NOISE_SEED;
+        }
+        self.control = value;
+    }
+
+    fn clock_oscillator(&mut self) {
+        if self.control & TEST != 0 {
+            self.msb_rising = false;
+            return;
+        }
+        let old = self.accumulator;
+        self.accumulator = (old + self.freq as u32) & 0xFFFFFF;
+        self.msb_rising = old & 0x800000 == 0 && self.accumulator & 0x800000 != 0;
+        // The noise shift register is clocked by bit 19.
+        if old & 0x080000 == 0 && self.accumulator & 0x080000 != 0 {
+            let bit = (self.noise >> 22 ^ self.noise >> 17) & 1;
+            self.noise = (self.noise << 1 | bit) & 0x7FFFFF;
+        }
+    }
+
+    fn rate_period(&self) -> u16 {
+        let rate = match self.state {
+            EnvelopeState::Attack => self.ad >> 4,
+            EnvelopeState::DecaySustain => self.ad & 0x0F,
+            EnvelopeState::Release => self.sr & 0x0F,
+        };
+        RATE_PERIODS[rate as usize]
+    }
+
+    // The rate counter is only compared for equality with the period, so
+    // switching to a shorter period than it has already counted past
+    // leaves the envelope stuck until the counter wraps at 2^15: the ADSR
+    // delay bug.
+    fn clock_envelope(&mut self) {
+        self.rate_counter = (self.rate_counter + 1) & 0x7FFF;
+        if self.rate_counter != self.rate_period() {
+            return;
+        }
+        self.rate_counter = 0;
+        if self.state != EnvelopeState::Attack {
+            self.exponential_counter += 1;
+            if self.exponential_counter < self.exponential_period() {
+                return;
+            }
+        }
+        self.exponential_counter = 0;
+        if self.hold_zero {
+            return;
+        }
+        match self.state {
+            EnvelopeState::Attack => {
+                self.level = self.level.wrapping_add(1);
+                if self.level == 0xFF {
+                    self.state = EnvelopeState::DecaySustain;
+                }
+            }
+            EnvelopeState::DecaySustain => {
+                if self.level != (self.sr >> 4) * 0x11 {
+                    self.level -= 1;
+                }
+            }
+            EnvelopeState::Release => self.level = self.level.wrapping_sub(1),
+        }
+        if self.level == 0 {
+            self.hold_zero = true;
+        }
+    }
+
+    // Decay and release slow down as the level falls, approximating an
+    // exponential curve.
+    fn exponential_period(&self) -> u8 {
+        match self.level {
+            0x5E..=0xFF => 1,
+            0x37..=0x5D => 2,
+            0x1B..=0x36 => 4,
+            0x0F..=0x1A => 8,
+            0x07..=0x0E => 16,
+            0x01..=0x06 => 30,
+            0x00 => 1,
+        }
+    }
+
+    /// The 12-bit waveform output, with `ring_msb` the accumulator MSB of
+    /// the voice that modulates this one. Combined waveforms are the AND of
+    /// their components, which is close to, but brighter than, the chips.
+    fn waveform(&self, ring_msb: bool) -> u16 {
+        if self.control & 0xF0 == 0 {
+            return 0;
+        }
+        let acc = self.accumulator;
+        let mut output = 0xFFF;
+        if self.control & TRIANGLE != 0 {
+            let mut msb = acc & 0x800000 != 0;
+            if self.control & RING != 0 {
+                msb ^= ring_msb;
+            }
+            let value = if msb { !acc } else { acc };
+            output &= (value >> 11) as u16 & 0xFFF;
+        }
+        if self.control & SAWTOOTH != 0 {
+            output &= (acc >> 12) as u16;
+        }
+        if self.control & PULSE != 0 && self.control & TEST == 0 && ((acc >> 12) as u16) < self.pw {
+            output = 0;
+        }
+        if self.control & NOISE != 0 {
+            let n = self.noise;
+            output &= ((n >> 9 & 0x800)
+                | (n >> 8 & 0x400)
+                | (n >> 5 & 0x200)
+                | (n >> 3 & 0x100)
+                | (n >> 2 & 0x080)
+                | (n << 1 & 0x040)
+                | (n << 3 & 0x020)
+                | (n << 4 & 0x010)) as u16;
+        }
+        output
+    }
+}
+
+impl SaveState for Voice {
+    fn save(&self, out: &mut StateWriter) {
+        out.u16(self.freq);
+        out.u16(self.pw);
+        for value in [self.control, self.ad, self.sr] {
+            out.u8(value);
+        }
+        out.u32(self.accumulator);
+        out.bool(self.msb_rising);
+        out.u32(self.noise);
+        out.u8(self.state as u8);
+        out.u8(self.level);
+        out.u16(self.rate_counter);
+        out.u8(self.exponential_counter);
+        out.bool(self.hold_zero);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("voice", version, 1)?;
+        self.freq = input.u16()?;
+        self.pw = input.u16()?;
+        for value in [&mut self.control, &mut self.ad, &mut self.sr] {
+            *value = input.u8()?;
+        }
+        self.accumulator = input.u32()? & 0xFFFFFF;
+        self.msb_rising = input.bool()?;
+        self.noise = input.u32()? & 0x7FFFFF;
+        self.state = match input.u8()? {
+            0 => EnvelopeState::Attack,
+            1 => EnvelopeState::DecaySustain,
+            2 => EnvelopeState::Release,
+            _ => return Err(savestate::Error::Invalid("invalid envelope state")),
+        };
+        self.level = input.u8()?;
+        self.rate_counter = input.u16()? & 0x7FFF;
+        self.exponential_counter = input.u8()?;
+        self.hold_zero = input.bool()?;
+        Ok(())
+    }
+}
+
+/// A MOS 6581 or 8580 Sound Interface Device. It occupies 32 registers.
+///
+/// The SID is clocked with the CPU and renders 16-bit mono samples at
+/// `sample_rate`, each the average of the output over the cycles it
+/// covers, for the host to collect with `take_samples`. The filter is a
+/// state-variable filter with approximate cutoff curves, and EXT IN is
+/// silent.
+#[derive(Debug, Clone)]
+pub struct Sid {
+    model: Model,
+    clock: u64,
+    sample_rate: u64,
+    voices: [Voice; 3],
+    fc: u16,
+    res_filt: u8,
+    mode_vol: u8,
+    pot_x: u8,
+    pot_y: u8,
+    bus_value: u8,
+
+    w0: f64,
+    inv_q: f64,
+    lp: f64,
+    bp: f64,
+    hp: f64,
+
+    phase: u64,
+    sum: f64,
+    count: u32,
+    samples: Vec<i16>,
+}
+
+impl Sid {
+    /// A SID clocked at `clock` Hz, rendering `sample_rate` samples a
+    /// second.
+    pub fn new(model: Model, clock: u64, sample_rate: u32) -> Self {
+        let mut sid = Sid {
+            model,
+            clock,
+            sample_rate: sample_rate as u64,
+            voices: [Voice::new(), Voice::new(), Voice::new()],
+            fc: 0,
+            res_filt: 0,
+            mode_vol: 0,
+            pot_x: 0xFF,
+            pot_y: 0xFF,
+            bus_value: 0,
+            w0: 0.0,
+            inv_q: 0.0,
+            lp: 0.0,
+            bp: 0.0,
+            hp: 0.0,
+            phase: 0,
+            sum: 0.0,
+            count: 0,
+            samples: Vec::new(),
+        };
+        sid.update_filter();
+        sid
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    /// Takes the samples rendered so far.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Sets the values read back from the paddle registers.
+    pub fn set_pots(&mut self, x: u8, y: u8) {
+        self.pot_x = x;
+        self.pot_y = y;
+    }
+
+    fn cutoff(&self) -> f64 {
+        let fc = self.fc as f64 / 2047.0;
+        match self.model {
+            Model::Mos6581 => 220.0 + 17780.0 * fc.powf(1.5),
+            Model::Mos8580 => 30.0 + 12470.0 * fc,
+        }
+    }
+
+    fn update_filter(&mut self) {
+        self.w0 = 2.0 * PI * self.cutoff() / self.clock as f64;
+        self.inv_q = 1.0 / (0.707 + (self.res_filt >> 4) as f64 / 15.0);
+    }
+
+    // The zero level of the waveform DACs. The 6581's sits low, so silent
+    // voices and volume changes add a DC offset, which is how samples are
+    // played through the volume register.
+    fn wave_zero(&self) -> f64 {
+        match self.model {
+            Model::Mos6581 => 0x380 as f64,
+            Model::Mos8580 => 0x800 as f64,
+        }
+    }
+
+    fn clock_cycle(&mut self) {
+        for voice in &mut self.voices {
+            voice.clock_oscillator();
+            voice.clock_envelope();
+        }
+        // Each voice is synced to, and ring modulated by, the one before.
+        for i in 0..3 {
+            let source = (i + 2) % 3;
+            if self.voices[i].control & SYNC != 0 && self.voices[source].msb_rising {
+                self.voices[i].accumulator = 0;
+            }
+        }
+
+        let (mut filtered, mut direct) = (0.0, 0.0);
+        for i in 0..3 {
+            let voice = &self.voices[i];
+            let ring_msb = self.voices[(i + 2) % 3].accumulator & 0x800000 != 0;
+            let wave = voice.waveform(ring_msb) as f64 - self.wave_zero();
+            let output = wave / 2048.0 * voice.level as f64 / 255.0;
+            if self.res_filt & 1 << i != 0 {
+                filtered += output;
+            } else if i != 2 || self.mode_vol & 0x80 == 0 {
+                direct += output;
+            }
+        }
+
+        self.bp -= self.w0 * self.hp;
+        self.lp -= self.w0 * self.bp;
+        self.hp = self.bp * self.inv_q - self.lp - filtered;
+        let mut output = direct;
+        for (bit, value) in [(0x10, self.lp), (0x20, self.bp), (0x40, self.hp)] {
+            if self.mode_vol & bit != 0 {
+                output += value;
+            }
+        }
+        if self.model == Model::Mos6581 {
+            output -= 1.0 / 9.0;
+        }
+        output *= (self.mode_vol & 0x0F) as f64 / 15.0;
+
+        self.sum += output;
+        self.count += 1;
+        self.phase += self.sample_rate;
+        if self.phase >= self.clock {
+            self.phase -= self.clock;
+            let sample = self.sum / self.count as f64 / 6.0 * i16::MAX as f64;
+            self.samples.push(sample.clamp(i16::MIN as f64, i16::MAX as f64) as i16);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+}
+
+impl Device for Sid {
+    fn peek(&self, offset: u16) -> u8 {
+        match offset & 0x1F {
+            0x19 => self.pot_x,
+            0x1A => self.pot_y,
+            0x1B => (self.voices[2].waveform(self.voices[1].accumulator & 0x800000 != 0) >> 4) as u8,
+            0x1C => self.voices[2].level,
+            // The write-only registers read back what was last written.
+            _ => self.bus_value,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        let reg = (offset & 0x1F) as usize;
+        self.bus_value = value;
+        if reg < 21 {
+            let voice = &mut self.voices[reg / 7];
+            match reg % 7 {
+                0 => voice.freq = (voice.freq & 0xFF00) | value as u16,
+                1 => voice.freq = (voice.freq & 0x00FF) | (value as u16) << 8,
+                2 => voice.pw = (voice.pw & 0x0F00) | value as u16,
+                3 => voice.pw = (voice.pw & 0x00FF) | (value as u16 & 0x0F) << 8,
+                4 => voice.write_control(value),
+                5 => voice.ad = value,
+                _ => voice.sr = value,
+            }
+            return;
+        }
+        match reg {
+            0x15 => self.fc = (self.fc & 0x7F8) | (value as u16 & 7),
+            0x16 => self.fc = (self.fc & 7) | (value as u16) << 3,
+            0x17 => self.res_filt = value,
+            0x18 => self.mode_vol = value,
+            _ => return,
+        }
+        self.update_filter();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.clock_cycle();
+        }
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+// The model, clock and sample rate are configuration, and the samples
+// not yet taken belong to the host, so none of them are saved.
+impl SaveState for Sid {
+    fn save(&self, out: &mut StateWriter) {
+        for (name, voice) in ["voice1", "voice2", "voice3"].into_iter().zip(&self.voices) {
+            out.section(name, voice);
+        }
+        out.u16(self.fc);
+        for value in [self.res_filt, self.mode_vol, self.pot_x, self.pot_y, self.bus_value] {
+            out.u8(value);
+        }
+        for value in [self.lp, self.bp, self.hp] {
+            out.f64(value);
+        }
+        out.u64(self.phase);
+        out.f64(self.sum);
+        out.u32(self.count);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("sid", version, 1)?;
+        for (name, voice) in ["voice1", "voice2", "voice3"].into_iter().zip(&mut self.voices) {
+            input.section(name, voice)?;
+        }
+        self.fc = input.u16()? & 0x7FF;
+        for value in [&mut self.res_filt, &mut self.mode_vol, &mut self.pot_x, &mut self.pot_y, &mut self.bus_value] {
+            *value = input.u8()?;
+        }
+        for value in [&mut self.lp, &mut self.bp, &mut self.hp] {
+            *value = input.f64()?;
+        }
+        self.phase = input.u64()? % self.clock.max(1);
+        self.sum = input.f64()?;
+        self.count = input.u32()?;
+        self.update_filter();
+        Ok(())
+    }
+}
+
+/// Writes 16-bit mono samples as a WAV file.
+pub fn write_wav(writer: &mut impl Write, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_size = samples.len() as u32 * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM, one channel.
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAL: u64 = 985_248;
+
+    fn rms(samples: &[i16]) -> f64 {
+        (samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    // Voice 3 at `freq` with `control`, attack 0 and full sustain.
+    fn voice3(sid: &mut Sid, freq: u16, control: u8) {
+        sid.write(0x0E, freq as u8);
+        sid.write(0x0F, (freq >> 8) as u8);
+        sid.write(0x13, 0x00);
+        sid.write(0x14, 0xF0);
+        sid.write(0x12, control);
+    }
+
+    #[test]
+    fn test_oscillators() {
+        let mut sid = Sid::new(Model::Mos8580, PAL, 44_100);
+        voice3(&mut sid, 0x1000, SAWTOOTH);
+        sid.tick(16);
+        // 16 * 0x1000 = 0x10000, so the top byte of the sawtooth is 1.
+        assert_eq!(sid.peek(0x1B), 0x01);
+        sid.write(0x12, SAWTOOTH | TEST);
+        assert_eq!(sid.peek(0x1B), 0x00);
+
+        // Pulse width $800 is a square wave.
+        sid.write(0x10, 0x00);
+        sid.write(0x11, 0x08);
+        sid.write(0x12, PULSE);
+        sid.tick(0x7FF);
+        assert_eq!(sid.peek(0x1B), 0x00);
+        sid.tick(1);
+        assert_eq!(sid.peek(0x1B), 0xFF);
+
+        // Noise changes with bit 19 of the accumulator.
+        sid.write(0x12, NOISE);
+        let mut values = std::collections::HashSet::new();
+        for _ in 0..256 {
+            sid.tick(256);
+            values.insert(sid.peek(0x1B));
+        }
+        assert!(values.len() > 64);
+    }
+
+    #[test]
+    fn test_sync_and_ring() {
+        let mut sid = Sid::new(Model::Mos8580, PAL, 44_100);
+        // Voice 2 runs faster than voice 1 but is synced to it.
+        sid.write(0x00, 0x00);
+        sid.write(0x01, 0x10);
+        sid.write(0x07, 0x00);
+        sid.write(0x08, 0x41);
+        sid.write(0x0B, SAWTOOTH | SYNC);
+        sid.tick(0x800);
+        assert_eq!(sid.voices[0].accumulator, 0x800000);
+        assert_eq!(sid.voices[1].accumulator, 0);
+
+        // Ring modulation inverts the triangle while the modulator's MSB is
+        // set.
+        voice3(&mut sid, 0x0100, TRIANGLE | RING);
+        assert_eq!(sid.voices[2].waveform(false), 0);
+        assert_eq!(sid.voices[2].waveform(true), 0xFFF);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut sid = Sid::new(Model::Mos8580, PAL, 44_100);
+        // Attack 0 steps every 9 cycles; decay 0 to sustain level 8.
+        sid.write(0x13, 0x00);
+        sid.write(0x14, 0x80);
+        sid.write(0x12, GATE);
+        sid.tick(9 * 255 - 1);
+        assert_eq!(sid.peek(0x1C), 0xFE);
+        sid.tick(1);
+        assert_eq!(sid.peek(0x1C), 0xFF);
+        sid.tick(100_000);
+        assert_eq!(sid.peek(0x1C), 0x88);
+        // Release to zero, where it stays.
+        sid.write(0x12, 0);
+        sid.tick(100_000);
+        assert_eq!(sid.peek(0x1C), 0x00);
+    }
+
+    #[test]
+    fn test_adsr_delay_bug() {
+        let mut sid = Sid::new(Model::Mos6581, PAL, 44_100);
+        // The slowest attack has counted 20000 cycles when it's switched to
+        // the fastest, so the counter has to wrap before the next step.
+        sid.write(0x13, 0xF0);
+        sid.write(0x12, GATE);
+        sid.tick(20_000);
+        assert_eq!(sid.peek(0x1C), 0);
+        sid.write(0x13, 0x00);
+        sid.tick(32_768 - 20_000);
+        assert_eq!(sid.peek(0x1C), 0);
+        sid.tick(9);
+        assert_eq!(sid.peek(0x1C), 1);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut sid = Sid::new(Model::Mos6581, PAL, 44_100);
+        sid.write(0x15, 0x03);
+        sid.write(0x16, 0x40);
+        sid.write(0x17, 0xF4);
+        sid.write(0x18, 0x1F);
+        voice3(&mut sid, 0x2345, NOISE | GATE);
+        sid.tick(10_000);
+        sid.take_samples();
+        let state = savestate::save("sid", &sid);
+
+        let mut restored = Sid::new(Model::Mos6581, PAL, 44_100);
+        savestate::load("sid", &mut restored, &state).unwrap();
+        assert_eq!((restored.peek(0x1B), restored.peek(0x1C)), (sid.peek(0x1B), sid.peek(0x1C)));
+        sid.tick(10_000);
+        restored.tick(10_000);
+        assert_eq!((restored.peek(0x1B), restored.peek(0x1C)), (sid.peek(0x1B), sid.peek(0x1C)));
+        assert_eq!(restored.take_samples(), sid.take_samples());
+    }
+
+    #[test]
+    fn test_filter() {
+        let render = |fc: u8, mode: u8| {
+            let mut sid = Sid::new(Model::Mos8580, PAL, 44_100);
+            voice3(&mut sid, 0x4000, SAWTOOTH | GATE);
+            sid.write(0x16, fc);
+            sid.write(0x17, 0x04);
+            sid.write(0x18, mode | 0x0F);
+            sid.tick(PAL / 10);
+            let samples = sid.take_samples();
+            assert_eq!(samples.len(), 4409);
+            rms(&samples[1000..])
+        };
+        // A 960 Hz sawtooth through low and high pass filters.
+        let open = render(0xFF, 0x10);
+        let closed = render(0x04, 0x10);
+        assert!(closed < open / 4.0, "{} {}", closed, open);
+        assert!(render(0x04, 0x40) > render(0xFF, 0x40) * 4.0);
+    }
+
+    #[test]
+    fn test_write_wav() {
+        let mut file = Vec::new();
+        write_wav(&mut file, 44_100, &[0, -1, 0x1234]).unwrap();
+        assert_eq!(file.len(), 44 + 6);
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(file[4..8], 42u32.to_le_bytes());
+        assert_eq!(file[24..28], 44_100u32.to_le_bytes());
+        assert_eq!(file[44..], [0x00, 0x00, 0xFF, 0xFF, 0x34, 0x12]);
+    }
+}
@@ -0,0 +1,713 @@
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+const CA2: u8 = 0x01;
+const CA1: u8 = 0x02;
+const SR: u8 = 0x04;
+const CB2: u8 = 0x08;
+const CB1: u8 = 0x10;
+const T2: u8 = 0x20;
+const T1: u8 = 0x40;
+
+/// A MOS 6522 Versatile Interface Adapter, clocked at the CPU rate. It
+/// occupies 16 registers.
+///
+/// The host side drives the input pins with `set_port_a`, `set_ca1` etc.
+/// and reads the outputs with `port_a`, `ca2` etc. Undriven inputs read as
+/// high, as if pulled up.
+#[derive(Debug, Clone)]
+pub struct Via {
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    pa_in: u8,
+    pb_in: u8,
+    pa_latch: u8,
+    pb_latch: u8,
+
+    t1_counter: u16,
+    t1_latch: u16,
+    t1_armed: bool,
+    t1_reload: bool,
+    pb7: bool,
+    t2_counter: u16,
+    t2_latch: u8,
+    t2_armed: bool,
+    t2_reload: bool,
+
+    sr: u8,
+    sr_bits: u8,
+    sr_active: bool,
+    // The CB1 shift clock output, and CB2 while shifting out.
+    sr_clock: bool,
+    sr_out: bool,
+
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+
+    ca1: bool,
+    ca2_in: bool,
+    ca2_out: bool,
+    ca2_pulse: bool,
+    cb1: bool,
+    cb2_in: bool,
+    cb2_out: bool,
+    cb2_pulse: bool,
+}
+
+impl Default for Via {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Via {
+    pub fn new() -> Self {
+        Via {
+            ora: 0,
+            orb: 0,
+            ddra: 0,
+            ddrb: 0,
+            pa_in: 0xFF,
+            pb_in: 0xFF,
+            pa_latch: 0,
+            pb_latch: 0,
+            t1_counter: 0xFFFF,
+            t1_latch: 0xFFFF,
+            t1_armed: false,
+            t1_reload: false,
+            pb7: true,
+            t2_counter: 0xFFFF,
+            t2_latch: 0xFF,
+            t2_armed: false,
+            t2_reload: false,
+            sr: 0,
+            sr_bits: 0,
+            sr_active: false,
+            sr_clock: true,
+            sr_out: true,
+            acr: 0,
+            pcr: 0,
+            ifr: 0,
+            ier: 0,
+            ca1: true,
+            ca2_in: true,
+            ca2_out: true,
+            ca2_pulse: false,
+            cb1: true,
+            cb2_in: true,
+            cb2_out: true,
+            cb2_pulse: false,
+        }
+    }
+
+    /// The levels of the PA pins.
+    pub fn port_a(&self) -> u8 {
+        (self.ora & self.ddra) | (self.pa_in & !self.ddra)
+    }
+
+    /// The levels of the PB pins, with PB7 driven by timer 1 if enabled.
+    pub fn port_b(&self) -> u8 {
+        let value = (self.orb & self.ddrb) | (self.pb_in & !self.ddrb);
+        match self.acr & 0x80 {
+            0 => value,
+            _ => (value & 0x7F) | (self.pb7 as u8) << 7,
+        }
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.pa_in = value;
+    }
+
+    /// Drives the PB inputs. Timer 2 counts falling edges on PB6 in pulse
+    /// counting mode.
+    pub fn set_port_b(&mut self, value: u8) {
+        let falling = self.pb_in & !value & 0x40 != 0;
+        self.pb_in = value;
+        if falling && self.acr & 0x20 != 0 {
+            self.count_t2();
+        }
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        if self.edge(self.ca1, level, self.pcr & 0x01 != 0) {
+            self.ifr |= CA1;
+            self.pa_latch = self.port_a();
+            if self.ca2_mode() == 4 {
+                self.ca2_out = true;
+            }
+        }
+        self.ca1 = level;
+    }
+
+    pub fn set_ca2(&mut self, level: bool) {
+        let mode = self.ca2_mode();
+        if mode < 4 && self.edge(self.ca2_in, level, mode & 2 != 0) {
+            self.ifr |= CA2;
+        }
+        self.ca2_in = level;
+    }
+
+    pub fn set_cb1(&mut self, level: bool) {
+        if self.edge(self.cb1, level, self.pcr & 0x10 != 0) {
+            self.ifr |= CB1;
+            self.pb_latch = self.port_b();
+            if self.cb2_mode() == 4 {
+                self.cb2_out = true;
+            }
+        }
+        if matches!(self.sr_mode(), 3 | 7) && level != self.cb1 {
+            self.shift_edge(level);
+        }
+        self.cb1 = level;
+    }
+
+    pub fn set_cb2(&mut self, level: bool) {
+        let mode = self.cb2_mode();
+        if mode < 4 && self.edge(self.cb2_in, level, mode & 2 != 0) {
+            self.ifr |= CB2;
+        }
+        self.cb2_in = level;
+    }
+
+    pub fn ca2(&self) -> bool {
+        match self.ca2_mode() {
+            0..=3 => self.ca2_in,
+            4 | 5 => self.ca2_out,
+            6 => false,
+            _ => true,
+        }
+    }
+
+    /// CB1, which is the shift clock output in the internally clocked
+    /// shift register modes.
+    pub fn cb1(&self) -> bool {
+        match self.sr_mode() {
+            1 | 2 | 4 | 5 | 6 => self.sr_clock,
+            _ => self.cb1,
+        }
+    }
+
+    /// CB2, which carries the data in the shift out modes.
+    pub fn cb2(&self) -> bool {
+        match (self.sr_mode(), self.cb2_mode()) {
+            (4..=7, _) => self.sr_out,
+            (_, 0..=3) => self.cb2_in,
+            (_, 4 | 5) => self.cb2_out,
+            (_, 6) => false,
+            _ => true,
+        }
+    }
+
+    fn edge(&self, old: bool, new: bool, positive: bool) -> bool {
+        old != new && new == positive
+    }
+
+    fn ca2_mode(&self) -> u8 {
+        (self.pcr >> 1) & 7
+    }
+
+    fn cb2_mode(&self) -> u8 {
+        (self.pcr >> 5) & 7
+    }
+
+    fn sr_mode(&self) -> u8 {
+        (self.acr >> 2) & 7
+    }
+
+    // Accessing port A or B clears its flags, except CA2/CB2 flags in the
+    // independent interrupt modes.
+    fn clear_port_flags(&mut self, c1: u8, c2: u8, mode: u8) {
+        self.ifr &= !c1;
+        if !matches!(mode, 1 | 3) {
+            self.ifr &= !c2;
+        }
+    }
+
+    fn start_shift(&mut self) {
+        self.ifr &= !SR;
+        self.sr_bits = 0;
+        self.sr_active = self.sr_mode() != 0;
+    }
+
+    // One edge of the shift clock: data goes out on falling edges, and is
+    // sampled and counted on rising edges.
+    fn shift_edge(&mut self, rising: bool) {
+        let mode = self.sr_mode();
+        if !self.sr_active || mode == 0 {
+            return;
+        }
+        if !rising {
+            if mode >= 4 {
+                self.sr_out = self.sr & 0x80 != 0;
+                self.sr = self.sr.rotate_left(1);
+            }
+            return;
+        }
+        if mode < 4 {
+            self.sr = (self.sr << 1) | self.cb2_in as u8;
+        }
+        self.sr_bits += 1;
+        if self.sr_bits == 8 && mode != 4 {
+            self.sr_active = false;
+            self.ifr |= SR;
+        }
+        self.sr_bits %= 8;
+    }
+
+    fn toggle_shift_clock(&mut self) {
+        if self.sr_active {
+            self.sr_clock = !self.sr_clock;
+            self.shift_edge(self.sr_clock);
+        }
+    }
+
+    // One count of timer 2 as a 16-bit timer.
+    fn count_t2(&mut self) {
+        let (counter, underflow) = self.t2_counter.overflowing_sub(1);
+        self.t2_counter = counter;
+        if underflow && self.t2_armed {
+            self.ifr |= T2;
+            self.t2_armed = false;
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.ca2_pulse {
+            self.ca2_pulse = false;
+            self.ca2_out = true;
+        }
+        if self.cb2_pulse {
+            self.cb2_pulse = false;
+            self.cb2_out = true;
+        }
+
+        if self.t1_reload {
+            self.t1_reload = false;
+            self.t1_counter = self.t1_latch;
+        } else {
+            let (counter, underflow) = self.t1_counter.overflowing_sub(1);
+            self.t1_counter = counter;
+            if underflow {
+                let free_running = self.acr & 0x40 != 0;
+                if self.t1_armed {
+                    self.ifr |= T1;
+                    self.pb7 = !free_running || !self.pb7;
+                    self.t1_armed = free_running;
+                }
+                self.t1_reload = free_running;
+            }
+        }
+
+        match self.sr_mode() {
+            // Timer 2's low byte divides the shift clock.
+            1 | 4 | 5 => {
+                let low = match self.t2_reload {
+                    true => self.t2_latch,
+                    false => (self.t2_counter as u8).wrapping_sub(1),
+                };
+                self.t2_reload = !self.t2_reload && low == 0xFF;
+                self.t2_counter = (self.t2_counter & 0xFF00) | low as u16;
+                if self.t2_reload {
+                    self.toggle_shift_clock();
+                }
+            }
+            mode => {
+                if self.acr & 0x20 == 0 {
+                    self.count_t2();
+                }
+                if matches!(mode, 2 | 6) {
+                    self.toggle_shift_clock();
+                }
+            }
+        }
+    }
+
+    fn read_register(&self, reg: u16) -> u8 {
+        match reg {
+            0x0 => {
+                let pins = if self.acr & 0x02 != 0 { self.pb_latch } else { self.port_b() };
+                (self.orb & self.ddrb) | (pins & !self.ddrb)
+            }
+            0x1 | 0xF => if self.acr & 0x01 != 0 { self.pa_latch } else { self.port_a() },
+            0x2 => self.ddrb,
+            0x3 => self.ddra,
+            0x4 => self.t1_counter as u8,
+            0x5 => (self.t1_counter >> 8) as u8,
+            0x6 => self.t1_latch as u8,
+            0x7 => (self.t1_latch >> 8) as u8,
+            0x8 => self.t2_counter as u8,
+            0x9 => (self.t2_counter >> 8) as u8,
+            0xA => self.sr,
+            0xB => self.acr,
+            0xC => self.pcr,
+            0xD => self.ifr | ((self.ifr & self.ier & 0x7F != 0) as u8) << 7,
+            _ => self.ier | 0x80,
+        }
+    }
+}
+
+impl Device for Via {
+    fn peek(&self, offset: u16) -> u8 {
+        self.read_register(offset & 0xF)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        let reg = offset & 0xF;
+        let value = self.read_register(reg);
+        match reg {
+            0x0 => self.clear_port_flags(CB1, CB2, self.cb2_mode()),
+            0x1 => {
+                self.clear_port_flags(CA1, CA2, self.ca2_mode());
+                if matches!(self.ca2_mode(), 4 | 5) {
+                    self.ca2_out = false;
+                    self.ca2_pulse = self.ca2_mode() == 5;
+                }
+            }
+            0x4 => self.ifr &= !T1,
+            0x8 => self.ifr &= !T2,
+            0xA => self.start_shift(),
+            _ => {}
+        }
+        value
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset & 0xF {
+            0x0 => {
+                self.orb = value;
+                self.clear_port_flags(CB1, CB2, self.cb2_mode());
+                if matches!(self.cb2_mode(), 4 | 5) {
+                    self.cb2_out = false;
+                    self.cb2_pulse = self.cb2_mode() == 5;
+                }
+            }
+            0x1 => {
+                self.ora = value;
+                self.clear_port_flags(CA1, CA2, self.ca2_mode());
+                if matches!(self.ca2_mode(), 4 | 5) {
+                    self.ca2_out = false;
+                    self.ca2_pulse = self.ca2_mode() == 5;
+                }
+            }
+            0x2 => self.ddrb = value,
+            0x3 => self.ddra = value,
+            0x4 | 0x6 => self.t1_latch = (self.t1_latch & 0xFF00) | value as u16,
+            0x5 => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | (value as u16) << 8;
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.t1_reload = false;
+                self.ifr &= !T1;
+                self.pb7 = false;
+            }
+            0x7 => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | (value as u16) << 8;
+                self.ifr &= !T1;
+            }
+            0x8 => self.t2_latch = value,
+            0x9 => {
+                self.t2_counter = (value as u16) << 8 | self.t2_latch as u16;
+                self.t2_armed = true;
+                self.t2_reload = false;
+                self.ifr &= !T2;
+            }
+            0xA => {
+                self.sr = value;
+                self.start_shift();
+            }
+            0xB => {
+                self.acr = value;
+                if self.sr_mode() == 0 {
+                    self.sr_active = false;
+                }
+            }
+            0xC => self.pcr = value,
+            0xD => self.ifr &= !value,
+            0xE => match value & 0x80 {
+                0 => self.ier &= !value,
+                _ => self.ier |= value & 0x7F,
+            },
+            _ => self.ora = value,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.ifr & self.ier & 0x7F != 0
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Via {
+    fn save(&self, out: &mut StateWriter) {
+        for value in [
+            self.ora, self.orb, self.ddra, self.ddrb, self.pa_in, self.pb_in, self.pa_latch, self.pb_latch,
+            self.t2_latch, self.sr, self.sr_bits, self.acr, self.pcr, self.ifr, self.ier,
+        ] {
+            out.u8(value);
+        }
+        for value in [self.t1_counter, self.t1_latch, self.t2_counter] {
+            out.u16(value);
+        }
+        for value in [
+            self.t1_armed, self.t1_reload, self.pb7, self.t2_armed, self.t2_reload, self.sr_active, self.sr_clock,
+            self.sr_out, self.ca1, self.ca2_in, self.ca2_out, self.ca2_pulse, self.cb1, self.cb2_in, self.cb2_out,
+            self.cb2_pulse,
+        ] {
+            out.bool(value);
+        }
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("via", version, 1)?;
+        for value in [
+            &mut self.ora, &mut self.orb, &mut self.ddra, &mut self.ddrb, &mut self.pa_in, &mut self.pb_in,
+            &mut self.pa_latch, &mut self.pb_latch, &mut self.t2_latch, &mut self.sr, &mut self.sr_bits,
+            &mut self.acr, &mut self.pcr, &mut self.ifr, &mut self.ier,
+        ] {
+            *value = input.u8()?;
+        }
+        for value in [&mut self.t1_counter, &mut self.t1_latch, &mut self.t2_counter] {
+            *value = input.u16()?;
+        }
+        for value in [
+            &mut self.t1_armed, &mut self.t1_reload, &mut self.pb7, &mut self.t2_armed, &mut self.t2_reload,
+            &mut self.sr_active, &mut self.sr_clock, &mut self.sr_out, &mut self.ca1, &mut self.ca2_in,
+            &mut self.ca2_out, &mut self.ca2_pulse, &mut self.cb1, &mut self.cb2_in, &mut self.cb2_out,
+            &mut self.cb2_pulse,
+        ] {
+            *value = input.bool()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORB: u16 = 0x0;
+    const ORA: u16 = 0x1;
+    const DDRB: u16 = 0x2;
+    const DDRA: u16 = 0x3;
+    const T1CL: u16 = 0x4;
+    const T1CH: u16 = 0x5;
+    const T2CL: u16 = 0x8;
+    const T2CH: u16 = 0x9;
+    const SR_: u16 = 0xA;
+    const ACR: u16 = 0xB;
+    const PCR: u16 = 0xC;
+    const IFR: u16 = 0xD;
+    const IER: u16 = 0xE;
+
+    // Cycles until the flags in `mask` are set.
+    fn cycles_until(via: &mut Via, mask: u8, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            via.tick(1);
+            via.peek(IFR) & mask != 0
+        })
+    }
+
+    #[test]
+    fn test_ports() {
+        let mut via = Via::new();
+        via.write(DDRA, 0xF0);
+        via.write(ORA, 0xAA);
+        via.set_port_a(0x05);
+        assert_eq!(via.port_a(), 0xA5);
+        assert_eq!(via.read(ORA), 0xA5);
+
+        via.write(DDRB, 0x0F);
+        via.write(ORB, 0x3C);
+        via.set_port_b(0x00);
+        assert_eq!(via.read(ORB), 0x0C);
+        assert_eq!(via.port_b(), 0x0C);
+    }
+
+    #[test]
+    fn test_t1_one_shot() {
+        let mut via = Via::new();
+        via.write(ACR, 0x80);
+        via.write(T1CL, 10);
+        via.write(T1CH, 0);
+        assert_eq!(via.port_b() & 0x80, 0);
+        // Fires as the counter rolls over from zero.
+        assert_eq!(cycles_until(&mut via, T1, 100), Some(11));
+        assert_eq!(via.port_b() & 0x80, 0x80);
+
+        // Reading the low counter clears the flag; no second interrupt.
+        via.read(T1CL);
+        assert_eq!(cycles_until(&mut via, T1, 1000), None);
+    }
+
+    #[test]
+    fn test_t1_free_running() {
+        let mut via = Via::new();
+        via.write(ACR, 0xC0);
+        via.write(IER, 0x80 | T1);
+        via.write(T1CL, 10);
+        via.write(T1CH, 0);
+        assert_eq!(cycles_until(&mut via, T1, 100), Some(11));
+        assert!(via.irq());
+        assert_eq!(via.read(IFR), 0x80 | T1);
+        let pb7 = via.port_b() & 0x80;
+        via.write(IFR, T1);
+        assert!(!via.irq());
+        // Reloads from the latch with a period of N + 2.
+        assert_eq!(cycles_until(&mut via, T1, 100), Some(12));
+        assert_ne!(via.port_b() & 0x80, pb7);
+    }
+
+    #[test]
+    fn test_t2() {
+        let mut via = Via::new();
+        via.write(T2CL, 5);
+        via.write(T2CH, 0);
+        assert_eq!(cycles_until(&mut via, T2, 100), Some(6));
+        via.read(T2CL);
+        assert_eq!(cycles_until(&mut via, T2, 70_000), None);
+
+        // Pulse counting on PB6.
+        via.write(ACR, 0x20);
+        via.write(T2CL, 2);
+        via.write(T2CH, 0);
+        via.tick(100);
+        for _ in 0..3 {
+            assert_eq!(via.peek(IFR) & T2, 0);
+            via.set_port_b(0x00);
+            via.set_port_b(0xFF);
+        }
+        assert_eq!(via.peek(IFR) & T2, T2);
+    }
+
+    #[test]
+    fn test_shift_out() {
+        let mut via = Via::new();
+        // Shift out under φ2.
+        via.write(ACR, 0x18);
+        via.write(SR_, 0xA5);
+        let mut bits = Vec::new();
+        for _ in 0..8 {
+            via.tick(1);
+            bits.push(via.cb2() as u8);
+            via.tick(1);
+        }
+        assert_eq!(bits, [1, 0, 1, 0, 0, 1, 0, 1]);
+        assert_eq!(via.peek(IFR) & SR, SR);
+        via.tick(100);
+        assert_eq!(via.peek(SR_), 0xA5);
+
+        // Under timer 2, each half bit takes N + 2 cycles.
+        via.write(ACR, 0x14);
+        via.write(T2CL, 3);
+        via.write(T2CH, 0);
+        via.write(SR_, 0xFF);
+        assert_eq!(cycles_until(&mut via, SR, 1000), Some(4 + 15 * 5));
+    }
+
+    #[test]
+    fn test_shift_in_external() {
+        let mut via = Via::new();
+        via.write(ACR, 0x0C);
+        via.read(SR_);
+        for bit in [0, 1, 1, 0, 0, 0, 1, 1] {
+            via.set_cb2(bit == 1);
+            via.set_cb1(false);
+            via.set_cb1(true);
+        }
+        assert_eq!(via.peek(SR_), 0x63);
+        assert_eq!(via.peek(IFR) & SR, SR);
+        via.read(SR_);
+        assert_eq!(via.peek(IFR) & SR, 0);
+    }
+
+    #[test]
+    fn test_ca1_latch_and_handshake() {
+        let mut via = Via::new();
+        // CA1 positive edge, CA2 handshake output, port A latching.
+        via.write(PCR, 0x09);
+        via.write(ACR, 0x01);
+        via.write(IER, 0x80 | CA1);
+        via.set_port_a(0x42);
+        via.set_ca1(false);
+        via.set_ca1(true);
+        assert!(via.irq());
+        via.set_port_a(0x00);
+        assert_eq!(via.read(ORA), 0x42);
+        assert!(!via.irq());
+        // Reading ORA pulls CA2 low until the next CA1 edge.
+        assert!(!via.ca2());
+        via.set_ca1(false);
+        assert!(!via.ca2());
+        via.set_ca1(true);
+        assert!(via.ca2());
+
+        // Pulse output mode: low for one cycle after a write.
+        via.write(PCR, 0x0A);
+        via.write(ORA, 0);
+        assert!(!via.ca2());
+        via.tick(1);
+        assert!(via.ca2());
+    }
+
+    #[test]
+    fn test_ca2_independent_interrupt() {
+        let mut via = Via::new();
+        // CA2 independent interrupt on a negative edge.
+        via.write(PCR, 0x02);
+        via.set_ca2(false);
+        assert_eq!(via.peek(IFR) & CA2, CA2);
+        via.read(ORA);
+        assert_eq!(via.peek(IFR) & CA2, CA2);
+        via.write(IFR, CA2);
+        assert_eq!(via.peek(IFR), 0);
+    }
+
+    #[test]
+    fn test_interrupt_enable() {
+        let mut via = Via::new();
+        via.write(IER, 0x80 | T1 | T2);
+        assert_eq!(via.read(IER), 0x80 | T1 | T2);
+        via.write(IER, T1);
+        assert_eq!(via.read(IER), 0x80 | T2);
+        via.write(T1CL, 1);
+        via.write(T1CH, 0);
+        via.tick(5);
+        assert_eq!(via.read(IFR), T1);
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut via = Via::new();
+        via.write(DDRA, 0x0F);
+        via.write(ORA, 0x05);
+        via.write(IER, 0x80 | T1);
+        via.write(T1CL, 10);
+        via.write(T1CH, 0);
+        via.tick(4);
+        let state = savestate::save("via", &via);
+
+        let mut restored = Via::new();
+        savestate::load("via", &mut restored, &state).unwrap();
+        assert_eq!(restored.port_a() & 0x0F, 0x05);
+        assert_eq!(cycles_until(&mut restored, T1, 20), cycles_until(&mut via, T1, 20));
+        assert!(restored.irq());
+    }
+}
@@ -0,0 +1,584 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::debugger::{Access, Debugger, StopReason, WatchKind};
+use crate::{Bus, Cpu, Variant};
+
+// Instructions executed between checks for a ^C from the client.
+const CONTINUE_CHUNK: u64 = 10_000;
+// Instructions kept for reverse execution.
+const JOURNAL_LIMIT: usize = 100_000;
+// The largest packet we accept, as told to the client. Memory reads are
+// cut to what fits in a reply.
+const PACKET_SIZE: usize = 0x1000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>6502</architecture>
+  <feature name="org.6502.core">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// What the session should do after a packet has been handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Reply(String),
+    Step,
+    Continue,
+    ReverseStep,
+    ReverseContinue,
+    Detach,
+    Kill,
+}
+
+/// Handles GDB remote serial protocol packets against a `Cpu` and its bus.
+///
+/// Registers are exposed in the order A, X, Y, SP, PC, P, with PC 16 bits
+/// wide and the rest 8 bits.
+#[derive(Default)]
+pub struct GdbStub {
+    pub debugger: Debugger,
+    // (Z packet type, address, kind) -> debugger id
+    points: Vec<(u8, u16, u16, usize)>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, b| sum.wrapping_add(b))
+}
+
+pub fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint { .. } => format!("T{:02x}swbreak:;", SIGTRAP),
+        StopReason::Watchpoint { addr, access, kind, .. } => {
+            let name = match (kind, access) {
+                (_, Access::Execute) => return format!("T{:02x}hwbreak:;", SIGTRAP),
+                (WatchKind::ReadWrite, _) => "awatch",
+                (_, Access::Write) => "watch",
+                (_, Access::Read) => "rwatch",
+            };
+            format!("T{:02x}{}:{:04x};", SIGTRAP, name, addr)
+        }
+        StopReason::IllegalOpcode { .. } => format!("S{:02x}", SIGILL),
+        StopReason::Step | StopReason::Limit => format!("S{:02x}", SIGTRAP),
+        StopReason::HistoryStart => format!("T{:02x}replaylog:begin;", SIGTRAP),
+    }
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        let mut stub = Self::default();
+        stub.debugger.set_journal_limit(JOURNAL_LIMIT);
+        stub
+    }
+
+    fn registers<V>(cpu: &Cpu<V>) -> Vec<u8> {
+        let [pc_low, pc_high] = cpu.pc.to_le_bytes();
+        vec![
+            cpu.reg.get_a(), cpu.reg.get_x(), cpu.reg.get_y(),
+            cpu.sp, pc_low, pc_high, cpu.reg.get_status(false),
+        ]
+    }
+
+    fn set_register<V>(cpu: &mut Cpu<V>, n: u32, bytes: &[u8]) -> Option<()> {
+        // Setting a register must not disturb the flags, so go through P.
+        let p = cpu.reg.get_status(false);
+        match (n, bytes) {
+            (0, [v]) => cpu.reg.update_a(*v),
+            (1, [v]) => cpu.reg.update_x(*v),
+            (2, [v]) => cpu.reg.update_y(*v),
+            (3, [v]) => cpu.sp = *v,
+            (4, [low, high]) => cpu.pc = u16::from_le_bytes([*low, *high]),
+            (5, [v]) => cpu.reg.set_status(*v),
+            _ => return None,
+        }
+        if n != 5 {
+            cpu.reg.set_status(p);
+        }
+        Some(())
+    }
+
+    fn set_registers<V>(cpu: &mut Cpu<V>, bytes: &[u8]) -> Option<()> {
+        let [a, x, y, sp, pc_low, pc_high, p] = *bytes else { return None };
+        cpu.reg.update_a(a);
+        cpu.reg.update_x(x);
+        cpu.reg.update_y(y);
+        cpu.reg.set_status(p);
+        cpu.sp = sp;
+        cpu.pc = u16::from_le_bytes([pc_low, pc_high]);
+        Some(())
+    }
+
+    fn insert_point(&mut self, kind: u8, addr: u16, len: u16) -> Option<()> {
+        let end = addr.checked_add(len.max(1) - 1)?;
+        let id = match kind {
+            b'0' | b'1' => self.debugger.add_breakpoint(addr),
+            b'2' => self.debugger.add_watchpoint(addr..=end, WatchKind::Write),
+            b'3' => self.debugger.add_watchpoint(addr..=end, WatchKind::Read),
+            b'4' => self.debugger.add_watchpoint(addr..=end, WatchKind::ReadWrite),
+            _ => return None,
+        };
+        self.points.push((kind, addr, len, id));
+        Some(())
+    }
+
+    fn remove_point(&mut self, kind: u8, addr: u16, len: u16) {
+        let pos = self.points.iter().position(|p| (p.0, p.1, p.2) == (kind, addr, len));
+        if let Some(pos) = pos {
+            let (_, _, _, id) = self.points.remove(pos);
+            self.debugger.remove(id);
+        }
+    }
+
+    fn read_features(annex: &str) -> Option<String> {
+        let (name, range) = annex.split_once(':')?;
+        let (offset, len) = range.split_once(',')?;
+        let (offset, len) = (parse_hex(offset)? as usize, parse_hex(len)? as usize);
+        if name != "target.xml" {
+            return None;
+        }
+        let doc = TARGET_XML.get(offset.min(TARGET_XML.len())..)?;
+        Some(match doc.len() > len {
+            true => format!("m{}", &doc[..len]),
+            false => format!("l{}", doc),
+        })
+    }
+
+    /// Handles one packet (without framing). Unsupported packets get an
+    /// empty reply as the protocol requires.
+    pub fn handle<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B, packet: &str) -> Action {
+        self.try_handle(cpu, bus, packet)
+            .unwrap_or_else(|| Action::Reply("E01".to_string()))
+    }
+
+    fn try_handle<V: Variant, B: Bus>(&mut self, cpu: &mut Cpu<V>, bus: &mut B, packet: &str) -> Option<Action> {
+        let reply = |s: &str| Some(Action::Reply(s.to_string()));
+        let (command, args) = packet.split_at(packet.len().min(1));
+        match command {
+            "?" => reply(&format!("S{:02x}", SIGTRAP)),
+            "g" => reply(&hex(&Self::registers(cpu))),
+            "G" => {
+                Self::set_registers(cpu, &unhex(args)?)?;
+                self.debugger.clear_journal();
+                reply("OK")
+            }
+            "p" => {
+                let regs = Self::registers(cpu);
+                match parse_hex(args)? {
+                    n @ 0..=3 => reply(&hex(&regs[n as usize..n as usize + 1])),
+                    4 => reply(&hex(&regs[4..6])),
+                    5 => reply(&hex(&regs[6..7])),
+                    _ => None,
+                }
+            }
+            "P" => {
+                let (n, value) = args.split_once('=')?;
+                Self::set_register(cpu, parse_hex(n)?, &unhex(value)?)?;
+                self.debugger.clear_journal();
+                reply("OK")
+            }
+            "m" => {
+                let (addr, len) = args.split_once(',')?;
+                let (addr, len) = (parse_hex(addr)? as u16, parse_hex(len)? as usize);
+                let bytes: Vec<u8> = (0..len.min(PACKET_SIZE / 2))
+                    .map(|i| bus.peek(addr.wrapping_add(i as u16)))
+                    .collect();
+                reply(&hex(&bytes))
+            }
+            "M" => {
+                let (range, data) = args.split_once(':')?;
+                let (addr, _) = range.split_once(',')?;
+                let addr = parse_hex(addr)?;
+                for (i, byte) in unhex(data)?.into_iter().enumerate() {
+                    bus.write((addr as u16).wrapping_add(i as u16), byte);
+                }
+                // Undoing past a write made by hand would lose it.
+                self.debugger.clear_journal();
+                reply("OK")
+            }
+            "s" | "c" => {
+                if !args.is_empty() {
+                    cpu.pc = parse_hex(args)? as u16;
+                }
+                Some(if command == "s" { Action::Step } else { Action::Continue })
+            }
+            "b" => match args {
+                "s" => Some(Action::ReverseStep),
+                "c" => Some(Action::ReverseContinue),
+                _ => reply(""),
+            },
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let kind = fields.next()?.bytes().next()?;
+                let addr = parse_hex(fields.next()?)? as u16;
+                let len = parse_hex(fields.next()?)? as u16;
+                if !(b'0'..=b'4').contains(&kind) {
+                    return reply("");
+                }
+                if command == "Z" {
+                    self.insert_point(kind, addr, len)?;
+                } else {
+                    self.remove_point(kind, addr, len);
+                }
+                reply("OK")
+            }
+            "H" | "T" => reply("OK"),
+            "D" => Some(Action::Detach),
+            "k" => Some(Action::Kill),
+            _ => match packet {
+                "qAttached" => reply("1"),
+                "qC" => reply("QC1"),
+                "qfThreadInfo" => reply("m1"),
+                "qsThreadInfo" => reply("l"),
+                "qOffsets" => reply("Text=0;Data=0;Bss=0"),
+                _ if packet.starts_with("qSupported") => {
+                    reply(&format!(
+                        "PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+;ReverseStep+;ReverseContinue+",
+                        PACKET_SIZE
+                    ))
+                }
+                _ if packet.starts_with("qXfer:features:read:") => {
+                    Some(Action::Reply(Self::read_features(&packet["qXfer:features:read:".len()..])?))
+                }
+                _ => reply(""),
+            },
+        }
+    }
+}
+
+/// A single client connection speaking the packet framing of the protocol.
+pub struct Session<'a, V, B> {
+    stream: TcpStream,
+    no_ack: bool,
+    pub stub: GdbStub,
+    pub cpu: &'a mut Cpu<V>,
+    pub bus: &'a mut B,
+}
+
+impl<'a, V: Variant, B: Bus> Session<'a, V, B> {
+    pub fn new(stream: TcpStream, cpu: &'a mut Cpu<V>, bus: &'a mut B) -> Self {
+        Session { stream, no_ack: false, stub: GdbStub::new(), cpu, bus }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Reads the next packet, answering interrupt requests with a stop
+    /// reply. Returns `None` when the client hangs up.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(0x03) => {
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                }
+            }
+            let mut sum = [0; 2];
+            self.stream.read_exact(&mut sum)?;
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let valid = core::str::from_utf8(&sum).ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .is_some_and(|sum| sum == checksum(&data));
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid || self.no_ack {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.stream.peek(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(n) if n > 0 && byte[0] == 0x03 => {
+                self.stream.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn resume(&mut self, reverse: bool) -> io::Result<String> {
+        loop {
+            let debugger = &mut self.stub.debugger;
+            let reason = match reverse {
+                false => debugger.run_until_stop(self.cpu, self.bus, CONTINUE_CHUNK),
+                true => debugger.reverse_continue(self.cpu, self.bus, CONTINUE_CHUNK),
+            };
+            if reason != StopReason::Limit {
+                return Ok(stop_reply(reason));
+            }
+            if self.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    /// Serves packets until the client detaches, kills or disconnects.
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            if packet == "QStartNoAckMode" {
+                self.send("OK")?;
+                self.no_ack = true;
+                continue;
+            }
+            let reply = match self.stub.handle(self.cpu, self.bus, &packet) {
+                Action::Reply(reply) => reply,
+                Action::Step => {
+                    let reason = self.stub.debugger.step(self.cpu, self.bus);
+                    stop_reply(reason.unwrap_or(StopReason::Limit))
+                }
+                Action::ReverseStep => {
+                    let reason = self.stub.debugger.reverse_step(self.cpu, self.bus);
+                    stop_reply(reason.unwrap_or(StopReason::Limit))
+                }
+                Action::Continue => self.resume(false)?,
+                Action::ReverseContinue => self.resume(true)?,
+                Action::Detach => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                Action::Kill => return Ok(()),
+            };
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+}
+
+/// Listens on `addr` and serves a single debugger connection.
+pub fn serve<V: Variant, B: Bus>(addr: impl ToSocketAddrs, cpu: &mut Cpu<V>, bus: &mut B) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    Session::new(stream, cpu, bus).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmos6502::Nmos6502;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    fn get_machine() -> (Cpu<Nmos6502>, MockBus) {
+        let mut bus = MockBus([0; 65536]);
+        // $0200: LDA #$42; STA $10; JMP $0200
+        let program = [0xA9, 0x42, 0x85, 0x10, 0x4C, 0x00, 0x02];
+        bus.0[0x0200..0x0200 + program.len()].copy_from_slice(&program);
+        let mut cpu = Cpu::<Nmos6502>::new();
+        cpu.pc = 0x0200;
+        cpu.sp = 0xFD;
+        (cpu, bus)
+    }
+
+    fn reply(s: &str) -> Action {
+        Action::Reply(s.to_string())
+    }
+
+    #[test]
+    fn test_registers() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut stub = GdbStub::new();
+        cpu.reg.update_a(0x12);
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "g"), reply("120000fd000220"));
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "p4"), reply("0002"));
+
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "G010203ff3412e1"), reply("OK"));
+        assert_eq!((cpu.reg.get_a(), cpu.reg.get_x(), cpu.reg.get_y()), (1, 2, 3));
+        assert_eq!((cpu.sp, cpu.pc), (0xFF, 0x1234));
+        assert!(cpu.reg.c && cpu.reg.n && !cpu.reg.z);
+
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "P0=00"), reply("OK"));
+        assert_eq!(cpu.reg.get_a(), 0);
+        assert!(!cpu.reg.z, "flags are only changed through P");
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "P4=0002"), reply("OK"));
+        assert_eq!(cpu.pc, 0x0200);
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "P9=00"), reply("E01"));
+    }
+
+    #[test]
+    fn test_memory() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut stub = GdbStub::new();
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "m200,3"), reply("a94285"));
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "M10,2:beef"), reply("OK"));
+        assert_eq!((bus.read(0x10), bus.read(0x11)), (0xBE, 0xEF));
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "M10,2:bee"), reply("E01"));
+
+        // Reads wrap around the address space and are cut to a packet.
+        (bus.0[0xFFFF], bus.0[0]) = (0x12, 0x34);
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "mffffffff,2"), reply("1234"));
+        match stub.handle(&mut cpu, &mut bus, "m0,ffffffff") {
+            Action::Reply(data) => assert_eq!(data.len(), PACKET_SIZE),
+            action => panic!("unexpected {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut stub = GdbStub::new();
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "Z0,204,1"), reply("OK"));
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "c"), Action::Continue);
+        let reason = stub.debugger.run_until_stop(&mut cpu, &mut bus, 100);
+        assert_eq!(stop_reply(reason), "T05swbreak:;");
+        assert_eq!(cpu.pc, 0x0204);
+
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "z0,204,1"), reply("OK"));
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "Z2,10,1"), reply("OK"));
+        let reason = stub.debugger.run_until_stop(&mut cpu, &mut bus, 100);
+        assert_eq!(stop_reply(reason), "T05watch:0010;");
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "z2,10,1"), reply("OK"));
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "Z4,10,1"), reply("OK"));
+        let reason = stub.debugger.run_until_stop(&mut cpu, &mut bus, 100);
+        assert_eq!(stop_reply(reason), "T05awatch:0010;");
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "Z9,10,1"), reply(""));
+    }
+
+    #[test]
+    fn test_reverse() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut stub = GdbStub::new();
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "bs"), Action::ReverseStep);
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "bc"), Action::ReverseContinue);
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "bx"), reply(""));
+
+        stub.debugger.step(&mut cpu, &mut bus);
+        stub.debugger.step(&mut cpu, &mut bus);
+        assert_eq!(bus.read(0x10), 0x42);
+        assert_eq!(stub.debugger.reverse_step(&mut cpu, &mut bus), None);
+        assert_eq!((cpu.pc, bus.read(0x10)), (0x0202, 0));
+        let reason = stub.debugger.reverse_continue(&mut cpu, &mut bus, 100);
+        assert_eq!(stop_reply(reason), "T05replaylog:begin;");
+        assert_eq!(cpu.pc, 0x0200);
+
+        // Writes from the debugger forget the history.
+        for packet in ["M10,1:00", "G010203ff0002e1", "P0=00"] {
+            stub.debugger.step(&mut cpu, &mut bus);
+            assert!(!stub.debugger.journal().is_empty());
+            assert_eq!(stub.handle(&mut cpu, &mut bus, packet), reply("OK"));
+            assert!(stub.debugger.journal().is_empty(), "{}", packet);
+        }
+    }
+
+    #[test]
+    fn test_queries() {
+        let (mut cpu, mut bus) = get_machine();
+        let mut stub = GdbStub::new();
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "?"), reply("S05"));
+        assert_eq!(stub.handle(&mut cpu, &mut bus, "vMustReplyEmpty"), reply(""));
+        let Action::Reply(xml) = stub.handle(&mut cpu, &mut bus, "qXfer:features:read:target.xml:0,ffff") else {
+            panic!();
+        };
+        assert!(xml.starts_with("l<?xml"));
+        let Action::Reply(xml) = stub.handle(&mut cpu, &mut bus, "qXfer:features:read:target.xml:0,10") else {
+            panic!();
+        };
+        assert_eq!(xml, "m<?xml version=\"1");
+    }
+
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut transact = |data: &str| {
+                write!(stream, "${}#{:02x}", data, checksum(data)).unwrap();
+                let mut got = Vec::new();
+                let mut byte = [0];
+                while got.iter().rposition(|&b| b == b'#').is_none_or(|pos| got.len() < pos + 3) {
+                    stream.read_exact(&mut byte).unwrap();
+                    got.push(byte[0]);
+                }
+                stream.write_all(b"+").unwrap();
+                String::from_utf8(got).unwrap()
+            };
+            let step = transact("s");
+            let regs = transact("g");
+            let cont = transact("c");
+            let detach = transact("D");
+            (step, regs, cont, detach)
+        });
+
+        let (mut cpu, mut bus) = get_machine();
+        let (stream, _) = listener.accept().unwrap();
+        let mut session = Session::new(stream, &mut cpu, &mut bus);
+        session.stub.insert_point(b'2', 0x10, 1).unwrap();
+        session.run().unwrap();
+
+        let (step, regs, cont, detach) = client.join().unwrap();
+        let framed = |data: &str| format!("+${}#{:02x}", data, checksum(data));
+        assert_eq!(step, framed("S05"));
+        assert_eq!(regs, framed("420000fd020220"));
+        assert_eq!(cont, framed("T05watch:0010;"));
+        assert_eq!(detach, framed("OK"));
+    }
+}
@@ -3,6 +3,8 @@ pub enum AddressingMode {
     IMP, ACC, IMM, ZPG, ZPX,
     ZPY, REL, ABS, ABX, ABY,
     IND, INX, INY,
+    // 65C02 only: (zp), (abs,X) and the zp,rel operands of BBR/BBS.
+    ZPI, IAX, ZPR,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -19,6 +21,10 @@ pub enum InstructionCode {
     SED, SEI, STA, STX, STY,
     TAX, TAY, TSX, TXA, TXS,
     TYA,
+    // 65C02 only.
+    BRA, PHX, PHY, PLX, PLY,
+    STZ, TRB, TSB, STP, WAI,
+    BBR(u8), BBS(u8), RMB(u8), SMB(u8),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -29,4 +35,16 @@ pub enum OperationInput {
     ADR(u16),
 }
 
-pub type Instruction = (InstructionCode, OperationInput);
\ No newline at end of file
+pub type Instruction = (InstructionCode, OperationInput);
+
+impl AddressingMode {
+    pub fn operand_len(self) -> u16 {
+        match self {
+            AddressingMode::IMP | AddressingMode::ACC => 0,
+            AddressingMode::ABS | AddressingMode::ABX |
+            AddressingMode::ABY | AddressingMode::IND |
+            AddressingMode::IAX | AddressingMode::ZPR => 2,
+            _ => 1,
+        }
+    }
+}
@@ -1,11 +1,32 @@
 pub mod instruction;
 
 pub mod nmos6502;
+pub mod cmos65c02;
+pub mod debugger;
+pub mod gdb;
+pub(crate) mod json;
+pub mod dbginfo;
+pub mod dap;
+pub mod loader;
+pub mod snapshot;
+pub mod savestate;
+pub mod machine;
+pub mod rewind;
+pub mod replay;
+pub mod device;
+pub mod memory_map;
+pub mod banking;
+pub mod video;
+pub mod system;
 
 mod cpu;
 mod registers;
 
 pub trait Variant {
+    /// 65C02 behaviour: valid decimal mode flags, no `JMP ($xxFF)` page wrap,
+    /// D cleared on interrupts and different dummy bus cycles.
+    const CMOS: bool = false;
+
     fn decode(opcode: u8) -> Option<(
         crate::instruction::InstructionCode,
         crate::instruction::AddressingMode
@@ -15,6 +36,19 @@ pub trait Variant {
 pub trait Bus {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, value: u8);
+
+    /// A read on a cycle where the CPU ignores the data, such as the second
+    /// cycle of an implied instruction. These have the same side effects as
+    /// `read` on real hardware.
+    fn dummy_read(&self, addr: u16) {
+        self.read(addr);
+    }
+
+    /// Reads a byte for a debugger, without the side effects of `read`.
+    /// Buses with read-sensitive registers should override this.
+    fn peek(&self, addr: u16) -> u8 {
+        self.read(addr)
+    }
 }
 
 #[derive(Default, Clone, Copy)]
@@ -22,6 +56,12 @@ pub struct Cpu<V> {
     pub reg: registers::RegisterState,
     pub pc: u16,
     pub sp: u8,
+    /// Bus cycles elapsed; every cycle is one read or write.
+    pub cycles: u64,
+    /// Set by `WAI` until the next interrupt.
+    pub waiting: bool,
+    /// Set by `STP` until the next reset.
+    pub stopped: bool,
 
     _variant: core::marker::PhantomData<V>,
 }
@@ -32,6 +72,9 @@ impl<V: Variant> Cpu<V> {
             reg: registers::RegisterState::default(),
             pc: 0,
             sp: 0,
+            cycles: 0,
+            waiting: false,
+            stopped: false,
             _variant: core::marker::PhantomData::<V>,
         }
     }
@@ -0,0 +1,166 @@
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use crate::Bus;
+
+mod ihex;
+mod o65;
+mod prg;
+mod ptp;
+mod srec;
+
+pub use ihex::load_ihex;
+pub use o65::{Export, Layout, O65};
+pub use prg::load_prg;
+pub use ptp::load_ptp;
+pub use srec::load_srec;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    Syntax(&'static str),
+    Checksum { expected: u16, found: u16 },
+    AddressOutOfRange(u32),
+    UnsupportedRecord(u8),
+    UndefinedSymbol(String),
+}
+
+/// A load error. `line` is the 1-based line of the offending record in
+/// text formats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub line: Option<usize>,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    fn new(kind: ErrorKind) -> Self {
+        Error { line: None, kind }
+    }
+
+    fn at(line: usize, kind: ErrorKind) -> Self {
+        Error { line: Some(line), kind }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        match &self.kind {
+            ErrorKind::Syntax(msg) => write!(f, "{}", msg),
+            ErrorKind::Checksum { expected, found } => {
+                write!(f, "checksum mismatch (expected {:02X}, found {:02X})", expected, found)
+            }
+            ErrorKind::AddressOutOfRange(addr) => write!(f, "address ${:X} is outside the 64K address space", addr),
+            ErrorKind::UnsupportedRecord(kind) => write!(f, "unsupported record type {:02X}", kind),
+            ErrorKind::UndefinedSymbol(name) => write!(f, "undefined symbol `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// What a loader wrote and where execution should start, if the image said.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadInfo {
+    pub entry: Option<u16>,
+    pub regions: Vec<RangeInclusive<u16>>,
+}
+
+impl LoadInfo {
+    fn add(&mut self, start: u16, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let end = start + (len - 1) as u16;
+        match self.regions.last_mut() {
+            Some(last) if last.end().wrapping_add(1) == start && *last.end() != 0xFFFF => {
+                *last = *last.start()..=end;
+            }
+            _ => self.regions.push(start..=end),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.iter().map(|r| r.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    // Writes `data` at `addr`, which may come from a wider address field.
+    fn write(&mut self, bus: &mut impl Bus, line: Option<usize>, addr: u32, data: &[u8]) -> Result<(), Error> {
+        let end = addr.checked_add(data.len().saturating_sub(1) as u32);
+        if !matches!(end, Some(end) if end <= 0xFFFF) {
+            return Err(Error { line, kind: ErrorKind::AddressOutOfRange(end.unwrap_or(addr)) });
+        }
+        for (i, byte) in data.iter().enumerate() {
+            bus.write(addr as u16 + i as u16, *byte);
+        }
+        self.add(addr as u16, data.len());
+        Ok(())
+    }
+}
+
+fn to_u16(line: usize, addr: u32) -> Result<u16, Error> {
+    u16::try_from(addr).map_err(|_| Error::at(line, ErrorKind::AddressOutOfRange(addr)))
+}
+
+// Decodes the hex digits of a record, after its leading marker.
+fn decode_hex(line: usize, digits: &str) -> Result<Vec<u8>, Error> {
+    if !digits.len().is_multiple_of(2) {
+        return Err(Error::at(line, ErrorKind::Syntax("odd number of hex digits")));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            digits.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(Error::at(line, ErrorKind::Syntax("invalid hex digit")))
+        })
+        .collect()
+}
+
+/// Copies a raw binary to `base`.
+pub fn load_raw(data: &[u8], base: u16, bus: &mut impl Bus) -> Result<LoadInfo, Error> {
+    let mut info = LoadInfo::default();
+    info.write(bus, None, base as u32, data)?;
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    #[test]
+    fn test_load_raw() {
+        let mut bus = MockBus([0; 65536]);
+        let info = load_raw(&[1, 2, 3], 0xFFFD, &mut bus).unwrap();
+        assert_eq!(&bus.0[0xFFFD..], &[1, 2, 3]);
+        assert_eq!(info.regions, vec![0xFFFD..=0xFFFF]);
+        assert_eq!(info.entry, None);
+
+        let err = load_raw(&[1, 2, 3], 0xFFFE, &mut bus).unwrap_err();
+        assert_eq!(err, Error { line: None, kind: ErrorKind::AddressOutOfRange(0x10000) });
+        assert_eq!(err.to_string(), "address $10000 is outside the 64K address space");
+        assert!(load_raw(&[], 0, &mut bus).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut info = LoadInfo::default();
+        info.add(0x0200, 0x10);
+        info.add(0x0210, 0x10);
+        info.add(0x0300, 1);
+        assert_eq!(info.regions, vec![0x0200..=0x021F, 0x0300..=0x0300]);
+        assert_eq!(info.len(), 0x21);
+    }
+}
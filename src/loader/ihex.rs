@@ -0,0 +1,121 @@
+use super::{decode_hex, to_u16, Error, ErrorKind, LoadInfo};
+use crate::Bus;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Loads an Intel HEX image. Records after the end-of-file record are
+/// ignored.
+pub fn load_ihex(src: &str, bus: &mut impl Bus) -> Result<LoadInfo, Error> {
+    let mut info = LoadInfo::default();
+    // Added to every data record address, set by record types 02 and 04.
+    let mut base: u32 = 0;
+
+    for (n, text) in src.lines().enumerate() {
+        let line = n + 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let digits = text.strip_prefix(':').ok_or(Error::at(line, ErrorKind::Syntax("record does not start with `:`")))?;
+        let bytes = decode_hex(line, digits)?;
+        if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize {
+            return Err(Error::at(line, ErrorKind::Syntax("record length does not match byte count")));
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+        if expected != checksum[0] {
+            return Err(Error::at(line, ErrorKind::Checksum { expected: expected.into(), found: checksum[0].into() }));
+        }
+
+        let addr = u16::from_be_bytes([body[1], body[2]]) as u32;
+        let data = &body[4..];
+        let field = |len: usize| match data.len() == len {
+            true => Ok(data.iter().fold(0u32, |acc, b| acc << 8 | *b as u32)),
+            false => Err(Error::at(line, ErrorKind::Syntax("wrong data length for record type"))),
+        };
+        match body[3] {
+            DATA => info.write(bus, Some(line), base + addr, data)?,
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS => base = field(2)? << 4,
+            EXTENDED_LINEAR_ADDRESS => base = field(2)? << 16,
+            START_SEGMENT_ADDRESS => {
+                let cs_ip = field(4)?;
+                info.entry = Some(to_u16(line, ((cs_ip >> 16) << 4) + (cs_ip & 0xFFFF))?);
+            }
+            START_LINEAR_ADDRESS => info.entry = Some(to_u16(line, field(4)?)?),
+            kind => return Err(Error::at(line, ErrorKind::UnsupportedRecord(kind))),
+        }
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    #[test]
+    fn test_data_and_entry() {
+        let mut bus = MockBus([0; 65536]);
+        let src = "\
+:03020000A9428D83
+:02020300000CED
+:0400000500000200F5
+:00000001FF
+";
+        let info = load_ihex(src, &mut bus).unwrap();
+        assert_eq!(&bus.0[0x0200..0x0205], &[0xA9, 0x42, 0x8D, 0x00, 0x0C]);
+        assert_eq!(info.regions, vec![0x0200..=0x0204]);
+        assert_eq!(info.entry, Some(0x0200));
+        assert_eq!(info.len(), 5);
+    }
+
+    #[test]
+    fn test_extended_addresses() {
+        let mut bus = MockBus([0; 65536]);
+        // Segment $0F00 puts offset $0010 at $F010.
+        let src = ":020000020F00ED\n:01001000EA05\n:040000030F000FF0EB\n:00000001FF\n";
+        let info = load_ihex(src, &mut bus).unwrap();
+        assert_eq!(bus.0[0xF010], 0xEA);
+        assert_eq!(info.entry, Some(0xFFF0));
+
+        let src = ":020000040001F9\n:01000000EA15\n";
+        let err = load_ihex(src, &mut bus).unwrap_err();
+        assert_eq!(err, Error::at(2, ErrorKind::AddressOutOfRange(0x10000)));
+
+        let src = ":02000004FFFFFC\n:02FFFF000102FD\n";
+        let err = load_ihex(src, &mut bus).unwrap_err();
+        assert_eq!(err, Error::at(2, ErrorKind::AddressOutOfRange(0xFFFFFFFF)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut bus = MockBus([0; 65536]);
+        let err = load_ihex(":00000001FF\n", &mut bus);
+        assert!(err.is_ok());
+
+        let err = load_ihex("\n:03020000A9428D4C\n", &mut bus).unwrap_err();
+        assert_eq!(err, Error::at(2, ErrorKind::Checksum { expected: 0x83, found: 0x4C }));
+        assert_eq!(err.to_string(), "line 2: checksum mismatch (expected 83, found 4C)");
+
+        let err = load_ihex("03020000A9428D4B", &mut bus).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Syntax("record does not start with `:`"));
+        let err = load_ihex(":04020000A9428D4B", &mut bus).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Syntax("record length does not match byte count"));
+        let err = load_ihex(":0302000GA9428D4B", &mut bus).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Syntax("invalid hex digit"));
+        let err = load_ihex(":00000006FA", &mut bus).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnsupportedRecord(6));
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+
+use super::{Error, ErrorKind, LoadInfo};
+use crate::Bus;
+
+const MAGIC: [u8; 5] = [0x01, 0x00, b'o', b'6', b'5'];
+
+const MODE_65816: u16 = 0x8000;
+const MODE_PAGED: u16 = 0x4000;
+const MODE_SIZE32: u16 = 0x2000;
+
+const SEG_UNDEFINED: u8 = 0;
+const SEG_ABSOLUTE: u8 = 1;
+const SEG_TEXT: u8 = 2;
+const SEG_DATA: u8 = 3;
+const SEG_BSS: u8 = 4;
+const SEG_ZERO: u8 = 5;
+
+const RELOC_WORD: u8 = 0x80;
+const RELOC_HIGH: u8 = 0x40;
+const RELOC_LOW: u8 = 0x20;
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::Syntax("truncated o65 file"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reloc {
+    offset: usize,
+    kind: u8,
+    segment: u8,
+    // Index into the undefined references for undefined relocations.
+    symbol: u16,
+    // Low byte of the original value for HIGH relocations.
+    low: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub name: String,
+    pub segment: u8,
+    pub value: u16,
+}
+
+/// Where each segment of an o65 file is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub text: u16,
+    pub data: u16,
+    pub bss: u16,
+    pub zero: u16,
+}
+
+/// A parsed o65 relocatable object, as produced by xa.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct O65 {
+    pub mode: u16,
+    pub text_base: u16,
+    pub text: Vec<u8>,
+    pub data_base: u16,
+    pub data: Vec<u8>,
+    pub bss_base: u16,
+    pub bss_len: u16,
+    pub zero_base: u16,
+    pub zero_len: u16,
+    pub stack_len: u16,
+    pub options: Vec<(u8, Vec<u8>)>,
+    pub undefined: Vec<String>,
+    pub exports: Vec<Export>,
+    text_relocs: Vec<Reloc>,
+    data_relocs: Vec<Reloc>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    size32: bool,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, Error> {
+        let b = *self.bytes.get(self.pos).ok_or_else(truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], Error> {
+        let bytes = self.bytes.get(self.pos..self.pos + len).ok_or_else(truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    // A header word, 32 bits wide in size32 mode; must fit the 6502.
+    fn word(&mut self) -> Result<u16, Error> {
+        if !self.size32 {
+            return self.u16();
+        }
+        let value = u32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        u16::try_from(value).map_err(|_| Error::new(ErrorKind::AddressOutOfRange(value)))
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        let rest = &self.bytes[self.pos..];
+        let len = rest.iter().position(|b| *b == 0).ok_or_else(truncated)?;
+        self.pos += len + 1;
+        Ok(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+
+    fn relocs(&mut self, paged: bool) -> Result<Vec<Reloc>, Error> {
+        let mut relocs = Vec::new();
+        // Offsets count from the byte before the segment start.
+        let mut offset: isize = -1;
+        loop {
+            match self.byte()? {
+                0 => return Ok(relocs),
+                255 => offset += 254,
+                n => {
+                    offset += n as isize;
+                    let typebyte = self.byte()?;
+                    let (kind, segment) = (typebyte & 0xE0, typebyte & 0x07);
+                    let (mut symbol, mut low) = (0, 0);
+                    if segment == SEG_UNDEFINED {
+                        symbol = self.word()?;
+                    }
+                    match kind {
+                        RELOC_WORD | RELOC_LOW => {}
+                        RELOC_HIGH => {
+                            if !paged {
+                                low = self.byte()?;
+                            }
+                        }
+                        _ => return Err(Error::new(ErrorKind::Syntax("unsupported relocation type"))),
+                    }
+                    relocs.push(Reloc { offset: offset as usize, kind, segment, symbol, low });
+                }
+            }
+        }
+    }
+}
+
+impl O65 {
+    pub fn parse(bytes: &[u8]) -> Result<O65, Error> {
+        if !bytes.starts_with(&MAGIC) {
+            return Err(Error::new(ErrorKind::Syntax("not an o65 file")));
+        }
+        let mut r = Reader { bytes, pos: MAGIC.len() + 1, size32: false };
+        let mode = r.u16()?;
+        if mode & MODE_65816 != 0 {
+            return Err(Error::new(ErrorKind::Syntax("65816 o65 files are not supported")));
+        }
+        r.size32 = mode & MODE_SIZE32 != 0;
+
+        let text_base = r.word()?;
+        let text_len = r.word()?;
+        let data_base = r.word()?;
+        let data_len = r.word()?;
+        let bss_base = r.word()?;
+        let bss_len = r.word()?;
+        let zero_base = r.word()?;
+        let zero_len = r.word()?;
+        let stack_len = r.word()?;
+
+        let mut options = Vec::new();
+        loop {
+            let len = r.byte()? as usize;
+            if len == 0 {
+                break;
+            }
+            let option = r.take(len.checked_sub(1).ok_or_else(truncated)?)?;
+            let (kind, data) = option.split_first().ok_or_else(truncated)?;
+            options.push((*kind, data.to_vec()));
+        }
+
+        let text = r.take(text_len as usize)?.to_vec();
+        let data = r.take(data_len as usize)?.to_vec();
+
+        let count = r.word()?;
+        let undefined = (0..count).map(|_| r.name()).collect::<Result<_, _>>()?;
+
+        let paged = mode & MODE_PAGED != 0;
+        let text_relocs = r.relocs(paged)?;
+        let data_relocs = r.relocs(paged)?;
+
+        let count = r.word()?;
+        let mut exports = Vec::new();
+        for _ in 0..count {
+            let name = r.name()?;
+            let segment = r.byte()?;
+            let value = r.word()?;
+            exports.push(Export { name, segment, value });
+        }
+
+        Ok(O65 {
+            mode, text_base, text, data_base, data, bss_base, bss_len,
+            zero_base, zero_len, stack_len, options, undefined, exports,
+            text_relocs, data_relocs,
+        })
+    }
+
+    /// Places text at `base` followed directly by data and bss. The zero
+    /// page segment keeps its assembled address.
+    pub fn layout_at(&self, base: u16) -> Layout {
+        let data = base.wrapping_add(self.text.len() as u16);
+        let bss = data.wrapping_add(self.data.len() as u16);
+        Layout { text: base, data, bss, zero: self.zero_base }
+    }
+
+    fn delta(&self, segment: u8, layout: &Layout) -> u16 {
+        match segment {
+            SEG_TEXT => layout.text.wrapping_sub(self.text_base),
+            SEG_DATA => layout.data.wrapping_sub(self.data_base),
+            SEG_BSS => layout.bss.wrapping_sub(self.bss_base),
+            SEG_ZERO => layout.zero.wrapping_sub(self.zero_base),
+            _ => 0,
+        }
+    }
+
+    fn relocate(
+        &self,
+        segment: &mut [u8],
+        relocs: &[Reloc],
+        layout: &Layout,
+        imports: &HashMap<String, u16>,
+    ) -> Result<(), Error> {
+        for reloc in relocs {
+            let delta = match reloc.segment {
+                SEG_UNDEFINED => {
+                    let name = self.undefined.get(reloc.symbol as usize).ok_or_else(truncated)?;
+                    *imports.get(name).ok_or_else(|| Error::new(ErrorKind::UndefinedSymbol(name.clone())))?
+                }
+                SEG_ABSOLUTE..=SEG_ZERO => self.delta(reloc.segment, layout),
+                _ => return Err(Error::new(ErrorKind::Syntax("invalid relocation segment"))),
+            };
+            let at = reloc.offset;
+            let len = if reloc.kind == RELOC_WORD { 2 } else { 1 };
+            if at + len > segment.len() {
+                return Err(Error::new(ErrorKind::Syntax("relocation outside segment")));
+            }
+            match reloc.kind {
+                RELOC_WORD => {
+                    let value = u16::from_le_bytes([segment[at], segment[at + 1]]).wrapping_add(delta);
+                    segment[at..at + 2].copy_from_slice(&value.to_le_bytes());
+                }
+                RELOC_HIGH => {
+                    let value = u16::from_le_bytes([reloc.low, segment[at]]).wrapping_add(delta);
+                    segment[at] = (value >> 8) as u8;
+                }
+                _ => segment[at] = segment[at].wrapping_add(delta as u8),
+            }
+        }
+        Ok(())
+    }
+
+    /// Relocates the text and data segments to `layout` and writes them to
+    /// the bus. Undefined references are resolved from `imports`. The entry
+    /// point reported is the start of the text segment.
+    pub fn load(&self, bus: &mut impl Bus, layout: Layout, imports: &HashMap<String, u16>) -> Result<LoadInfo, Error> {
+        let mut text = self.text.clone();
+        let mut data = self.data.clone();
+        self.relocate(&mut text, &self.text_relocs, &layout, imports)?;
+        self.relocate(&mut data, &self.data_relocs, &layout, imports)?;
+
+        let mut info = LoadInfo::default();
+        info.write(bus, None, layout.text as u32, &text)?;
+        info.write(bus, None, layout.data as u32, &data)?;
+        info.entry = Some(layout.text);
+        Ok(info)
+    }
+
+    /// Exported symbols with their values for `layout`.
+    pub fn exports_at(&self, layout: &Layout) -> Vec<(String, u16)> {
+        self.exports.iter()
+            .map(|e| (e.name.clone(), e.value.wrapping_add(self.delta(e.segment, layout))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    fn object() -> Vec<u8> {
+        let mut o = vec![0x01, 0x00, b'o', b'6', b'5', 0x00, 0x00, 0x00];
+        for word in [0x1000u16, 11, 0x2000, 6, 0x3000, 0x10, 0x80, 4, 0] {
+            o.extend_from_slice(&word.to_le_bytes());
+        }
+        o.extend_from_slice(&[4, 0, b'a', 0, 0]);
+        // JMP $1003; LDA #>$2005; LDA #<$2005; JSR print; RTS
+        o.extend_from_slice(&[0x4C, 0x03, 0x10, 0xA9, 0x20, 0xA9, 0x05, 0x20, 0x00, 0x00, 0x60]);
+        o.extend_from_slice(&[0x00, 0x10, 0x02, 0x03, 0x04, 0x05]);
+        o.extend_from_slice(&[1, 0, b'p', b'r', b'i', b'n', b't', 0]);
+        o.extend_from_slice(&[2, 0x82, 3, 0x43, 0x05, 2, 0x23, 2, 0x80, 0, 0, 0]);
+        o.extend_from_slice(&[1, 0x82, 0]);
+        o.extend_from_slice(&[1, 0, b's', b't', b'a', b'r', b't', 0, SEG_TEXT, 0x00, 0x10]);
+        o
+    }
+
+    #[test]
+    fn test_parse() {
+        let o65 = O65::parse(&object()).unwrap();
+        assert_eq!((o65.text_base, o65.text.len()), (0x1000, 11));
+        assert_eq!((o65.bss_base, o65.bss_len, o65.zero_base), (0x3000, 0x10, 0x80));
+        assert_eq!(o65.options, vec![(0, vec![b'a', 0])]);
+        assert_eq!(o65.undefined, vec!["print".to_string()]);
+        assert_eq!(o65.text_relocs.len(), 4);
+        assert_eq!(o65.text_relocs[1], Reloc { offset: 4, kind: RELOC_HIGH, segment: SEG_DATA, symbol: 0, low: 5 });
+        assert_eq!(o65.exports[0], Export { name: "start".to_string(), segment: SEG_TEXT, value: 0x1000 });
+    }
+
+    #[test]
+    fn test_load_relocated() {
+        let o65 = O65::parse(&object()).unwrap();
+        let layout = Layout { text: 0x0800, data: 0x09FC, bss: 0x0A00, zero: 0x10 };
+        let imports = HashMap::from([("print".to_string(), 0xFFD2)]);
+        let mut bus = MockBus([0; 65536]);
+        let info = o65.load(&mut bus, layout, &imports).unwrap();
+
+        let text = [0x4C, 0x03, 0x08, 0xA9, 0x0A, 0xA9, 0x01, 0x20, 0xD2, 0xFF, 0x60];
+        assert_eq!(&bus.0[0x0800..0x080B], &text);
+        assert_eq!(&bus.0[0x09FC..0x0A02], &[0x00, 0x08, 0x02, 0x03, 0x04, 0x05]);
+        assert_eq!(info.entry, Some(0x0800));
+        assert_eq!(o65.exports_at(&layout), vec![("start".to_string(), 0x0800)]);
+
+        let layout = o65.layout_at(0xC000);
+        assert_eq!(layout, Layout { text: 0xC000, data: 0xC00B, bss: 0xC011, zero: 0x80 });
+    }
+
+    #[test]
+    fn test_undefined_high() {
+        let mut o = vec![0x01, 0x00, b'o', b'6', b'5', 0x00, 0x00, 0x00];
+        for word in [0x1000u16, 4, 0x2000, 0, 0x3000, 0, 0x80, 0, 0] {
+            o.extend_from_slice(&word.to_le_bytes());
+        }
+        o.push(0);
+        // LDA #>(vec + $80); LDA #<chrout
+        o.extend_from_slice(&[0xA9, 0x00, 0xA9, 0x00]);
+        o.extend_from_slice(&[2, 0, b'c', b'h', b'r', b'o', b'u', b't', 0, b'v', b'e', b'c', 0]);
+        o.extend_from_slice(&[2, 0x40, 1, 0, 0x80, 2, 0x20, 0, 0, 0]);
+        o.extend_from_slice(&[0, 0, 0]);
+
+        let o65 = O65::parse(&o).unwrap();
+        assert_eq!(o65.text_relocs[0], Reloc { offset: 1, kind: RELOC_HIGH, segment: SEG_UNDEFINED, symbol: 1, low: 0x80 });
+        let imports = HashMap::from([("chrout".to_string(), 0xFFD2), ("vec".to_string(), 0x03A0)]);
+        let mut bus = MockBus([0; 65536]);
+        o65.load(&mut bus, o65.layout_at(0x1000), &imports).unwrap();
+        assert_eq!(&bus.0[0x1000..0x1004], &[0xA9, 0x04, 0xA9, 0xD2]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let o65 = O65::parse(&object()).unwrap();
+        let mut bus = MockBus([0; 65536]);
+        let err = o65.load(&mut bus, o65.layout_at(0x0800), &HashMap::new()).unwrap_err();
+        assert_eq!(err.to_string(), "undefined symbol `print`");
+
+        let mut bytes = object();
+        bytes.truncate(40);
+        assert_eq!(O65::parse(&bytes).unwrap_err(), truncated());
+        assert_eq!(O65::parse(b"PK").unwrap_err().kind, ErrorKind::Syntax("not an o65 file"));
+    }
+}
@@ -0,0 +1,68 @@
+use super::{Error, ErrorKind, LoadInfo};
+use crate::Bus;
+
+// BASIC token for SYS.
+const SYS: u8 = 0x9E;
+
+/// Loads a Commodore `.prg` file at the address given by its first two
+/// bytes. If the program starts with a BASIC `SYS <addr>` line, that
+/// address is reported as the entry point.
+pub fn load_prg(data: &[u8], bus: &mut impl Bus) -> Result<LoadInfo, Error> {
+    let [low, high, body @ ..] = data else {
+        return Err(Error::new(ErrorKind::Syntax("missing load address")));
+    };
+    let base = u16::from_le_bytes([*low, *high]);
+    let mut info = LoadInfo::default();
+    info.write(bus, None, base as u32, body)?;
+    info.entry = sys_address(body);
+    Ok(info)
+}
+
+// Line link (2), line number (2), then the tokenised line.
+fn sys_address(body: &[u8]) -> Option<u16> {
+    let line = body.get(4..)?;
+    let line = &line[..line.iter().position(|b| *b == 0)?];
+    let digits: String = line.strip_prefix(&[SYS])?
+        .iter()
+        .map(|b| *b as char)
+        .skip_while(|c| *c == ' ' || *c == '(')
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    #[test]
+    fn test_load_prg() {
+        let mut bus = MockBus([0; 65536]);
+        let info = load_prg(&[0x00, 0xC0, 0xA9, 0x01, 0x60], &mut bus).unwrap();
+        assert_eq!(&bus.0[0xC000..0xC003], &[0xA9, 0x01, 0x60]);
+        assert_eq!(info.regions, vec![0xC000..=0xC002]);
+        assert_eq!(info.entry, None);
+
+        let err = load_prg(&[0x01], &mut bus).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Syntax("missing load address"));
+    }
+
+    #[test]
+    fn test_basic_stub() {
+        let mut bus = MockBus([0; 65536]);
+        // 10 SYS 2062
+        let prg = [
+            0x01, 0x08, 0x0C, 0x08, 0x0A, 0x00, 0x9E, b' ', b'2', b'0', b'6', b'2', 0x00, 0x00, 0x00,
+            0xEE, 0x20, 0xD0, 0x60,
+        ];
+        let info = load_prg(&prg, &mut bus).unwrap();
+        assert_eq!(info.entry, Some(2062));
+        assert_eq!(bus.0[2062], 0xEE);
+    }
+}
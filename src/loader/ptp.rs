@@ -0,0 +1,77 @@
+use super::{decode_hex, Error, ErrorKind, LoadInfo};
+use crate::Bus;
+
+/// Loads a MOS Technology paper tape image, as punched and read by the
+/// KIM-1 monitor. Each record is `;`, a byte count, a 16-bit address, the
+/// data and a 16-bit sum of the preceding bytes. The last record has a
+/// count of zero and gives the number of data records in place of the
+/// address. Leading NULs and blank lines, the tape's leader, are skipped.
+pub fn load_ptp(src: &str, bus: &mut impl Bus) -> Result<LoadInfo, Error> {
+    let mut info = LoadInfo::default();
+    let mut data_records: u16 = 0;
+
+    for (n, text) in src.lines().enumerate() {
+        let line = n + 1;
+        let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if text.is_empty() {
+            continue;
+        }
+        let rest = text.strip_prefix(';').ok_or(Error::at(line, ErrorKind::Syntax("record does not start with `;`")))?;
+        let bytes = decode_hex(line, rest)?;
+        if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize {
+            return Err(Error::at(line, ErrorKind::Syntax("record length does not match byte count")));
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 2);
+        let expected = body.iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+        let found = u16::from_be_bytes([checksum[0], checksum[1]]);
+        if expected != found {
+            return Err(Error::at(line, ErrorKind::Checksum { expected, found }));
+        }
+
+        let addr = u16::from_be_bytes([body[1], body[2]]);
+        if body[0] == 0 {
+            if addr != data_records {
+                return Err(Error::at(line, ErrorKind::Syntax("record count does not match data records")));
+            }
+            break;
+        }
+        info.write(bus, Some(line), addr as u32, &body[3..])?;
+        data_records += 1;
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    #[test]
+    fn test_load_ptp() {
+        let mut bus = MockBus([0; 65536]);
+        let src = "\0\0\0\n;05020001020304000011\n;02FFFEAA5502FE\n;0000020002\n";
+        let info = load_ptp(src, &mut bus).unwrap();
+        assert_eq!(&bus.0[0x0200..0x0205], &[1, 2, 3, 4, 0]);
+        assert_eq!(&bus.0[0xFFFE..], &[0xAA, 0x55]);
+        assert_eq!(info.regions, vec![0x0200..=0x0204, 0xFFFE..=0xFFFF]);
+        assert_eq!(info.entry, None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut bus = MockBus([0; 65536]);
+        let err = load_ptp(";05020001020304000010\n", &mut bus).unwrap_err();
+        assert_eq!(err, Error::at(1, ErrorKind::Checksum { expected: 0x0011, found: 0x0010 }));
+        assert_eq!(err.to_string(), "line 1: checksum mismatch (expected 11, found 10)");
+        let err = load_ptp(";05020001020304000011\n;0000020002\n", &mut bus).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: record count does not match data records");
+        let err = load_ptp(";050200010203\n", &mut bus).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Syntax("record length does not match byte count"));
+    }
+}
@@ -0,0 +1,116 @@
+use super::{decode_hex, to_u16, Error, ErrorKind, LoadInfo};
+use crate::Bus;
+
+/// Loads a Motorola S-record image (S19, S28 or S37). The record count in
+/// S5/S6 records, if present, is checked against the data records seen.
+pub fn load_srec(src: &str, bus: &mut impl Bus) -> Result<LoadInfo, Error> {
+    let mut info = LoadInfo::default();
+    let mut data_records: u32 = 0;
+
+    for (n, text) in src.lines().enumerate() {
+        let line = n + 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let rest = text.strip_prefix(['S', 's']).ok_or(Error::at(line, ErrorKind::Syntax("record does not start with `S`")))?;
+        let kind = rest.chars().next()
+            .and_then(|c| c.to_digit(10))
+            .ok_or(Error::at(line, ErrorKind::Syntax("missing record type")))? as u8;
+        let bytes = decode_hex(line, &rest[1..])?;
+        if bytes.len() < 2 || bytes.len() != 1 + bytes[0] as usize {
+            return Err(Error::at(line, ErrorKind::Syntax("record length does not match byte count")));
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = !body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if expected != checksum[0] {
+            return Err(Error::at(line, ErrorKind::Checksum { expected: expected.into(), found: checksum[0].into() }));
+        }
+
+        let addr_len = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(Error::at(line, ErrorKind::UnsupportedRecord(kind))),
+        };
+        let fields = &body[1..];
+        if fields.len() < addr_len {
+            return Err(Error::at(line, ErrorKind::Syntax("record too short for its address")));
+        }
+        let (addr, data) = fields.split_at(addr_len);
+        let addr = addr.iter().fold(0u32, |acc, b| acc << 8 | *b as u32);
+        match kind {
+            0 => {}
+            1..=3 => {
+                info.write(bus, Some(line), addr, data)?;
+                data_records += 1;
+            }
+            5 | 6 => {
+                if addr != data_records {
+                    return Err(Error::at(line, ErrorKind::Syntax("record count does not match data records")));
+                }
+            }
+            _ => {
+                info.entry = Some(to_u16(line, addr)?);
+                break;
+            }
+        }
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBus([u8; 65536]);
+    impl Bus for MockBus {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    #[test]
+    fn test_s19() {
+        let mut bus = MockBus([0; 65536]);
+        let src = "\
+S00600004844521B
+S1060200A9428D7F
+S1050203000CE9
+S5030002FA
+S9030200FA
+";
+        let info = load_srec(src, &mut bus).unwrap();
+        assert_eq!(&bus.0[0x0200..0x0205], &[0xA9, 0x42, 0x8D, 0x00, 0x0C]);
+        assert_eq!(info.regions, vec![0x0200..=0x0204]);
+        assert_eq!(info.entry, Some(0x0200));
+    }
+
+    #[test]
+    fn test_s28() {
+        let mut bus = MockBus([0; 65536]);
+        let info = load_srec("S2050000FFEA11\nS804000400F7\n", &mut bus).unwrap();
+        assert_eq!(bus.0[0xFF], 0xEA);
+        assert_eq!(info.entry, Some(0x0400));
+
+        let err = load_srec("S2050100FFEA10\n", &mut bus).unwrap_err();
+        assert_eq!(err, Error::at(1, ErrorKind::AddressOutOfRange(0x100FF)));
+
+        // The last byte would be past the end of a 32-bit address.
+        let err = load_srec("S307FFFFFFFF0102F9\n", &mut bus).unwrap_err();
+        assert_eq!(err, Error::at(1, ErrorKind::AddressOutOfRange(0xFFFFFFFF)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut bus = MockBus([0; 65536]);
+        let err = load_srec("S00600004844521B\nS1060200A9428D80\n", &mut bus).unwrap_err();
+        assert_eq!(err, Error::at(2, ErrorKind::Checksum { expected: 0x7F, found: 0x80 }));
+        let err = load_srec("S1060200A9428D7F\nS5030002FA\n", &mut bus).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: record count does not match data records");
+        let err = load_srec("S4030000FC\n", &mut bus).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnsupportedRecord(4));
+        let err = load_srec(":1060200", &mut bus).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Syntax("record does not start with `S`"));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use crate::{Bus, Cpu, Variant};
+
+/// A CPU together with the bus it runs on and the levels of its interrupt
+/// inputs. `true` means a line is asserted (pulled low).
+#[derive(Clone)]
+pub struct Machine<V, B> {
+    pub cpu: Cpu<V>,
+    pub bus: B,
+    pub(crate) lines: Lines,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Lines {
+    irq: bool,
+    nmi: bool,
+    nmi_pending: bool,
+}
+
+impl Lines {
+    // Takes a pending interrupt, then executes one instruction.
+    pub(crate) fn step<V: Variant>(&mut self, cpu: &mut Cpu<V>, bus: &mut impl Bus) {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            cpu.nmi(bus);
+        } else if self.irq {
+            cpu.irq(bus);
+        }
+        cpu.step(bus);
+    }
+}
+
+impl<V: Variant, B: Bus> Machine<V, B> {
+    pub fn new(bus: B) -> Self {
+        Machine { cpu: Cpu::new(), bus, lines: Lines::default() }
+    }
+
+    /// Takes a pending interrupt, then executes one instruction. IRQ is
+    /// level triggered and NMI edge triggered.
+    pub fn step(&mut self) {
+        self.lines.step(&mut self.cpu, &mut self.bus);
+    }
+
+    pub fn irq_line(&self) -> bool {
+        self.lines.irq
+    }
+
+    pub fn nmi_line(&self) -> bool {
+        self.lines.nmi
+    }
+
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.lines.irq = asserted;
+    }
+
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        self.lines.nmi_pending |= asserted && !self.lines.nmi;
+        self.lines.nmi = asserted;
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset(&mut self.bus);
+    }
+
+    pub fn irq(&mut self) {
+        self.cpu.irq(&mut self.bus);
+    }
+
+    pub fn nmi(&mut self) {
+        self.cpu.nmi(&mut self.bus);
+    }
+}
+
+impl<V: Variant, B: SaveState> SaveState for Machine<V, B> {
+    fn state_version(&self) -> u16 {
+        2
+    }
+
+    fn save(&self, out: &mut StateWriter) {
+        out.section("cpu", &self.cpu);
+        out.section("bus", &self.bus);
+        out.bool(self.lines.irq);
+        out.bool(self.lines.nmi);
+        out.bool(self.lines.nmi_pending);
+    }
+
+    // Version 1 had no interrupt lines.
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        if version != 1 {
+            savestate::expect_version("machine", version, 2)?;
+        }
+        input.section("cpu", &mut self.cpu)?;
+        input.section("bus", &mut self.bus)?;
+        self.lines = match version {
+            1 => Lines::default(),
+            _ => Lines { irq: input.bool()?, nmi: input.bool()?, nmi_pending: input.bool()? },
+        };
+        Ok(())
+    }
+}
+
+impl<V: Variant, B: SaveState> Machine<V, B> {
+    /// Writes a complete save state of the CPU and everything on the bus.
+    pub fn save(&self) -> Vec<u8> {
+        savestate::save("machine", self)
+    }
+
+    /// Restores a save state written by `save`. On error the machine may be
+    /// partly restored and should be reset or reloaded.
+    pub fn load(&mut self, data: &[u8]) -> Result<(), savestate::Error> {
+        savestate::load("machine", self, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmos6502::Nmos6502;
+
+    // 64K of RAM with a latch at $8000 standing in for banking hardware.
+    struct Board {
+        ram: Box<[u8; 65536]>,
+        bank: u8,
+    }
+
+    impl Bus for Board {
+        fn read(&self, addr: u16) -> u8 { self.ram[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) {
+            match addr {
+                0x8000 => self.bank = value,
+                _ => self.ram[addr as usize] = value,
+            }
+        }
+    }
+
+    impl SaveState for Board {
+        fn save(&self, out: &mut StateWriter) {
+            out.section("ram", &self.ram);
+            out.u8(self.bank);
+        }
+
+        fn load(&mut self, _: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+            input.section("ram", &mut self.ram)?;
+            self.bank = input.u8()?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut ram = Box::new([0; 65536]);
+        // INC $10; STA $8000; JMP $0200
+        ram[0x0200..0x0208].copy_from_slice(&[0xE6, 0x10, 0x8D, 0x00, 0x80, 0x4C, 0x00, 0x02]);
+        let mut machine = Machine::<Nmos6502, _>::new(Board { ram, bank: 0 });
+        machine.cpu.pc = 0x0200;
+        machine.cpu.reg.update_a(3);
+        for _ in 0..3 {
+            machine.step();
+        }
+        let state = machine.save();
+        let cycles = machine.cpu.cycles;
+
+        for _ in 0..30 {
+            machine.step();
+        }
+        machine.bus.bank = 0;
+        assert_eq!(machine.bus.ram[0x10], 11);
+
+        machine.load(&state).unwrap();
+        assert_eq!(machine.bus.ram[0x10], 1);
+        assert_eq!(machine.bus.bank, 3);
+        assert_eq!(machine.cpu.pc, 0x0200);
+        assert_eq!(machine.cpu.cycles, cycles);
+        assert_eq!(machine.cpu.reg.get_a(), 3);
+    }
+
+    #[test]
+    fn test_interrupt_lines() {
+        let mut ram = Box::new([0; 65536]);
+        // $0200: CLI; NOP; NOP  handlers: IRQ $0300 INX; RTI  NMI $0310 INY; RTI
+        ram[0x0200..0x0203].copy_from_slice(&[0x58, 0xEA, 0xEA]);
+        ram[0x0300..0x0302].copy_from_slice(&[0xE8, 0x40]);
+        ram[0x0310..0x0312].copy_from_slice(&[0xC8, 0x40]);
+        ram[0xFFFA..].copy_from_slice(&[0x10, 0x03, 0, 0, 0x00, 0x03]);
+        let mut machine = Machine::<Nmos6502, _>::new(Board { ram, bank: 0 });
+        machine.cpu.pc = 0x0200;
+        machine.cpu.sp = 0xFF;
+        machine.cpu.reg.i = true;
+
+        // Masked until CLI, then taken while held.
+        machine.set_irq_line(true);
+        machine.step();
+        assert_eq!(machine.cpu.pc, 0x0201);
+        machine.step();
+        assert_eq!((machine.cpu.pc, machine.cpu.reg.get_x()), (0x0301, 1));
+        machine.step();
+        machine.step();
+        assert_eq!(machine.cpu.reg.get_x(), 2);
+        machine.set_irq_line(false);
+        machine.step();
+        machine.step();
+
+        // A held NMI line triggers once.
+        machine.set_nmi_line(true);
+        let state = machine.save();
+        for _ in 0..4 {
+            machine.step();
+        }
+        assert_eq!(machine.cpu.reg.get_y(), 1);
+
+        machine.load(&state).unwrap();
+        assert!(machine.nmi_line());
+        machine.step();
+        assert_eq!(machine.cpu.reg.get_y(), 1);
+    }
+}
@@ -1,4 +1,18 @@
-use cpu_6502::{nmos6502::Nmos6502, Bus, Cpu};
+use core::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use cpu_6502::device::acia::Acia;
+use cpu_6502::device::serial::{self, Port};
+use cpu_6502::device::sid::{self, Sid};
+use cpu_6502::device::Scheduler;
+use cpu_6502::machine::Machine;
+use cpu_6502::memory_map::MemoryMap;
+use cpu_6502::system::apple1::{self, Apple1};
+use cpu_6502::system::ben_eater::{self, BenEater, LcdWiring};
+use cpu_6502::system::kim1::{self, Kim1};
+use cpu_6502::system::vic20::{self, Vic20};
+use cpu_6502::{loader, nmos6502::Nmos6502, Bus, Cpu};
 
 struct Memory([u8; 65536]);
 impl Bus for Memory {
@@ -7,10 +21,342 @@ impl Bus for Memory {
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("gdb") => gdb(&args[1..]),
+        Some("dap") => dap(),
+        Some("serial") => serial(&args[1..]),
+        Some("sid") => play_sid(&args[1..]),
+        Some("apple1") => run_apple1(&args[1..]),
+        Some("kim1") => run_kim1(&args[1..]),
+        Some("ben-eater") => run_ben_eater(&args[1..]),
+        Some("vic20") => run_vic20(&args[1..]),
+        _ => demo(),
+    }
+}
+
+fn load_image(mem: &mut impl Bus, path: &str, base: u16) -> Option<u16> {
+    let data = std::fs::read(path).expect("failed to read image");
+    let ext = std::path::Path::new(path).extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+    let text = || String::from_utf8_lossy(&data).into_owned();
+    let info = match ext.as_deref() {
+        Some("hex" | "ihex") => loader::load_ihex(&text(), mem),
+        Some("s19" | "s28" | "s37" | "srec" | "mot") => loader::load_srec(&text(), mem),
+        Some("prg") => loader::load_prg(&data, mem),
+        Some("ptp") => loader::load_ptp(&text(), mem),
+        Some("o65") => loader::O65::parse(&data).and_then(|o65| {
+            o65.load(mem, o65.layout_at(base), &Default::default())
+        }),
+        _ => loader::load_raw(&data, base, mem),
+    };
+    info.unwrap_or_else(|e| panic!("{}: {}", path, e)).entry
+}
+
+fn parse_address(arg: Option<&String>, default: u16) -> u16 {
+    arg.map_or(default, |a| u16::from_str_radix(a.trim_start_matches('$'), 16).expect("invalid address"))
+}
+
+// Usage: gdb <image> [load address in hex] [port]
+fn gdb(args: &[String]) {
+    let Some(path) = args.first() else {
+        eprintln!("usage: gdb <image> [load address] [port]");
+        std::process::exit(2);
+    };
+    let base = parse_address(args.get(1), 0);
+    let port = args.get(2).map_or(1234, |p| p.parse().expect("invalid port"));
+
+    let mut mem = Memory([0; 65536]);
+    let entry = load_image(&mut mem, path, base);
+
+    let mut cpu = Cpu::<Nmos6502>::new();
+    cpu.reset(&mut mem);
+    if let Some(entry) = entry {
+        cpu.pc = entry;
+    }
+    eprintln!("waiting for debugger on port {}", port);
+    cpu_6502::gdb::serve(("127.0.0.1", port), &mut cpu, &mut mem).expect("gdb session failed");
+}
+
+// Usage: serial <image> [load address] [ACIA address] [pty]
+//
+// Runs the image at 1 MHz in 64K of RAM with a 6551 at $D000 (by default),
+// connected to stdin/stdout or, given "pty", to a new pseudo-terminal.
+fn serial(args: &[String]) {
+    let Some(path) = args.first() else {
+        eprintln!("usage: serial <image> [load address] [ACIA address] [pty]");
+        std::process::exit(2);
+    };
+    let base = parse_address(args.get(1), 0);
+    let address = parse_address(args.get(2), 0xD000);
+    const CLOCK: u64 = 1_000_000;
+
+    let acia = Rc::new(RefCell::new(Acia::new(CLOCK)));
+    let mut map = MemoryMap::new();
+    map.add_ram(0x0000..=0xFFFF, 0xFFFF);
+    map.add_device(address..=address.saturating_add(3), 3, acia.clone());
+    let entry = load_image(&mut map, path, base);
+
+    let mut port: Box<dyn Port> = match args.get(3).map(String::as_str) {
+        #[cfg(target_os = "linux")]
+        Some("pty") => {
+            let pty = serial::Pty::open().expect("failed to open a pty");
+            eprintln!("serial port at {}", pty.path().display());
+            Box::new(pty)
+        }
+        Some(other) => panic!("unknown port {:?}", other),
+        None => Box::new(serial::Stdio::new(true)),
+    };
+
+    let mut machine = Machine::<Nmos6502, _>::new(map);
+    machine.reset();
+    if let Some(entry) = entry {
+        machine.cpu.pc = entry;
+    }
+    let mut scheduler = Scheduler::new();
+    scheduler.add(acia.clone());
+
+    // Runs in slices of a millisecond, sleeping to keep to real time.
+    let start = Instant::now();
+    let mut elapsed = 0;
+    loop {
+        elapsed += scheduler.run(&mut machine, CLOCK / 1000);
+        acia.borrow_mut().pump(port.as_mut()).expect("serial port failed");
+        let due = Duration::from_micros(elapsed * 1_000_000 / CLOCK);
+        if let Some(ahead) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+// Usage: apple1 [image] [load address]
+//
+// Boots an Apple-1 into the Woz Monitor with its terminal on
+// stdin/stdout. An image, such as Integer BASIC, is loaded at $E000 by
+// default; BASIC then starts with E000R.
+fn run_apple1(args: &[String]) {
+    let mut apple = Apple1::new();
+    if let Some(path) = args.first() {
+        load_image(&mut apple.machine.bus, path, parse_address(args.get(1), 0xE000));
+    }
+    let mut port = serial::Stdio::new(true);
+
+    let start = Instant::now();
+    let mut elapsed = 0;
+    loop {
+        elapsed += apple.run(apple1::CLOCK / 1000);
+        apple.pump(&mut port).expect("terminal failed");
+        let due = Duration::from_micros(elapsed * 1_000_000 / apple1::CLOCK);
+        if let Some(ahead) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+// Usage: kim1 <6530-002 ROM> <6530-003 ROM> [image] [baud]
+//
+// Boots a KIM-1 in TTY mode with the teletype on stdin/stdout, typing the
+// RUBOUT the monitor needs to measure the baud rate. An image, such as a
+// paper tape, is loaded at $0200 by default.
+fn run_kim1(args: &[String]) {
+    let [rom_002, rom_003, ..] = args else {
+        eprintln!("usage: kim1 <6530-002 ROM> <6530-003 ROM> [image] [baud]");
+        std::process::exit(2);
+    };
+    let rom = |path: &String| -> [u8; 1024] {
+        let data = std::fs::read(path).expect("failed to read ROM");
+        data.try_into().unwrap_or_else(|_| panic!("{}: a 6530 ROM is 1024 bytes", path))
+    };
+    let baud = args.get(3).map_or(1200, |b| b.parse().expect("invalid baud rate"));
+    if !kim1::BAUD.contains(&baud) {
+        eprintln!("kim1: the baud rate must be {} to {}", kim1::BAUD.start(), kim1::BAUD.end());
+        std::process::exit(2);
+    }
+    let mut kim = Kim1::new(&rom(rom_002), &rom(rom_003), baud);
+    if let Some(path) = args.get(2) {
+        load_image(&mut kim.machine.bus, path, 0x0200);
+    }
+    kim.press(0x7F);
+    let mut port = serial::Stdio::new(true);
+
+    let start = Instant::now();
+    let mut elapsed = 0;
+    loop {
+        elapsed += kim.run(kim1::CLOCK / 1000);
+        kim.pump(&mut port).expect("terminal failed");
+        let due = Duration::from_micros(elapsed * 1_000_000 / kim1::CLOCK);
+        if let Some(ahead) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+// Usage: ben-eater <32K ROM> [4bit] [acia]
+//
+// Runs Ben Eater's breadboard computer, redrawing its LCD on stderr when
+// it changes. With "acia" the serial interface is fitted and connected to
+// stdin/stdout; "4bit" wires the LCD for the 4-bit interface.
+fn run_ben_eater(args: &[String]) {
+    let Some(path) = args.first() else {
+        eprintln!("usage: ben-eater <32K ROM> [4bit] [acia]");
+        std::process::exit(2);
+    };
+    let data = std::fs::read(path).expect("failed to read ROM");
+    let rom: [u8; 0x8000] = data.try_into().unwrap_or_else(|_| panic!("{}: the ROM must be 32K", path));
+    let option = |name: &str| args[1..].iter().any(|a| a == name);
+    let wiring = if option("4bit") { LcdWiring::FourBit } else { LcdWiring::EightBit };
+
+    let mut computer = BenEater::new(&rom, wiring);
+    let acia = option("acia").then(|| computer.add_acia());
+    let mut port = serial::Stdio::new(true);
+
+    let start = Instant::now();
+    let mut elapsed = 0;
+    let mut shown = String::new();
+    loop {
+        elapsed += computer.run(ben_eater::CLOCK / 1000);
+        if let Some(acia) = &acia {
+            acia.borrow_mut().pump(&mut port).expect("serial port failed");
+        }
+        let text = computer.lcd_text();
+        if text != shown {
+            let border = format!("+{}+", "-".repeat(text.lines().next().map_or(0, str::len)));
+            eprintln!("{}", border);
+            text.lines().for_each(|line| eprintln!("|{}|", line));
+            eprintln!("{}", border);
+            shown = text;
+        }
+        let due = Duration::from_micros(elapsed * 1_000_000 / ben_eater::CLOCK);
+        if let Some(ahead) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+// Usage: vic20 <KERNAL ROM> <BASIC ROM> [script] [seconds] [ntsc]
+//
+// Boots a VIC-20 to BASIC. Given a script, types it, lets it run for a
+// few seconds (2 by default) and prints the screen. Otherwise stdin is
+// typed in and the screen printed whenever it changes.
+fn run_vic20(args: &[String]) {
+    let model = if args.iter().any(|a| a == "ntsc") { vic20::Model::Ntsc } else { vic20::Model::Pal };
+    let args: Vec<&String> = args.iter().filter(|a| *a != "ntsc").collect();
+    let [kernal, basic, ..] = args[..] else {
+        eprintln!("usage: vic20 <KERNAL ROM> <BASIC ROM> [script] [seconds] [ntsc]");
+        std::process::exit(2);
+    };
+    let rom = |path: &String| -> [u8; 0x2000] {
+        let data = std::fs::read(path).expect("failed to read ROM");
+        data.try_into().unwrap_or_else(|_| panic!("{}: the ROM must be 8K", path))
+    };
+    let mut vic = Vic20::new(model, &rom(kernal), &rom(basic));
+    if !vic.boot() {
+        eprintln!("BASIC did not start; the screen shows:\n{}", vic.screen_text());
+        std::process::exit(1);
+    }
+
+    let clock = model.clock();
+    if let Some(script) = args.get(2) {
+        vic.type_keys(&std::fs::read_to_string(script).expect("failed to read script"));
+        while vic.typing() {
+            vic.run(clock / 50);
+        }
+        let seconds: u64 = args.get(3).map_or(2, |s| s.parse().expect("invalid duration"));
+        vic.run(seconds * clock);
+        print!("{}", vic.screen_text());
+        return;
+    }
+
+    let mut port = serial::Stdio::new(true);
+    let start = Instant::now();
+    let mut elapsed = 0;
+    let mut shown = String::new();
+    loop {
+        elapsed += vic.run(clock / 50);
+        if !vic.typing() {
+            while let Some(key) = port.poll().expect("terminal failed") {
+                vic.press(key);
+            }
+        }
+        let text = vic.screen_text();
+        if text != shown {
+            println!("{}{}", text, "-".repeat(22));
+            shown = text;
+        }
+        let due = Duration::from_micros(elapsed * 1_000_000 / clock);
+        if let Some(ahead) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+// Calls the subroutine at `addr` with `a` in the accumulator and runs
+// until it returns, to an RTS-adjusted address of $0000.
+fn call(machine: &mut Machine<Nmos6502, MemoryMap>, scheduler: &mut Scheduler, addr: u16, a: u8) {
+    machine.cpu.sp = 0xFD;
+    machine.bus.write(0x01FF, 0xFF);
+    machine.bus.write(0x01FE, 0xFF);
+    machine.cpu.reg.update_a(a);
+    machine.cpu.pc = addr;
+    let start = machine.cpu.cycles;
+    while machine.cpu.pc != 0x0000 {
+        if machine.cpu.cycles - start > 10_000_000 {
+            panic!("routine at ${:04X} did not return", addr);
+        }
+        scheduler.step(machine);
+    }
+}
+
+// Usage: sid <image> <init address> <play address> <output.wav> [seconds] [song]
+//
+// Renders a music routine on a PAL C64's 6581: calls init with the song
+// number in A, then play once a frame.
+fn play_sid(args: &[String]) {
+    let [path, init, play, output, ..] = args else {
+        eprintln!("usage: sid <image> <init address> <play address> <output.wav> [seconds] [song]");
+        std::process::exit(2);
+    };
+    let (init, play) = (parse_address(Some(init), 0), parse_address(Some(play), 0));
+    let seconds: u64 = args.get(4).map_or(60, |s| s.parse().expect("invalid duration"));
+    let song: u8 = args.get(5).map_or(0, |s| s.parse().expect("invalid song number"));
+    const CLOCK: u64 = 985_248;
+    const FRAME: u64 = 312 * 63;
+
+    let chip = Rc::new(RefCell::new(Sid::new(sid::Model::Mos6581, CLOCK, 44_100)));
+    let mut map = MemoryMap::new();
+    map.add_ram(0x0000..=0xFFFF, 0xFFFF);
+    map.add_device(0xD400..=0xD7FF, 0x1F, chip.clone());
+    load_image(&mut map, path, 0);
+
+    let mut machine = Machine::<Nmos6502, _>::new(map);
+    let mut scheduler = Scheduler::new();
+    scheduler.add(chip.clone());
+    call(&mut machine, &mut scheduler, init, song);
+    chip.borrow_mut().take_samples();
+    for _ in 0..seconds * CLOCK / FRAME {
+        let start = machine.cpu.cycles;
+        call(&mut machine, &mut scheduler, play, 0);
+        let elapsed = machine.cpu.cycles - start;
+        scheduler.tick(&mut machine, FRAME.saturating_sub(elapsed));
+    }
+
+    let mut chip = chip.borrow_mut();
+    let mut file = std::io::BufWriter::new(std::fs::File::create(output).expect("failed to create output"));
+    sid::write_wav(&mut file, chip.sample_rate(), &chip.take_samples()).expect("failed to write output");
+}
+
+// Speaks the debug adapter protocol on stdin/stdout; the program is
+// given by the client's launch request.
+fn dap() {
+    let input = cpu_6502::dap::spawn_reader(std::io::BufReader::new(std::io::stdin()));
+    let mut server = cpu_6502::dap::DapServer::new(Cpu::<Nmos6502>::new(), Memory([0; 65536]), std::io::stdout());
+    server.run(input).expect("dap session failed");
+}
+
+fn demo() {
     let mut mem = Memory([0; 65536]);
     mem.write(0xFFFC, 0xA0); // Set PC to A0 on reset
 
-    let mut cpu = Cpu::new(Nmos6502);
+    let mut cpu = Cpu::<Nmos6502>::new();
     cpu.pc = 0x00A0;
     cpu.sp = 0x01;
 
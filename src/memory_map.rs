@@ -0,0 +1,383 @@
+use core::cell::{Cell, RefCell};
+use core::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::device::Device;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use crate::Bus;
+
+/// What reads from unmapped addresses return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenBus {
+    /// The last value that was on the data bus.
+    #[default]
+    Last,
+    Value(u8),
+}
+
+enum Contents {
+    Memory { data: Vec<u8>, writable: bool },
+    Device(Rc<RefCell<dyn Device>>),
+}
+
+struct Region {
+    range: RangeInclusive<u16>,
+    mask: u16,
+    contents: Contents,
+}
+
+impl Region {
+    fn offset(&self, addr: u16) -> u16 {
+        (addr - self.range.start()) & self.mask
+    }
+}
+
+const UNMAPPED: u16 = u16::MAX;
+
+/// A bus assembled from RAM, ROM and device regions.
+///
+/// A region answers for its whole address range, with the offset into the
+/// region masked so that it mirrors every `mask + 1` bytes. Regions added
+/// later take precedence where they overlap. Writes to ROM are ignored, and
+/// unmapped reads return the open bus value.
+///
+/// Save states hold the RAM and the state of each device, and can only be
+/// loaded into a map laid out the same way. Read-only regions are left out.
+pub struct MemoryMap {
+    regions: Vec<Region>,
+    // Region index for each address.
+    table: Box<[u16]>,
+    open_bus: OpenBus,
+    last: Cell<u8>,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        MemoryMap {
+            regions: Vec::new(),
+            table: vec![UNMAPPED; 0x10000].into_boxed_slice(),
+            open_bus: OpenBus::default(),
+            last: Cell::new(0),
+        }
+    }
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_open_bus(&mut self, open_bus: OpenBus) {
+        self.open_bus = open_bus;
+    }
+
+    fn add(&mut self, range: RangeInclusive<u16>, mask: u16, contents: Contents) -> usize {
+        let id = self.regions.len();
+        assert!(id < UNMAPPED as usize, "too many regions");
+        for addr in range.clone() {
+            self.table[addr as usize] = id as u16;
+        }
+        self.regions.push(Region { range, mask, contents });
+        id
+    }
+
+    /// Maps zeroed RAM of `mask + 1` bytes, mirrored across `range`.
+    pub fn add_ram(&mut self, range: RangeInclusive<u16>, mask: u16) -> usize {
+        let data = vec![0; mask as usize + 1];
+        self.add(range, mask, Contents::Memory { data, writable: true })
+    }
+
+    /// Maps a ROM image, mirrored across `range`. Its length must be a
+    /// power of two no larger than 64K.
+    pub fn add_rom(&mut self, range: RangeInclusive<u16>, data: Vec<u8>) -> usize {
+        assert!(data.len().is_power_of_two() && data.len() <= 0x10000, "ROM size must be a power of two");
+        let mask = (data.len() - 1) as u16;
+        self.add(range, mask, Contents::Memory { data, writable: false })
+    }
+
+    /// Maps a device's registers, mirrored every `mask + 1` bytes.
+    pub fn add_device(&mut self, range: RangeInclusive<u16>, mask: u16, device: Rc<RefCell<dyn Device>>) -> usize {
+        self.add(range, mask, Contents::Device(device))
+    }
+
+    /// The contents of a RAM or ROM region.
+    pub fn memory(&self, id: usize) -> Option<&[u8]> {
+        match &self.regions.get(id)?.contents {
+            Contents::Memory { data, .. } => Some(data),
+            Contents::Device(_) => None,
+        }
+    }
+
+    pub fn memory_mut(&mut self, id: usize) -> Option<&mut [u8]> {
+        match &mut self.regions.get_mut(id)?.contents {
+            Contents::Memory { data, .. } => Some(data),
+            Contents::Device(_) => None,
+        }
+    }
+
+    /// Makes a RAM or ROM region read-only or writable, e.g. to protect
+    /// RAM holding a loaded ROM image. Returns false for devices.
+    pub fn set_write_protected(&mut self, id: usize, protected: bool) -> bool {
+        match self.regions.get_mut(id).map(|r| &mut r.contents) {
+            Some(Contents::Memory { writable, .. }) => {
+                *writable = !protected;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn region(&self, addr: u16) -> Option<&Region> {
+        self.regions.get(self.table[addr as usize] as usize)
+    }
+
+    fn open_bus_value(&self) -> u8 {
+        match self.open_bus {
+            OpenBus::Last => self.last.get(),
+            OpenBus::Value(value) => value,
+        }
+    }
+}
+
+impl Bus for MemoryMap {
+    fn read(&self, addr: u16) -> u8 {
+        let value = match self.region(addr) {
+            Some(region) => match &region.contents {
+                Contents::Memory { data, .. } => data[region.offset(addr) as usize],
+                Contents::Device(device) => device.borrow_mut().read(region.offset(addr)),
+            },
+            None => self.open_bus_value(),
+        };
+        self.last.set(value);
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.last.set(value);
+        let index = self.table[addr as usize] as usize;
+        let Some(region) = self.regions.get_mut(index) else { return };
+        let offset = region.offset(addr);
+        match &mut region.contents {
+            Contents::Memory { data, writable: true } => data[offset as usize] = value,
+            Contents::Memory { writable: false, .. } => {}
+            Contents::Device(device) => device.borrow_mut().write(offset, value),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match self.region(addr) {
+            Some(region) => match &region.contents {
+                Contents::Memory { data, .. } => data[region.offset(addr) as usize],
+                Contents::Device(device) => device.borrow().peek(region.offset(addr)),
+            },
+            None => self.open_bus_value(),
+        }
+    }
+}
+
+impl SaveState for MemoryMap {
+    fn save(&self, out: &mut StateWriter) {
+        out.u8(self.last.get());
+        out.u32(self.regions.len() as u32);
+        for region in &self.regions {
+            match &region.contents {
+                Contents::Memory { data, writable } => {
+                    out.bool(*writable);
+                    if *writable {
+                        out.bytes(data);
+                    }
+                }
+                Contents::Device(device) => {
+                    if let Some(state) = device.borrow().state() {
+                        out.section("device", state);
+                    }
+                }
+            }
+        }
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("memory map", version, 1)?;
+        self.last.set(input.u8()?);
+        if input.u32()? as usize != self.regions.len() {
+            return Err(savestate::Error::Invalid("save state is for a different memory map"));
+        }
+        for region in &mut self.regions {
+            match &mut region.contents {
+                Contents::Memory { data, writable } => {
+                    *writable = input.bool()?;
+                    if *writable {
+                        input.bytes_into(data)?;
+                    }
+                }
+                Contents::Device(device) => {
+                    if let Some(state) = device.borrow_mut().state_mut() {
+                        input.section("device", state)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A latch that counts how often it was read.
+    #[derive(Default)]
+    struct Latch {
+        value: u8,
+        reads: u32,
+        offsets: Vec<u16>,
+    }
+
+    impl Device for Latch {
+        fn peek(&self, _: u16) -> u8 {
+            self.value
+        }
+
+        fn read(&mut self, offset: u16) -> u8 {
+            self.reads += 1;
+            self.offsets.push(offset);
+            self.value
+        }
+
+        fn write(&mut self, offset: u16, value: u8) {
+            self.offsets.push(offset);
+            self.value = value;
+        }
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut map = MemoryMap::new();
+        // NES style: 2K of RAM mirrored through $1FFF.
+        let ram = map.add_ram(0x0000..=0x1FFF, 0x07FF);
+        map.write(0x0801, 0x42);
+        assert_eq!(map.read(0x0001), 0x42);
+        assert_eq!(map.read(0x1801), 0x42);
+        assert_eq!(map.memory(ram).unwrap().len(), 0x800);
+
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        map.add_device(0x2000..=0x3FFF, 0x0007, latch.clone());
+        map.write(0x3FF9, 7);
+        assert_eq!(map.read(0x2001), 7);
+        assert_eq!(latch.borrow().offsets, [1, 1]);
+    }
+
+    #[test]
+    fn test_rom() {
+        let mut map = MemoryMap::new();
+        let rom: Vec<u8> = (0..0x4000).map(|i| i as u8).collect();
+        let id = map.add_rom(0x8000..=0xFFFF, rom);
+        assert_eq!(map.read(0xC005), 5);
+        map.write(0x8005, 0xFF);
+        assert_eq!(map.read(0x8005), 5);
+
+        assert!(map.set_write_protected(id, false));
+        map.write(0x8005, 0xFF);
+        assert_eq!(map.read(0xC005), 0xFF);
+    }
+
+    #[test]
+    fn test_overlap() {
+        let mut map = MemoryMap::new();
+        map.add_ram(0x0000..=0xFFFF, 0xFFFF);
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        map.add_device(0xD010..=0xD013, 0x0003, latch.clone());
+        map.write(0xD012, 9);
+        map.write(0xD014, 10);
+        assert_eq!((map.read(0xD010), map.read(0xD014)), (9, 10));
+        assert_eq!(latch.borrow().offsets, [2, 0]);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut map = MemoryMap::new();
+        map.add_ram(0x0000..=0x00FF, 0x00FF);
+        map.write(0x10, 0x5A);
+        assert_eq!(map.read(0x10), 0x5A);
+        assert_eq!(map.read(0x4000), 0x5A);
+
+        map.set_open_bus(OpenBus::Value(0xFF));
+        assert_eq!(map.read(0x4000), 0xFF);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut map = MemoryMap::new();
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        map.add_device(0x6000..=0x600F, 0x000F, latch.clone());
+        map.write(0x6000, 3);
+        assert_eq!(map.peek(0x6000), 3);
+        assert_eq!(latch.borrow().reads, 0);
+        map.read(0x6000);
+        assert_eq!(latch.borrow().reads, 1);
+    }
+
+    impl SaveState for Latch {
+        fn save(&self, out: &mut StateWriter) {
+            out.u8(self.value);
+        }
+
+        fn load(&mut self, _: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+            self.value = input.u8()?;
+            Ok(())
+        }
+    }
+
+    // A latch with a save state.
+    struct SavedLatch(Latch);
+
+    impl Device for SavedLatch {
+        fn peek(&self, offset: u16) -> u8 {
+            self.0.peek(offset)
+        }
+
+        fn write(&mut self, offset: u16, value: u8) {
+            self.0.write(offset, value);
+        }
+
+        fn state(&self) -> Option<&dyn SaveState> {
+            Some(&self.0)
+        }
+
+        fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+            Some(&mut self.0)
+        }
+    }
+
+    fn saved_map() -> (MemoryMap, Rc<RefCell<SavedLatch>>) {
+        let mut map = MemoryMap::new();
+        map.add_ram(0x0000..=0x07FF, 0x07FF);
+        map.add_device(0x6000..=0x600F, 0x000F, Rc::new(RefCell::new(Latch::default())));
+        let latch = Rc::new(RefCell::new(SavedLatch(Latch::default())));
+        map.add_device(0x7000..=0x700F, 0x000F, latch.clone());
+        map.add_rom(0x8000..=0xFFFF, vec![0xEA; 0x8000]);
+        (map, latch)
+    }
+
+    #[test]
+    fn test_save_state() {
+        let (mut map, _) = saved_map();
+        map.write(0x0123, 0x45);
+        map.write(0x7000, 0x67);
+        let state = savestate::save("bus", &map);
+
+        let (mut restored, latch) = saved_map();
+        savestate::load("bus", &mut restored, &state).unwrap();
+        // The last value on the bus, from the write to the latch.
+        assert_eq!(restored.read(0x4000), 0x67);
+        assert_eq!(restored.read(0x0123), 0x45);
+        assert_eq!(latch.borrow().0.value, 0x67);
+
+        let mut other = MemoryMap::new();
+        other.add_ram(0x0000..=0x07FF, 0x07FF);
+        assert_eq!(
+            savestate::load("bus", &mut other, &state),
+            Err(savestate::Error::Invalid("save state is for a different memory map"))
+        );
+    }
+}
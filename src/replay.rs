@@ -0,0 +1,437 @@
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use crate::machine::Machine;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use crate::{Bus, Variant};
+
+/// Something from outside the machine that affects its execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Irq(bool),
+    Nmi(bool),
+    /// A byte read from one of the designated input addresses.
+    Read { addr: u16, value: u8 },
+}
+
+/// An input stamped with the CPU cycle it happened on. Line changes take
+/// effect at the instruction boundary at `cycle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub cycle: u64,
+    pub input: Input,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Divergence {
+    /// The state at a checkpoint hashed differently than when recorded.
+    Hash { cycle: u64, expected: u64, found: u64 },
+    /// An input read happened that the recording doesn't have.
+    Read { cycle: u64, addr: u16 },
+    /// A state hash was due at a different cycle than when recorded.
+    Checkpoint { expected: u64, found: u64 },
+    /// A recorded event was not reached at its cycle.
+    Missed(Event),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Hash { cycle, expected, found } => {
+                write!(f, "state hash at cycle {} is {:016x}, recorded {:016x}", cycle, found, expected)
+            }
+            Divergence::Read { cycle, addr } => write!(f, "unrecorded read of ${:04X} at cycle {}", addr, cycle),
+            Divergence::Checkpoint { expected, found } => {
+                write!(f, "checkpoint at cycle {}, recorded at cycle {}", found, expected)
+            }
+            Divergence::Missed(event) => write!(f, "{:?} at cycle {} was not reached", event.input, event.cycle),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// The inputs of a run and hashes of the machine state taken every
+/// `hash_interval` cycles.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub inputs: Vec<RangeInclusive<u16>>,
+    pub hash_interval: u64,
+    pub events: Vec<Event>,
+    /// (cycle, hash) pairs, taken at the first instruction boundary at or
+    /// after each interval.
+    pub hashes: Vec<(u64, u64)>,
+}
+
+// Written as a save state section so recordings can be stored in files
+// with `savestate::save`.
+impl SaveState for Recording {
+    fn save(&self, out: &mut StateWriter) {
+        out.u32(self.inputs.len() as u32);
+        for range in &self.inputs {
+            out.u16(*range.start());
+            out.u16(*range.end());
+        }
+        out.u64(self.hash_interval);
+        out.u32(self.events.len() as u32);
+        for event in &self.events {
+            out.u64(event.cycle);
+            match event.input {
+                Input::Irq(asserted) => {
+                    out.u8(0);
+                    out.bool(asserted);
+                }
+                Input::Nmi(asserted) => {
+                    out.u8(1);
+                    out.bool(asserted);
+                }
+                Input::Read { addr, value } => {
+                    out.u8(2);
+                    out.u16(addr);
+                    out.u8(value);
+                }
+            }
+        }
+        out.u32(self.hashes.len() as u32);
+        for &(cycle, hash) in &self.hashes {
+            out.u64(cycle);
+            out.u64(hash);
+        }
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("recording", version, 1)?;
+        self.inputs = (0..input.u32()?)
+            .map(|_| Ok(input.u16()?..=input.u16()?))
+            .collect::<Result<_, savestate::Error>>()?;
+        self.hash_interval = input.u64()?;
+        self.events = (0..input.u32()?)
+            .map(|_| {
+                let cycle = input.u64()?;
+                let input = match input.u8()? {
+                    0 => Input::Irq(input.bool()?),
+                    1 => Input::Nmi(input.bool()?),
+                    2 => Input::Read { addr: input.u16()?, value: input.u8()? },
+                    _ => return Err(savestate::Error::Invalid("unknown input event")),
+                };
+                Ok(Event { cycle, input })
+            })
+            .collect::<Result<_, _>>()?;
+        self.hashes = (0..input.u32()?)
+            .map(|_| Ok((input.u64()?, input.u64()?)))
+            .collect::<Result<_, savestate::Error>>()?;
+        Ok(())
+    }
+}
+
+// 64-bit FNV-1a.
+fn hash(data: impl IntoIterator<Item = u8>) -> u64 {
+    data.into_iter().fold(0xCBF2_9CE4_8422_2325, |h, b| (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+/// Hashes the CPU, the interrupt lines and every byte of the address space
+/// except the input ranges, read with `Bus::peek`.
+pub fn state_hash<V: Variant, B: Bus>(machine: &Machine<V, B>, inputs: &[RangeInclusive<u16>]) -> u64 {
+    let cpu = savestate::save("cpu", &machine.cpu);
+    let lines = [machine.irq_line() as u8, machine.nmi_line() as u8];
+    let memory = (0..=0xFFFF)
+        .filter(|addr| !inputs.iter().any(|r| r.contains(addr)))
+        .map(|addr| machine.bus.peek(addr));
+    hash(cpu.into_iter().chain(lines).chain(memory))
+}
+
+// Counts bus cycles so input reads can be stamped, and either logs input
+// reads or serves them from a recording.
+struct InputBus<'a, B> {
+    bus: &'a mut B,
+    inputs: &'a [RangeInclusive<u16>],
+    cycle: Cell<u64>,
+    replay: Option<(&'a [Event], &'a Cell<usize>)>,
+    log: RefCell<Vec<Event>>,
+    diverged: Cell<Option<Divergence>>,
+}
+
+impl<'a, B: Bus> InputBus<'a, B> {
+    fn new(bus: &'a mut B, inputs: &'a [RangeInclusive<u16>], cycle: u64) -> Self {
+        InputBus {
+            bus,
+            inputs,
+            cycle: Cell::new(cycle),
+            replay: None,
+            log: RefCell::new(Vec::new()),
+            diverged: Cell::new(None),
+        }
+    }
+
+    fn is_input(&self, addr: u16) -> bool {
+        self.inputs.iter().any(|r| r.contains(&addr))
+    }
+
+    fn tick(&self) -> u64 {
+        let cycle = self.cycle.get();
+        self.cycle.set(cycle + 1);
+        cycle
+    }
+}
+
+impl<B: Bus> Bus for InputBus<'_, B> {
+    fn read(&self, addr: u16) -> u8 {
+        let cycle = self.tick();
+        if !self.is_input(addr) {
+            return self.bus.read(addr);
+        }
+        let Some((events, next)) = self.replay else {
+            let value = self.bus.read(addr);
+            self.log.borrow_mut().push(Event { cycle, input: Input::Read { addr, value } });
+            return value;
+        };
+        match events.get(next.get()) {
+            Some(&Event { cycle: c, input: Input::Read { addr: a, value } }) if c == cycle && a == addr => {
+                next.set(next.get() + 1);
+                value
+            }
+            _ => {
+                self.diverged.set(Some(Divergence::Read { cycle, addr }));
+                self.bus.read(addr)
+            }
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.tick();
+        self.bus.write(addr, value)
+    }
+
+    // Input devices are only touched while recording.
+    fn dummy_read(&self, addr: u16) {
+        self.tick();
+        if self.replay.is_none() || !self.is_input(addr) {
+            self.bus.dummy_read(addr)
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+}
+
+// The state hash for a checkpoint, if one is due.
+fn checkpoint<V: Variant, B: Bus>(
+    machine: &Machine<V, B>,
+    recording: &Recording,
+    last: Option<u64>,
+) -> Option<(u64, u64)> {
+    let (cycle, interval) = (machine.cpu.cycles, recording.hash_interval);
+    let due = interval > 0 && last.is_none_or(|last| cycle / interval > last / interval);
+    due.then(|| (cycle, state_hash(machine, &recording.inputs)))
+}
+
+/// Runs a machine while logging its external inputs.
+pub struct Recorder {
+    recording: Recording,
+}
+
+impl Recorder {
+    /// Records reads from the `inputs` address ranges and hashes the state
+    /// every `hash_interval` cycles (never if zero).
+    pub fn new(inputs: Vec<RangeInclusive<u16>>, hash_interval: u64) -> Self {
+        Recorder { recording: Recording { inputs, hash_interval, ..Recording::default() } }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+
+    pub fn set_irq_line<V: Variant, B: Bus>(&mut self, machine: &mut Machine<V, B>, asserted: bool) {
+        if machine.irq_line() != asserted {
+            self.recording.events.push(Event { cycle: machine.cpu.cycles, input: Input::Irq(asserted) });
+            machine.set_irq_line(asserted);
+        }
+    }
+
+    pub fn set_nmi_line<V: Variant, B: Bus>(&mut self, machine: &mut Machine<V, B>, asserted: bool) {
+        if machine.nmi_line() != asserted {
+            self.recording.events.push(Event { cycle: machine.cpu.cycles, input: Input::Nmi(asserted) });
+            machine.set_nmi_line(asserted);
+        }
+    }
+
+    pub fn step<V: Variant, B: Bus>(&mut self, machine: &mut Machine<V, B>) {
+        let rec = &mut self.recording;
+        if let Some(hash) = checkpoint(machine, rec, rec.hashes.last().map(|h| h.0)) {
+            rec.hashes.push(hash);
+        }
+        let mut bus = InputBus::new(&mut machine.bus, &rec.inputs, machine.cpu.cycles);
+        machine.lines.step(&mut machine.cpu, &mut bus);
+        rec.events.extend(bus.log.into_inner());
+    }
+}
+
+/// Runs a machine from the state a recording started in, feeding it the
+/// recorded inputs instead of reading the input devices.
+pub struct Replayer {
+    recording: Recording,
+    next_event: Cell<usize>,
+    next_hash: usize,
+}
+
+impl Replayer {
+    pub fn new(recording: Recording) -> Self {
+        Replayer { recording, next_event: Cell::new(0), next_hash: 0 }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Whether every recorded event has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.next_event.get() == self.recording.events.len()
+    }
+
+    /// Applies the line changes due now, checks the state hash if a
+    /// checkpoint is due and executes one instruction. On a divergence
+    /// the instruction may already have executed.
+    pub fn step<V: Variant, B: Bus>(&mut self, machine: &mut Machine<V, B>) -> Result<(), Divergence> {
+        let rec = &self.recording;
+        let cycle = machine.cpu.cycles;
+        while let Some(&event) = rec.events.get(self.next_event.get()) {
+            match event.input {
+                _ if event.cycle < cycle => return Err(Divergence::Missed(event)),
+                Input::Irq(asserted) if event.cycle == cycle => machine.set_irq_line(asserted),
+                Input::Nmi(asserted) if event.cycle == cycle => machine.set_nmi_line(asserted),
+                _ => break,
+            }
+            self.next_event.set(self.next_event.get() + 1);
+        }
+
+        let last = self.next_hash.checked_sub(1).map(|i| rec.hashes[i].0);
+        if let (Some((cycle, found)), Some(&(at, expected))) = (checkpoint(machine, rec, last), rec.hashes.get(self.next_hash)) {
+            if at != cycle {
+                return Err(Divergence::Checkpoint { expected: at, found: cycle });
+            }
+            if found != expected {
+                return Err(Divergence::Hash { cycle, expected, found });
+            }
+            self.next_hash += 1;
+        }
+
+        let mut bus = InputBus::new(&mut machine.bus, &rec.inputs, cycle);
+        bus.replay = Some((&rec.events, &self.next_event));
+        machine.lines.step(&mut machine.cpu, &mut bus);
+        bus.diverged.get().map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmos6502::Nmos6502;
+
+    // RAM with a noise source at $D000 whose sequence depends on `seed`.
+    struct Board {
+        ram: Box<[u8; 65536]>,
+        noise: Cell<u32>,
+    }
+
+    impl Bus for Board {
+        fn read(&self, addr: u16) -> u8 {
+            match addr {
+                0xD000 => {
+                    let x = self.noise.get();
+                    let x = x ^ x << 13;
+                    let x = x ^ x >> 17;
+                    self.noise.set(x ^ x << 5);
+                    x as u8
+                }
+                _ => self.ram[addr as usize],
+            }
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.ram[addr as usize] = value;
+        }
+    }
+
+    fn get_machine(seed: u32) -> Machine<Nmos6502, Board> {
+        let mut ram = Box::new([0; 65536]);
+        // loop: LDA $D000; ADC $20; STA $20; INX; CLI; JMP loop
+        ram[0x0200..0x020B].copy_from_slice(&[0xAD, 0x00, 0xD0, 0x65, 0x20, 0x85, 0x20, 0xE8, 0x58, 0x4C, 0x00]);
+        ram[0x020B] = 0x02;
+        // IRQ: INC $30; RTI  NMI: INC $31; RTI
+        ram[0x0300..0x0303].copy_from_slice(&[0xE6, 0x30, 0x40]);
+        ram[0x0310..0x0313].copy_from_slice(&[0xE6, 0x31, 0x40]);
+        ram[0xFFFA..].copy_from_slice(&[0x10, 0x03, 0, 0, 0x00, 0x03]);
+        let mut machine = Machine::new(Board { ram, noise: Cell::new(seed) });
+        machine.cpu.pc = 0x0200;
+        machine.cpu.sp = 0xFF;
+        machine
+    }
+
+    fn record(steps: u32) -> (Machine<Nmos6502, Board>, Recording) {
+        let mut machine = get_machine(1);
+        let mut recorder = Recorder::new(vec![0xD000..=0xD000], 500);
+        for i in 0..steps {
+            match i % 97 {
+                10 => recorder.set_irq_line(&mut machine, true),
+                14 => recorder.set_irq_line(&mut machine, false),
+                50 => recorder.set_nmi_line(&mut machine, true),
+                51 => recorder.set_nmi_line(&mut machine, false),
+                _ => {}
+            }
+            recorder.step(&mut machine);
+        }
+        (machine, recorder.finish())
+    }
+
+    fn replay(recording: Recording, steps: u32) -> Result<Machine<Nmos6502, Board>, Divergence> {
+        let mut machine = get_machine(12345);
+        let mut replayer = Replayer::new(recording);
+        for _ in 0..steps {
+            replayer.step(&mut machine)?;
+        }
+        assert!(replayer.is_finished());
+        Ok(machine)
+    }
+
+    #[test]
+    fn test_replay() {
+        let (recorded, recording) = record(2000);
+        assert!(recording.hashes.len() > 10);
+        assert!(recorded.bus.ram[0x30] > 0 && recorded.bus.ram[0x31] > 0);
+
+        let replayed = replay(recording.clone(), 2000).unwrap();
+        assert_eq!(recorded.cpu.snapshot(), replayed.cpu.snapshot());
+        assert!(recorded.bus.ram[..] == replayed.bus.ram[..]);
+        // The noise source itself was never read.
+        assert_eq!(replayed.bus.noise.get(), 12345);
+    }
+
+    #[test]
+    fn test_divergence() {
+        let (_, mut recording) = record(2000);
+        let i = recording.events.len() / 2;
+        let Input::Read { addr, value } = recording.events[i].input else { panic!() };
+        recording.events[i].input = Input::Read { addr, value: value ^ 1 };
+        let err = replay(recording.clone(), 2000).err().unwrap();
+        assert!(matches!(err, Divergence::Hash { .. }), "{}", err);
+
+        recording.events.remove(i);
+        let err = replay(recording, 2000).err().unwrap();
+        assert!(matches!(err, Divergence::Read { addr: 0xD000, .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_save_recording() {
+        let (_, recording) = record(300);
+        let data = savestate::save("recording", &recording);
+        let mut loaded = Recording::default();
+        savestate::load("recording", &mut loaded, &data).unwrap();
+        assert_eq!(loaded, recording);
+    }
+}
@@ -0,0 +1,421 @@
+use core::fmt;
+use std::collections::VecDeque;
+
+use crate::machine::Machine;
+use crate::savestate::{self, SaveState};
+use crate::{Bus, Variant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewindError {
+    /// The target lies before the oldest state still in the buffer.
+    OutOfRange { oldest: u64 },
+    State(savestate::Error),
+}
+
+impl fmt::Display for RewindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewindError::OutOfRange { oldest } => write!(f, "can't rewind past instruction {}", oldest),
+            RewindError::State(e) => write!(f, "can't restore state: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RewindError {}
+
+impl From<savestate::Error> for RewindError {
+    fn from(e: savestate::Error) -> Self {
+        RewindError::State(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Point {
+    instructions: u64,
+    cycles: u64,
+}
+
+// A full save state followed by states stored as run-length encoded XOR
+// differences from it.
+struct Group {
+    point: Point,
+    keyframe: Vec<u8>,
+    deltas: Vec<(Point, Vec<u8>)>,
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(|(_, d)| d.len()).sum::<usize>()
+    }
+}
+
+// Encodes `state ^ base` as (zero run, literal run, literal bytes) triples.
+// Both lengths are LEB128 varints.
+fn encode_delta(base: &[u8], state: &[u8]) -> Vec<u8> {
+    fn varint(out: &mut Vec<u8>, mut n: usize) {
+        while n >= 0x80 {
+            out.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    let diff: Vec<u8> = base.iter().zip(state).map(|(a, b)| a ^ b).collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < diff.len() {
+        let zeros = diff[i..].iter().take_while(|&&b| b == 0).count();
+        i += zeros;
+        let literal = diff[i..].iter().take_while(|&&b| b != 0).count();
+        varint(&mut out, zeros);
+        varint(&mut out, literal);
+        out.extend_from_slice(&diff[i..i + literal]);
+        i += literal;
+    }
+    out
+}
+
+fn decode_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    fn varint(data: &[u8], pos: &mut usize) -> usize {
+        let mut n = 0;
+        let mut shift = 0;
+        loop {
+            let b = data[*pos];
+            *pos += 1;
+            n |= ((b & 0x7F) as usize) << shift;
+            if b & 0x80 == 0 {
+                return n;
+            }
+            shift += 7;
+        }
+    }
+
+    let mut state = base.to_vec();
+    let (mut pos, mut i) = (0, 0);
+    while pos < delta.len() {
+        i += varint(delta, &mut pos);
+        let literal = varint(delta, &mut pos);
+        for (s, d) in state[i..i + literal].iter_mut().zip(&delta[pos..pos + literal]) {
+            *s ^= d;
+        }
+        pos += literal;
+        i += literal;
+    }
+    state
+}
+
+/// Records machine states while running so execution can be wound back.
+///
+/// A state is captured every `interval` cycles (e.g. once per video frame).
+/// Every `keyframe_every`th capture is a full save state; the rest are
+/// stored as compressed differences from the last keyframe. The oldest
+/// keyframe and its deltas are dropped whenever the buffer grows beyond
+/// `budget` bytes.
+///
+/// Rewinding restores the nearest earlier state and re-executes forward, so
+/// it is exact as long as the machine is deterministic. Each instruction is
+/// executed by a `step` closure, such as `|m| scheduler.step(m)` for a
+/// machine with clocked devices or `Machine::step` for one without; the
+/// replay must use the same one as the original run.
+pub struct Rewind {
+    interval: u64,
+    keyframe_every: usize,
+    budget: usize,
+    groups: VecDeque<Group>,
+    used: usize,
+    instructions: u64,
+    last_capture: Option<u64>,
+}
+
+impl Rewind {
+    pub fn new(interval: u64, keyframe_every: usize, budget: usize) -> Self {
+        Rewind {
+            interval,
+            keyframe_every: keyframe_every.max(1),
+            budget,
+            groups: VecDeque::new(),
+            used: 0,
+            instructions: 0,
+            last_capture: None,
+        }
+    }
+
+    /// Instructions executed through `step`, counting from creation.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Bytes of state held in the buffer.
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+
+    /// The oldest instruction count that can be rewound to.
+    pub fn oldest(&self) -> Option<u64> {
+        self.groups.front().map(|g| g.point.instructions)
+    }
+
+    /// Executes one instruction with `step`, capturing the state beforehand
+    /// when due.
+    pub fn step<V: Variant, B: Bus + SaveState>(
+        &mut self,
+        machine: &mut Machine<V, B>,
+        mut step: impl FnMut(&mut Machine<V, B>),
+    ) {
+        let due = self.last_capture.is_none_or(|last| machine.cpu.cycles.wrapping_sub(last) >= self.interval);
+        if due {
+            self.capture(machine);
+        }
+        step(machine);
+        self.instructions += 1;
+    }
+
+    /// Captures the current state regardless of the interval.
+    pub fn capture<V: Variant, B: SaveState>(&mut self, machine: &Machine<V, B>) {
+        let point = Point { instructions: self.instructions, cycles: machine.cpu.cycles };
+        let state = machine.save();
+        self.last_capture = Some(point.cycles);
+
+        match self.groups.back_mut() {
+            Some(group) if group.deltas.len() + 1 < self.keyframe_every && group.keyframe.len() == state.len() => {
+                let delta = encode_delta(&group.keyframe, &state);
+                self.used += delta.len();
+                group.deltas.push((point, delta));
+            }
+            _ => {
+                self.used += state.len();
+                self.groups.push_back(Group { point, keyframe: state, deltas: Vec::new() });
+            }
+        }
+        while self.used > self.budget && self.groups.len() > 1 {
+            let group = self.groups.pop_front().unwrap();
+            self.used -= group.size();
+        }
+    }
+
+    // Restores the latest state at or before `pred` and drops everything
+    // captured after it.
+    fn restore<V: Variant, B: SaveState>(
+        &mut self,
+        machine: &mut Machine<V, B>,
+        pred: impl Fn(Point) -> bool,
+    ) -> Result<Point, RewindError> {
+        let oldest = self.oldest().unwrap_or(self.instructions);
+        let g = self.groups.iter().rposition(|g| pred(g.point)).ok_or(RewindError::OutOfRange { oldest })?;
+        let group = &mut self.groups[g];
+        let d = group.deltas.iter().rposition(|(p, _)| pred(*p));
+        let (point, state) = match d {
+            Some(d) => (group.deltas[d].0, decode_delta(&group.keyframe, &group.deltas[d].1)),
+            None => (group.point, group.keyframe.clone()),
+        };
+        machine.load(&state)?;
+
+        for (_, delta) in group.deltas.drain(d.map_or(0, |d| d + 1)..) {
+            self.used -= delta.len();
+        }
+        for group in self.groups.drain(g + 1..) {
+            self.used -= group.size();
+        }
+        self.instructions = point.instructions;
+        self.last_capture = Some(point.cycles);
+        Ok(point)
+    }
+
+    /// Winds the machine back by `count` instructions.
+    pub fn rewind_instructions<V: Variant, B: Bus + SaveState>(
+        &mut self,
+        machine: &mut Machine<V, B>,
+        count: u64,
+        mut step: impl FnMut(&mut Machine<V, B>),
+    ) -> Result<(), RewindError> {
+        let oldest = self.oldest().unwrap_or(self.instructions);
+        let target = self.instructions.checked_sub(count).ok_or(RewindError::OutOfRange { oldest })?;
+        let point = self.restore(machine, |p| p.instructions <= target)?;
+        for _ in point.instructions..target {
+            self.step(machine, &mut step);
+        }
+        Ok(())
+    }
+
+    /// Winds the machine back by `cycles`, landing on the last instruction
+    /// boundary at or before that point.
+    pub fn rewind_cycles<V: Variant, B: Bus + SaveState>(
+        &mut self,
+        machine: &mut Machine<V, B>,
+        cycles: u64,
+        mut step: impl FnMut(&mut Machine<V, B>),
+    ) -> Result<(), RewindError> {
+        let oldest = self.oldest().unwrap_or(self.instructions);
+        let target = machine.cpu.cycles.checked_sub(cycles).ok_or(RewindError::OutOfRange { oldest })?;
+        let point = self.restore(machine, |p| p.cycles <= target)?;
+        while machine.cpu.cycles < target {
+            self.step(machine, &mut step);
+        }
+        if machine.cpu.cycles > target {
+            // Overshot by part of an instruction; replay one fewer.
+            let count = self.instructions - point.instructions - 1;
+            self.restore(machine, |p| p.instructions <= point.instructions)?;
+            for _ in 0..count {
+                self.step(machine, &mut step);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::via::Via;
+    use crate::device::Scheduler;
+    use crate::memory_map::MemoryMap;
+    use crate::nmos6502::Nmos6502;
+    use core::cell::RefCell;
+    use std::rc::Rc;
+
+    fn get_machine() -> Machine<Nmos6502, Ram> {
+        let mut ram = Ram(Box::new([0; 65536]));
+        // loop: INC $10,X; INX; BNE loop; INC $0F; JMP loop
+        ram.0[0x0200..0x020B].copy_from_slice(&[0xF6, 0x10, 0xE8, 0xD0, 0xFB, 0xE6, 0x0F, 0x4C, 0x00, 0x02, 0x00]);
+        let mut machine = Machine::new(ram);
+        machine.cpu.pc = 0x0200;
+        machine
+    }
+
+    struct Ram(Box<[u8; 65536]>);
+    impl Bus for Ram {
+        fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+        fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+    }
+
+    impl SaveState for Ram {
+        fn save(&self, out: &mut savestate::StateWriter) {
+            self.0.save(out);
+        }
+
+        fn load(&mut self, version: u16, input: &mut savestate::StateReader) -> Result<(), savestate::Error> {
+            self.0.load(version, input)
+        }
+    }
+
+    fn run(steps: u64) -> Machine<Nmos6502, Ram> {
+        let mut machine = get_machine();
+        for _ in 0..steps {
+            machine.step();
+        }
+        machine
+    }
+
+    fn assert_same(a: &Machine<Nmos6502, Ram>, b: &Machine<Nmos6502, Ram>) {
+        assert_eq!(a.cpu.snapshot(), b.cpu.snapshot());
+        assert!(a.bus.0[..] == b.bus.0[..]);
+    }
+
+    #[test]
+    fn test_delta_encoding() {
+        let base = vec![0u8; 300];
+        let mut state = base.clone();
+        state[1] = 5;
+        state[2] = 6;
+        state[299] = 7;
+        let delta = encode_delta(&base, &state);
+        assert!(delta.len() < 10);
+        assert_eq!(decode_delta(&base, &delta), state);
+    }
+
+    #[test]
+    fn test_rewind_instructions() {
+        let mut machine = get_machine();
+        let mut rewind = Rewind::new(100, 8, usize::MAX);
+        for _ in 0..2000 {
+            rewind.step(&mut machine, Machine::step);
+        }
+        rewind.rewind_instructions(&mut machine, 777, Machine::step).unwrap();
+        assert_eq!(rewind.instructions(), 1223);
+        assert_same(&machine, &run(1223));
+
+        // Recording continues from the rewound point.
+        for _ in 0..10 {
+            rewind.step(&mut machine, Machine::step);
+        }
+        rewind.rewind_instructions(&mut machine, 1, Machine::step).unwrap();
+        assert_same(&machine, &run(1232));
+    }
+
+    #[test]
+    fn test_rewind_cycles() {
+        let mut machine = get_machine();
+        let mut rewind = Rewind::new(64, 4, usize::MAX);
+        for _ in 0..1000 {
+            rewind.step(&mut machine, Machine::step);
+        }
+        let target = machine.cpu.cycles - 501;
+        rewind.rewind_cycles(&mut machine, 501, Machine::step).unwrap();
+
+        let mut reference = get_machine();
+        let mut steps = 0;
+        loop {
+            let mut next = get_machine();
+            for _ in 0..=steps {
+                next.step();
+            }
+            if next.cpu.cycles > target {
+                break;
+            }
+            reference = next;
+            steps += 1;
+        }
+        assert_same(&machine, &reference);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut machine = get_machine();
+        let mut rewind = Rewind::new(10, 4, 300_000);
+        for _ in 0..5000 {
+            rewind.step(&mut machine, Machine::step);
+        }
+        assert!(rewind.memory_used() <= 300_000);
+        let oldest = rewind.oldest().unwrap();
+        assert!(oldest > 0);
+
+        let err = rewind.rewind_instructions(&mut machine, 5000 - oldest + 1, Machine::step).unwrap_err();
+        assert_eq!(err, RewindError::OutOfRange { oldest });
+        rewind.rewind_instructions(&mut machine, 5000 - oldest, Machine::step).unwrap();
+        assert_same(&machine, &run(oldest));
+    }
+
+    #[test]
+    fn test_rewind_with_devices() {
+        fn get_machine() -> (Machine<Nmos6502, MemoryMap>, Scheduler) {
+            let via = Rc::new(RefCell::new(Via::new()));
+            let mut map = MemoryMap::new();
+            map.add_ram(0x0000..=0x7FFF, 0x7FFF);
+            map.add_device(0x9000..=0x900F, 0x0F, via.clone());
+            let mut machine = Machine::new(map);
+            // loop: LDA $9004; STA $10; JMP loop
+            for (i, byte) in [0xAD, 0x04, 0x90, 0x85, 0x10, 0x4C, 0x00, 0x02].into_iter().enumerate() {
+                machine.bus.write(0x0200 + i as u16, byte);
+            }
+            machine.cpu.pc = 0x0200;
+            let mut scheduler = Scheduler::new();
+            scheduler.add(via);
+            (machine, scheduler)
+        }
+
+        let (mut machine, mut scheduler) = get_machine();
+        let mut rewind = Rewind::new(100, 8, usize::MAX);
+        for _ in 0..2000 {
+            rewind.step(&mut machine, |m| scheduler.step(m));
+        }
+        rewind.rewind_instructions(&mut machine, 777, |m| scheduler.step(m)).unwrap();
+
+        let (mut reference, mut scheduler) = get_machine();
+        for _ in 0..1223 {
+            scheduler.step(&mut reference);
+        }
+        assert_eq!(machine.cpu.snapshot(), reference.cpu.snapshot());
+        assert_eq!(machine.bus.peek(0x10), reference.bus.peek(0x10));
+        assert_eq!(machine.bus.peek(0x9004), reference.bus.peek(0x9004));
+    }
+}
@@ -0,0 +1,401 @@
+use core::fmt;
+
+use crate::snapshot::{CpuSnapshot, SNAPSHOT_VERSION};
+use crate::{Cpu, Variant};
+
+/// Magic bytes at the start of every save state.
+pub const MAGIC: &[u8; 8] = b"6502SAVE";
+/// Version of the container layout itself; sections carry their own.
+pub const FORMAT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    BadMagic,
+    UnsupportedFormat(u16),
+    UnsupportedVersion { section: String, version: u16 },
+    MissingSection { expected: String, found: Option<String> },
+    /// A section's data was shorter or longer than its component expects.
+    SizeMismatch { section: String },
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadMagic => write!(f, "not a save state"),
+            Error::UnsupportedFormat(v) => write!(f, "unsupported save state format {}", v),
+            Error::UnsupportedVersion { section, version } => {
+                write!(f, "unsupported version {} of section `{}`", version, section)
+            }
+            Error::MissingSection { expected, found: Some(found) } => {
+                write!(f, "expected section `{}`, found `{}`", expected, found)
+            }
+            Error::MissingSection { expected, found: None } => write!(f, "missing section `{}`", expected),
+            Error::SizeMismatch { section } => write!(f, "wrong size for section `{}`", section),
+            Error::Truncated => write!(f, "unexpected end of save state"),
+            Error::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A component that can be written to and restored from a save state:
+/// the CPU, RAM, bank-switching hardware and peripherals.
+pub trait SaveState {
+    /// Version of the data written by `save`; `load` receives the version
+    /// that was saved so it can read older layouts.
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save(&self, out: &mut StateWriter);
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), Error>;
+}
+
+/// Little-endian writer for section data. Components made of other
+/// components write each part as a nested section.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, value: f64) {
+        self.u64(value.to_bits());
+    }
+
+    /// Writes a length-prefixed byte string.
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Writes `component` as a section tagged with `name` and its version.
+    pub fn section(&mut self, name: &str, component: &(impl SaveState + ?Sized)) {
+        let mut data = StateWriter::new();
+        component.save(&mut data);
+        self.bytes(name.as_bytes());
+        self.u16(component.state_version());
+        self.bytes(&data.buf);
+    }
+}
+
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(Error::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Invalid("invalid boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, Error> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads a byte string written by `StateWriter::bytes` into `out`,
+    /// which must have the same length.
+    pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<(), Error> {
+        let data = self.bytes()?;
+        if data.len() != out.len() {
+            return Err(Error::Invalid("byte string has the wrong length"));
+        }
+        out.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Restores `component` from the next section, which must be `name`.
+    pub fn section(&mut self, name: &str, component: &mut (impl SaveState + ?Sized)) -> Result<(), Error> {
+        let missing = |found| Error::MissingSection { expected: name.to_string(), found };
+        if self.is_empty() {
+            return Err(missing(None));
+        }
+        let found = self.bytes()?;
+        if found != name.as_bytes() {
+            return Err(missing(Some(String::from_utf8_lossy(found).into_owned())));
+        }
+        let version = self.u16()?;
+        let mut data = StateReader::new(self.bytes()?);
+        match component.load(version, &mut data) {
+            Err(Error::Truncated) => Err(Error::SizeMismatch { section: name.to_string() }),
+            Err(e) => Err(e),
+            Ok(()) if !data.is_empty() => Err(Error::SizeMismatch { section: name.to_string() }),
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+/// Writes the container header followed by `component` as the section `name`.
+pub fn save(name: &str, component: &(impl SaveState + ?Sized)) -> Vec<u8> {
+    let mut out = StateWriter::new();
+    out.buf.extend_from_slice(MAGIC);
+    out.u16(FORMAT_VERSION);
+    out.section(name, component);
+    out.into_bytes()
+}
+
+/// Restores `component` from a save state written by `save`.
+pub fn load(name: &str, component: &mut (impl SaveState + ?Sized), data: &[u8]) -> Result<(), Error> {
+    let mut input = StateReader::new(data);
+    if input.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(Error::BadMagic);
+    }
+    let format = input.u16()?;
+    if format != FORMAT_VERSION {
+        return Err(Error::UnsupportedFormat(format));
+    }
+    input.section(name, component)?;
+    match input.is_empty() {
+        true => Ok(()),
+        false => Err(Error::Invalid("trailing data after save state")),
+    }
+}
+
+/// Checks a section version against the single version a component reads.
+pub fn expect_version(section: &str, version: u16, supported: u16) -> Result<(), Error> {
+    match version == supported {
+        true => Ok(()),
+        false => Err(Error::UnsupportedVersion { section: section.to_string(), version }),
+    }
+}
+
+impl<V: Variant> SaveState for Cpu<V> {
+    fn state_version(&self) -> u16 {
+        SNAPSHOT_VERSION
+    }
+
+    fn save(&self, out: &mut StateWriter) {
+        let s = self.snapshot();
+        out.u8(s.a);
+        out.u8(s.x);
+        out.u8(s.y);
+        out.u8(s.p);
+        out.u8(s.sp);
+        out.u16(s.pc);
+        out.u64(s.cycles);
+        out.bool(s.waiting);
+        out.bool(s.stopped);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), Error> {
+        expect_version("cpu", version, SNAPSHOT_VERSION)?;
+        let snapshot = CpuSnapshot {
+            version,
+            a: input.u8()?,
+            x: input.u8()?,
+            y: input.u8()?,
+            p: input.u8()?,
+            sp: input.u8()?,
+            pc: input.u16()?,
+            cycles: input.u64()?,
+            waiting: input.bool()?,
+            stopped: input.bool()?,
+        };
+        self.restore(&snapshot).map_err(|_| Error::Invalid("invalid CPU snapshot"))
+    }
+}
+
+impl<const N: usize> SaveState for [u8; N] {
+    fn save(&self, out: &mut StateWriter) {
+        out.bytes(self);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), Error> {
+        expect_version("memory", version, 1)?;
+        input.bytes_into(self)
+    }
+}
+
+impl SaveState for Vec<u8> {
+    fn save(&self, out: &mut StateWriter) {
+        out.bytes(self);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), Error> {
+        expect_version("memory", version, 1)?;
+        *self = input.bytes()?.to_vec();
+        Ok(())
+    }
+}
+
+impl<T: SaveState + ?Sized> SaveState for Box<T> {
+    fn state_version(&self) -> u16 {
+        (**self).state_version()
+    }
+
+    fn save(&self, out: &mut StateWriter) {
+        (**self).save(out)
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), Error> {
+        (**self).load(version, input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Timer {
+        counter: u16,
+        running: bool,
+    }
+
+    impl SaveState for Timer {
+        fn state_version(&self) -> u16 {
+            2
+        }
+
+        fn save(&self, out: &mut StateWriter) {
+            out.u16(self.counter);
+            out.bool(self.running);
+        }
+
+        // Version 1 had no `running` flag.
+        fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), Error> {
+            self.counter = input.u16()?;
+            self.running = match version {
+                1 => true,
+                2 => input.bool()?,
+                _ => return Err(Error::UnsupportedVersion { section: "timer".to_string(), version }),
+            };
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Board {
+        ram: [u8; 4],
+        timer: Timer,
+    }
+
+    impl SaveState for Board {
+        fn save(&self, out: &mut StateWriter) {
+            out.section("ram", &self.ram);
+            out.section("timer", &self.timer);
+        }
+
+        fn load(&mut self, _: u16, input: &mut StateReader) -> Result<(), Error> {
+            input.section("ram", &mut self.ram)?;
+            input.section("timer", &mut self.timer)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let board = Board { ram: [1, 2, 3, 4], timer: Timer { counter: 0x1234, running: true } };
+        let data = save("board", &board);
+        assert!(data.starts_with(MAGIC));
+
+        let mut restored = Board::default();
+        load("board", &mut restored, &data).unwrap();
+        assert_eq!(restored, board);
+    }
+
+    #[test]
+    fn test_older_section_version() {
+        let mut out = StateWriter::new();
+        out.bytes(b"timer");
+        out.u16(1);
+        out.bytes(&[0x34, 0x12]);
+        let data = out.into_bytes();
+
+        let mut timer = Timer::default();
+        StateReader::new(&data).section("timer", &mut timer).unwrap();
+        assert_eq!(timer, Timer { counter: 0x1234, running: true });
+    }
+
+    #[test]
+    fn test_errors() {
+        let board = Board::default();
+        let mut data = save("board", &board);
+        let mut restored = Board::default();
+
+        assert_eq!(load("board", &mut restored, b"not a save state"), Err(Error::BadMagic));
+        assert_eq!(load("machine", &mut restored, &data), Err(Error::MissingSection {
+            expected: "machine".to_string(),
+            found: Some("board".to_string()),
+        }));
+        assert_eq!(load("board", &mut restored, &data[..data.len() - 1]), Err(Error::Truncated));
+
+        let mut small = [0u8; 2];
+        let ram = save("ram", &[0u8; 4]);
+        assert_eq!(load("ram", &mut small, &ram), Err(Error::Invalid("byte string has the wrong length")));
+
+        data[MAGIC.len()] = 9;
+        assert_eq!(load("board", &mut restored, &data), Err(Error::UnsupportedFormat(9)));
+    }
+}
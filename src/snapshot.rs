@@ -0,0 +1,149 @@
+use core::fmt;
+
+use crate::{Cpu, Variant};
+
+/// Version written by `Cpu::snapshot`. Bump it whenever a field is added or
+/// its meaning changes, and keep `Cpu::restore` accepting older versions.
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// The complete state of a `Cpu`, decoupled from its in-memory layout so it
+/// can be stored in save states and bug reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuSnapshot {
+    pub version: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    /// Status register as pushed by `PHP`, with B and bit 5 set.
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub cycles: u64,
+    pub waiting: bool,
+    pub stopped: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    UnsupportedVersion(u16),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl<V: Variant> Cpu<V> {
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            version: SNAPSHOT_VERSION,
+            a: self.reg.get_a(),
+            x: self.reg.get_x(),
+            y: self.reg.get_y(),
+            p: self.reg.get_status(true),
+            sp: self.sp,
+            pc: self.pc,
+            cycles: self.cycles,
+            waiting: self.waiting,
+            stopped: self.stopped,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) -> Result<(), SnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+        self.reg.update_a(snapshot.a);
+        self.reg.update_x(snapshot.x);
+        self.reg.update_y(snapshot.y);
+        self.reg.set_status(snapshot.p);
+        self.sp = snapshot.sp;
+        self.pc = snapshot.pc;
+        self.cycles = snapshot.cycles;
+        self.waiting = snapshot.waiting;
+        self.stopped = snapshot.stopped;
+        Ok(())
+    }
+
+    pub fn from_snapshot(snapshot: &CpuSnapshot) -> Result<Self, SnapshotError> {
+        let mut cpu = Cpu::new();
+        cpu.restore(snapshot)?;
+        Ok(cpu)
+    }
+}
+
+// `Cpu` itself serialises as its snapshot so the format stays versioned.
+#[cfg(feature = "serde")]
+impl<V: Variant> serde::Serialize for Cpu<V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, V: Variant> serde::Deserialize<'de> for Cpu<V> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = CpuSnapshot::deserialize(deserializer)?;
+        Cpu::from_snapshot(&snapshot).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmos6502::Nmos6502;
+
+    fn get_cpu() -> Cpu<Nmos6502> {
+        let mut cpu = Cpu::<Nmos6502>::new();
+        cpu.reg.update_a(0x12);
+        cpu.reg.update_x(0x80);
+        cpu.reg.update_y(0x00);
+        cpu.reg.c = true;
+        cpu.reg.d = true;
+        cpu.sp = 0xF0;
+        cpu.pc = 0xC000;
+        cpu.cycles = 123_456;
+        cpu.waiting = true;
+        cpu
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cpu = get_cpu();
+        let snapshot = cpu.snapshot();
+        assert_eq!(snapshot.p, 0b0011_1011);
+
+        let restored = Cpu::<Nmos6502>::from_snapshot(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert!(restored.reg.z);
+        assert!(!restored.reg.n);
+    }
+
+    #[test]
+    fn test_version() {
+        let mut snapshot = get_cpu().snapshot();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        let err = Cpu::<Nmos6502>::from_snapshot(&snapshot).err();
+        assert_eq!(err, Some(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let snapshot = get_cpu().snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(json.starts_with(r#"{"version":1,"a":18,"#));
+        assert_eq!(serde_json::from_str::<CpuSnapshot>(&json).unwrap(), snapshot);
+
+        let cpu: Cpu<Nmos6502> = serde_json::from_str(&serde_json::to_string(&get_cpu()).unwrap()).unwrap();
+        assert_eq!(cpu.snapshot(), snapshot);
+        let newer = json.replace(r#""version":1"#, r#""version":2"#);
+        assert!(serde_json::from_str::<Cpu<Nmos6502>>(&newer).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+//! Complete machines assembled from the CPU, a memory map and devices.
+
+pub mod apple1;
+pub mod ben_eater;
+pub mod kim1;
+pub mod vic20;
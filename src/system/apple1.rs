@@ -0,0 +1,253 @@
+use core::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+
+use crate::device::pia::Pia;
+use crate::device::serial::Port;
+use crate::device::{Device, Scheduler};
+use crate::machine::Machine;
+use crate::memory_map::MemoryMap;
+use crate::nmos6502::Nmos6502;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+/// The Apple-1's clock: the 14.31818 MHz crystal divided by 14.
+pub const CLOCK: u64 = 1_022_727;
+
+/// Steve Wozniak's monitor, the Apple-1's only ROM, at $FF00.
+#[rustfmt::skip]
+pub const WOZ_MONITOR: [u8; 256] = [
+    0xD8, 0x58, 0xA0, 0x7F, 0x8C, 0x12, 0xD0, 0xA9, 0xA7, 0x8D, 0x11, 0xD0, 0x8D, 0x13, 0xD0, 0xC9,
+    0xDF, 0xF0, 0x13, 0xC9, 0x9B, 0xF0, 0x03, 0xC8, 0x10, 0x0F, 0xA9, 0xDC, 0x20, 0xEF, 0xFF, 0xA9,
+    0x8D, 0x20, 0xEF, 0xFF, 0xA0, 0x01, 0x88, 0x30, 0xF6, 0xAD, 0x11, 0xD0, 0x10, 0xFB, 0xAD, 0x10,
+    0xD0, 0x99, 0x00, 0x02, 0x20, 0xEF, 0xFF, 0xC9, 0x8D, 0xD0, 0xD4, 0xA0, 0xFF, 0xA9, 0x00, 0xAA,
+    0x0A, 0x85, 0x2B, 0xC8, 0xB9, 0x00, 0x02, 0xC9, 0x8D, 0xF0, 0xD4, 0xC9, 0xAE, 0x90, 0xF4, 0xF0,
+    0xF0, 0xC9, 0xBA, 0xF0, 0xEB, 0xC9, 0xD2, 0xF0, 0x3B, 0x86, 0x28, 0x86, 0x29, 0x84, 0x2A, 0xB9,
+    0x00, 0x02, 0x49, 0xB0, 0xC9, 0x0A, 0x90, 0x06, 0x69, 0x88, 0xC9, 0xFA, 0x90, 0x11, 0x0A, 0x0A,
+    0x0A, 0x0A, 0xA2, 0x04, 0x0A, 0x26, 0x28, 0x26, 0x29, 0xCA, 0xD0, 0xF8, 0xC8, 0xD0, 0xE0, 0xC4,
+    0x2A, 0xF0, 0x97, 0x24, 0x2B, 0x50, 0x10, 0xA5, 0x28, 0x81, 0x26, 0xE6, 0x26, 0xD0, 0xB5, 0xE6,
+    0x27, 0x4C, 0x44, 0xFF, 0x6C, 0x24, 0x00, 0x30, 0x2B, 0xA2, 0x02, 0xB5, 0x27, 0x95, 0x25, 0x95,
+    0x23, 0xCA, 0xD0, 0xF7, 0xD0, 0x14, 0xA9, 0x8D, 0x20, 0xEF, 0xFF, 0xA5, 0x25, 0x20, 0xDC, 0xFF,
+    0xA5, 0x24, 0x20, 0xDC, 0xFF, 0xA9, 0xBA, 0x20, 0xEF, 0xFF, 0xA9, 0xA0, 0x20, 0xEF, 0xFF, 0xA1,
+    0x24, 0x20, 0xDC, 0xFF, 0x86, 0x2B, 0xA5, 0x24, 0xC5, 0x28, 0xA5, 0x25, 0xE5, 0x29, 0xB0, 0xC1,
+    0xE6, 0x24, 0xD0, 0x02, 0xE6, 0x25, 0xA5, 0x24, 0x29, 0x07, 0x10, 0xC8, 0x48, 0x4A, 0x4A, 0x4A,
+    0x4A, 0x20, 0xE5, 0xFF, 0x68, 0x29, 0x0F, 0x09, 0xB0, 0xC9, 0xBA, 0x90, 0x02, 0x69, 0x06, 0x2C,
+    0x12, 0xD0, 0x30, 0xFB, 0x8D, 0x12, 0xD0, 0x60, 0x00, 0x00, 0x00, 0x0F, 0x00, 0xFF, 0x00, 0x00,
+];
+
+// The PIA with the keyboard on port A and the display on port B. A key is
+// latched on PA0-6 with PA7 high and strobed in on CA1. Writing a
+// character to port B pulls CB2 (DA) low; the display takes it at once and
+// acknowledges on CB1, so PB7 (display busy) always reads low.
+struct Terminal {
+    pia: Pia,
+    keys: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Terminal {
+    fn new() -> Self {
+        let mut pia = Pia::new();
+        pia.set_port_b(0x00);
+        Terminal { pia, keys: VecDeque::new(), output: Vec::new() }
+    }
+
+    // Strobes the next key in once the last one has been read.
+    fn feed(&mut self) {
+        if self.pia.peek(1) & 0x80 == 0 {
+            if let Some(key) = self.keys.pop_front() {
+                self.pia.set_port_a(key | 0x80);
+                self.pia.set_ca1(false);
+                self.pia.set_ca1(true);
+            }
+        }
+    }
+}
+
+impl Device for Terminal {
+    fn peek(&self, offset: u16) -> u8 {
+        self.pia.peek(offset)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        self.pia.read(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.pia.write(offset, value);
+        if !self.pia.cb2() {
+            self.output.push(self.pia.port_b() & 0x7F);
+            self.pia.set_cb1(false);
+            self.pia.set_cb1(true);
+        }
+    }
+
+    // The PIA's interrupt outputs are not connected.
+    fn tick(&mut self, cycles: u64) {
+        self.pia.tick(cycles);
+        self.feed();
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+// The displayed output belongs to the host and is not saved.
+impl SaveState for Terminal {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("pia", &self.pia);
+        out.bytes(&self.keys.iter().copied().collect::<Vec<_>>());
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("terminal", version, 1)?;
+        input.section("pia", &mut self.pia)?;
+        self.keys = input.bytes()?.iter().copied().collect();
+        Ok(())
+    }
+}
+
+/// An Apple-1 with 32K of RAM at $0000, 4K more at $E000 for Integer
+/// BASIC, the Woz Monitor and the keyboard and display PIA at $D010.
+///
+/// The terminal is upper case only. Keys go in with `press` or
+/// `type_keys`, which map lower case to upper, newlines to CR and
+/// backspace to the monitor's rubout, `_`. Output comes back from `take_output` as ASCII with CR as
+/// a newline. Characters are displayed instantly rather than at the real
+/// terminal's 60 a second.
+pub struct Apple1 {
+    pub machine: Machine<Nmos6502, MemoryMap>,
+    pub scheduler: Scheduler,
+    terminal: Rc<RefCell<Terminal>>,
+}
+
+impl Default for Apple1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apple1 {
+    /// An Apple-1 that has been reset into the monitor.
+    pub fn new() -> Self {
+        let terminal = Rc::new(RefCell::new(Terminal::new()));
+        let mut map = MemoryMap::new();
+        map.add_ram(0x0000..=0x7FFF, 0x7FFF);
+        map.add_ram(0xE000..=0xEFFF, 0x0FFF);
+        map.add_device(0xD010..=0xD013, 3, terminal.clone());
+        map.add_rom(0xFF00..=0xFFFF, WOZ_MONITOR.to_vec());
+
+        let mut machine = Machine::new(map);
+        machine.reset();
+        let mut scheduler = Scheduler::new();
+        scheduler.add(terminal.clone());
+        Apple1 { machine, scheduler, terminal }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        let key = match key {
+            b'\n' => b'\r',
+            0x08 | 0x7F => b'_',
+            _ => key.to_ascii_uppercase() & 0x7F,
+        };
+        self.terminal.borrow_mut().keys.push_back(key);
+    }
+
+    pub fn type_keys(&mut self, text: &str) {
+        text.bytes().for_each(|key| self.press(key));
+    }
+
+    /// Whether keys are still waiting to be read.
+    pub fn typing(&self) -> bool {
+        !self.terminal.borrow().keys.is_empty()
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let output = std::mem::take(&mut self.terminal.borrow_mut().output);
+        output.into_iter().map(|c| if c == b'\r' { b'\n' } else { c }).collect()
+    }
+
+    /// Runs for at least `cycles`, returning the number that elapsed.
+    pub fn run(&mut self, cycles: u64) -> u64 {
+        self.scheduler.run(&mut self.machine, cycles)
+    }
+
+    /// Sends the display output to `port` and types what it receives,
+    /// a key at a time.
+    pub fn pump(&mut self, port: &mut dyn Port) -> io::Result<()> {
+        let output = self.take_output();
+        if !output.is_empty() {
+            port.send(&output)?;
+        }
+        if !self.typing() {
+            if let Some(key) = port.poll()? {
+                self.press(key);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bus;
+
+    fn run_until_idle(apple: &mut Apple1) -> String {
+        while apple.typing() {
+            apple.run(10_000);
+        }
+        apple.run(100_000);
+        String::from_utf8(apple.take_output()).unwrap()
+    }
+
+    #[test]
+    fn test_examine() {
+        let mut apple = Apple1::new();
+        assert_eq!(run_until_idle(&mut apple), "\\\n");
+        apple.type_keys("ff00.ff07\n");
+        assert_eq!(run_until_idle(&mut apple), "FF00.FF07\n\nFF00: D8 58 A0 7F 8C 12 D0 A9\n");
+    }
+
+    #[test]
+    fn test_store_and_run() {
+        let mut apple = Apple1::new();
+        // LDA #'A'+$80 / JSR ECHO / JMP GETLINE
+        apple.type_keys("300: A9 C1 20 EF FF 4C 1F FF\n300R\n");
+        let output = run_until_idle(&mut apple);
+        assert!(output.ends_with("300R\n\n0300: A9A\n"), "{:?}", output);
+        assert_eq!(apple.machine.bus.peek(0x0305), 0x4C);
+    }
+
+    #[test]
+    fn test_backspace() {
+        let mut apple = Apple1::new();
+        apple.type_keys("FF0\x08FF01\n");
+        assert!(run_until_idle(&mut apple).ends_with("FF0_FF01\n\nFF01: 58\n"));
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut apple = Apple1::new();
+        run_until_idle(&mut apple);
+        apple.type_keys("ff00.ff03\n");
+        apple.run(100);
+        apple.take_output();
+        assert!(!apple.terminal.borrow().keys.is_empty());
+        let bus = savestate::save("bus", &apple.machine.bus);
+        let cpu = savestate::save("cpu", &apple.machine.cpu);
+
+        let mut restored = Apple1::new();
+        run_until_idle(&mut restored);
+        savestate::load("bus", &mut restored.machine.bus, &bus).unwrap();
+        savestate::load("cpu", &mut restored.machine.cpu, &cpu).unwrap();
+        let output = run_until_idle(&mut apple);
+        assert!(output.ends_with("\nFF00: D8 58 A0 7F\n"), "{:?}", output);
+        assert_eq!(run_until_idle(&mut restored), output);
+    }
+}
@@ -0,0 +1,226 @@
+use core::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cmos65c02::Cmos65c02;
+use crate::device::acia::Acia;
+use crate::device::hd44780::Hd44780;
+use crate::device::via::Via;
+use crate::device::{Device, Scheduler};
+use crate::machine::Machine;
+use crate::memory_map::MemoryMap;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+pub const CLOCK: u64 = 1_000_000;
+
+/// How the LCD is connected to the VIA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LcdWiring {
+    /// D0-D7 on port B, with E, R/W and RS on PA7, PA6 and PA5.
+    #[default]
+    EightBit,
+    /// D4-D7 on PB0-3, with RS, R/W and E on PB4, PB5 and PB6.
+    FourBit,
+}
+
+// The VIA with the LCD on its ports. The LCD sees the pin levels after
+// every register write, and drives the data lines back while it is read.
+struct Board {
+    via: Via,
+    lcd: Hd44780,
+    wiring: LcdWiring,
+}
+
+impl Board {
+    fn update_lcd(&mut self) {
+        let (pa, pb) = (self.via.port_a(), self.via.port_b());
+        match self.wiring {
+            LcdWiring::EightBit => self.lcd.set_pins(pa & 0x20 != 0, pa & 0x40 != 0, pa & 0x80 != 0, pb),
+            LcdWiring::FourBit => self.lcd.set_pins(pb & 0x10 != 0, pb & 0x20 != 0, pb & 0x40 != 0, pb << 4),
+        }
+        let data = self.lcd.data_out();
+        self.via.set_port_b(match self.wiring {
+            LcdWiring::EightBit => data.unwrap_or(0xFF),
+            LcdWiring::FourBit => data.map_or(0xFF, |d| 0xF0 | d >> 4),
+        });
+    }
+}
+
+impl Device for Board {
+    fn peek(&self, offset: u16) -> u8 {
+        self.via.peek(offset)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        self.via.read(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.via.write(offset, value);
+        self.update_lcd();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.via.tick(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.via.irq()
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Board {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("via", &self.via);
+        out.section("lcd", &self.lcd);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("board", version, 1)?;
+        input.section("via", &mut self.via)?;
+        input.section("lcd", &mut self.lcd)
+    }
+}
+
+/// Ben Eater's breadboard computer: a 65C02 at 1 MHz with 16K of RAM at
+/// $0000, a 6522 VIA at $6000 driving a 16x2 HD44780 LCD, and 32K of ROM
+/// at $8000. Like the address decoding on the board, the VIA answers
+/// throughout $6000-$7FFF and the optional 65C51 ACIA, added with
+/// `add_acia`, throughout $5000-$5FFF.
+pub struct BenEater {
+    pub machine: Machine<Cmos65c02, MemoryMap>,
+    pub scheduler: Scheduler,
+    board: Rc<RefCell<Board>>,
+}
+
+impl BenEater {
+    /// A computer that has been reset into `rom`.
+    pub fn new(rom: &[u8; 0x8000], wiring: LcdWiring) -> Self {
+        let board = Rc::new(RefCell::new(Board { via: Via::new(), lcd: Hd44780::new(16, 2), wiring }));
+        let mut map = MemoryMap::new();
+        map.add_ram(0x0000..=0x3FFF, 0x3FFF);
+        map.add_device(0x6000..=0x7FFF, 0x0F, board.clone());
+        map.add_rom(0x8000..=0xFFFF, rom.to_vec());
+
+        let mut machine = Machine::new(map);
+        machine.reset();
+        let mut scheduler = Scheduler::new();
+        scheduler.add(board.clone());
+        BenEater { machine, scheduler, board }
+    }
+
+    /// Fits the W65C51N ACIA of the serial interface videos, with its
+    /// transmitter bug, and returns it for the host to connect.
+    pub fn add_acia(&mut self) -> Rc<RefCell<Acia>> {
+        let mut acia = Acia::new(CLOCK);
+        acia.set_wdc_bug(true);
+        let acia = Rc::new(RefCell::new(acia));
+        self.machine.bus.add_device(0x5000..=0x5FFF, 3, acia.clone());
+        self.scheduler.add(acia.clone());
+        acia
+    }
+
+    /// The LCD's contents, a line per row.
+    pub fn lcd_text(&self) -> String {
+        self.board.borrow().lcd.text()
+    }
+
+    /// Runs for at least `cycles`, returning the number that elapsed.
+    pub fn run(&mut self, cycles: u64) -> u64 {
+        self.scheduler.run(&mut self.machine, cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bus;
+
+    // Ben's first LCD program: sets up the ports, initializes the display
+    // and prints "Hi", polling the busy flag before each transfer.
+    #[rustfmt::skip]
+    const HELLO: [u8; 125] = [
+        0xA2, 0xFF, 0x9A, 0xA9, 0xFF, 0x8D, 0x02, 0x60, 0xA9, 0xE0, 0x8D, 0x03, 0x60, 0xA9, 0x38, 0x20,
+        0x51, 0x80, 0xA9, 0x0E, 0x20, 0x51, 0x80, 0xA9, 0x06, 0x20, 0x51, 0x80, 0xA9, 0x01, 0x20, 0x51,
+        0x80, 0xA9, 0x48, 0x20, 0x67, 0x80, 0xA9, 0x69, 0x20, 0x67, 0x80, 0x4C, 0x2B, 0x80, 0x48, 0xA9,
+        0x00, 0x8D, 0x02, 0x60, 0xA9, 0x40, 0x8D, 0x01, 0x60, 0xA9, 0xC0, 0x8D, 0x01, 0x60, 0xAD, 0x00,
+        0x60, 0x29, 0x80, 0xD0, 0xEF, 0xA9, 0x40, 0x8D, 0x01, 0x60, 0xA9, 0xFF, 0x8D, 0x02, 0x60, 0x68,
+        0x60, 0x20, 0x2E, 0x80, 0x8D, 0x00, 0x60, 0xA9, 0x00, 0x8D, 0x01, 0x60, 0xA9, 0x80, 0x8D, 0x01,
+        0x60, 0xA9, 0x00, 0x8D, 0x01, 0x60, 0x60, 0x20, 0x2E, 0x80, 0x8D, 0x00, 0x60, 0xA9, 0x20, 0x8D,
+        0x01, 0x60, 0xA9, 0xA0, 0x8D, 0x01, 0x60, 0xA9, 0x20, 0x8D, 0x01, 0x60, 0x60,
+    ];
+
+    #[test]
+    fn test_hello() {
+        let mut rom = [0xEA; 0x8000];
+        rom[..HELLO.len()].copy_from_slice(&HELLO);
+        rom[0x7FFC..].copy_from_slice(&[0x00, 0x80, 0x00, 0x00]);
+        let mut computer = BenEater::new(&rom, LcdWiring::EightBit);
+        computer.run(2000);
+        assert_eq!(computer.lcd_text(), "Hi              \n                ");
+        assert_eq!(computer.machine.cpu.pc, 0x802B);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut rom = [0xEA; 0x8000];
+        rom[..HELLO.len()].copy_from_slice(&HELLO);
+        rom[0x7FFC..].copy_from_slice(&[0x00, 0x80, 0x00, 0x00]);
+        let mut computer = BenEater::new(&rom, LcdWiring::EightBit);
+        computer.run(2000);
+        let bus = savestate::save("bus", &computer.machine.bus);
+
+        let mut restored = BenEater::new(&rom, LcdWiring::EightBit);
+        savestate::load("bus", &mut restored.machine.bus, &bus).unwrap();
+        assert_eq!(restored.lcd_text(), computer.lcd_text());
+        assert_eq!(restored.machine.bus.peek(0x6002), 0xFF);
+    }
+
+    #[test]
+    fn test_four_bit_wiring() {
+        let mut computer = BenEater::new(&[0xEA; 0x8000], LcdWiring::FourBit);
+        let bus = &mut computer.machine.bus;
+        bus.write(0x6002, 0x7F);
+        // Nibbles with RS = PB4 and E = PB6.
+        let mut send = |rs: u8, nibble: u8| {
+            bus.write(0x6000, rs | nibble);
+            bus.write(0x6000, rs | 0x40 | nibble);
+            bus.write(0x6000, rs | nibble);
+        };
+        send(0, 0x2);
+        for (rs, byte) in [(0, 0x28), (0, 0x0C), (0x10, b'O'), (0x10, b'K')] {
+            send(rs, byte >> 4);
+            send(rs, byte & 0x0F);
+        }
+        assert!(computer.lcd_text().starts_with("OK  "));
+
+        // The busy flag and address counter come back on PB0-3.
+        let bus = &mut computer.machine.bus;
+        bus.write(0x6002, 0x70);
+        bus.write(0x6000, 0x60);
+        assert_eq!(bus.read(0x6000) & 0x0F, 0x0);
+        bus.write(0x6000, 0x20);
+        bus.write(0x6000, 0x60);
+        assert_eq!(bus.read(0x6000) & 0x0F, 0x2);
+    }
+
+    #[test]
+    fn test_acia() {
+        let mut computer = BenEater::new(&[0xEA; 0x8000], LcdWiring::EightBit);
+        let acia = computer.add_acia();
+        // 19200 baud, 8N1, receiver enabled, as in Ben's serial code.
+        computer.machine.bus.write(0x5003, 0x1F);
+        computer.machine.bus.write(0x5002, 0x0B);
+        acia.borrow_mut().receive(b'x');
+        computer.run(1000);
+        assert_eq!(computer.machine.bus.read(0x5000), b'x');
+        assert_eq!(computer.machine.bus.read(0x7FF3), 0x00);
+    }
+}
@@ -0,0 +1,385 @@
+use core::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::device::rriot::Rriot;
+use crate::device::serial::Port;
+use crate::device::{Device, Scheduler};
+use crate::machine::Machine;
+use crate::memory_map::MemoryMap;
+use crate::nmos6502::Nmos6502;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+pub const CLOCK: u64 = 1_000_000;
+
+/// The baud rates the teletype can run at. `Kim1::new` clamps to these.
+pub const BAUD: RangeInclusive<u64> = 110..=9600;
+
+// Start bit, 8 data bits and 2 stop bits, as on a Teletype.
+const FRAME_BITS: u64 = 11;
+
+// The 6530-002 with the teletype interface on its ports. The line is high
+// when idle (mark): the TTY's keyboard drives PA7 and the monitor drives
+// the printer from PB0. PB1-4 pick an output of the 74145 decoder that
+// scans the keypad rows on PA0-6; the TTY jumper connects output 3 to
+// PA0, which the monitor reads to choose TTY mode. The keyboard loop is
+// also wired to the printer, so typed characters are echoed by hardware.
+struct Tty {
+    u2: Rriot,
+    bit_cycles: u64,
+    keys: VecDeque<u8>,
+    // Bits still to send, LSB first, and cycles left in the current one.
+    rx_frame: u16,
+    rx_bits: u8,
+    rx_timer: u64,
+    // Cycles since the KIM's own output or our last character.
+    quiet: u64,
+    // Receiver for the printer line: cycles to the next sample and the
+    // bits still to sample, or None while waiting for a start bit.
+    tx_timer: u64,
+    tx_bits: Option<u8>,
+    tx_shift: u8,
+    output: Vec<u8>,
+}
+
+impl Tty {
+    fn new(baud: u64) -> Self {
+        let mut tty = Tty {
+            u2: Rriot::new(),
+            bit_cycles: CLOCK / baud.clamp(*BAUD.start(), *BAUD.end()),
+            keys: VecDeque::new(),
+            rx_frame: 0,
+            rx_bits: 0,
+            rx_timer: 0,
+            quiet: 0,
+            tx_timer: 0,
+            tx_bits: None,
+            tx_shift: 0,
+            output: Vec::new(),
+        };
+        tty.update_inputs();
+        tty
+    }
+
+    fn rx_line(&self) -> bool {
+        self.rx_bits == 0 || self.rx_frame & 1 != 0
+    }
+
+    fn printer_line(&self) -> bool {
+        self.u2.port_b() & 0x01 != 0 && self.rx_line()
+    }
+
+    fn update_inputs(&mut self) {
+        let jumper = if (self.u2.port_b() >> 1) & 0x0F == 3 { 0x7E } else { 0x7F };
+        let rx = if self.rx_line() { 0x80 } else { 0x00 };
+        self.u2.set_port_a(jumper | rx);
+    }
+
+    fn clock(&mut self) {
+        // Keyboard: a character goes out once the KIM has been quiet for
+        // two character times, as a typist would wait for it to print.
+        if self.rx_bits == 0 {
+            self.quiet += 1;
+            if self.quiet >= 2 * FRAME_BITS * self.bit_cycles {
+                if let Some(key) = self.keys.pop_front() {
+                    self.rx_frame = 0x600 | (key as u16 | 0x80) << 1;
+                    self.rx_bits = FRAME_BITS as u8;
+                    self.rx_timer = self.bit_cycles;
+                }
+            }
+        } else {
+            self.rx_timer -= 1;
+            if self.rx_timer == 0 {
+                self.rx_frame >>= 1;
+                self.rx_bits -= 1;
+                self.rx_timer = self.bit_cycles;
+                self.quiet = 0;
+            }
+        }
+        if self.u2.port_b() & 0x01 == 0 {
+            self.quiet = 0;
+        }
+
+        // Printer: samples the middle of each data bit.
+        match self.tx_bits {
+            None if !self.printer_line() => {
+                self.tx_timer = self.bit_cycles * 3 / 2;
+                self.tx_bits = Some(8);
+            }
+            None => {}
+            Some(bits) => {
+                self.tx_timer -= 1;
+                if self.tx_timer > 0 {
+                    return;
+                }
+                if bits > 0 {
+                    self.tx_shift = self.tx_shift >> 1 | if self.printer_line() { 0x80 } else { 0 };
+                    self.tx_timer = self.bit_cycles;
+                    self.tx_bits = Some(bits - 1);
+                } else if self.printer_line() {
+                    self.output.push(self.tx_shift & 0x7F);
+                    self.tx_bits = None;
+                } else {
+                    // No stop bit: wait for the line to go back to mark.
+                    self.tx_timer = 1;
+                }
+            }
+        }
+    }
+}
+
+impl Device for Tty {
+    fn peek(&self, offset: u16) -> u8 {
+        self.u2.peek(offset)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        self.u2.read(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.u2.write(offset, value);
+        self.update_inputs();
+    }
+
+    // The timer interrupt on PB7 is not connected.
+    fn tick(&mut self, cycles: u64) {
+        self.u2.tick(cycles);
+        for _ in 0..cycles {
+            self.clock();
+        }
+        self.update_inputs();
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+// The baud rate is configuration and the printed output belongs to the
+// host, so neither is saved.
+impl SaveState for Tty {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("u2", &self.u2);
+        out.bytes(&self.keys.iter().copied().collect::<Vec<_>>());
+        out.u16(self.rx_frame);
+        out.u8(self.rx_bits);
+        out.u64(self.rx_timer);
+        out.u64(self.quiet);
+        out.u64(self.tx_timer);
+        out.bool(self.tx_bits.is_some());
+        out.u8(self.tx_bits.unwrap_or(0));
+        out.u8(self.tx_shift);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("tty", version, 1)?;
+        input.section("u2", &mut self.u2)?;
+        self.keys = input.bytes()?.iter().copied().collect();
+        self.rx_frame = input.u16()?;
+        self.rx_bits = input.u8()?;
+        self.rx_timer = input.u64()?;
+        self.quiet = input.u64()?;
+        self.tx_timer = input.u64()?;
+        let receiving = input.bool()?;
+        let bits = input.u8()?;
+        self.tx_bits = receiving.then_some(bits);
+        self.tx_shift = input.u8()?;
+        // Both timers count down to 0 while a character is under way.
+        if (self.rx_bits > 0 && self.rx_timer == 0) || (receiving && self.tx_timer == 0) {
+            return Err(savestate::Error::Invalid("invalid TTY timer"));
+        }
+        self.update_inputs();
+        Ok(())
+    }
+}
+
+/// A KIM-1 in TTY mode: 1K of RAM and two 6530s, the 6530-002 with the
+/// monitor ROM at $1C00 and the 6530-003 with the cassette routines at
+/// $1800. Their RAM is at $1780-$17FF and their I/O at $1740 (002) and
+/// $1700 (003). The top three address lines are not decoded, so the
+/// monitor's vectors appear at $FFFA; only that mirror is mapped.
+///
+/// The teletype runs at the baud rate given to `new`, clamped to `BAUD`.
+/// After a reset the monitor measures it from a RUBOUT (DEL), which must
+/// be the first key typed. Keys go in with `press` or `type_keys`, which
+/// map lower case to upper and newlines to CR, and the printer's output,
+/// including the hardware echo, comes back from `take_output` with NULs
+/// and CRs dropped.
+/// The application ports are those of `u3`, whose timer interrupt is
+/// taken to be jumpered to IRQ.
+pub struct Kim1 {
+    pub machine: Machine<Nmos6502, MemoryMap>,
+    pub scheduler: Scheduler,
+    pub u3: Rc<RefCell<Rriot>>,
+    tty: Rc<RefCell<Tty>>,
+}
+
+impl Kim1 {
+    /// A KIM-1 that has been reset into the monitor, given the 6530-002
+    /// and 6530-003 ROMs.
+    pub fn new(rom_002: &[u8; 1024], rom_003: &[u8; 1024], baud: u64) -> Self {
+        let tty = Rc::new(RefCell::new(Tty::new(baud)));
+        let u3 = Rc::new(RefCell::new(Rriot::new()));
+        let mut map = MemoryMap::new();
+        map.add_ram(0x0000..=0x03FF, 0x03FF);
+        map.add_device(0x1700..=0x173F, 0x0F, u3.clone());
+        map.add_device(0x1740..=0x177F, 0x0F, tty.clone());
+        map.add_ram(0x1780..=0x17FF, 0x7F);
+        map.add_rom(0x1800..=0x1BFF, rom_003.to_vec());
+        map.add_rom(0x1C00..=0x1FFF, rom_002.to_vec());
+        map.add_rom(0xFC00..=0xFFFF, rom_002.to_vec());
+
+        let mut machine = Machine::new(map);
+        machine.reset();
+        let mut scheduler = Scheduler::new();
+        scheduler.add(tty.clone());
+        scheduler.add(u3.clone());
+        Kim1 { machine, scheduler, u3, tty }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        let key = if key == b'\n' { b'\r' } else { key.to_ascii_uppercase() };
+        self.tty.borrow_mut().keys.push_back(key);
+    }
+
+    pub fn type_keys(&mut self, text: &str) {
+        text.bytes().for_each(|key| self.press(key));
+    }
+
+    /// Whether keys are still waiting to be sent.
+    pub fn typing(&self) -> bool {
+        !self.tty.borrow().keys.is_empty()
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let mut output = std::mem::take(&mut self.tty.borrow_mut().output);
+        output.retain(|&c| !matches!(c, 0x00 | b'\r' | 0x7F));
+        output
+    }
+
+    /// Runs for at least `cycles`, returning the number that elapsed.
+    pub fn run(&mut self, cycles: u64) -> u64 {
+        self.scheduler.run(&mut self.machine, cycles)
+    }
+
+    /// Sends the printer output to `port` and types what it receives,
+    /// a key at a time.
+    pub fn pump(&mut self, port: &mut dyn Port) -> io::Result<()> {
+        let output = self.take_output();
+        if !output.is_empty() {
+            port.send(&output)?;
+        }
+        if !self.typing() {
+            if let Some(key) = port.poll()? {
+                self.press(key);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bus;
+
+    const SAD: u16 = 0x00;
+    const PADD: u16 = 0x01;
+    const SBD: u16 = 0x02;
+    const PBDD: u16 = 0x03;
+
+    #[test]
+    fn test_printer() {
+        let mut tty = Tty::new(9600);
+        let bit = tty.bit_cycles;
+        tty.write(PBDD, 0x3F);
+        // 'K' (0x4B) with a mark parity bit, LSB first after the start bit.
+        for level in [0, 1, 1, 0, 1, 0, 0, 1, 1, 1, 1] {
+            tty.write(SBD, 0x06 | level);
+            tty.tick(bit);
+        }
+        assert_eq!(tty.output, b"K");
+    }
+
+    #[test]
+    fn test_keyboard() {
+        let mut tty = Tty::new(9600);
+        let bit = tty.bit_cycles;
+        tty.write(PADD, 0x00);
+        tty.keys.push_back(b'A');
+        // Nothing is sent until the line has been quiet for a while.
+        tty.tick(2 * FRAME_BITS * bit - 1);
+        assert_ne!(tty.read(SAD) & 0x80, 0);
+        tty.tick(1);
+        let mut value = 0u16;
+        for i in 0..FRAME_BITS {
+            tty.tick(bit / 2);
+            value |= ((tty.read(SAD) >> 7) as u16) << i;
+            tty.tick(bit - bit / 2);
+        }
+        assert_eq!(value, 0x600 | 0xC1 << 1);
+        // And the echo is seen by the printer.
+        assert_eq!(tty.output, b"A");
+    }
+
+    #[test]
+    fn test_system() {
+        let mut rom_002 = [0xEA; 1024];
+        rom_002[0x3FC..].copy_from_slice(&[0x00, 0x1C, 0x00, 0x1C]);
+        let mut kim = Kim1::new(&rom_002, &[0xEA; 1024], 1200);
+        assert_eq!(kim.machine.cpu.pc, 0x1C00);
+
+        // The TTY jumper shows on PA0 when decoder output 3 is selected,
+        // as the monitor's INITS leaves it.
+        kim.machine.bus.write(0x1743, 0x3F);
+        kim.machine.bus.write(0x1742, 0x07);
+        assert_eq!(kim.machine.bus.read(0x1740), 0xFE);
+        kim.machine.bus.write(0x1742, 0x09);
+        assert_eq!(kim.machine.bus.read(0x1740), 0xFF);
+
+        kim.machine.bus.write(0x17FF, 0x12);
+        assert_eq!(kim.machine.bus.read(0x17FF), 0x12);
+        kim.machine.bus.write(0x1703, 0xFF);
+        kim.machine.bus.write(0x1702, 0x5A);
+        assert_eq!(kim.u3.borrow().port_b(), 0x5A);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut tty = Tty::new(9600);
+        let bit = tty.bit_cycles;
+        tty.write(PADD, 0x00);
+        tty.keys.extend(b"AB");
+        // Halfway through sending 'A'.
+        tty.tick(2 * FRAME_BITS * bit + 4 * bit + bit / 2);
+        assert!(tty.rx_bits > 0);
+        let state = savestate::save("tty", &tty);
+
+        let mut restored = Tty::new(9600);
+        savestate::load("tty", &mut restored, &state).unwrap();
+        assert_eq!(restored.keys, [b'B']);
+        for _ in 0..FRAME_BITS * bit {
+            tty.tick(1);
+            restored.tick(1);
+            assert_eq!(restored.read(SAD), tty.read(SAD));
+        }
+        assert_eq!((restored.rx_bits, restored.output.as_slice()), (0, &b"A"[..]));
+    }
+
+    #[test]
+    fn test_baud_clamped() {
+        assert_eq!(Tty::new(0).bit_cycles, CLOCK / 110);
+        assert_eq!(Tty::new(2_000_000).bit_cycles, CLOCK / 9600);
+        let mut tty = Tty::new(u64::MAX);
+        tty.keys.push_back(b'A');
+        tty.tick(CLOCK);
+        assert_eq!(tty.output, b"A");
+    }
+}
@@ -0,0 +1,602 @@
+use core::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::device::via::Via;
+use crate::device::{Device, Scheduler};
+use crate::machine::Machine;
+use crate::memory_map::MemoryMap;
+use crate::nmos6502::Nmos6502;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use crate::Bus;
+
+/// The TV standard, which sets the clock and raster timing. The KERNAL
+/// ROM has to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    #[default]
+    Pal,
+    Ntsc,
+}
+
+impl Model {
+    pub fn clock(self) -> u64 {
+        match self {
+            Model::Pal => 1_108_405,
+            Model::Ntsc => 1_022_727,
+        }
+    }
+
+    // Raster lines per frame and cycles per line.
+    fn raster(self) -> (u16, u64) {
+        match self {
+            Model::Pal => (312, 71),
+            Model::Ntsc => (261, 65),
+        }
+    }
+}
+
+// The keyboard matrix as the KERNAL scans it, by the VIA 2 port B bit
+// that selects a column and then the port A bit that reads a row. NUL
+// marks keys with no ASCII equivalent: £, CTRL, the cursor keys, RUN/STOP,
+// the shifts, the Commodore key, HOME and the function keys.
+const KEYS: [&[u8; 8]; 8] = [
+    b"13579+\0\x08", b"_WRYIP*\r", b"\0ADGJL;\0", b"\0\0XVN,/\0",
+    b" ZCBM.\0\0", b"\0SFHK:=\0", b"QETUO@^\0", b"24680-\0\0",
+];
+const LEFT_SHIFT: (u8, u8) = (3, 1);
+const SHIFTED: [(u8, u8); 14] = [
+    (b'!', b'1'), (b'"', b'2'), (b'#', b'3'), (b'$', b'4'), (b'%', b'5'), (b'&', b'6'), (b'\'', b'7'),
+    (b'(', b'8'), (b')', b'9'), (b'[', b':'), (b']', b';'), (b'<', b','), (b'>', b'.'), (b'?', b'/'),
+];
+
+// How long a typed key is held and then released. The KERNAL scans the
+// keyboard every 1/60 s and needs to see both. BASIC gets longer after
+// RETURN to deal with the line.
+const HOLD: u64 = 30_000;
+const RELEASE: u64 = 30_000;
+const RELEASE_RETURN: u64 = 150_000;
+
+// The matrix position of an ASCII key, and whether it needs shift.
+fn key_position(key: u8) -> Option<(u8, u8, bool)> {
+    let (key, shift) = match key {
+        b'\n' => (b'\r', false),
+        0x7F => (0x08, false),
+        _ => match SHIFTED.iter().find(|(shifted, _)| *shifted == key) {
+            Some(&(_, base)) => (base, true),
+            None => (key.to_ascii_uppercase(), false),
+        },
+    };
+    let index = KEYS.iter().flat_map(|column| column.iter()).position(|&k| k == key && k != 0)?;
+    Some(((index / 8) as u8, (index % 8) as u8, shift))
+}
+
+// The 6560/6561 Video Interface Chip, as far as the CPU sees it: its
+// registers and the raster counter. It draws nothing.
+struct Vic {
+    registers: [u8; 16],
+    raster: u16,
+    cycle: u64,
+    lines: u16,
+    line_cycles: u64,
+}
+
+impl Vic {
+    fn new(model: Model) -> Self {
+        let (lines, line_cycles) = model.raster();
+        Vic { registers: [0; 16], raster: 0, cycle: 0, lines, line_cycles }
+    }
+}
+
+impl Device for Vic {
+    fn peek(&self, offset: u16) -> u8 {
+        match offset & 0x0F {
+            3 => (self.registers[3] & 0x7F) | ((self.raster & 1) as u8) << 7,
+            4 => (self.raster >> 1) as u8,
+            // The paddles, which are not connected.
+            8 | 9 => 0xFF,
+            reg => self.registers[reg as usize],
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.registers[offset as usize & 0x0F] = value;
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycle += cycles;
+        while self.cycle >= self.line_cycles {
+            self.cycle -= self.line_cycles;
+            self.raster = (self.raster + 1) % self.lines;
+        }
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Vic {
+    fn save(&self, out: &mut StateWriter) {
+        out.bytes(&self.registers);
+        out.u16(self.raster);
+        out.u64(self.cycle);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("vic", version, 1)?;
+        input.bytes_into(&mut self.registers)?;
+        self.raster = input.u16()?;
+        self.cycle = input.u64()?;
+        if self.raster >= self.lines || self.cycle >= self.line_cycles {
+            return Err(savestate::Error::Invalid("raster position is for a different model"));
+        }
+        Ok(())
+    }
+}
+
+// The 1K x 4 colour RAM. Only the low nibble is stored; the high one
+// reads as 0.
+struct ColourRam([u8; 0x400]);
+
+impl Device for ColourRam {
+    fn peek(&self, offset: u16) -> u8 {
+        self.0[offset as usize & 0x3FF]
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.0[offset as usize & 0x3FF] = value & 0x0F;
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(&self.0)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(&mut self.0)
+    }
+}
+
+// VIA 1, whose interrupt output is wired to NMI. The RESTORE key is on
+// CA1.
+struct Via1(Via);
+
+impl Device for Via1 {
+    fn peek(&self, offset: u16) -> u8 {
+        self.0.peek(offset)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        self.0.read(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.0.write(offset, value);
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.0.tick(cycles);
+    }
+
+    fn nmi(&self) -> bool {
+        self.0.irq()
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(&self.0)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(&mut self.0)
+    }
+}
+
+// VIA 2 with the keyboard: port B drives the columns low and port A reads
+// the rows. Typed keys are pressed and released in turn, on top of any
+// held down by the host.
+struct Keyboard {
+    via: Via,
+    matrix: [u8; 8],
+    typed: [u8; 8],
+    keys: VecDeque<u8>,
+    timer: u64,
+}
+
+impl Keyboard {
+    fn update_inputs(&mut self) {
+        let columns = self.via.port_b();
+        let rows = (0..8)
+            .filter(|c| columns & 1 << c == 0)
+            .fold(0, |rows, c| rows | self.matrix[c] | self.typed[c]);
+        self.via.set_port_a(!rows);
+    }
+
+    fn type_next(&mut self, cycles: u64) {
+        self.timer = self.timer.saturating_sub(cycles);
+        if self.timer > 0 {
+            return;
+        }
+        if self.typed.iter().any(|&rows| rows != 0) {
+            let enter = self.typed[1] & 0x80 != 0;
+            self.typed = [0; 8];
+            self.timer = if enter { RELEASE_RETURN } else { RELEASE };
+        } else if let Some(key) = self.keys.pop_front() {
+            if let Some((column, row, shift)) = key_position(key) {
+                self.typed[column as usize] |= 1 << row;
+                if shift {
+                    self.typed[LEFT_SHIFT.0 as usize] |= 1 << LEFT_SHIFT.1;
+                }
+                self.timer = HOLD;
+            }
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn peek(&self, offset: u16) -> u8 {
+        self.via.peek(offset)
+    }
+
+    fn read(&mut self, offset: u16) -> u8 {
+        self.via.read(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.via.write(offset, value);
+        self.update_inputs();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.via.tick(cycles);
+        self.type_next(cycles);
+        self.update_inputs();
+    }
+
+    fn irq(&self) -> bool {
+        self.via.irq()
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+impl SaveState for Keyboard {
+    fn save(&self, out: &mut StateWriter) {
+        out.section("via", &self.via);
+        out.bytes(&self.matrix);
+        out.bytes(&self.typed);
+        out.bytes(&self.keys.iter().copied().collect::<Vec<_>>());
+        out.u64(self.timer);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("keyboard", version, 1)?;
+        input.section("via", &mut self.via)?;
+        input.bytes_into(&mut self.matrix)?;
+        input.bytes_into(&mut self.typed)?;
+        self.keys = input.bytes()?.iter().copied().collect();
+        self.timer = input.u64()?;
+        self.update_inputs();
+        Ok(())
+    }
+}
+
+/// An unexpanded VIC-20: 5K of RAM, the VIC's registers at $9000, VIA 1
+/// (NMI) at $9110, VIA 2 (IRQ, keyboard) at $9120, colour RAM at $9400,
+/// BASIC at $C000 and the KERNAL at $E000. Nothing is drawn, but the text
+/// screen can be read back with `screen_text`. More RAM can be mapped into
+/// `machine.bus` before a `machine.reset()`.
+///
+/// Keys go in with `press` or `type_keys`, which press and release each
+/// in turn, adding shift for the shifted symbols, or with `set_key` at a
+/// matrix position: the VIA 2 port B and port A bits of the column and
+/// row.
+pub struct Vic20 {
+    pub machine: Machine<Nmos6502, MemoryMap>,
+    pub scheduler: Scheduler,
+    model: Model,
+    vic: Rc<RefCell<Vic>>,
+    via1: Rc<RefCell<Via1>>,
+    keyboard: Rc<RefCell<Keyboard>>,
+}
+
+impl Vic20 {
+    /// A VIC-20 that has been reset, given its 8K KERNAL and BASIC ROMs.
+    pub fn new(model: Model, kernal: &[u8; 0x2000], basic: &[u8; 0x2000]) -> Self {
+        let vic = Rc::new(RefCell::new(Vic::new(model)));
+        let via1 = Rc::new(RefCell::new(Via1(Via::new())));
+        let keyboard = Rc::new(RefCell::new(Keyboard {
+            via: Via::new(),
+            matrix: [0; 8],
+            typed: [0; 8],
+            keys: VecDeque::new(),
+            timer: 0,
+        }));
+        let mut map = MemoryMap::new();
+        map.add_ram(0x0000..=0x03FF, 0x03FF);
+        map.add_ram(0x1000..=0x1FFF, 0x0FFF);
+        map.add_device(0x9000..=0x90FF, 0x0F, vic.clone());
+        map.add_device(0x9110..=0x911F, 0x0F, via1.clone());
+        map.add_device(0x9120..=0x912F, 0x0F, keyboard.clone());
+        map.add_device(0x9400..=0x97FF, 0x03FF, Rc::new(RefCell::new(ColourRam([0; 0x400]))));
+        map.add_rom(0xC000..=0xDFFF, basic.to_vec());
+        map.add_rom(0xE000..=0xFFFF, kernal.to_vec());
+
+        let mut machine = Machine::new(map);
+        machine.reset();
+        let mut scheduler = Scheduler::new();
+        scheduler.add(vic.clone());
+        scheduler.add(via1.clone());
+        scheduler.add(keyboard.clone());
+        Vic20 { machine, scheduler, model, vic, via1, keyboard }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Runs until BASIC has printed READY., for at most five seconds.
+    /// Returns whether it did.
+    pub fn boot(&mut self) -> bool {
+        let frame = self.model.clock() / 50;
+        for _ in 0..250 {
+            self.run(frame);
+            if self.screen_text().lines().any(|line| line == "READY.") {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.keyboard.borrow_mut().keys.push_back(key);
+    }
+
+    pub fn type_keys(&mut self, text: &str) {
+        self.keyboard.borrow_mut().keys.extend(text.bytes());
+    }
+
+    /// Whether typed keys are still waiting or held down.
+    pub fn typing(&self) -> bool {
+        let keyboard = self.keyboard.borrow();
+        !keyboard.keys.is_empty() || keyboard.timer > 0
+    }
+
+    pub fn set_key(&mut self, column: u8, row: u8, pressed: bool) {
+        let mut keyboard = self.keyboard.borrow_mut();
+        match pressed {
+            true => keyboard.matrix[column as usize & 7] |= 1 << (row & 7),
+            false => keyboard.matrix[column as usize & 7] &= !(1 << (row & 7)),
+        }
+        keyboard.update_inputs();
+    }
+
+    pub fn set_restore(&mut self, pressed: bool) {
+        self.via1.borrow_mut().0.set_ca1(!pressed);
+    }
+
+    /// The text screen, a line per row with trailing spaces removed.
+    /// Reversed characters, such as the cursor, read as normal ones and
+    /// graphics characters as `#`.
+    pub fn screen_text(&self) -> String {
+        let registers = self.vic.borrow().registers;
+        let columns = (registers[2] & 0x7F) as u16;
+        let rows = ((registers[3] >> 1) & 0x3F) as u16;
+        let lower_case = registers[5] & 0x0F == 0x02;
+        // VIC address bit 13 selects the bottom 8K of the CPU's space,
+        // and its absence $8000-$9FFF.
+        let vic_address = ((registers[5] & 0xF0) as u16) << 6 | ((registers[2] & 0x80) as u16) << 2;
+        let base = match vic_address & 0x2000 {
+            0 => 0x8000 | vic_address,
+            _ => vic_address & 0x1FFF,
+        };
+        let mut text = String::new();
+        for row in 0..rows {
+            let mut line: String = (0..columns)
+                .map(|column| screen_char(self.machine.bus.peek(base + row * columns + column), lower_case))
+                .collect();
+            line.truncate(line.trim_end().len());
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Runs for at least `cycles`, returning the number that elapsed.
+    pub fn run(&mut self, cycles: u64) -> u64 {
+        self.scheduler.run(&mut self.machine, cycles)
+    }
+}
+
+// Decodes a screen code in the upper case and graphics set, or the lower
+// and upper case one.
+fn screen_char(code: u8, lower_case: bool) -> char {
+    match code & 0x7F {
+        0x00 => '@',
+        c @ 0x01..=0x1A if lower_case => (b'a' + c - 1) as char,
+        c @ 0x01..=0x1A => (b'A' + c - 1) as char,
+        0x1B => '[',
+        0x1C => '£',
+        0x1D => ']',
+        0x1E => '↑',
+        0x1F => '←',
+        c @ 0x20..=0x3F => c as char,
+        c @ 0x41..=0x5A if lower_case => (b'A' + c - 0x41) as char,
+        _ => '#',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vic20() -> Vic20 {
+        let mut kernal = [0xEA; 0x2000];
+        kernal[0x1FFC..].copy_from_slice(&[0x00, 0xE0, 0x00, 0xE0]);
+        Vic20::new(Model::Pal, &kernal, &[0; 0x2000])
+    }
+
+    // Reads the rows with the given column selected, as SCNKEY does.
+    fn scan(vic20: &mut Vic20, column: u8) -> u8 {
+        let bus = &mut vic20.machine.bus;
+        bus.write(0x9122, 0xFF);
+        bus.write(0x9120, !(1 << column));
+        bus.read(0x9121)
+    }
+
+    #[test]
+    fn test_key_positions() {
+        assert_eq!(key_position(b'a'), Some((2, 1, false)));
+        assert_eq!(key_position(b'\n'), Some((1, 7, false)));
+        assert_eq!(key_position(b'"'), Some((7, 0, true)));
+        assert_eq!(key_position(b' '), Some((4, 0, false)));
+        assert_eq!(key_position(b'{'), None);
+    }
+
+    #[test]
+    fn test_keyboard() {
+        let mut vic20 = vic20();
+        vic20.set_key(4, 0, true);
+        assert_eq!(scan(&mut vic20, 4), 0xFE);
+        assert_eq!(scan(&mut vic20, 3), 0xFF);
+        vic20.set_key(4, 0, false);
+        assert_eq!(scan(&mut vic20, 4), 0xFF);
+
+        // A shifted key is held with left shift, then released.
+        vic20.type_keys("$1");
+        vic20.run(1);
+        assert_eq!((scan(&mut vic20, 7), scan(&mut vic20, 3)), (0xFD, 0xFD));
+        vic20.run(HOLD);
+        assert_eq!((scan(&mut vic20, 7), scan(&mut vic20, 3)), (0xFF, 0xFF));
+        vic20.run(RELEASE);
+        assert_eq!((scan(&mut vic20, 0), scan(&mut vic20, 3)), (0xFE, 0xFF));
+        assert!(vic20.typing());
+        vic20.run(HOLD + RELEASE);
+        assert!(!vic20.typing());
+    }
+
+    #[test]
+    fn test_screen_text() {
+        let mut vic20 = vic20();
+        let bus = &mut vic20.machine.bus;
+        // The KERNAL's layout: 22 x 23 characters at $1E00.
+        bus.write(0x9002, 0x96);
+        bus.write(0x9003, 0x2E);
+        bus.write(0x9005, 0xF0);
+        for addr in 0x1E00..0x1E00 + 22 * 23 {
+            bus.write(addr, 0x20);
+        }
+        for (i, code) in [0x12, 0x05, 0x01, 0x04, 0x19, 0x2E].into_iter().enumerate() {
+            bus.write(0x1E00 + 22 + i as u16, code);
+        }
+        bus.write(0x1E00 + 44, 0xA0);
+        let text = vic20.screen_text();
+        assert_eq!(text.lines().count(), 23);
+        assert!(text.starts_with("\nREADY.\n\n"));
+
+        vic20.machine.bus.write(0x9005, 0xF2);
+        assert!(vic20.screen_text().starts_with("\nready.\n"));
+    }
+
+    #[test]
+    fn test_interrupts() {
+        let mut vic20 = vic20();
+        // VIA 1 interrupts are NMIs: RESTORE on CA1.
+        vic20.machine.bus.write(0x911E, 0x82);
+        vic20.set_restore(true);
+        vic20.run(1);
+        assert!(vic20.machine.nmi_line() && !vic20.machine.irq_line());
+        vic20.set_restore(false);
+        vic20.machine.bus.read(0x9111);
+
+        // VIA 2's timer 1 is the jiffy interrupt.
+        vic20.machine.bus.write(0x912E, 0xC0);
+        vic20.machine.bus.write(0x9124, 0x10);
+        vic20.machine.bus.write(0x9125, 0x00);
+        vic20.run(20);
+        assert!(vic20.machine.irq_line());
+    }
+
+    #[test]
+    fn test_colour_ram() {
+        let mut vic20 = vic20();
+        vic20.machine.bus.write(0x9400, 0xF6);
+        vic20.machine.bus.write(0x97FF, 0x3A);
+        assert_eq!((vic20.machine.bus.read(0x9400), vic20.machine.bus.read(0x97FF)), (0x06, 0x0A));
+    }
+
+    // Needs PAL KERNAL and BASIC ROMs:
+    // VIC20_KERNAL=... VIC20_BASIC=... cargo test vic20 -- --ignored
+    #[test]
+    #[ignore]
+    fn test_basic() {
+        let rom = |var: &str| -> [u8; 0x2000] {
+            let path = std::env::var(var).unwrap_or_else(|_| panic!("set {} to the path of an 8K VIC-20 ROM", var));
+            let data = std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+            data.try_into().unwrap_or_else(|_| panic!("{}: the ROM must be 8K", path))
+        };
+        let mut vic20 = Vic20::new(Model::Pal, &rom("VIC20_KERNAL"), &rom("VIC20_BASIC"));
+        assert!(vic20.boot(), "no READY.:\n{}", vic20.screen_text());
+
+        vic20.type_keys("PRINT 2+2\n");
+        let frame = Model::Pal.clock() / 50;
+        while vic20.typing() {
+            vic20.run(frame);
+        }
+        vic20.run(10 * frame);
+        let text = vic20.screen_text();
+        let lines: Vec<_> = text.lines().collect();
+        let i = lines.iter().position(|line| *line == "PRINT 2+2").unwrap_or_else(|| panic!("{}", text));
+        assert_eq!(lines.get(i + 1), Some(&" 4"), "{}", text);
+        assert_eq!(lines.get(i + 2), Some(&"READY."), "{}", text);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut saved = vic20();
+        saved.machine.bus.write(0x9005, 0xF2);
+        saved.machine.bus.write(0x9400, 0x06);
+        // VIA 1 and VIA 2 timers, and a key on its way in.
+        saved.machine.bus.write(0x911B, 0x40);
+        saved.machine.bus.write(0x9114, 0x00);
+        saved.machine.bus.write(0x9115, 0x30);
+        saved.machine.bus.write(0x912E, 0xC0);
+        saved.machine.bus.write(0x9124, 0x00);
+        saved.machine.bus.write(0x9125, 0x20);
+        saved.type_keys("ab");
+        saved.run(1000);
+        let bus = savestate::save("bus", &saved.machine.bus);
+        let cpu = savestate::save("cpu", &saved.machine.cpu);
+
+        let mut restored = vic20();
+        savestate::load("bus", &mut restored.machine.bus, &bus).unwrap();
+        savestate::load("cpu", &mut restored.machine.cpu, &cpu).unwrap();
+        assert!(restored.typing());
+        for _ in 0..50 {
+            saved.run(337);
+            restored.run(337);
+            for addr in [0x9004, 0x9005, 0x9114, 0x9115, 0x9124, 0x9125, 0x912D, 0x9400] {
+                assert_eq!(restored.machine.bus.peek(addr), saved.machine.bus.peek(addr), "${:04X}", addr);
+            }
+            assert_eq!((scan(&mut restored, 2), restored.typing()), (scan(&mut saved, 2), saved.typing()));
+        }
+    }
+
+    #[test]
+    fn test_raster() {
+        let mut vic20 = vic20();
+        let start = vic20.machine.bus.read(0x9004);
+        vic20.run(71 * 2 * 10);
+        let lines = vic20.machine.bus.read(0x9004).wrapping_sub(start);
+        assert!((9..=11).contains(&lines), "{}", lines);
+    }
+}
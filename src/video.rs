@@ -0,0 +1,426 @@
+//! Headless video: framebuffers that emulated video chips draw into, saved
+//! as PNG or PPM files and compared against golden images.
+
+use core::cell::RefCell;
+use core::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::device::{Device, Scheduler};
+use crate::machine::Machine;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use crate::{Bus, Variant};
+
+pub mod png;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file isn't a valid image.
+    Format(&'static str),
+    Unsupported(&'static str),
+    /// The file extension isn't `.png` or `.ppm`.
+    UnknownFormat(PathBuf),
+    SizeMismatch { expected: (usize, usize), found: (usize, usize) },
+    /// A frame differs from its golden image, and was saved to `actual`.
+    Mismatch { golden: PathBuf, actual: PathBuf, pixels: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Format(message) | Error::Unsupported(message) => write!(f, "{}", message),
+            Error::UnknownFormat(path) => write!(f, "{}: unknown image format", path.display()),
+            Error::SizeMismatch { expected, found } => {
+                write!(f, "expected a {}x{} image, found {}x{}", expected.0, expected.1, found.0, found.1)
+            }
+            Error::Mismatch { golden, actual, pixels } => {
+                write!(f, "{} pixels differ from {}; the frame was saved to {}", pixels, golden.display(), actual.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// An image of `0xRRGGBB` pixels, row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Png,
+    Ppm,
+}
+
+impl Format {
+    fn of(path: &Path) -> Result<Self, Error> {
+        match path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase()).as_deref() {
+            Some("png") => Ok(Format::Png),
+            Some("ppm") => Ok(Format::Ppm),
+            _ => Err(Error::UnknownFormat(path.to_path_buf())),
+        }
+    }
+}
+
+// The length of a `width` x `height` image stored in `bytes` per pixel,
+// if both it and the framebuffer's pixels would fit in memory.
+fn image_len(width: usize, height: usize, bytes: usize) -> Result<usize, Error> {
+    let pixels = width.checked_mul(height).ok_or(Error::Format("image too large"))?;
+    match (pixels.checked_mul(bytes), pixels.checked_mul(size_of::<u32>())) {
+        (Some(len), Some(memory)) if len <= isize::MAX as usize && memory <= isize::MAX as usize => Ok(len),
+        _ => Err(Error::Format("image too large")),
+    }
+}
+
+impl Framebuffer {
+    /// A black framebuffer.
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer { width, height, pixels: vec![0; width * height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, rgb: u32) {
+        self.pixels[y * self.width + x] = rgb & 0xFFFFFF;
+    }
+
+    pub fn fill(&mut self, rgb: u32) {
+        self.pixels.fill(rgb & 0xFFFFFF);
+    }
+
+    /// The number of pixels that differ from `other`.
+    pub fn diff(&self, other: &Framebuffer) -> Result<usize, Error> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Err(Error::SizeMismatch { expected: (other.width, other.height), found: (self.width, self.height) });
+        }
+        Ok(self.pixels.iter().zip(&other.pixels).filter(|(a, b)| a != b).count())
+    }
+
+    pub fn write_png(&self, writer: &mut impl Write) -> io::Result<()> {
+        png::write(self, writer)
+    }
+
+    /// Writes a binary (P6) PPM.
+    pub fn write_ppm(&self, writer: &mut impl Write) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let data: Vec<u8> = self.pixels.iter().flat_map(|p| p.to_be_bytes()[1..].to_vec()).collect();
+        writer.write_all(&data)
+    }
+
+    pub fn read_png(data: &[u8]) -> Result<Self, Error> {
+        png::read(data)
+    }
+
+    /// Reads a binary (P6) PPM with a maximum value of 255.
+    pub fn read_ppm(data: &[u8]) -> Result<Self, Error> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while fields.len() < 4 {
+            match data.get(pos) {
+                Some(b'#') => {
+                    while data.get(pos).is_some_and(|&c| c != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => pos += 1,
+                Some(_) => {
+                    let start = pos;
+                    while data.get(pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+                        pos += 1;
+                    }
+                    fields.push(&data[start..pos]);
+                }
+                None => return Err(Error::Format("truncated PPM header")),
+            }
+        }
+        if fields[0] != b"P6" {
+            return Err(Error::Unsupported("only binary PPMs are supported"));
+        }
+        let number = |field: &[u8]| {
+            std::str::from_utf8(field).ok().and_then(|s| s.parse::<usize>().ok()).ok_or(Error::Format("bad PPM header"))
+        };
+        let (width, height) = (number(fields[1])?, number(fields[2])?);
+        if number(fields[3])? != 255 {
+            return Err(Error::Unsupported("only 8-bit PPMs are supported"));
+        }
+        // A single whitespace character separates the header from the data.
+        let len = image_len(width, height, 3)?;
+        let pixels = data.get(pos + 1..).and_then(|data| data.get(..len)).ok_or(Error::Format("truncated PPM data"))?;
+        let pixels = pixels.chunks(3).map(|p| u32::from_be_bytes([0, p[0], p[1], p[2]])).collect();
+        Ok(Framebuffer { width, height, pixels })
+    }
+
+    /// Saves the frame as a PNG or PPM, going by the file extension.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let format = Format::of(path)?;
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        match format {
+            Format::Png => self.write_png(&mut file)?,
+            Format::Ppm => self.write_ppm(&mut file)?,
+        }
+        Ok(file.flush()?)
+    }
+
+    /// Loads a PNG or PPM, going by the file extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let format = Format::of(path)?;
+        let data = std::fs::read(path)?;
+        match format {
+            Format::Png => Self::read_png(&data),
+            Format::Ppm => Self::read_ppm(&data),
+        }
+    }
+
+    /// Compares the frame with the golden image at `path`. On a mismatch
+    /// the frame is saved next to it with `.actual` before the extension,
+    /// for inspection. With `GOLDEN_UPDATE` set in the environment, the
+    /// golden image is (re)written instead.
+    pub fn check_golden(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        if std::env::var_os("GOLDEN_UPDATE").is_some() {
+            return self.save(path);
+        }
+        let golden = Self::load(path)?;
+        match self.diff(&golden)? {
+            0 => Ok(()),
+            pixels => {
+                let mut actual = path.to_path_buf();
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                actual.set_file_name(format!("{}.actual.{}", stem, extension));
+                self.save(&actual)?;
+                Err(Error::Mismatch { golden: path.to_path_buf(), actual, pixels })
+            }
+        }
+    }
+}
+
+/// A video chip, which draws into a framebuffer as it is clocked.
+pub trait Video {
+    fn framebuffer(&self) -> &Framebuffer;
+}
+
+/// Saves frames at chosen points in a machine's run.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    // By cycle, latest first.
+    points: Vec<(u64, PathBuf)>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Saves the frame to `path` once the CPU's cycle count reaches
+    /// `cycle`, at the end of the instruction that reaches it.
+    pub fn at(&mut self, cycle: u64, path: impl Into<PathBuf>) -> &mut Self {
+        self.points.push((cycle, path.into()));
+        self.points.sort_by_key(|point| core::cmp::Reverse(point.0));
+        self
+    }
+
+    /// Runs the machine until every frame has been saved, returning their
+    /// paths in order.
+    pub fn run<V: Variant, B: Bus>(
+        &mut self,
+        scheduler: &mut Scheduler,
+        machine: &mut Machine<V, B>,
+        video: &RefCell<impl Video + ?Sized>,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let mut saved = Vec::new();
+        while let Some((cycle, path)) = self.points.pop() {
+            let cycles = cycle.saturating_sub(machine.cpu.cycles);
+            scheduler.run(machine, cycles);
+            video.borrow().framebuffer().save(&path)?;
+            saved.push(path);
+        }
+        Ok(saved)
+    }
+}
+
+/// The 16 colours of the C64.
+pub const PALETTE: [u32; 16] = [
+    0x000000, 0xFFFFFF, 0x880000, 0xAAFFEE, 0xCC44CC, 0x00CC55, 0x0000AA, 0xEEEE77, 0xDD8855, 0x664400, 0xFF7777, 0x333333,
+    0x777777, 0xAAFF66, 0x0088FF, 0xBBBBBB,
+];
+
+/// A memory mapped display of one byte per pixel, row by row, each the
+/// index of a colour in `PALETTE` (the low nibble). It's the display of
+/// the easy6502 simulator when mapped as 32x32 at $0200.
+#[derive(Debug, Clone)]
+pub struct Bitmap {
+    memory: Vec<u8>,
+    frame: Framebuffer,
+}
+
+impl Bitmap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Bitmap { memory: vec![0; width * height], frame: Framebuffer::new(width, height) }
+    }
+}
+
+impl Device for Bitmap {
+    fn peek(&self, offset: u16) -> u8 {
+        self.memory.get(offset as usize).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        let offset = offset as usize;
+        if offset < self.memory.len() {
+            self.memory[offset] = value;
+            let width = self.frame.width;
+            self.frame.set(offset % width, offset / width, PALETTE[value as usize & 0x0F]);
+        }
+    }
+
+    fn state(&self) -> Option<&dyn SaveState> {
+        Some(self)
+    }
+
+    fn state_mut(&mut self) -> Option<&mut dyn SaveState> {
+        Some(self)
+    }
+}
+
+// The frame is drawn from the memory, so only the memory is saved.
+impl SaveState for Bitmap {
+    fn save(&self, out: &mut StateWriter) {
+        out.bytes(&self.memory);
+    }
+
+    fn load(&mut self, version: u16, input: &mut StateReader) -> Result<(), savestate::Error> {
+        savestate::expect_version("bitmap", version, 1)?;
+        input.bytes_into(&mut self.memory)?;
+        let width = self.frame.width;
+        for (offset, value) in self.memory.iter().enumerate() {
+            self.frame.set(offset % width, offset / width, PALETTE[*value as usize & 0x0F]);
+        }
+        Ok(())
+    }
+}
+
+impl Video for Bitmap {
+    fn framebuffer(&self) -> &Framebuffer {
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::memory_map::MemoryMap;
+    use crate::nmos6502::Nmos6502;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cpu-6502-video-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_ppm() {
+        let mut frame = Framebuffer::new(2, 2);
+        frame.set(1, 0, 0x123456);
+        frame.set(0, 1, 0xFFFFFF);
+        let mut file = Vec::new();
+        frame.write_ppm(&mut file).unwrap();
+        assert_eq!(&file[..11], b"P6\n2 2\n255\n");
+        assert_eq!(Framebuffer::read_ppm(&file).unwrap(), frame);
+        assert_eq!(Framebuffer::read_ppm(b"P6 # size\n1 1 255\n\x01\x02\x03").unwrap().get(0, 0), 0x010203);
+        assert!(Framebuffer::read_ppm(b"P3\n1 1\n255\n1 2 3").is_err());
+
+        let huge = format!("P6\n{} {}\n255\n", usize::MAX / 2, 3);
+        assert!(matches!(Framebuffer::read_ppm(huge.as_bytes()), Err(Error::Format("image too large"))));
+    }
+
+    #[test]
+    fn test_bitmap_save_state() {
+        let mut bitmap = Bitmap::new(4, 2);
+        bitmap.write(1, 2);
+        bitmap.write(6, 0x1F);
+        let state = savestate::save("bitmap", &bitmap);
+
+        let mut restored = Bitmap::new(4, 2);
+        savestate::load("bitmap", &mut restored, &state).unwrap();
+        assert_eq!((restored.peek(1), restored.peek(6)), (2, 0x1F));
+        assert_eq!(restored.framebuffer().get(1, 0), PALETTE[2]);
+        assert_eq!(restored.framebuffer().get(2, 1), PALETTE[0xF]);
+        assert!(savestate::load("bitmap", &mut Bitmap::new(2, 2), &state).is_err());
+    }
+
+    #[test]
+    fn test_capture_and_golden() {
+        let dir = temp_dir("capture");
+        let mut map = MemoryMap::new();
+        let ram = map.add_ram(0x0000..=0xFFFF, 0xFFFF);
+        let screen = Rc::new(RefCell::new(Bitmap::new(32, 32)));
+        map.add_device(0x0200..=0x05FF, 0x03FF, screen.clone());
+        // Fills the screen with colours, one pixel per loop:
+        //   LDX #0; loop: TXA; STA $0200,X; STA $0300,X; INX; BNE loop; BRK
+        let memory = map.memory_mut(ram).unwrap();
+        memory[0x0600..0x0610].copy_from_slice(&[
+            0xA2, 0x00, 0x8A, 0x9D, 0x00, 0x02, 0x9D, 0x00, 0x03, 0xE8, 0xD0, 0xF6, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        let mut machine = Machine::<Nmos6502, _>::new(map);
+        machine.cpu.pc = 0x0600;
+        let mut scheduler = Scheduler::new();
+
+        let mut capture = Capture::new();
+        capture.at(20_000, dir.join("done.png")).at(2 + 17 * 64, dir.join("early.ppm"));
+        let paths = capture.run(&mut scheduler, &mut machine, &*screen).unwrap();
+        assert_eq!(paths, [dir.join("early.ppm"), dir.join("done.png")]);
+
+        // 64 pixels in, the first two rows are drawn.
+        let early = Framebuffer::load(&paths[0]).unwrap();
+        assert_eq!(early.get(31, 1), PALETTE[63 & 0x0F]);
+        assert_eq!(early.get(0, 2), 0);
+        let done = Framebuffer::load(&paths[1]).unwrap();
+        assert_eq!(done.get(31, 7), PALETTE[0xFF & 0x0F]);
+        assert_eq!(done.get(0, 8), PALETTE[0]);
+        assert_eq!(done.get(1, 8), PALETTE[1]);
+
+        // Run with GOLDEN_UPDATE set to regenerate the golden images.
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        early.check_golden(golden.join("bitmap_early.ppm")).unwrap();
+        screen.borrow().framebuffer().check_golden(golden.join("bitmap_done.png")).unwrap();
+
+        if std::env::var_os("GOLDEN_UPDATE").is_none() {
+            std::fs::copy(golden.join("bitmap_done.png"), dir.join("golden.png")).unwrap();
+            let err = early.check_golden(dir.join("golden.png")).unwrap_err();
+            assert!(matches!(err, Error::Mismatch { pixels, .. } if pixels > 0), "{}", err);
+            assert!(dir.join("golden.actual.png").exists());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,366 @@
+//! A PNG encoder writing uncompressed deflate blocks, and a decoder for the
+//! 8-bit RGB and RGBA images other tools save golden images as.
+
+use std::io::{self, Write};
+
+use super::{image_len, Error, Framebuffer};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for byte in chunks.iter().flat_map(|c| c.iter()) {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+pub fn write(frame: &Framebuffer, writer: &mut impl Write) -> io::Result<()> {
+    let mut header = Vec::with_capacity(13);
+    header.extend((frame.width as u32).to_be_bytes());
+    header.extend((frame.height as u32).to_be_bytes());
+    // 8-bit RGB, no interlacing.
+    header.extend([8, 2, 0, 0, 0]);
+
+    // Each row is preceded by filter type 0.
+    let mut raw = Vec::with_capacity((frame.width * 3 + 1) * frame.height);
+    for row in frame.pixels.chunks(frame.width.max(1)) {
+        raw.push(0);
+        for pixel in row {
+            raw.extend(&pixel.to_be_bytes()[1..]);
+        }
+    }
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend((block.len() as u16).to_le_bytes());
+        zlib.extend((!(block.len() as u16)).to_le_bytes());
+        zlib.extend(block);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    writer.write_all(&SIGNATURE)?;
+    write_chunk(writer, b"IHDR", &header)?;
+    write_chunk(writer, b"IDAT", &zlib)?;
+    write_chunk(writer, b"IEND", &[])
+}
+
+pub fn read(data: &[u8]) -> Result<Framebuffer, Error> {
+    let data = data.strip_prefix(&SIGNATURE).ok_or(Error::Format("not a PNG file"))?;
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let body = rest.get(8..8 + len).ok_or(Error::Format("truncated PNG chunk"))?;
+        chunks.push((&rest[4..8], body));
+        rest = rest.get(12 + len..).ok_or(Error::Format("truncated PNG chunk"))?;
+    }
+
+    let header = match chunks.first() {
+        Some((b"IHDR", header)) if header.len() == 13 => *header,
+        _ => return Err(Error::Format("missing PNG header")),
+    };
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    let channels = match (header[8], header[9], header[12]) {
+        (8, 2, 0) => 3,
+        (8, 6, 0) => 4,
+        _ => return Err(Error::Unsupported("only 8-bit, non-interlaced RGB and RGBA PNGs are supported")),
+    };
+    image_len(width, height, channels)?;
+    // Each row is preceded by its filter type.
+    let len = width.checked_mul(channels)
+        .and_then(|stride| (stride + 1).checked_mul(height))
+        .ok_or(Error::Format("image too large"))?;
+    let stride = width * channels;
+
+    let zlib: Vec<u8> = chunks.iter().filter(|(kind, _)| kind == b"IDAT").flat_map(|(_, body)| body.iter().copied()).collect();
+    let raw = inflate(zlib.get(2..).ok_or(Error::Format("truncated PNG data"))?, len)?;
+    if raw.len() < len {
+        return Err(Error::Format("truncated PNG data"));
+    }
+    let mut frame = Framebuffer::new(width, height);
+    let mut previous = vec![0u8; stride];
+    for y in 0..height {
+        let start = y * (stride + 1);
+        let filter = raw[start];
+        let mut row = raw[start + 1..start + 1 + stride].to_vec();
+        for i in 0..stride {
+            let a = if i >= channels { row[i - channels] } else { 0 };
+            let b = previous[i];
+            let c = if i >= channels { previous[i - channels] } else { 0 };
+            row[i] = row[i].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(Error::Format("bad PNG filter")),
+            });
+        }
+        for (x, pixel) in row.chunks(channels).enumerate() {
+            frame.set(x, y, u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]));
+        }
+        previous = row;
+    }
+    Ok(frame)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Result<u32, Error> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(Error::Format("truncated deflate stream"))?;
+            self.buffer |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+}
+
+// A canonical Huffman code, as symbol counts per code length and the
+// symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Format("bad deflate code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Decompresses a deflate stream of at most `limit` bytes.
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    let mut bits = Bits { data, pos: 0, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.buffer = 0;
+                bits.count = 0;
+                let header = data.get(bits.pos..bits.pos + 4).ok_or(Error::Format("truncated deflate stream"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let start = bits.pos + 4;
+                out.extend(data.get(start..start + len).ok_or(Error::Format("truncated deflate stream"))?);
+                bits.pos = start + len;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(&mut bits, &mut out, limit, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let literals = bits.take(5)? as usize + 257;
+                let distances = bits.take(5)? as usize + 1;
+                let code_lengths = bits.take(4)? as usize + 4;
+                let mut lengths = [0u8; 19];
+                for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+                    lengths[i] = bits.take(3)? as u8;
+                }
+                let code = Huffman::new(&lengths);
+                let mut lengths = Vec::with_capacity(literals + distances);
+                while lengths.len() < literals + distances {
+                    let (value, repeat) = match code.decode(&mut bits)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => (*lengths.last().ok_or(Error::Format("bad deflate code lengths"))?, 3 + bits.take(2)?),
+                        17 => (0, 3 + bits.take(3)?),
+                        _ => (0, 11 + bits.take(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+                }
+                if lengths.len() != literals + distances {
+                    return Err(Error::Format("bad deflate code lengths"));
+                }
+                let (literal_lengths, distance_lengths) = lengths.split_at(literals);
+                inflate_block(&mut bits, &mut out, limit, &Huffman::new(literal_lengths), &Huffman::new(distance_lengths))?;
+            }
+            _ => return Err(Error::Format("bad deflate block type")),
+        }
+        if out.len() > limit {
+            return Err(Error::Format("too much PNG data"));
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, limit: usize, literals: &Huffman, distances: &Huffman) -> Result<(), Error> {
+    loop {
+        if out.len() > limit {
+            return Err(Error::Format("too much PNG data"));
+        }
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() {
+                    return Err(Error::Format("bad deflate length"));
+                }
+                let len = LENGTH_BASE[i] as usize + bits.take(LENGTH_EXTRA[i] as u32)? as usize;
+                let d = distances.decode(bits)? as usize;
+                if d >= DISTANCE_BASE.len() {
+                    return Err(Error::Format("bad deflate distance"));
+                }
+                let distance = DISTANCE_BASE[d] as usize + bits.take(DISTANCE_EXTRA[d] as u32)? as usize;
+                let start = out.len().checked_sub(distance).ok_or(Error::Format("bad deflate distance"))?;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut frame = Framebuffer::new(300, 250);
+        for y in 0..250 {
+            for x in 0..300 {
+                frame.set(x, y, (x * 0x010203 + y * 0x030201) as u32 & 0xFFFFFF);
+            }
+        }
+        let mut file = Vec::new();
+        write(&frame, &mut file).unwrap();
+        assert_eq!(read(&file).unwrap(), frame);
+    }
+
+    #[test]
+    fn test_compressed() {
+        // A 3x2 RGB image with filters None and Sub, compressed by zlib.
+        let file = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00,
+            0x03, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0x12, 0x16, 0xF1, 0x4D, 0x00, 0x00, 0x00, 0x11, 0x49,
+            0x44, 0x41, 0x54, 0x78, 0xDA, 0x63, 0xF8, 0xCF, 0xC0, 0xC0, 0x00, 0xC6, 0x8C, 0x70, 0x00, 0x00, 0x2D, 0x1E, 0x03,
+            0x08, 0x13, 0xAC, 0x20, 0x74, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+        let frame = read(&file).unwrap();
+        assert_eq!((frame.width(), frame.height()), (3, 2));
+        assert_eq!(frame.pixels(), [0xFF0000, 0x00FF00, 0x0000FF, 0x010101, 0x020202, 0x030303]);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut file = SIGNATURE.to_vec();
+        let header = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 8, 2, 0, 0, 0];
+        write_chunk(&mut file, b"IHDR", &header).unwrap();
+        write_chunk(&mut file, b"IDAT", &[0x78, 0x01, 1, 0, 0, 0xFF, 0xFF]).unwrap();
+        assert!(matches!(read(&file), Err(Error::Format("image too large"))));
+    }
+
+    #[test]
+    fn test_inflate_dynamic() {
+        let zlib = [
+            0x78, 0xDA, 0xB5, 0xCB, 0xC9, 0x11, 0x80, 0x20, 0x10, 0x44, 0xD1, 0x54, 0x3A, 0x02, 0x13, 0x30, 0x1A, 0x50, 0x94,
+            0x51, 0x61, 0x10, 0x59, 0x84, 0xE8, 0x9D, 0x32, 0x02, 0x2F, 0x1E, 0xBB, 0xFE, 0xEB, 0x64, 0x0D, 0xCE, 0x4C, 0xD3,
+            0x0E, 0x1D, 0xB9, 0x7A, 0x2C, 0x7C, 0x63, 0xCB, 0x2E, 0x5C, 0xE0, 0x62, 0x22, 0x92, 0xE4, 0x43, 0xF5, 0x86, 0x99,
+            0xD7, 0xF1, 0x5D, 0xFF, 0xE0, 0xA0, 0xC4, 0xB9, 0x06, 0x2D, 0xA8, 0x52, 0xB2, 0x58, 0xA8, 0x18, 0x49, 0xDD, 0x78,
+            0x1C, 0x74, 0x66, 0x8E, 0xF2, 0x5D, 0xAF, 0xE1, 0x3B, 0x7C, 0x00, 0xAA, 0x17, 0x4E, 0xAB,
+        ];
+        let text = [
+            "the quick brown fox jumps over the lazy dog; ".repeat(3),
+            "pack my box with five dozen liquor jugs. ".repeat(2),
+        ]
+        .concat();
+        assert_eq!(inflate(&zlib[2..], text.len()).unwrap(), text.as_bytes());
+        assert!(inflate(&zlib[2..], text.len() - 1).is_err());
+    }
+}
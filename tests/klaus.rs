@@ -0,0 +1,64 @@
+use cpu_6502::{nmos6502::Nmos6502, Bus, Cpu};
+
+const FUNCTIONAL_TEST: &[u8] = include_bytes!("fixtures/6502_functional_test.bin");
+const DECIMAL_TEST: &[u8] = include_bytes!("fixtures/6502_decimal_test.bin");
+
+const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+const FUNCTIONAL_TEST_CASE: u16 = 0x0200;
+
+const DECIMAL_ERROR: u16 = 0x000B;
+const STP: u8 = 0xDB;
+
+struct Memory(Box<[u8; 65536]>);
+impl Bus for Memory {
+    fn read(&self, addr: u16) -> u8 { self.0[addr as usize] }
+    fn write(&mut self, addr: u16, value: u8) { self.0[addr as usize] = value }
+}
+
+fn load(image: &[u8], base: u16) -> Memory {
+    let mut mem = Memory(Box::new([0; 65536]));
+    mem.0[base as usize..base as usize + image.len()].copy_from_slice(image);
+    mem
+}
+
+// Runs until the program traps (a jump or branch to itself) or `done`
+// returns true, giving back the final PC.
+fn run(cpu: &mut Cpu<Nmos6502>, mem: &mut Memory, done: impl Fn(&Cpu<Nmos6502>, &Memory) -> bool) -> u16 {
+    loop {
+        if done(cpu, mem) {
+            return cpu.pc;
+        }
+        let pc = cpu.pc;
+        cpu.step(mem);
+        if cpu.pc == pc {
+            return pc;
+        }
+    }
+}
+
+#[test]
+fn functional_test() {
+    let mut mem = load(FUNCTIONAL_TEST, 0x0000);
+    let mut cpu = Cpu::<Nmos6502>::new();
+    cpu.pc = 0x0400;
+
+    let pc = run(&mut cpu, &mut mem, |_, _| false);
+    assert!(
+        pc == FUNCTIONAL_SUCCESS,
+        "trapped at ${:04X} in test ${:02X}",
+        pc, mem.read(FUNCTIONAL_TEST_CASE),
+    );
+}
+
+#[test]
+fn decimal_test() {
+    let mut mem = load(DECIMAL_TEST, 0x0200);
+    let mut cpu = Cpu::<Nmos6502>::new();
+    cpu.pc = 0x0200;
+    cpu.sp = 0xFF;
+
+    let pc = run(&mut cpu, &mut mem, |cpu, mem| mem.read(cpu.pc) == STP);
+    assert_eq!(mem.read(pc), STP, "trapped at ${:04X}", pc);
+    assert_eq!(mem.read(DECIMAL_ERROR), 0, "decimal mode results differ, N1=${:02X} N2=${:02X} carry={}",
+        mem.read(0x00), mem.read(0x01), cpu.reg.get_y());
+}
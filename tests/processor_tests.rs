@@ -0,0 +1,184 @@
+//! Runner for the single step test vectors of the ProcessorTests project
+//! (<https://github.com/SingleStepTests/ProcessorTests>), `6502/v1` and
+//! `wdc65c02/v1`. Each case gives the state before and after one
+//! instruction and, when available, the bus activity of every cycle.
+//!
+//! A few hand checked cases are bundled in `tests/fixtures`. The full suites
+//! run with `cargo test --test processor_tests -- --ignored` when
+//! `PROCESSOR_TESTS_6502` or `PROCESSOR_TESTS_65C02` points at a directory
+//! of `xx.json` files.
+
+use std::cell::RefCell;
+use std::path::Path;
+
+use cpu_6502::cmos65c02::Cmos65c02;
+use cpu_6502::nmos6502::Nmos6502;
+use cpu_6502::{Bus, Cpu, Variant};
+use serde_json::Value;
+
+type Cycle = (u16, u8, &'static str);
+
+struct RecordingBus {
+    mem: Box<[u8; 65536]>,
+    cycles: RefCell<Vec<Cycle>>,
+}
+
+impl Bus for RecordingBus {
+    fn read(&self, addr: u16) -> u8 {
+        let value = self.mem[addr as usize];
+        self.cycles.borrow_mut().push((addr, value, "read"));
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.cycles.get_mut().push((addr, value, "write"));
+        self.mem[addr as usize] = value;
+    }
+}
+
+fn field(state: &Value, key: &str) -> Result<i64, String> {
+    state[key].as_i64().ok_or_else(|| format!("missing `{}`", key))
+}
+
+fn ram(state: &Value) -> Result<Vec<(u16, u8)>, String> {
+    let entries = state["ram"].as_array().ok_or("missing `ram`")?;
+    entries.iter()
+        .map(|e| match (e[0].as_i64(), e[1].as_i64()) {
+            (Some(addr), Some(value)) => Ok((addr as u16, value as u8)),
+            _ => Err(format!("invalid ram entry {}", e)),
+        })
+        .collect()
+}
+
+fn set_state<V: Variant>(cpu: &mut Cpu<V>, bus: &mut RecordingBus, state: &Value) -> Result<(), String> {
+    cpu.pc = field(state, "pc")? as u16;
+    cpu.sp = field(state, "s")? as u8;
+    cpu.reg.update_a(field(state, "a")? as u8);
+    cpu.reg.update_x(field(state, "x")? as u8);
+    cpu.reg.update_y(field(state, "y")? as u8);
+    cpu.reg.set_status(field(state, "p")? as u8);
+    for (addr, value) in ram(state)? {
+        bus.mem[addr as usize] = value;
+    }
+    Ok(())
+}
+
+fn check_state<V: Variant>(cpu: &Cpu<V>, bus: &RecordingBus, state: &Value) -> Result<(), String> {
+    // B and bit 5 aren't stored in P.
+    let registers = [
+        ("pc", cpu.pc as i64, 0xFFFF),
+        ("s", cpu.sp as i64, 0xFF),
+        ("a", cpu.reg.get_a() as i64, 0xFF),
+        ("x", cpu.reg.get_x() as i64, 0xFF),
+        ("y", cpu.reg.get_y() as i64, 0xFF),
+        ("p", cpu.reg.get_status(false) as i64, 0xCF),
+    ];
+    for (name, actual, mask) in registers {
+        let expected = field(state, name)?;
+        if actual & mask != expected & mask {
+            return Err(format!("{}: expected ${:02X}, found ${:02X}", name, expected, actual));
+        }
+    }
+    for (addr, expected) in ram(state)? {
+        let actual = bus.mem[addr as usize];
+        if actual != expected {
+            return Err(format!("${:04X}: expected ${:02X}, found ${:02X}", addr, expected, actual));
+        }
+    }
+    Ok(())
+}
+
+fn check_cycles(bus: &RecordingBus, expected: &[Value]) -> Result<(), String> {
+    let actual = bus.cycles.borrow();
+    for (i, cycle) in expected.iter().enumerate() {
+        let expected = (cycle[0].as_i64(), cycle[1].as_i64(), cycle[2].as_str());
+        let found = actual.get(i).map(|&(addr, value, kind)| (Some(addr as i64), Some(value as i64), Some(kind)));
+        if found != Some(expected) {
+            return Err(format!("cycle {}: expected {}, found {:?}", i + 1, cycle, actual.get(i)));
+        }
+    }
+    if actual.len() != expected.len() {
+        return Err(format!("expected {} cycles, found {}", expected.len(), actual.len()));
+    }
+    Ok(())
+}
+
+fn run_case<V: Variant>(case: &Value) -> Result<(), String> {
+    let mut cpu = Cpu::<V>::new();
+    let mut bus = RecordingBus { mem: Box::new([0; 65536]), cycles: RefCell::new(Vec::new()) };
+    set_state(&mut cpu, &mut bus, &case["initial"])?;
+
+    cpu.step(&mut bus);
+
+    check_state(&cpu, &bus, &case["final"])?;
+    if let Some(cycles) = case["cycles"].as_array() {
+        check_cycles(&bus, cycles)?;
+        if cpu.cycles != cycles.len() as u64 {
+            return Err(format!("cycle count: expected {}, found {}", cycles.len(), cpu.cycles));
+        }
+    }
+    Ok(())
+}
+
+/// Runs every case in `path`, returning a description of each failure.
+/// Cases for opcodes the variant doesn't decode are skipped.
+fn run_file<V: Variant>(path: &Path) -> Vec<String> {
+    let src = std::fs::read_to_string(path).unwrap();
+    let cases: Value = serde_json::from_str(&src).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let mut failures = Vec::new();
+    for case in cases.as_array().expect("expected an array of cases") {
+        let name = case["name"].as_str().unwrap_or("?");
+        let opcode = name.split(' ').next().and_then(|op| u8::from_str_radix(op, 16).ok());
+        if opcode.is_some_and(|op| V::decode(op).is_none()) {
+            continue;
+        }
+        if let Err(message) = run_case::<V>(case) {
+            failures.push(format!("{} `{}`: {}", path.display(), name, message));
+        }
+    }
+    failures
+}
+
+fn run_suite<V: Variant>(var: &str) {
+    let dir = std::env::var(var)
+        .unwrap_or_else(|_| panic!("set {} to a directory of ProcessorTests JSON files", var));
+    let mut paths: Vec<_> = std::fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    let failures: Vec<String> = paths.iter().flat_map(|path| run_file::<V>(path)).collect();
+    for failure in failures.iter().take(50) {
+        eprintln!("{}", failure);
+    }
+    assert!(failures.is_empty(), "{} failing cases", failures.len());
+}
+
+fn assert_passes<V: Variant>(fixture: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/processor_tests").join(fixture);
+    let failures = run_file::<V>(&path);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn nmos6502_fixture() {
+    assert_passes::<Nmos6502>("6502.json");
+}
+
+#[test]
+fn cmos65c02_fixture() {
+    assert_passes::<Cmos65c02>("65c02.json");
+}
+
+#[test]
+#[ignore]
+fn nmos6502_suite() {
+    run_suite::<Nmos6502>("PROCESSOR_TESTS_6502");
+}
+
+#[test]
+#[ignore]
+fn cmos65c02_suite() {
+    run_suite::<Cmos65c02>("PROCESSOR_TESTS_65C02");
+}